@@ -0,0 +1,116 @@
+//! Benchmark of logins under concurrent load.
+//!
+//! Serves the API with passwords hashed by bcrypt on the hashing pool, and
+//! sends increasingly large bursts of concurrent logins. For each burst size,
+//! reports the login throughput, the number of logins rejected because the
+//! pool's queue was full, and the latency of a request which needs no hashing
+//! at all, which stays low as long as hashing does not stall the runtime.
+//!
+//! Run with `cargo bench --bench concurrent_logins`. The bcrypt cost defaults
+//! to 10, and can be set with the `BCRYPT_COST` environment variable.
+
+use axum_api::{
+    create_api_router, create_well_known_router,
+    database::SimpleMemoryDatabase,
+    password::{BcryptHasher, HashingPool},
+    token::{SigningKey, TokenManager},
+    ServerState,
+};
+use reqwest::StatusCode;
+use serde_json::json;
+use std::{
+    env,
+    error::Error,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const ADDRESS: &str = "127.0.0.1:29300";
+const BURST_SIZES: [usize; 4] = [1, 8, 32, 128];
+const BURSTS: usize = 3;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let cost = env::var("BCRYPT_COST").map_or(Ok(10), |cost| cost.parse())?;
+    let workers = std::thread::available_parallelism()?.get();
+
+    let database = SimpleMemoryDatabase::new()
+        .with_password_hasher(Arc::new(BcryptHasher::new(cost)?))?
+        .with_hashing_pool(HashingPool::new(workers, workers * 16));
+    let token_manager = TokenManager::new(
+        Duration::from_secs(600),
+        Duration::from_secs(30),
+        SigningKey::from_secret(jsonwebtoken::Algorithm::HS256, b"secret")?,
+    );
+    let router = create_api_router()
+        .merge(create_well_known_router())
+        .with_state(ServerState::new(database, token_manager));
+    let server = axum::Server::bind(&ADDRESS.parse()?)
+        .serve(router.into_make_service_with_connect_info::<SocketAddr>());
+    let server_task = tokio::spawn(server);
+
+    let client = reqwest::Client::new();
+    let credentials = json!({"email": "email@addre.ss", "password": "password"});
+    client
+        .post(format!("http://{ADDRESS}/register"))
+        .json(&credentials)
+        .send()
+        .await?;
+
+    println!(
+        "bcrypt cost {cost}, {workers} hashing workers, queue of {}",
+        workers * 16
+    );
+    println!(
+        "{:>10} {:>12} {:>10} {:>22}",
+        "burst", "logins/s", "rejected", "other request latency"
+    );
+
+    for burst_size in BURST_SIZES {
+        let mut succeeded = 0;
+        let mut rejected = 0;
+        let mut elapsed = Duration::ZERO;
+        let mut max_latency = Duration::ZERO;
+
+        for _ in 0..BURSTS {
+            let start = Instant::now();
+            let logins = (0..burst_size)
+                .map(|_| {
+                    let request = client
+                        .post(format!("http://{ADDRESS}/login"))
+                        .json(&credentials)
+                        .send();
+                    tokio::spawn(request)
+                })
+                .collect::<Vec<_>>();
+
+            // give the logins a head start, so that they occupy the pool
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            let request_start = Instant::now();
+            client
+                .get(format!("http://{ADDRESS}/.well-known/jwks.json"))
+                .send()
+                .await?;
+            max_latency = max_latency.max(request_start.elapsed());
+
+            for login in logins {
+                match login.await??.status() {
+                    StatusCode::OK => succeeded += 1,
+                    StatusCode::SERVICE_UNAVAILABLE => rejected += 1,
+                    status => return Err(format!("unexpected login status {status}").into()),
+                }
+            }
+            elapsed += start.elapsed();
+        }
+
+        println!(
+            "{burst_size:>10} {:>12.1} {rejected:>10} {:>19.1} ms",
+            f64::from(succeeded) / elapsed.as_secs_f64(),
+            max_latency.as_secs_f64() * 1000.0
+        );
+    }
+
+    server_task.abort();
+    Ok(())
+}
@@ -1,19 +1,53 @@
 //! API routing.
 
 use crate::{
-    database::{self, Database},
+    auth::{AuthenticatedUser, MaybeVerifiedUser},
+    database::{self, ApiKey, Database, DatabaseError, OAuthClient, UserProfile},
+    mail::Mail,
+    oauth::{self, AuthorizationParams, OAuthError},
+    password::{PasswordHashError, PasswordRule, PolicyViolation},
+    roles::{Admin, RequireRole},
     server_state::ServerState,
+    throttling::LoginSubject,
+    token::{self, EmailChange, TokenError},
+    validation::{self, Valid, Validate, ValidationErrors},
+    verification::UnverifiedLogin,
 };
 use axum::{
-    extract::State,
-    headers::{authorization::Bearer, Authorization},
-    http::StatusCode,
-    response::IntoResponse,
-    routing::{get, post},
-    Json, Router, TypedHeader,
+    extract::{
+        rejection::{FormRejection, QueryRejection},
+        ConnectInfo, Path, Query, State,
+    },
+    headers::{
+        authorization::{Basic, Bearer},
+        Authorization,
+    },
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post, put},
+    Form, Json, Router, TypedHeader,
 };
-use serde_json::json;
-use tracing::{info, warn};
+use serde::{Deserialize, Deserializer};
+use serde_json::{json, Map, Value};
+use std::{
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Time after which clients are asked to retry when too many passwords are
+/// already waiting to be hashed.
+const HASHING_OVERLOADED_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Number of users listed per page unless a `limit` is given.
+const DEFAULT_USER_LISTING_LIMIT: usize = 50;
+
+/// Maximum number of users listed per page.
+const MAX_USER_LISTING_LIMIT: usize = 100;
+
+/// Maximum lifetime of an expiring API key.
+const MAX_API_KEY_LIFETIME: Duration = Duration::from_secs(10 * 365 * 24 * 60 * 60);
 
 /// Creates a router for API endpoints.
 pub fn create_api_router<D: Database + 'static>() -> Router<ServerState<D>> {
@@ -21,58 +55,2021 @@ pub fn create_api_router<D: Database + 'static>() -> Router<ServerState<D>> {
         .route("/register", post(register))
         .route("/login", post(login))
         .route("/token", get(get_token))
+        .route("/token/refresh", post(refresh_token))
+        .route("/logout", post(logout))
+        .route("/logout/all", post(logout_all))
+        .route("/password", put(change_password))
+        .route("/password/forgot", post(forgot_password))
+        .route("/password/reset", post(reset_password))
+        .route("/verify", get(verify_email))
+        .route("/verify/resend", post(resend_verification))
+        .route(
+            "/me",
+            get(get_profile)
+                .patch(update_profile)
+                .delete(delete_account),
+        )
+        .route("/me/email", post(change_email))
+        .route("/me/export", get(export_account))
+        .route("/me/api-keys", get(list_api_keys).post(create_api_key))
+        .route("/me/api-keys/:key_id", delete(delete_api_key))
+        .route(
+            "/oauth/authorize",
+            get(oauth_authorize).post(oauth_authorize_decision),
+        )
+        .route("/oauth/token", post(oauth_token))
+}
+
+/// Creates a router for user management endpoints, which only administrators
+/// may use.
+pub fn create_admin_router<D: Database + 'static>() -> Router<ServerState<D>> {
+    Router::new()
+        .route("/admin/users", get(list_users))
+        .route("/admin/users/:user_id", get(get_user).delete(delete_user))
+        .route("/admin/users/:user_id/disable", post(disable_user))
+        .route("/admin/users/:user_id/enable", post(enable_user))
+        .route("/admin/users/:user_id/roles", put(set_user_roles))
+        .route(
+            "/admin/users/:user_id/password-reset",
+            post(force_password_reset),
+        )
+}
+
+/// Creates a router for well-known endpoints, which are conventionally served
+/// from the root of a host rather than alongside the API endpoints.
+pub fn create_well_known_router<D: Database + 'static>() -> Router<ServerState<D>> {
+    Router::new().route("/.well-known/jwks.json", get(get_jwks))
 }
 
 /// Handler for user registration.
 async fn register<D: Database>(
     State(state): State<ServerState<D>>,
-    Json(user): Json<database::User>,
-) -> impl IntoResponse {
-    if !state.database().try_add_user(user).await {
-        // TODO: assumed to be an e-mail conflict, but other kinds of errors are
-        // possible in the future
-        info!("could not add new user to database due to email conflict with existing user");
-        return StatusCode::CONFLICT;
+    Valid(user): Valid<database::User>,
+) -> Response {
+    if let Err(errors) =
+        check_password_policy(&state, "password", &user.password, &user.email).await
+    {
+        info!("rejected password violating the password policy during registration");
+        return errors.into_response();
     }
 
-    StatusCode::OK
+    let email = user.email.clone();
+    match state.database().try_add_user(user).await {
+        Ok(()) => {
+            if state.email_verification().is_some() {
+                send_verification_mail(&state, email);
+            }
+            StatusCode::OK.into_response()
+        }
+        Err(DatabaseError::Conflict) => {
+            info!("could not add new user to database due to email conflict with existing user");
+            StatusCode::CONFLICT.into_response()
+        }
+        Err(e) => database_error_response(&e),
+    }
 }
 
 /// Handler for generating an API token for a user.
+///
+/// If login throttling is enabled, logins are rejected with
+/// `429 Too Many Requests` while the account or the client's IP address is
+/// blocked due to failed logins.
 async fn login<D: Database>(
     State(state): State<ServerState<D>>,
-    Json(user): Json<database::User>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    Valid(user): Valid<database::User>,
+) -> Response {
+    if let Err(response) =
+        check_credentials(&state, connect_info, &user, StatusCode::UNAUTHORIZED).await
+    {
+        return response;
+    }
+
+    let profile = match state.database().get_user_by_email(&user.email).await {
+        Ok(profile) => profile,
+        Err(e) => return (database_error_status(&e), "").into_response(),
+    };
+
+    let response = issue_tokens(&state, &profile, None).await;
+    if response.status() == StatusCode::OK {
+        if let Err(e) = state
+            .database()
+            .set_last_login(profile.id, SystemTime::now())
+            .await
+        {
+            warn!("could not record login of user: {e}");
+        }
+    }
+    response
+}
+
+/// Checks the credentials of a user who is logging in or confirming their
+/// password, subject to login throttling if it is enabled. Wrong credentials
+/// are rejected with the given status code.
+///
+/// # Errors
+///
+/// Returns the response to reject the request with if the credentials are
+/// wrong, if the account or the client's IP address is blocked due to failed
+/// logins, or if the credentials cannot be checked.
+async fn check_credentials<D: Database>(
+    state: &ServerState<D>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    user: &database::User,
+    wrong_credentials_status: StatusCode,
+) -> Result<(), Response> {
+    let mut subjects = vec![LoginSubject::Account(&user.email)];
+    if let Some(ConnectInfo(address)) = connect_info {
+        subjects.push(LoginSubject::Ip(address.ip()));
+    }
+
+    if let Some(login_throttling) = state.login_throttling() {
+        match login_throttling
+            .blocked_for(state.database(), &subjects)
+            .await
+        {
+            Ok(None) => {}
+            Ok(Some(blocked_for)) => {
+                info!("rejected credentials while blocked due to failed logins");
+                return Err(
+                    (StatusCode::TOO_MANY_REQUESTS, retry_after(blocked_for), "").into_response(),
+                );
+            }
+            Err(e) => return Err((database_error_status(&e), "").into_response()),
+        }
+    }
+
+    match state.database().validate_user(user).await {
+        Ok(()) => {}
+        Err(DatabaseError::NotFound | DatabaseError::WrongPassword) => {
+            info!("invalid credentials provided");
+            let Some(login_throttling) = state.login_throttling() else {
+                return Err((wrong_credentials_status, "").into_response());
+            };
+
+            return Err(
+                match login_throttling
+                    .record_failure(state.database(), &subjects)
+                    .await
+                {
+                    Ok(None) => (wrong_credentials_status, "").into_response(),
+                    Ok(Some(blocked_for)) => {
+                        (wrong_credentials_status, retry_after(blocked_for), "").into_response()
+                    }
+                    Err(e) => (database_error_status(&e), "").into_response(),
+                },
+            );
+        }
+        Err(e) => return Err(database_error_response(&e)),
+    }
+
+    if let Some(login_throttling) = state.login_throttling() {
+        if let Err(e) = login_throttling
+            .record_success(state.database(), subjects[0])
+            .await
+        {
+            warn!("could not reset failed logins of user: {e}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Request body for refreshing an API token.
+#[derive(Deserialize)]
+struct RefreshRequest {
+    refresh_token: String,
+}
+
+/// Handler for exchanging a refresh token for a new API token and refresh
+/// token.
+///
+/// Presenting a refresh token which has already been exchanged revokes every
+/// refresh token in its family, as it may have been stolen.
+async fn refresh_token<D: Database>(
+    State(state): State<ServerState<D>>,
+    Json(request): Json<RefreshRequest>,
 ) -> impl IntoResponse {
-    if !state.database().validate_user(&user).await {
-        info!("invalid credentials provided during login");
+    let Some((family_id, token_hash)) = token::parse_refresh_token(&request.refresh_token) else {
+        info!("malformed refresh token provided");
         return (StatusCode::UNAUTHORIZED, "").into_response();
-    }
+    };
 
-    let token = if let Ok(result) = state.token_manager().new_token(&user) {
-        result
-    } else {
-        warn!("could not create token for user");
-        return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+    let refresh_token = match state
+        .database()
+        .consume_refresh_token(&family_id, &token_hash)
+        .await
+    {
+        Ok(refresh_token) => refresh_token,
+        Err(DatabaseError::NotFound) => {
+            info!("unknown or expired refresh token provided");
+            return (StatusCode::UNAUTHORIZED, "").into_response();
+        }
+        Err(DatabaseError::Conflict) => {
+            warn!("reuse of rotated refresh token detected; revoking token family");
+            if let Err(e) = state
+                .database()
+                .revoke_refresh_token_family(&family_id)
+                .await
+            {
+                return (database_error_status(&e), "").into_response();
+            }
+            return (StatusCode::UNAUTHORIZED, "").into_response();
+        }
+        Err(e) => return (database_error_status(&e), "").into_response(),
     };
 
-    (StatusCode::OK, Json(json!({ "token": token }))).into_response()
+    let profile = match state.database().get_user(refresh_token.user_id).await {
+        Ok(profile) => profile,
+        Err(DatabaseError::NotFound) => {
+            info!("refresh token of nonexistent user provided");
+            return (StatusCode::UNAUTHORIZED, "").into_response();
+        }
+        Err(e) => return (database_error_status(&e), "").into_response(),
+    };
+
+    issue_tokens(&state, &profile, Some(&family_id)).await
 }
 
 /// Handler for checking the validity of a token.
-#[allow(clippy::unused_async)]
 async fn get_token<D: Database>(
     State(state): State<ServerState<D>>,
     TypedHeader(authorization): TypedHeader<Authorization<Bearer>>,
 ) -> impl IntoResponse {
     let token = authorization.token();
-    let token_payload = state
+    let token_payload = match state
         .token_manager()
-        .decode_and_validate_token(token.into());
+        .decode_and_validate_token(token.into(), state.database())
+        .await
+    {
+        Err(TokenError::Database(e)) => return database_error_status(&e).into_response(),
+        token_payload => token_payload,
+    };
 
     let mut response = json!({"token": token, "valid": token_payload.is_ok()});
     if let Ok(payload) = token_payload {
-        response["user_email"] = payload.user_email.into();
+        response["user_id"] = payload.sub.to_string().into();
+        response["email_verified"] = payload.email_verified.into();
+        response["roles"] = payload.roles.into();
+        response["scope"] = payload.scope.into();
+        if let Some(client_id) = payload.client_id {
+            response["client_id"] = client_id.into();
+        }
+    };
+
+    Json(response).into_response()
+}
+
+/// Handler for retrieving the public keys which verify API tokens, as a JSON
+/// web key set.
+#[allow(clippy::unused_async)]
+async fn get_jwks<D: Database>(State(state): State<ServerState<D>>) -> impl IntoResponse {
+    Json(state.token_manager().jwks())
+}
+
+/// Handler for revoking the token used to authenticate the request.
+async fn logout<D: Database>(
+    State(state): State<ServerState<D>>,
+    MaybeVerifiedUser(user): MaybeVerifiedUser,
+) -> impl IntoResponse {
+    // API keys do not expire on their own, so they are deleted instead
+    let result = if let Some(key_id) = &user.api_key_id {
+        state
+            .database()
+            .delete_api_key(user.user_id(), key_id)
+            .await
+    } else {
+        state
+            .token_manager()
+            .revoke_token(&user.token_payload, state.database())
+            .await
+    };
+
+    match result {
+        Ok(()) => StatusCode::OK,
+        Err(e) => database_error_status(&e),
+    }
+}
+
+/// Handler for revoking every token of the user who authenticated the
+/// request, including refresh tokens.
+async fn logout_all<D: Database>(
+    State(state): State<ServerState<D>>,
+    MaybeVerifiedUser(user): MaybeVerifiedUser,
+) -> impl IntoResponse {
+    if let Err(status) = reject_delegated_token(&user) {
+        return status;
+    }
+    if let Err(status) = reject_api_key(&user) {
+        return status;
+    }
+
+    match state
+        .database()
+        .revoke_user_tokens(
+            user.user_id(),
+            SystemTime::now(),
+            state.token_manager().tokens_expire_at(),
+        )
+        .await
+    {
+        Ok(()) => {
+            info!("revoked all tokens of user");
+            StatusCode::OK
+        }
+        Err(e) => database_error_status(&e),
+    }
+}
+
+/// Request body for changing a password.
+#[derive(Deserialize)]
+struct ChangePasswordRequest {
+    current_password: String,
+    new_password: String,
+}
+
+impl Validate for ChangePasswordRequest {
+    fn validate(self) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        errors.check(
+            "new_password",
+            validation::check_password(&self.new_password),
+        );
+        errors.into_result(self)
+    }
+}
+
+/// Handler for changing the password of the user who authenticated the
+/// request.
+///
+/// Every other token of the user is revoked and their API keys are deleted, so
+/// new tokens are issued in place of the one used for the request.
+async fn change_password<D: Database>(
+    State(state): State<ServerState<D>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    user: AuthenticatedUser,
+    Valid(request): Valid<ChangePasswordRequest>,
+) -> Response {
+    if let Err(status) = reject_delegated_token(&user) {
+        return status.into_response();
+    }
+    if let Err(status) = reject_api_key(&user) {
+        return status.into_response();
+    }
+
+    let database = state.database();
+
+    let profile = match database.get_user(user.user_id()).await {
+        Ok(profile) => profile,
+        Err(e) => return (database_error_status(&e), "").into_response(),
+    };
+
+    if let Err(errors) = check_password_policy(
+        &state,
+        "new_password",
+        &request.new_password,
+        &profile.email,
+    )
+    .await
+    {
+        info!("rejected password violating the password policy during password change");
+        return errors.into_response();
+    }
+
+    let credentials = database::User {
+        email: profile.email.clone(),
+        password: request.current_password,
     };
+    if let Err(response) =
+        check_credentials(&state, connect_info, &credentials, StatusCode::FORBIDDEN).await
+    {
+        return response;
+    }
+
+    if let Err(e) = database
+        .update_password(profile.id, request.new_password)
+        .await
+    {
+        return database_error_response(&e);
+    }
+    if let Err(e) = revoke_user_credentials(&state, profile.id).await {
+        return (database_error_status(&e), "").into_response();
+    }
+    info!("changed password of user");
+
+    issue_tokens(&state, &profile, None).await
+}
 
-    Json(response)
+/// Request body for requesting a password reset.
+#[derive(Deserialize)]
+struct ForgotPasswordRequest {
+    email: String,
+}
+
+impl Validate for ForgotPasswordRequest {
+    fn validate(self) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        let email = errors.check("email", validation::normalize_email(&self.email));
+        errors.into_result(Self {
+            email: email.unwrap_or_default(),
+        })
+    }
+}
+
+/// Handler for sending a password reset code to a user.
+///
+/// Responds the same way whether or not the user exists, so as to not reveal
+/// which e-mails are registered. The code is created and sent in the
+/// background, so that the response time does not reveal it either.
+async fn forgot_password<D: Database + 'static>(
+    State(state): State<ServerState<D>>,
+    Valid(request): Valid<ForgotPasswordRequest>,
+) -> impl IntoResponse {
+    let Some(mailer) = state.mailer() else {
+        warn!("password reset requested, but no mailer is configured");
+        return StatusCode::NOT_IMPLEMENTED;
+    };
+
+    tokio::spawn(async move {
+        let database = state.database();
+        let user_id = match database.get_user_by_email(&request.email).await {
+            Ok(profile) => profile.id,
+            Err(DatabaseError::NotFound) => {
+                info!("password reset requested for nonexistent user");
+                return;
+            }
+            Err(e) => {
+                warn!("could not look up user for password reset: {e}");
+                return;
+            }
+        };
+
+        let token_manager = state.token_manager();
+        let (code, reset) = token_manager.new_password_reset(user_id);
+        if let Err(e) = database.add_password_reset(reset).await {
+            warn!("could not store password reset code: {e}");
+            return;
+        }
+
+        let mail = Mail {
+            to: request.email,
+            subject: "Password reset".to_string(),
+            body: format!(
+                "Use the following code to reset your password: {code}\n\n\
+                The code expires in {} minutes. If you did not request a password reset, you \
+                can ignore this message.\n",
+                token_manager.password_reset_lifetime().as_secs() / 60
+            ),
+        };
+        if let Err(e) = mailer.send(mail).await {
+            warn!("could not send password reset code: {e}");
+        }
+    });
+
+    StatusCode::ACCEPTED
+}
+
+/// Request body for resetting a password.
+#[derive(Deserialize)]
+struct ResetPasswordRequest {
+    code: String,
+    new_password: String,
+}
+
+impl Validate for ResetPasswordRequest {
+    fn validate(self) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        errors.check(
+            "new_password",
+            validation::check_password(&self.new_password),
+        );
+        errors.into_result(self)
+    }
+}
+
+/// Handler for setting a new password with a password reset code.
+///
+/// Every token of the user is revoked and their API keys are deleted.
+async fn reset_password<D: Database>(
+    State(state): State<ServerState<D>>,
+    Valid(request): Valid<ResetPasswordRequest>,
+) -> Response {
+    let database = state.database();
+
+    let reset = match database
+        .consume_password_reset(&token::hash_token_string(&request.code))
+        .await
+    {
+        Ok(reset) => reset,
+        Err(DatabaseError::NotFound) => {
+            info!("invalid or expired password reset code provided");
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+        Err(e) => return database_error_status(&e).into_response(),
+    };
+
+    let profile = match database.get_user(reset.user_id).await {
+        Ok(profile) => profile,
+        Err(e) => return database_error_status(&e).into_response(),
+    };
+
+    // the policy may depend on the user's e-mail address, which is only known
+    // once the code is consumed, so the code is restored if the check fails
+    if let Err(errors) = check_password_policy(
+        &state,
+        "new_password",
+        &request.new_password,
+        &profile.email,
+    )
+    .await
+    {
+        info!("rejected password violating the password policy during password reset");
+        if let Err(e) = database.add_password_reset(reset).await {
+            return database_error_status(&e).into_response();
+        }
+        return errors.into_response();
+    }
+
+    if let Err(e) = database
+        .update_password(profile.id, request.new_password)
+        .await
+    {
+        return database_error_response(&e);
+    }
+    if let Err(e) = revoke_user_credentials(&state, profile.id).await {
+        return database_error_status(&e).into_response();
+    }
+    info!("reset password of user");
+
+    StatusCode::OK.into_response()
+}
+
+/// Query parameters of an e-mail verification link.
+#[derive(Deserialize)]
+struct VerifyQuery {
+    code: String,
+}
+
+/// Handler for verification links, which marks the e-mail address of the
+/// user the link was sent to as verified, or confirms a change of a user's
+/// e-mail address to the one the link was sent to.
+async fn verify_email<D: Database>(
+    State(state): State<ServerState<D>>,
+    Query(query): Query<VerifyQuery>,
+) -> impl IntoResponse {
+    let token_manager = state.token_manager();
+    if let Ok(change) = token_manager.decode_email_change_token(&query.code) {
+        return confirm_email_change(&state, change).await;
+    }
+
+    let email = match token_manager.decode_email_verification_token(&query.code) {
+        Ok(email) => email,
+        Err(e) => {
+            info!("invalid e-mail verification code provided: {e}");
+            return StatusCode::BAD_REQUEST;
+        }
+    };
+
+    match state.database().set_user_verified(&email).await {
+        Ok(()) => {
+            info!("verified e-mail address of user");
+            StatusCode::OK
+        }
+        Err(e) => database_error_status(&e),
+    }
+}
+
+/// Request body for resending a verification link.
+#[derive(Deserialize)]
+struct ResendVerificationRequest {
+    email: String,
+}
+
+impl Validate for ResendVerificationRequest {
+    fn validate(self) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        let email = errors.check("email", validation::normalize_email(&self.email));
+        errors.into_result(Self {
+            email: email.unwrap_or_default(),
+        })
+    }
+}
+
+/// Handler for sending another verification link to a user.
+///
+/// Responds the same way whether or not the user exists or is already
+/// verified, so as to not reveal which e-mails are registered.
+async fn resend_verification<D: Database>(
+    State(state): State<ServerState<D>>,
+    Valid(request): Valid<ResendVerificationRequest>,
+) -> impl IntoResponse {
+    if state.email_verification().is_none() || state.mailer().is_none() {
+        warn!("verification link requested, but e-mail verification is not configured");
+        return StatusCode::NOT_IMPLEMENTED;
+    }
+
+    match state.database().is_user_verified(&request.email).await {
+        Ok(false) => send_verification_mail(&state, request.email),
+        Ok(true) | Err(DatabaseError::NotFound) => {
+            info!("verification link requested for nonexistent or verified user");
+        }
+        Err(e) => return database_error_status(&e),
+    }
+
+    StatusCode::ACCEPTED
+}
+
+/// Sends a verification link to a user in the background.
+fn send_verification_mail<D: Database>(state: &ServerState<D>, user_email: String) {
+    let (Some(mailer), Some(email_verification)) = (state.mailer(), state.email_verification())
+    else {
+        warn!("cannot send verification link, as no mailer is configured");
+        return;
+    };
+
+    let code = match state
+        .token_manager()
+        .new_email_verification_token(&user_email)
+    {
+        Ok(code) => code,
+        Err(e) => {
+            warn!("could not create e-mail verification token: {e}");
+            return;
+        }
+    };
+    let mail = Mail {
+        to: user_email,
+        subject: "Verify your e-mail address".to_string(),
+        body: format!(
+            "Open the following link to verify your e-mail address: {}\n",
+            email_verification.link(&code)
+        ),
+    };
+
+    tokio::spawn(async move {
+        if let Err(e) = mailer.send(mail).await {
+            warn!("could not send verification link: {e}");
+        }
+    });
+}
+
+/// Request body for changing an e-mail address.
+#[derive(Deserialize)]
+struct ChangeEmailRequest {
+    password: String,
+    new_email: String,
+}
+
+impl Validate for ChangeEmailRequest {
+    fn validate(self) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        let new_email = errors.check("new_email", validation::normalize_email(&self.new_email));
+        errors.into_result(Self {
+            new_email: new_email.unwrap_or_default(),
+            ..self
+        })
+    }
+}
+
+/// Handler for changing the e-mail address of the user who authenticated the
+/// request.
+///
+/// The change only takes effect once confirmed through a link sent to the new
+/// address. Unverified users may change their address as well, e.g. to fix a
+/// typo made while registering.
+async fn change_email<D: Database>(
+    State(state): State<ServerState<D>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    MaybeVerifiedUser(user): MaybeVerifiedUser,
+    Valid(request): Valid<ChangeEmailRequest>,
+) -> Response {
+    if let Err(status) = reject_delegated_token(&user) {
+        return status.into_response();
+    }
+    if let Err(status) = reject_api_key(&user) {
+        return status.into_response();
+    }
+
+    if state.email_verification().is_none() || state.mailer().is_none() {
+        warn!("e-mail change requested, but e-mail verification is not configured");
+        return StatusCode::NOT_IMPLEMENTED.into_response();
+    }
+    let database = state.database();
+
+    let profile = match database.get_user(user.user_id()).await {
+        Ok(profile) => profile,
+        Err(e) => return database_error_status(&e).into_response(),
+    };
+
+    let credentials = database::User {
+        email: profile.email.clone(),
+        password: request.password,
+    };
+    if let Err(response) =
+        check_credentials(&state, connect_info, &credentials, StatusCode::FORBIDDEN).await
+    {
+        return response;
+    }
+
+    match database.user_exists(&request.new_email).await {
+        Ok(false) => {}
+        Ok(true) => {
+            info!("e-mail change requested to an address which is already registered");
+            return StatusCode::CONFLICT.into_response();
+        }
+        Err(e) => return database_error_status(&e).into_response(),
+    }
+
+    send_email_change_mail(
+        &state,
+        EmailChange {
+            user_id: profile.id,
+            old_email: profile.email,
+            new_email: request.new_email,
+        },
+    );
+
+    StatusCode::ACCEPTED.into_response()
+}
+
+/// Sends a link confirming an e-mail change to the new address in the
+/// background.
+fn send_email_change_mail<D: Database>(state: &ServerState<D>, change: EmailChange) {
+    let (Some(mailer), Some(email_verification)) = (state.mailer(), state.email_verification())
+    else {
+        warn!("cannot send e-mail change link, as no mailer is configured");
+        return;
+    };
+
+    let code = match state.token_manager().new_email_change_token(&change) {
+        Ok(code) => code,
+        Err(e) => {
+            warn!("could not create e-mail change token: {e}");
+            return;
+        }
+    };
+    let mail = Mail {
+        to: change.new_email,
+        subject: "Confirm your new e-mail address".to_string(),
+        body: format!(
+            "Open the following link to change the e-mail address of your account to this \
+            one: {}\n\n\
+            If you did not request this change, you can ignore this message.\n",
+            email_verification.link(&code)
+        ),
+    };
+
+    tokio::spawn(async move {
+        if let Err(e) = mailer.send(mail).await {
+            warn!("could not send e-mail change link: {e}");
+        }
+    });
+}
+
+/// Applies a confirmed e-mail change, and notifies the previous address.
+///
+/// The change is refused if the user's address has changed since it was
+/// requested, or the new address has been registered by another user.
+async fn confirm_email_change<D: Database>(
+    state: &ServerState<D>,
+    change: EmailChange,
+) -> StatusCode {
+    match state
+        .database()
+        .change_user_email(change.user_id, &change.old_email, &change.new_email)
+        .await
+    {
+        Ok(()) => info!("changed e-mail address of user"),
+        Err(DatabaseError::Conflict) => {
+            info!("outdated or conflicting e-mail change link provided");
+            return StatusCode::CONFLICT;
+        }
+        Err(e) => return database_error_status(&e),
+    }
+
+    if let Some(mailer) = state.mailer() {
+        let mail = Mail {
+            to: change.old_email,
+            subject: "Your e-mail address was changed".to_string(),
+            body: format!(
+                "The e-mail address of your account was changed to {}.\n",
+                change.new_email
+            ),
+        };
+        tokio::spawn(async move {
+            if let Err(e) = mailer.send(mail).await {
+                warn!("could not send e-mail change notice: {e}");
+            }
+        });
+    }
+
+    StatusCode::OK
+}
+
+/// Checks a new password of a user against the password policy and the list
+/// of breached passwords, if any.
+async fn check_password_policy<D: Database>(
+    state: &ServerState<D>,
+    field: &'static str,
+    password: &str,
+    user_email: &str,
+) -> Result<(), ValidationErrors> {
+    let mut violations = state
+        .password_policy()
+        .check(password, user_email)
+        .err()
+        .unwrap_or_default();
+
+    if let Some(breached_passwords) = state.breached_passwords() {
+        let password = password.to_string();
+        // the list is memory-mapped, so lookups may block on reading from disk
+        let breached = tokio::task::spawn_blocking(move || breached_passwords.contains(&password))
+            .await
+            .expect("breached password lookup panicked");
+        if breached {
+            violations.push(PolicyViolation {
+                rule: PasswordRule::BreachedPasswords,
+                message: "must not have appeared in a data breach".to_string(),
+            });
+        }
+    }
+
+    let mut errors = ValidationErrors::new();
+    errors.add_policy_violations(field, violations);
+    errors.into_result(())
+}
+
+/// Handler for retrieving the profile of the user who authenticated the
+/// request.
+async fn get_profile<D: Database>(
+    State(state): State<ServerState<D>>,
+    MaybeVerifiedUser(user): MaybeVerifiedUser,
+) -> Response {
+    match state.database().get_user(user.user_id()).await {
+        Ok(profile) => Json(profile_json(&profile)).into_response(),
+        Err(e) => database_error_status(&e).into_response(),
+    }
+}
+
+/// Request body for updating a profile. Omitted fields are left unchanged.
+#[derive(Deserialize)]
+struct UpdateProfileRequest {
+    /// New display name, or `null` to remove it.
+    #[serde(default, deserialize_with = "deserialize_nullable")]
+    display_name: Option<Option<String>>,
+
+    /// Metadata entries to set, or to remove if `null`, as in a JSON merge
+    /// patch. Other entries are left unchanged.
+    metadata: Option<Map<String, Value>>,
+}
+
+impl Validate for UpdateProfileRequest {
+    fn validate(self) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        let display_name = match self.display_name {
+            Some(Some(display_name)) => errors
+                .check(
+                    "display_name",
+                    validation::normalize_display_name(&display_name),
+                )
+                .map(Some),
+            display_name => display_name,
+        };
+
+        errors.into_result(Self {
+            display_name,
+            metadata: self.metadata,
+        })
+    }
+}
+
+/// Deserializes an optional field which may be `null`, so that a `null`
+/// value can be told apart from an omitted field.
+fn deserialize_nullable<'de, T: Deserialize<'de>, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Option<T>>, D::Error> {
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+/// Handler for updating the profile of the user who authenticated the
+/// request, responding with the updated profile.
+async fn update_profile<D: Database>(
+    State(state): State<ServerState<D>>,
+    user: AuthenticatedUser,
+    Valid(request): Valid<UpdateProfileRequest>,
+) -> Response {
+    if let Err(status) = reject_delegated_token(&user) {
+        return status.into_response();
+    }
+    if let Err(status) = reject_api_key(&user) {
+        return status.into_response();
+    }
+
+    let database = state.database();
+
+    let mut profile = match database.get_user(user.user_id()).await {
+        Ok(profile) => profile,
+        Err(e) => return database_error_status(&e).into_response(),
+    };
+
+    if let Some(display_name) = request.display_name {
+        profile.display_name = display_name;
+    }
+    for (key, value) in request.metadata.unwrap_or_default() {
+        if value.is_null() {
+            profile.metadata.remove(&key);
+        } else {
+            profile.metadata.insert(key, value);
+        }
+    }
+
+    let mut errors = ValidationErrors::new();
+    errors.check("metadata", validation::check_metadata(&profile.metadata));
+    if let Err(errors) = errors.into_result(()) {
+        return errors.into_response();
+    }
+
+    if let Err(e) = database
+        .update_user_profile(
+            profile.id,
+            profile.display_name.as_deref(),
+            &profile.metadata,
+        )
+        .await
+    {
+        return database_error_status(&e).into_response();
+    }
+    info!("updated profile of user");
+
+    Json(profile_json(&profile)).into_response()
+}
+
+/// Request body for deleting an account.
+#[derive(Deserialize)]
+struct DeleteAccountRequest {
+    password: String,
+}
+
+/// Handler for deleting the account of the user who authenticated the
+/// request, along with everything stored about them.
+///
+/// The user's tokens are revoked, so they are rejected from then on.
+async fn delete_account<D: Database>(
+    State(state): State<ServerState<D>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    MaybeVerifiedUser(user): MaybeVerifiedUser,
+    Json(request): Json<DeleteAccountRequest>,
+) -> Response {
+    if let Err(status) = reject_delegated_token(&user) {
+        return status.into_response();
+    }
+    if let Err(status) = reject_api_key(&user) {
+        return status.into_response();
+    }
+
+    let database = state.database();
+
+    let profile = match database.get_user(user.user_id()).await {
+        Ok(profile) => profile,
+        Err(e) => return database_error_status(&e).into_response(),
+    };
+
+    let credentials = database::User {
+        email: profile.email.clone(),
+        password: request.password,
+    };
+    if let Err(response) =
+        check_credentials(&state, connect_info, &credentials, StatusCode::FORBIDDEN).await
+    {
+        return response;
+    }
+
+    if let Err(e) = database
+        .clear_login_failures(&LoginSubject::Account(&profile.email).key())
+        .await
+    {
+        return database_error_status(&e).into_response();
+    }
+    let tokens_expire_at = state.token_manager().tokens_expire_at();
+    if let Err(e) = database.delete_user(profile.id, tokens_expire_at).await {
+        return database_error_status(&e).into_response();
+    }
+    info!("deleted user");
+
+    StatusCode::OK.into_response()
+}
+
+/// Handler for exporting everything stored about the user who authenticated
+/// the request, as a JSON file to download.
+///
+/// Password hashes and token hashes are left out, as they are of no use to
+/// the user.
+async fn export_account<D: Database>(
+    State(state): State<ServerState<D>>,
+    MaybeVerifiedUser(user): MaybeVerifiedUser,
+) -> Response {
+    if let Err(status) = reject_delegated_token(&user) {
+        return status.into_response();
+    }
+    if let Err(status) = reject_api_key(&user) {
+        return status.into_response();
+    }
+
+    let database = state.database();
+
+    let data = match database.export_user(user.user_id()).await {
+        Ok(data) => data,
+        Err(e) => return database_error_status(&e).into_response(),
+    };
+    let login_failures = match database
+        .get_login_failures(&LoginSubject::Account(&data.profile.email).key())
+        .await
+    {
+        Ok(login_failures) => login_failures,
+        Err(e) => return database_error_status(&e).into_response(),
+    };
+
+    let archive = json!({
+        "exported_at": token::unix_timestamp(SystemTime::now()),
+        "profile": profile_json(&data.profile),
+        "email_verified": data.email_verified,
+        "refresh_tokens": data
+            .refresh_tokens
+            .iter()
+            .map(|t| json!({
+                "family_id": t.family_id,
+                "expires_at": t.expires_at,
+                "consumed": t.consumed,
+            }))
+            .collect::<Vec<_>>(),
+        "password_resets": data
+            .password_resets
+            .iter()
+            .map(|r| json!({ "expires_at": r.expires_at }))
+            .collect::<Vec<_>>(),
+        "api_keys": data.api_keys.iter().map(api_key_json).collect::<Vec<_>>(),
+        "tokens_revoked_before": data.tokens_revoked_before.map(token::unix_timestamp),
+        "login_failures": login_failures.map(|f| json!({
+            "count": f.count,
+            "last_failure": token::unix_timestamp(f.last_failure),
+        })),
+    });
+    info!("exported data of user");
+
+    (
+        [(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"account.json\"",
+        )],
+        Json(archive),
+    )
+        .into_response()
+}
+
+/// Request body for creating an API key.
+#[derive(Deserialize)]
+struct CreateApiKeyRequest {
+    name: String,
+    #[serde(default)]
+    scopes: Vec<String>,
+
+    /// Lifetime of the key in seconds, unless it should not expire.
+    expires_in: Option<u64>,
+}
+
+impl Validate for CreateApiKeyRequest {
+    fn validate(self) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        let name = errors.check("name", validation::normalize_display_name(&self.name));
+        let scopes = errors.check("scopes", validation::normalize_grant_names(&self.scopes));
+        if self.expires_in.is_some_and(|expires_in| {
+            expires_in == 0 || expires_in > MAX_API_KEY_LIFETIME.as_secs()
+        }) {
+            errors.add(
+                "expires_in",
+                format!(
+                    "must be between 1 and {} seconds",
+                    MAX_API_KEY_LIFETIME.as_secs()
+                ),
+            );
+        }
+
+        errors.into_result(Self {
+            name: name.unwrap_or_default(),
+            scopes: scopes.unwrap_or_default(),
+            expires_in: self.expires_in,
+        })
+    }
+}
+
+/// Handler for creating a personal API key for the user who authenticated
+/// the request.
+///
+/// The key is only ever returned in this response. Its scopes must be roles or
+/// permissions which the user is granted. Neither API keys nor tokens which
+/// users issued to OAuth clients can create keys, as new keys are not limited
+/// to their scopes.
+async fn create_api_key<D: Database>(
+    State(state): State<ServerState<D>>,
+    user: AuthenticatedUser,
+    Valid(request): Valid<CreateApiKeyRequest>,
+) -> Response {
+    let database = state.database();
+
+    if let Err(status) = reject_delegated_token(&user) {
+        return status.into_response();
+    }
+    if let Err(status) = reject_api_key(&user) {
+        return status.into_response();
+    }
+
+    let profile = match database.get_user(user.user_id()).await {
+        Ok(profile) => profile,
+        Err(e) => return database_error_status(&e).into_response(),
+    };
+    if request
+        .scopes
+        .iter()
+        .any(|scope| !profile.roles.contains(scope) && !profile.permissions.contains(scope))
+    {
+        info!("rejected API key with scopes exceeding the user's roles and permissions");
+        let mut errors = ValidationErrors::new();
+        errors.add(
+            "scopes",
+            "must only contain roles and permissions granted to the user",
+        );
+        return errors.into_response();
+    }
+
+    let expires_at = request.expires_in.map(|expires_in| {
+        token::unix_timestamp(SystemTime::now() + Duration::from_secs(expires_in))
+    });
+    let (key, record) =
+        state
+            .token_manager()
+            .new_api_key(profile.id, &request.name, &request.scopes, expires_at);
+    let mut body = api_key_json(&record);
+    if let Err(e) = database.add_api_key(record).await {
+        return database_error_status(&e).into_response();
+    }
+    info!("created API key for user");
+
+    body["key"] = key.into();
+    Json(body).into_response()
+}
+
+/// Handler for listing the unexpired API keys of the user who authenticated
+/// the request, oldest first.
+async fn list_api_keys<D: Database>(
+    State(state): State<ServerState<D>>,
+    MaybeVerifiedUser(user): MaybeVerifiedUser,
+) -> Response {
+    if let Err(status) = reject_delegated_token(&user) {
+        return status.into_response();
+    }
+    if let Err(status) = reject_api_key(&user) {
+        return status.into_response();
+    }
+
+    let mut api_keys = match state.database().get_user_api_keys(user.user_id()).await {
+        Ok(api_keys) => api_keys,
+        Err(e) => return database_error_status(&e).into_response(),
+    };
+    api_keys.sort_unstable_by_key(|k| k.created_at);
+
+    Json(json!({
+        "api_keys": api_keys.iter().map(api_key_json).collect::<Vec<_>>(),
+    }))
+    .into_response()
+}
+
+/// Handler for deleting an API key of the user who authenticated the
+/// request, so that it is rejected from then on.
+async fn delete_api_key<D: Database>(
+    State(state): State<ServerState<D>>,
+    MaybeVerifiedUser(user): MaybeVerifiedUser,
+    Path(key_id): Path<String>,
+) -> impl IntoResponse {
+    if let Err(status) = reject_delegated_token(&user) {
+        return status;
+    }
+    if let Err(status) = reject_api_key(&user) {
+        return status;
+    }
+
+    match state
+        .database()
+        .delete_api_key(user.user_id(), &key_id)
+        .await
+    {
+        Ok(()) => {
+            info!("deleted API key of user");
+            StatusCode::OK
+        }
+        Err(e) => database_error_status(&e),
+    }
+}
+
+/// Rejects requests authenticated with a token which the user issued to an
+/// OAuth client, as such tokens are not meant to manage the user's account.
+fn reject_delegated_token(user: &AuthenticatedUser) -> Result<(), StatusCode> {
+    if user.token_payload.client_id.is_some() {
+        info!("rejected account management with token issued to OAuth client");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(())
+}
+
+/// Rejects requests authenticated with an API key, as keys are limited to the
+/// scopes they name and are not meant to manage the user's account.
+fn reject_api_key(user: &AuthenticatedUser) -> Result<(), StatusCode> {
+    if user.api_key_id.is_some() {
+        info!("rejected account management with API key");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    Ok(())
+}
+
+/// Revokes every token of a user and deletes their API keys, so that none of
+/// their credentials outlast e.g. a password change.
+async fn revoke_user_credentials<D: Database>(
+    state: &ServerState<D>,
+    user_id: Uuid,
+) -> Result<(), DatabaseError> {
+    let database = state.database();
+    database
+        .revoke_user_tokens(
+            user_id,
+            SystemTime::now(),
+            state.token_manager().tokens_expire_at(),
+        )
+        .await?;
+
+    for api_key in database.get_user_api_keys(user_id).await? {
+        match database.delete_api_key(user_id, &api_key.key_id).await {
+            Ok(()) | Err(DatabaseError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the JSON representation of an API key, leaving out the hash of
+/// its secret, with times in seconds since the unix epoch.
+fn api_key_json(key: &ApiKey) -> Value {
+    json!({
+        "id": key.key_id,
+        "name": key.name,
+        "scopes": key.scopes,
+        "created_at": token::unix_timestamp(key.created_at),
+        "expires_at": key.expires_at,
+    })
+}
+
+/// Form-encoded body of the login and consent page of the OAuth
+/// authorization endpoint.
+#[derive(Deserialize)]
+struct OAuthAuthorizationForm {
+    email: Option<String>,
+    password: Option<String>,
+
+    /// Either `allow` or `deny`, depending on the button which the user
+    /// pressed.
+    decision: Option<String>,
+}
+
+/// Handler for the OAuth authorization endpoint, as per RFC 6749, section
+/// 3.1, which shows users a page to log in and grant the client's request.
+async fn oauth_authorize<D: Database>(
+    State(state): State<ServerState<D>>,
+    params: Result<Query<AuthorizationParams>, QueryRejection>,
+) -> Response {
+    let Ok(Query(params)) = params else {
+        return oauth::error_page(StatusCode::BAD_REQUEST, "The request is malformed.");
+    };
+
+    match oauth::check_authorization_request(state.database(), params).await {
+        Ok(request) => oauth::authorization_page(&request, "", None, StatusCode::OK),
+        Err(response) => response,
+    }
+}
+
+/// Handler for the login and consent page of the OAuth authorization
+/// endpoint.
+///
+/// Users who log in and allow the request are redirected back to the client
+/// with an authorization code. Failed logins are subject to login throttling
+/// like those at `/login`, and show the page again with an error.
+async fn oauth_authorize_decision<D: Database>(
+    State(state): State<ServerState<D>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    params: Result<Query<AuthorizationParams>, QueryRejection>,
+    form: Result<Form<OAuthAuthorizationForm>, FormRejection>,
+) -> Response {
+    let (Ok(Query(params)), Ok(Form(form))) = (params, form) else {
+        return oauth::error_page(StatusCode::BAD_REQUEST, "The request is malformed.");
+    };
+    let request = match oauth::check_authorization_request(state.database(), params).await {
+        Ok(request) => request,
+        Err(response) => return response,
+    };
+
+    match form.decision.as_deref() {
+        Some("allow") => {}
+        Some("deny") => {
+            info!("user denied OAuth authorization request");
+            return request.redirect_error(&OAuthError::AccessDenied);
+        }
+        _ => return oauth::error_page(StatusCode::BAD_REQUEST, "The request is malformed."),
+    }
+
+    let email = form.email.unwrap_or_default();
+    let show_error = |message, status_code| {
+        oauth::authorization_page(&request, &email, Some(message), status_code)
+    };
+    let wrong_credentials = "The e-mail address or password is wrong.";
+    let unavailable = "Could not log in. Try again later.";
+
+    let user = database::User {
+        email: email.clone(),
+        password: form.password.unwrap_or_default(),
+    };
+    let Ok(user) = user.validate() else {
+        info!("invalid credentials provided during OAuth authorization");
+        return show_error(wrong_credentials, StatusCode::UNAUTHORIZED);
+    };
+
+    if let Err(response) =
+        check_credentials(&state, connect_info, &user, StatusCode::UNAUTHORIZED).await
+    {
+        let message = match response.status() {
+            StatusCode::UNAUTHORIZED => wrong_credentials,
+            StatusCode::TOO_MANY_REQUESTS => "Too many failed logins. Try again later.",
+            _ => unavailable,
+        };
+        let mut page = show_error(message, response.status());
+        if let Some(retry_after) = response.headers().get(header::RETRY_AFTER) {
+            page.headers_mut()
+                .insert(header::RETRY_AFTER, retry_after.clone());
+        }
+        return page;
+    }
+
+    let profile = match state.database().get_user_by_email(&user.email).await {
+        Ok(profile) => profile,
+        Err(e) => return show_error(unavailable, database_error_status(&e)),
+    };
+    match check_login_allowed(&state, &profile).await {
+        Ok(_) => {}
+        Err(StatusCode::FORBIDDEN) if profile.disabled => {
+            return show_error("This account is disabled.", StatusCode::FORBIDDEN);
+        }
+        Err(StatusCode::FORBIDDEN) => {
+            return show_error(
+                "Verify your e-mail address before logging in.",
+                StatusCode::FORBIDDEN,
+            );
+        }
+        Err(status_code) => return show_error(unavailable, status_code),
+    }
+
+    let (code, record) = state.token_manager().new_authorization_code(
+        &request.client.client_id,
+        profile.id,
+        request
+            .redirect_uri_given
+            .then_some(request.redirect_uri.as_str()),
+        &request.scopes,
+        &request.code_challenge,
+    );
+    if let Err(e) = state.database().add_authorization_code(record).await {
+        return request.redirect_error(&OAuthError::from(e));
+    }
+    if let Err(e) = state
+        .database()
+        .set_last_login(profile.id, SystemTime::now())
+        .await
+    {
+        warn!("could not record login of user: {e}");
+    }
+    info!("user granted OAuth authorization request");
+
+    request.redirect(&[("code", &code)])
+}
+
+/// Form-encoded request body of the OAuth token endpoint.
+#[derive(Deserialize)]
+struct OAuthTokenRequest {
+    grant_type: Option<String>,
+    scope: Option<String>,
+    code: Option<String>,
+    redirect_uri: Option<String>,
+    code_verifier: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+/// Handler for the OAuth token endpoint, as per RFC 6749, section 3.2.
+///
+/// The client credentials grant issues an access token identifying the client
+/// itself, and the authorization code grant issues one identifying the user
+/// who granted the code. Neither issues a refresh token.
+async fn oauth_token<D: Database>(
+    State(state): State<ServerState<D>>,
+    authorization: Option<TypedHeader<Authorization<Basic>>>,
+    request: Result<Form<OAuthTokenRequest>, FormRejection>,
+) -> Result<Response, OAuthError> {
+    let Form(request) = request.map_err(|e| OAuthError::InvalidRequest(e.body_text()))?;
+    let (client_id, client_secret) = oauth::client_credentials(
+        authorization
+            .as_ref()
+            .map(|TypedHeader(authorization)| authorization),
+        request.client_id,
+        request.client_secret,
+    )?;
+
+    let grant_type = request.grant_type.as_deref();
+    if grant_type.is_none() {
+        return Err(OAuthError::InvalidRequest("missing grant_type".into()));
+    } else if !matches!(
+        grant_type,
+        Some("client_credentials" | "authorization_code")
+    ) {
+        return Err(OAuthError::UnsupportedGrantType);
+    }
+
+    let client =
+        oauth::authenticate_client(state.database(), &client_id, client_secret.as_deref()).await?;
+    let body = if grant_type == Some("client_credentials") {
+        client_credentials_grant(&state, &client, request.scope.as_deref())?
+    } else {
+        authorization_code_grant(
+            &state,
+            &client,
+            request.code,
+            request.redirect_uri,
+            request.code_verifier,
+        )
+        .await?
+    };
+
+    Ok((
+        [
+            (header::CACHE_CONTROL, "no-store"),
+            (header::PRAGMA, "no-cache"),
+        ],
+        Json(body),
+    )
+        .into_response())
+}
+
+/// Issues an access token to a confidential OAuth client itself, as per RFC
+/// 6749, section 4.4, returning the body of the token response.
+fn client_credentials_grant<D: Database>(
+    state: &ServerState<D>,
+    client: &OAuthClient,
+    scope: Option<&str>,
+) -> Result<Value, OAuthError> {
+    if client.secret_hash.is_none() {
+        info!("public OAuth client attempted client credentials grant");
+        return Err(OAuthError::UnauthorizedClient);
+    }
+    let scopes = oauth::granted_scopes(&client.scopes, scope)?;
+
+    let token_manager = state.token_manager();
+    let Ok(access_token) = token_manager.new_client_token(&client.client_id, &scopes) else {
+        warn!("could not create token for OAuth client");
+        return Err(OAuthError::Unavailable(StatusCode::INTERNAL_SERVER_ERROR));
+    };
+    info!("issued token to OAuth client");
+
+    Ok(json!({
+        "access_token": access_token,
+        "token_type": "Bearer",
+        "expires_in": token_manager.lifetime().as_secs(),
+        "scope": scopes.join(" "),
+    }))
+}
+
+/// Exchanges an authorization code for an access token of the user who
+/// granted it, as per RFC 6749, section 4.1.3, returning the body of the
+/// token response.
+///
+/// The token grants those of the code's scopes which the user is still
+/// granted as permissions.
+async fn authorization_code_grant<D: Database>(
+    state: &ServerState<D>,
+    client: &OAuthClient,
+    code: Option<String>,
+    redirect_uri: Option<String>,
+    code_verifier: Option<String>,
+) -> Result<Value, OAuthError> {
+    let code = code.ok_or_else(|| OAuthError::InvalidRequest("missing code".into()))?;
+    let code_verifier =
+        code_verifier.ok_or_else(|| OAuthError::InvalidRequest("missing code_verifier".into()))?;
+    oauth::check_code_verifier(&code_verifier)?;
+
+    let record = match state
+        .database()
+        .consume_authorization_code(&token::hash_token_string(&code))
+        .await
+    {
+        Ok(record) => record,
+        Err(DatabaseError::NotFound) => {
+            info!("unknown, expired or exchanged authorization code provided");
+            return Err(OAuthError::InvalidGrant);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if record.client_id != client.client_id
+        || record.redirect_uri != redirect_uri
+        || record.code_challenge != token::code_challenge(&code_verifier)
+    {
+        info!("authorization code provided with mismatching client, redirect URI or verifier");
+        return Err(OAuthError::InvalidGrant);
+    }
+
+    let profile = match state.database().get_user(record.user_id).await {
+        Ok(profile) => profile,
+        Err(DatabaseError::NotFound) => {
+            info!("authorization code of nonexistent user provided");
+            return Err(OAuthError::InvalidGrant);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let email_verified = match check_login_allowed(state, &profile).await {
+        Ok(email_verified) => email_verified,
+        Err(StatusCode::FORBIDDEN) => return Err(OAuthError::InvalidGrant),
+        Err(status_code) => return Err(OAuthError::Unavailable(status_code)),
+    };
+    let scopes = record
+        .scopes
+        .into_iter()
+        .filter(|scope| profile.permissions.contains(scope))
+        .collect::<Vec<_>>();
+
+    let token_manager = state.token_manager();
+    let Ok(access_token) =
+        token_manager.new_delegated_token(profile.id, &client.client_id, &scopes, email_verified)
+    else {
+        warn!("could not create token for OAuth client on behalf of user");
+        return Err(OAuthError::Unavailable(StatusCode::INTERNAL_SERVER_ERROR));
+    };
+    info!("issued token to OAuth client on behalf of user");
+
+    Ok(json!({
+        "access_token": access_token,
+        "token_type": "Bearer",
+        "expires_in": token_manager.lifetime().as_secs(),
+        "scope": scopes.join(" "),
+    }))
+}
+
+/// Request body for assigning roles and permissions to a user.
+#[derive(Deserialize)]
+struct SetRolesRequest {
+    roles: Vec<String>,
+    #[serde(default)]
+    permissions: Vec<String>,
+}
+
+impl Validate for SetRolesRequest {
+    fn validate(self) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        let roles = errors.check("roles", validation::normalize_grant_names(&self.roles));
+        let permissions = errors.check(
+            "permissions",
+            validation::normalize_grant_names(&self.permissions),
+        );
+        errors.into_result(Self {
+            roles: roles.unwrap_or_default(),
+            permissions: permissions.unwrap_or_default(),
+        })
+    }
+}
+
+/// Handler for replacing the roles and permissions of a user, which only
+/// administrators may use.
+///
+/// Roles and permissions are embedded into tokens, so new ones take effect
+/// with the user's next token. If any are taken away, every token of the
+/// user is revoked instead, so that the change takes effect immediately.
+async fn set_user_roles<D: Database>(
+    State(state): State<ServerState<D>>,
+    _admin: RequireRole<Admin>,
+    Path(user_id): Path<Uuid>,
+    Valid(request): Valid<SetRolesRequest>,
+) -> Response {
+    let database = state.database();
+
+    let profile = match database.get_user(user_id).await {
+        Ok(profile) => profile,
+        Err(e) => return database_error_status(&e).into_response(),
+    };
+
+    if let Err(e) = database
+        .set_user_roles(user_id, &request.roles, &request.permissions)
+        .await
+    {
+        return database_error_status(&e).into_response();
+    }
+    info!(
+        "set roles of user to {:?} and permissions to {:?}",
+        request.roles, request.permissions
+    );
+
+    let revoked = profile.roles.iter().any(|r| !request.roles.contains(r))
+        || profile
+            .permissions
+            .iter()
+            .any(|p| !request.permissions.contains(p));
+    if revoked {
+        if let Err(e) = database
+            .revoke_user_tokens(
+                user_id,
+                SystemTime::now(),
+                state.token_manager().tokens_expire_at(),
+            )
+            .await
+        {
+            return database_error_status(&e).into_response();
+        }
+        info!("revoked tokens of user whose roles or permissions were taken away");
+    }
+
+    Json(profile_json(&UserProfile {
+        roles: request.roles,
+        permissions: request.permissions,
+        ..profile
+    }))
+    .into_response()
+}
+
+/// Query parameters for listing users.
+#[derive(Deserialize)]
+struct ListUsersQuery {
+    #[serde(default)]
+    email_prefix: String,
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
+impl Validate for ListUsersQuery {
+    fn validate(self) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        if self
+            .limit
+            .is_some_and(|limit| limit == 0 || limit > MAX_USER_LISTING_LIMIT)
+        {
+            errors.add(
+                "limit",
+                format!("must be between 1 and {MAX_USER_LISTING_LIMIT}"),
+            );
+        }
+        errors.into_result(self)
+    }
+}
+
+/// Handler for listing users, optionally only those whose e-mail starts with
+/// a prefix, which only administrators may use.
+///
+/// Users are listed a page at a time. Each page carries a `next_cursor`,
+/// which is passed as `cursor` to list the following page, until it is
+/// `null`. Pages may be shorter than the `limit` even if more users follow.
+async fn list_users<D: Database>(
+    State(state): State<ServerState<D>>,
+    _admin: RequireRole<Admin>,
+    Query(query): Query<ListUsersQuery>,
+) -> Response {
+    let query = match query.validate() {
+        Ok(query) => query,
+        Err(errors) => return errors.into_response(),
+    };
+
+    let page = match state
+        .database()
+        .list_users(
+            &query.email_prefix,
+            query.cursor.as_deref(),
+            query.limit.unwrap_or(DEFAULT_USER_LISTING_LIMIT),
+        )
+        .await
+    {
+        Ok(page) => page,
+        Err(DatabaseError::NotFound) => {
+            info!("malformed user listing cursor provided");
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+        Err(e) => return database_error_status(&e).into_response(),
+    };
+
+    Json(json!({
+        "users": page.users.iter().map(profile_json).collect::<Vec<_>>(),
+        "next_cursor": page.next_cursor,
+    }))
+    .into_response()
+}
+
+/// Handler for viewing a user's profile and whether their e-mail address is
+/// verified, which only administrators may use.
+async fn get_user<D: Database>(
+    State(state): State<ServerState<D>>,
+    _admin: RequireRole<Admin>,
+    Path(user_id): Path<Uuid>,
+) -> Response {
+    let database = state.database();
+
+    let profile = match database.get_user(user_id).await {
+        Ok(profile) => profile,
+        Err(e) => return database_error_status(&e).into_response(),
+    };
+    let email_verified = match database.is_user_verified(&profile.email).await {
+        Ok(email_verified) => email_verified,
+        Err(e) => return database_error_status(&e).into_response(),
+    };
+
+    let mut user = profile_json(&profile);
+    user["email_verified"] = email_verified.into();
+    Json(user).into_response()
+}
+
+/// Handler for disabling a user, which only administrators may use.
+///
+/// Disabled users cannot log in or refresh their tokens, every token they have
+/// been issued is revoked and their API keys are deleted.
+async fn disable_user<D: Database>(
+    State(state): State<ServerState<D>>,
+    _admin: RequireRole<Admin>,
+    Path(user_id): Path<Uuid>,
+) -> Response {
+    set_user_disabled(&state, user_id, true).await
+}
+
+/// Handler for re-enabling a disabled user, which only administrators may
+/// use.
+async fn enable_user<D: Database>(
+    State(state): State<ServerState<D>>,
+    _admin: RequireRole<Admin>,
+    Path(user_id): Path<Uuid>,
+) -> Response {
+    set_user_disabled(&state, user_id, false).await
+}
+
+/// Disables or re-enables a user and responds with their profile.
+async fn set_user_disabled<D: Database>(
+    state: &ServerState<D>,
+    user_id: Uuid,
+    disabled: bool,
+) -> Response {
+    let database = state.database();
+
+    let profile = match database.get_user(user_id).await {
+        Ok(profile) => profile,
+        Err(e) => return database_error_status(&e).into_response(),
+    };
+    if let Err(e) = database.set_user_disabled(user_id, disabled).await {
+        return database_error_status(&e).into_response();
+    }
+
+    if disabled {
+        // tokens cannot be issued anymore, so revoking the existing ones and
+        // deleting API keys keeps the user out
+        if let Err(e) = revoke_user_credentials(state, user_id).await {
+            return database_error_status(&e).into_response();
+        }
+        info!("disabled user, revoked their tokens and deleted their API keys");
+    } else {
+        info!("enabled user");
+    }
+
+    Json(profile_json(&UserProfile {
+        disabled,
+        ..profile
+    }))
+    .into_response()
+}
+
+/// Handler for forcing a user to reset their password, which only
+/// administrators may use.
+///
+/// The user's password is replaced with a random one, every token of the user
+/// is revoked and their API keys are deleted, after which they are sent a
+/// password reset code.
+async fn force_password_reset<D: Database>(
+    State(state): State<ServerState<D>>,
+    _admin: RequireRole<Admin>,
+    Path(user_id): Path<Uuid>,
+) -> Response {
+    let Some(mailer) = state.mailer() else {
+        warn!("forced password reset requested, but no mailer is configured");
+        return StatusCode::NOT_IMPLEMENTED.into_response();
+    };
+    let database = state.database();
+
+    let profile = match database.get_user(user_id).await {
+        Ok(profile) => profile,
+        Err(e) => return database_error_status(&e).into_response(),
+    };
+
+    if let Err(e) = database
+        .update_password(user_id, token::random_token_string(32))
+        .await
+    {
+        return database_error_response(&e);
+    }
+    if let Err(e) = revoke_user_credentials(&state, user_id).await {
+        return database_error_status(&e).into_response();
+    }
+
+    let token_manager = state.token_manager();
+    let (code, reset) = token_manager.new_password_reset(user_id);
+    if let Err(e) = database.add_password_reset(reset).await {
+        return database_error_status(&e).into_response();
+    }
+    info!("forced password reset of user");
+
+    let mail = Mail {
+        to: profile.email,
+        subject: "Password reset".to_string(),
+        body: format!(
+            "An administrator has reset your password. Use the following code to choose a new \
+            one: {code}\n\n\
+            The code expires in {} minutes.\n",
+            token_manager.password_reset_lifetime().as_secs() / 60
+        ),
+    };
+    tokio::spawn(async move {
+        if let Err(e) = mailer.send(mail).await {
+            warn!("could not send password reset code: {e}");
+        }
+    });
+
+    StatusCode::ACCEPTED.into_response()
+}
+
+/// Handler for deleting a user along with everything stored about them,
+/// which only administrators may use.
+async fn delete_user<D: Database>(
+    State(state): State<ServerState<D>>,
+    _admin: RequireRole<Admin>,
+    Path(user_id): Path<Uuid>,
+) -> Response {
+    let database = state.database();
+
+    let profile = match database.get_user(user_id).await {
+        Ok(profile) => profile,
+        Err(e) => return database_error_status(&e).into_response(),
+    };
+
+    if let Err(e) = database
+        .clear_login_failures(&LoginSubject::Account(&profile.email).key())
+        .await
+    {
+        return database_error_status(&e).into_response();
+    }
+    let tokens_expire_at = state.token_manager().tokens_expire_at();
+    if let Err(e) = database.delete_user(user_id, tokens_expire_at).await {
+        return database_error_status(&e).into_response();
+    }
+    info!("deleted user");
+
+    StatusCode::OK.into_response()
+}
+
+/// Returns the JSON representation of a profile, with times in seconds since
+/// the unix epoch.
+fn profile_json(profile: &UserProfile) -> Value {
+    json!({
+        "id": profile.id,
+        "email": profile.email,
+        "display_name": profile.display_name,
+        "created_at": profile.created_at.map(token::unix_timestamp),
+        "last_login_at": profile.last_login_at.map(token::unix_timestamp),
+        "metadata": profile.metadata,
+        "roles": profile.roles,
+        "permissions": profile.permissions,
+        "disabled": profile.disabled,
+    })
+}
+
+/// Creates an API token and a refresh token for a user and responds with
+/// them.
+///
+/// If `family_id` is given, the refresh token continues that token family. If
+/// the user has not verified their e-mail address, the API token is marked as
+/// such, or no tokens are issued at all, depending on the
+/// [`UnverifiedLogin`] policy. Disabled users are never issued tokens.
+async fn issue_tokens<D: Database>(
+    state: &ServerState<D>,
+    user: &UserProfile,
+    family_id: Option<&str>,
+) -> Response {
+    let token_manager = state.token_manager();
+
+    let email_verified = match check_login_allowed(state, user).await {
+        Ok(email_verified) => email_verified,
+        Err(status_code) => return (status_code, "").into_response(),
+    };
+
+    let token = if email_verified {
+        token_manager.new_token(user.id, &user.roles, &user.permissions)
+    } else {
+        token_manager.new_unverified_token(user.id, &user.roles, &user.permissions)
+    };
+    let Ok(token) = token else {
+        warn!("could not create token for user");
+        return (StatusCode::INTERNAL_SERVER_ERROR, "").into_response();
+    };
+
+    let (refresh_token, refresh_token_record) = token_manager.new_refresh_token(user.id, family_id);
+    if let Err(e) = state
+        .database()
+        .add_refresh_token(refresh_token_record)
+        .await
+    {
+        return (database_error_status(&e), "").into_response();
+    }
+
+    (
+        StatusCode::OK,
+        Json(json!({ "token": token, "refresh_token": refresh_token })),
+    )
+        .into_response()
+}
+
+/// Checks whether a user may obtain tokens, returning whether their e-mail
+/// address is verified.
+///
+/// # Errors
+///
+/// Returns `403 Forbidden` if the user is disabled, or if their e-mail address
+/// is unverified while unverified logins are refused. Returns another status
+/// code if the user's verification cannot be checked.
+async fn check_login_allowed<D: Database>(
+    state: &ServerState<D>,
+    user: &UserProfile,
+) -> Result<bool, StatusCode> {
+    if user.disabled {
+        info!("refused login of disabled user");
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let email_verified = state
+        .database()
+        .is_user_verified(&user.email)
+        .await
+        .map_err(|e| database_error_status(&e))?;
+    let unverified_login = state
+        .email_verification()
+        .map_or(UnverifiedLogin::Allow, |v| v.unverified_login);
+    if !email_verified && unverified_login == UnverifiedLogin::Refuse {
+        info!("refused login of unverified user");
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    Ok(email_verified)
+}
+
+/// Returns a `Retry-After` header for a duration, rounded up to whole
+/// seconds.
+fn retry_after(duration: Duration) -> [(header::HeaderName, String); 1] {
+    let seconds = duration.as_secs() + u64::from(duration.subsec_nanos() > 0);
+    [(header::RETRY_AFTER, seconds.to_string())]
+}
+
+/// Logs an unexpected database error and maps it to an appropriate status
+/// code.
+pub(crate) fn database_error_status(error: &DatabaseError) -> StatusCode {
+    match error {
+        DatabaseError::Conflict => StatusCode::CONFLICT,
+        DatabaseError::NotFound => StatusCode::NOT_FOUND,
+        DatabaseError::WrongPassword => StatusCode::UNAUTHORIZED,
+        DatabaseError::Unavailable(_) => {
+            warn!("database unavailable: {error}");
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+        DatabaseError::PasswordHashing(PasswordHashError::Overloaded) => {
+            warn!("rejected request: {error}");
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+        DatabaseError::CorruptRecord(_) | DatabaseError::PasswordHashing(_) => {
+            error!("database error: {error}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Maps a database error to a response like [`database_error_status`], asking
+/// clients to retry later if too many passwords are waiting to be hashed.
+fn database_error_response(error: &DatabaseError) -> Response {
+    let status = database_error_status(error);
+    if matches!(
+        error,
+        DatabaseError::PasswordHashing(PasswordHashError::Overloaded)
+    ) {
+        (status, retry_after(HASHING_OVERLOADED_RETRY_AFTER), "").into_response()
+    } else {
+        (status, "").into_response()
+    }
 }
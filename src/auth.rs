@@ -0,0 +1,280 @@
+//! Authentication of requests by API tokens.
+//!
+//! Requests can also be authenticated with a personal API key instead, given
+//! as an `ApiKey` credential in the `Authorization` header or in the
+//! `X-Api-Key` header.
+//!
+//! Routes outside of this crate can require a valid API token by taking an
+//! [`AuthenticatedUser`] as a handler argument, or by protecting a whole
+//! router with [`require_authentication`]:
+//!
+//! ```ignore
+//! let protected_router = Router::new()
+//!     .route("/secret", get(handler))
+//!     .route_layer(middleware::from_fn_with_state(
+//!         state.clone(),
+//!         require_authentication::<ScyllaDbSession, Body>,
+//!     ));
+//! ```
+
+use crate::{
+    api::database_error_status,
+    database::Database,
+    rate_limit::API_KEY_HEADER,
+    token::{ClientTokenPayload, TokenError, TokenPayload},
+    ServerState,
+};
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, State},
+    headers::{authorization::Bearer, Authorization},
+    http::{header, request::Parts, HeaderMap, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    TypedHeader,
+};
+use tracing::info;
+use uuid::Uuid;
+
+/// A user who authenticated a request with a valid, unrevoked API token,
+/// given as a bearer token in the `Authorization` header.
+///
+/// Extracting this rejects the request with `401 Unauthorized` if no valid
+/// token is given, or with `403 Forbidden` if the token is restricted due to
+/// the user not having verified their e-mail address.
+#[derive(Clone)]
+#[allow(clippy::module_name_repetitions)]
+pub struct AuthenticatedUser {
+    /// The payload of the token the request was authenticated with. For
+    /// requests authenticated with an API key, this describes the key.
+    pub token_payload: TokenPayload,
+
+    /// Id of the API key the request was authenticated with, if it was not
+    /// authenticated with a token.
+    pub api_key_id: Option<String>,
+}
+
+impl AuthenticatedUser {
+    /// Returns the id of the authenticated user.
+    #[must_use]
+    pub fn user_id(&self) -> Uuid {
+        self.token_payload.sub
+    }
+
+    /// Returns whether the user's token grants a role.
+    #[must_use]
+    pub fn has_role(&self, role: &str) -> bool {
+        self.token_payload.roles.iter().any(|r| r == role)
+    }
+
+    /// Returns whether the user's token grants a permission.
+    #[must_use]
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.token_payload.permissions().any(|p| p == permission)
+    }
+}
+
+#[async_trait]
+impl<D: Database> FromRequestParts<ServerState<D>> for AuthenticatedUser {
+    type Rejection = AuthenticationRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ServerState<D>,
+    ) -> Result<Self, Self::Rejection> {
+        let MaybeVerifiedUser(user) = MaybeVerifiedUser::from_request_parts(parts, state).await?;
+
+        if !user.token_payload.email_verified && state.restricts_unverified_users() {
+            info!("rejected restricted token of unverified user");
+            return Err(AuthenticationRejection::Unverified);
+        }
+
+        Ok(user)
+    }
+}
+
+/// A user who authenticated a request with a valid, unrevoked API token,
+/// whether or not their e-mail address is verified.
+///
+/// Unlike [`AuthenticatedUser`], this accepts the restricted tokens issued to
+/// unverified users under [`UnverifiedLogin::Restrict`], so it should only be
+/// used for endpoints which do not require a verified e-mail address.
+///
+/// [`UnverifiedLogin::Restrict`]: crate::verification::UnverifiedLogin::Restrict
+#[derive(Clone)]
+pub struct MaybeVerifiedUser(pub AuthenticatedUser);
+
+#[async_trait]
+impl<D: Database> FromRequestParts<ServerState<D>> for MaybeVerifiedUser {
+    type Rejection = AuthenticationRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ServerState<D>,
+    ) -> Result<Self, Self::Rejection> {
+        // the middleware may have authenticated the request already
+        if let Some(user) = parts.extensions.get::<AuthenticatedUser>() {
+            return Ok(Self(user.clone()));
+        }
+
+        if let Some(api_key) = request_api_key(&parts.headers) {
+            let token_payload = state
+                .token_manager()
+                .validate_api_key(api_key, state.database())
+                .await
+                .map_err(rejection)?;
+            return Ok(Self(AuthenticatedUser {
+                api_key_id: Some(token_payload.jti.clone()),
+                token_payload,
+            }));
+        }
+
+        let TypedHeader(authorization) =
+            TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state)
+                .await
+                .map_err(|_| AuthenticationRejection::MissingToken)?;
+
+        let token_payload = state
+            .token_manager()
+            .decode_and_validate_token(authorization.token().into(), state.database())
+            .await
+            .map_err(rejection)?;
+        Ok(Self(AuthenticatedUser {
+            token_payload,
+            api_key_id: None,
+        }))
+    }
+}
+
+/// An OAuth client which authenticated a request with an access token it
+/// obtained for itself through the client credentials grant.
+///
+/// Extracting this rejects the request with `401 Unauthorized` if no valid
+/// client token is given. Tokens issued to users are rejected.
+#[derive(Clone)]
+pub struct AuthenticatedClient {
+    /// The payload of the token the request was authenticated with.
+    pub token_payload: ClientTokenPayload,
+}
+
+impl AuthenticatedClient {
+    /// Returns the id of the authenticated client.
+    #[must_use]
+    pub fn client_id(&self) -> &str {
+        &self.token_payload.client_id
+    }
+
+    /// Returns whether the client's token grants a scope.
+    #[must_use]
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.token_payload.scopes().any(|s| s == scope)
+    }
+}
+
+#[async_trait]
+impl<D: Database> FromRequestParts<ServerState<D>> for AuthenticatedClient {
+    type Rejection = AuthenticationRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ServerState<D>,
+    ) -> Result<Self, Self::Rejection> {
+        let TypedHeader(authorization) =
+            TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state)
+                .await
+                .map_err(|_| AuthenticationRejection::MissingToken)?;
+
+        let token_payload = state
+            .token_manager()
+            .decode_client_token(authorization.token())
+            .map_err(rejection)?;
+        Ok(Self { token_payload })
+    }
+}
+
+/// Returns the personal API key given with a request, either as an `ApiKey`
+/// credential in the `Authorization` header or in the [`API_KEY_HEADER`].
+#[must_use]
+pub fn request_api_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("ApiKey "))
+        .or_else(|| {
+            headers
+                .get(API_KEY_HEADER)
+                .and_then(|value| value.to_str().ok())
+        })
+        .map(str::trim)
+}
+
+/// Maps an error which occurred while validating a token or API key to a
+/// rejection.
+fn rejection(error: TokenError) -> AuthenticationRejection {
+    match error {
+        TokenError::Database(e) => AuthenticationRejection::Unavailable(database_error_status(&e)),
+        e => {
+            info!("rejected token: {e}");
+            AuthenticationRejection::InvalidToken
+        }
+    }
+}
+
+/// The reason why a request could not be authenticated.
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub enum AuthenticationRejection {
+    /// No bearer token was given.
+    MissingToken,
+
+    /// The given token is malformed, expired, revoked or otherwise invalid.
+    InvalidToken,
+
+    /// The given token is restricted, as its user has not verified their
+    /// e-mail address.
+    Unverified,
+
+    /// The given token does not grant a role or permission which is required.
+    Forbidden,
+
+    /// The token could not be checked, e.g. due to the database being
+    /// unavailable. Holds the status code to respond with.
+    Unavailable(StatusCode),
+}
+
+impl IntoResponse for AuthenticationRejection {
+    fn into_response(self) -> Response {
+        // as per RFC 6750, section 3
+        let (status_code, challenge) = match self {
+            Self::MissingToken => (StatusCode::UNAUTHORIZED, "Bearer"),
+            Self::InvalidToken => (StatusCode::UNAUTHORIZED, "Bearer error=\"invalid_token\""),
+            Self::Unverified | Self::Forbidden => {
+                (StatusCode::FORBIDDEN, "Bearer error=\"insufficient_scope\"")
+            }
+            Self::Unavailable(status_code) => return status_code.into_response(),
+        };
+
+        (status_code, [(header::WWW_AUTHENTICATE, challenge)]).into_response()
+    }
+}
+
+/// Middleware which rejects requests without a valid API token, for use with
+/// [`axum::middleware::from_fn_with_state`].
+///
+/// The [`AuthenticatedUser`] is added to the request extensions, so handlers
+/// behind the middleware can extract it without validating the token again.
+pub async fn require_authentication<D: Database, B: Send>(
+    State(state): State<ServerState<D>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let (mut parts, body) = request.into_parts();
+    let user = match AuthenticatedUser::from_request_parts(&mut parts, &state).await {
+        Ok(user) => user,
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    let mut request = Request::from_parts(parts, body);
+    request.extensions_mut().insert(user);
+    next.run(request).await
+}
@@ -0,0 +1,680 @@
+//! In-memory database access.
+
+use super::{
+    ApiKey, AuthorizationCode, Database, DatabaseError, LoginFailures, OAuthClient, PasswordReset,
+    RateLimitBucket, RefreshToken, User, UserData, UserPage, UserProfile,
+};
+use crate::{
+    password::{self, HashingPool, PasswordHashError, PasswordHasher},
+    roles, token,
+};
+use axum::async_trait;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use uuid::Uuid;
+
+/// A simple, in-memory database.
+///
+/// Passwords are stored in plaintext, unless a password hasher is set with
+/// [`with_password_hasher`](Self::with_password_hasher), in which case they are
+/// hashed on a [`HashingPool`].
+#[derive(Clone)]
+#[allow(clippy::module_name_repetitions)]
+pub struct SimpleMemoryDatabase {
+    users: Arc<Mutex<Vec<StoredUser>>>,
+
+    /// Ids of users whose e-mail address has been verified.
+    verified_users: Arc<Mutex<HashSet<Uuid>>>,
+
+    /// Hasher for stored passwords, along with a hash which passwords are
+    /// checked against when a user does not exist.
+    password_hashing: Option<(Arc<dyn PasswordHasher>, Arc<str>)>,
+    hashing_pool: HashingPool,
+
+    refresh_tokens: Arc<Mutex<Vec<RefreshToken>>>,
+    password_resets: Arc<Mutex<Vec<PasswordReset>>>,
+    api_keys: Arc<Mutex<Vec<ApiKey>>>,
+
+    /// Registered OAuth clients, by client id.
+    oauth_clients: Arc<Mutex<HashMap<String, OAuthClient>>>,
+    authorization_codes: Arc<Mutex<Vec<AuthorizationCode>>>,
+
+    /// Failed logins along with their expiry times, by key.
+    login_failures: Arc<Mutex<HashMap<String, (LoginFailures, SystemTime)>>>,
+
+    /// Token buckets of rate limits along with their expiry times, by key.
+    rate_limit_buckets: Arc<Mutex<HashMap<String, (RateLimitBucket, SystemTime)>>>,
+
+    /// Expiry times of individually revoked tokens, by token id.
+    revoked_tokens: Arc<Mutex<HashMap<String, SystemTime>>>,
+
+    /// Times before which all of a user's tokens are revoked, by user id.
+    user_token_revocations: Arc<Mutex<HashMap<Uuid, SystemTime>>>,
+}
+
+/// A user as stored in a [`SimpleMemoryDatabase`].
+#[derive(Clone)]
+struct StoredUser {
+    profile: UserProfile,
+
+    /// The password, or its hash if a password hasher is set.
+    password: String,
+}
+
+impl SimpleMemoryDatabase {
+    /// Creates a database containing no records.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            users: Arc::new(Mutex::new(Vec::new())),
+            verified_users: Arc::new(Mutex::new(HashSet::new())),
+            password_hashing: None,
+            hashing_pool: HashingPool::default(),
+            refresh_tokens: Arc::new(Mutex::new(Vec::new())),
+            password_resets: Arc::new(Mutex::new(Vec::new())),
+            api_keys: Arc::new(Mutex::new(Vec::new())),
+            oauth_clients: Arc::new(Mutex::new(HashMap::new())),
+            authorization_codes: Arc::new(Mutex::new(Vec::new())),
+            login_failures: Arc::new(Mutex::new(HashMap::new())),
+            rate_limit_buckets: Arc::new(Mutex::new(HashMap::new())),
+            revoked_tokens: Arc::new(Mutex::new(HashMap::new())),
+            user_token_revocations: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Sets a hasher for the passwords of users added from now on.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the hasher fails to hash a password.
+    pub fn with_password_hasher(
+        mut self,
+        password_hasher: Arc<dyn PasswordHasher>,
+    ) -> Result<Self, PasswordHashError> {
+        let dummy_hash = password::dummy_hash(password_hasher.as_ref())?;
+        self.password_hashing = Some((password_hasher, dummy_hash.into()));
+        Ok(self)
+    }
+
+    /// Sets the pool which passwords are hashed and verified on, if a
+    /// password hasher is set. Defaults to [`HashingPool::default`].
+    #[must_use]
+    pub fn with_hashing_pool(mut self, hashing_pool: HashingPool) -> Self {
+        self.hashing_pool = hashing_pool;
+        self
+    }
+
+    /// Hashes a password to be stored, if a password hasher is set.
+    async fn hash_password(&self, password: String) -> Result<String, DatabaseError> {
+        let Some((password_hasher, _)) = &self.password_hashing else {
+            return Ok(password);
+        };
+
+        let password_hasher = Arc::clone(password_hasher);
+        self.hashing_pool
+            .run(move || password_hasher.hash(&password))
+            .await
+            .and_then(|hash| hash)
+            .map_err(DatabaseError::PasswordHashing)
+    }
+}
+
+impl Default for SimpleMemoryDatabase {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Database for SimpleMemoryDatabase {
+    async fn try_add_user(&self, mut user: User) -> Result<(), DatabaseError> {
+        user.password = self.hash_password(user.password).await?;
+
+        let mut users = self.users.lock().unwrap();
+
+        if users.iter().any(|u| u.profile.email == user.email) {
+            return Err(DatabaseError::Conflict);
+        }
+
+        users.push(StoredUser {
+            profile: UserProfile {
+                id: Uuid::new_v4(),
+                email: user.email,
+                display_name: None,
+                created_at: Some(SystemTime::now()),
+                last_login_at: None,
+                metadata: Map::new(),
+                roles: vec![roles::DEFAULT_ROLE.to_string()],
+                permissions: Vec::new(),
+                disabled: false,
+            },
+            password: user.password,
+        });
+        Ok(())
+    }
+
+    async fn validate_user(&self, user: &User) -> Result<(), DatabaseError> {
+        let stored_password = self
+            .users
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|u| u.profile.email == user.email)
+            .map(|u| u.password.clone());
+
+        // unknown users are checked against a dummy password, so that they
+        // take as long to reject as existing users
+        let matches = match &self.password_hashing {
+            Some((password_hasher, dummy_hash)) => {
+                let password_hasher = Arc::clone(password_hasher);
+                let password = user.password.clone();
+                let stored_hash = stored_password
+                    .clone()
+                    .unwrap_or_else(|| dummy_hash.to_string());
+                self.hashing_pool
+                    .run(move || password_hasher.verify(&password, &stored_hash))
+                    .await
+                    .map_err(DatabaseError::PasswordHashing)?
+                    .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?
+            }
+            None => password::constant_time_eq(
+                &Sha256::digest(user.password.as_bytes()),
+                &Sha256::digest(stored_password.as_deref().unwrap_or_default().as_bytes()),
+            ),
+        };
+
+        match stored_password {
+            None => Err(DatabaseError::NotFound),
+            Some(_) if !matches => Err(DatabaseError::WrongPassword),
+            Some(_) => Ok(()),
+        }
+    }
+
+    async fn get_user(&self, user_id: Uuid) -> Result<UserProfile, DatabaseError> {
+        self.users
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|u| u.profile.id == user_id)
+            .map(|u| u.profile.clone())
+            .ok_or(DatabaseError::NotFound)
+    }
+
+    async fn get_user_by_email(&self, email: &str) -> Result<UserProfile, DatabaseError> {
+        self.users
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|u| u.profile.email == email)
+            .map(|u| u.profile.clone())
+            .ok_or(DatabaseError::NotFound)
+    }
+
+    async fn update_user_profile(
+        &self,
+        user_id: Uuid,
+        display_name: Option<&str>,
+        metadata: &Map<String, Value>,
+    ) -> Result<(), DatabaseError> {
+        let mut users = self.users.lock().unwrap();
+        let stored_user = users
+            .iter_mut()
+            .find(|u| u.profile.id == user_id)
+            .ok_or(DatabaseError::NotFound)?;
+
+        stored_user.profile.display_name = display_name.map(str::to_string);
+        stored_user.profile.metadata = metadata.clone();
+        Ok(())
+    }
+
+    async fn set_user_roles(
+        &self,
+        user_id: Uuid,
+        roles: &[String],
+        permissions: &[String],
+    ) -> Result<(), DatabaseError> {
+        let mut users = self.users.lock().unwrap();
+        let stored_user = users
+            .iter_mut()
+            .find(|u| u.profile.id == user_id)
+            .ok_or(DatabaseError::NotFound)?;
+
+        stored_user.profile.roles = roles.to_vec();
+        stored_user.profile.permissions = permissions.to_vec();
+        Ok(())
+    }
+
+    async fn set_user_disabled(&self, user_id: Uuid, disabled: bool) -> Result<(), DatabaseError> {
+        let mut users = self.users.lock().unwrap();
+        let stored_user = users
+            .iter_mut()
+            .find(|u| u.profile.id == user_id)
+            .ok_or(DatabaseError::NotFound)?;
+
+        stored_user.profile.disabled = disabled;
+        Ok(())
+    }
+
+    async fn list_users(
+        &self,
+        email_prefix: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<UserPage, DatabaseError> {
+        // users are listed by id, and the cursor is the last id listed
+        let limit = limit.max(1);
+        let cursor = cursor
+            .map(|cursor| cursor.parse::<Uuid>().map_err(|_| DatabaseError::NotFound))
+            .transpose()?;
+
+        let mut profiles = self
+            .users
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|u| u.profile.email.starts_with(email_prefix))
+            .filter(|u| cursor.is_none_or(|cursor| u.profile.id > cursor))
+            .map(|u| u.profile.clone())
+            .collect::<Vec<_>>();
+        profiles.sort_unstable_by_key(|profile| profile.id);
+
+        let next_cursor = (profiles.len() > limit).then(|| profiles[limit - 1].id.to_string());
+        profiles.truncate(limit);
+        Ok(UserPage {
+            users: profiles,
+            next_cursor,
+        })
+    }
+
+    async fn set_last_login(
+        &self,
+        user_id: Uuid,
+        logged_in_at: SystemTime,
+    ) -> Result<(), DatabaseError> {
+        let mut users = self.users.lock().unwrap();
+        let stored_user = users
+            .iter_mut()
+            .find(|u| u.profile.id == user_id)
+            .ok_or(DatabaseError::NotFound)?;
+
+        stored_user.profile.last_login_at = Some(logged_in_at);
+        Ok(())
+    }
+
+    async fn user_exists(&self, email: &str) -> Result<bool, DatabaseError> {
+        Ok(self
+            .users
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|u| u.profile.email == email))
+    }
+
+    async fn is_user_verified(&self, email: &str) -> Result<bool, DatabaseError> {
+        let user_id = self.get_user_by_email(email).await?.id;
+        Ok(self.verified_users.lock().unwrap().contains(&user_id))
+    }
+
+    async fn set_user_verified(&self, email: &str) -> Result<(), DatabaseError> {
+        let user_id = self.get_user_by_email(email).await?.id;
+        self.verified_users.lock().unwrap().insert(user_id);
+        Ok(())
+    }
+
+    async fn change_user_email(
+        &self,
+        user_id: Uuid,
+        old_email: &str,
+        new_email: &str,
+    ) -> Result<(), DatabaseError> {
+        let mut users = self.users.lock().unwrap();
+        if users
+            .iter()
+            .any(|u| u.profile.email == new_email && u.profile.id != user_id)
+        {
+            return Err(DatabaseError::Conflict);
+        }
+
+        let stored_user = users
+            .iter_mut()
+            .find(|u| u.profile.id == user_id)
+            .ok_or(DatabaseError::NotFound)?;
+        if stored_user.profile.email != old_email {
+            return Err(DatabaseError::Conflict);
+        }
+
+        stored_user.profile.email = new_email.to_string();
+        self.verified_users.lock().unwrap().insert(user_id);
+        self.password_resets
+            .lock()
+            .unwrap()
+            .retain(|r| r.user_id != user_id);
+        Ok(())
+    }
+
+    async fn export_user(&self, user_id: Uuid) -> Result<UserData, DatabaseError> {
+        let profile = self.get_user(user_id).await?;
+        let api_keys = self.get_user_api_keys(user_id).await?;
+        let now = token::unix_timestamp(SystemTime::now());
+
+        Ok(UserData {
+            email_verified: self.verified_users.lock().unwrap().contains(&user_id),
+            refresh_tokens: self
+                .refresh_tokens
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|t| t.user_id == user_id && t.expires_at > now)
+                .cloned()
+                .collect(),
+            password_resets: self
+                .password_resets
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|r| r.user_id == user_id && r.expires_at > now)
+                .cloned()
+                .collect(),
+            api_keys,
+            tokens_revoked_before: self
+                .user_token_revocations
+                .lock()
+                .unwrap()
+                .get(&user_id)
+                .copied(),
+            profile,
+        })
+    }
+
+    async fn delete_user(
+        &self,
+        user_id: Uuid,
+        tokens_expire_at: SystemTime,
+    ) -> Result<(), DatabaseError> {
+        self.get_user(user_id).await?;
+
+        // revocations are kept in memory without expiring, so this one lasts
+        // for as long as the database rather than until `tokens_expire_at`
+        self.revoke_user_tokens(user_id, SystemTime::now(), tokens_expire_at)
+            .await?;
+        self.password_resets
+            .lock()
+            .unwrap()
+            .retain(|r| r.user_id != user_id);
+        self.api_keys
+            .lock()
+            .unwrap()
+            .retain(|k| k.user_id != user_id);
+        self.verified_users.lock().unwrap().remove(&user_id);
+        self.users
+            .lock()
+            .unwrap()
+            .retain(|u| u.profile.id != user_id);
+        Ok(())
+    }
+
+    async fn update_password(&self, user_id: Uuid, password: String) -> Result<(), DatabaseError> {
+        let password = self.hash_password(password).await?;
+
+        let mut users = self.users.lock().unwrap();
+        let stored_user = users
+            .iter_mut()
+            .find(|u| u.profile.id == user_id)
+            .ok_or(DatabaseError::NotFound)?;
+
+        stored_user.password = password;
+        Ok(())
+    }
+
+    async fn add_refresh_token(&self, token: RefreshToken) -> Result<(), DatabaseError> {
+        self.refresh_tokens.lock().unwrap().push(token);
+        Ok(())
+    }
+
+    async fn consume_refresh_token(
+        &self,
+        family_id: &str,
+        token_hash: &str,
+    ) -> Result<RefreshToken, DatabaseError> {
+        let now = token::unix_timestamp(SystemTime::now());
+        let mut refresh_tokens = self.refresh_tokens.lock().unwrap();
+        refresh_tokens.retain(|t| t.expires_at > now);
+
+        let token = refresh_tokens
+            .iter_mut()
+            .find(|t| t.family_id == family_id && t.token_hash == token_hash)
+            .ok_or(DatabaseError::NotFound)?;
+        if token.consumed {
+            return Err(DatabaseError::Conflict);
+        }
+
+        token.consumed = true;
+        Ok(token.clone())
+    }
+
+    async fn revoke_refresh_token_family(&self, family_id: &str) -> Result<(), DatabaseError> {
+        self.refresh_tokens
+            .lock()
+            .unwrap()
+            .retain(|t| t.family_id != family_id);
+        Ok(())
+    }
+
+    async fn add_password_reset(&self, reset: PasswordReset) -> Result<(), DatabaseError> {
+        self.password_resets.lock().unwrap().push(reset);
+        Ok(())
+    }
+
+    async fn consume_password_reset(
+        &self,
+        code_hash: &str,
+    ) -> Result<PasswordReset, DatabaseError> {
+        let now = token::unix_timestamp(SystemTime::now());
+        let mut password_resets = self.password_resets.lock().unwrap();
+        password_resets.retain(|r| r.expires_at > now);
+
+        let index = password_resets
+            .iter()
+            .position(|r| r.code_hash == code_hash)
+            .ok_or(DatabaseError::NotFound)?;
+        Ok(password_resets.swap_remove(index))
+    }
+
+    async fn add_api_key(&self, key: ApiKey) -> Result<(), DatabaseError> {
+        self.api_keys.lock().unwrap().push(key);
+        Ok(())
+    }
+
+    async fn get_api_key(&self, key_id: &str) -> Result<ApiKey, DatabaseError> {
+        let now = token::unix_timestamp(SystemTime::now());
+        let mut api_keys = self.api_keys.lock().unwrap();
+        api_keys.retain(|k| k.expires_at.is_none_or(|expires_at| expires_at > now));
+
+        api_keys
+            .iter()
+            .find(|k| k.key_id == key_id)
+            .cloned()
+            .ok_or(DatabaseError::NotFound)
+    }
+
+    async fn get_user_api_keys(&self, user_id: Uuid) -> Result<Vec<ApiKey>, DatabaseError> {
+        let now = token::unix_timestamp(SystemTime::now());
+        let mut api_keys = self.api_keys.lock().unwrap();
+        api_keys.retain(|k| k.expires_at.is_none_or(|expires_at| expires_at > now));
+
+        Ok(api_keys
+            .iter()
+            .filter(|k| k.user_id == user_id)
+            .cloned()
+            .collect())
+    }
+
+    async fn delete_api_key(&self, user_id: Uuid, key_id: &str) -> Result<(), DatabaseError> {
+        let mut api_keys = self.api_keys.lock().unwrap();
+        let index = api_keys
+            .iter()
+            .position(|k| k.user_id == user_id && k.key_id == key_id)
+            .ok_or(DatabaseError::NotFound)?;
+        api_keys.swap_remove(index);
+        Ok(())
+    }
+
+    async fn add_oauth_client(&self, client: OAuthClient) -> Result<(), DatabaseError> {
+        let mut oauth_clients = self.oauth_clients.lock().unwrap();
+        if oauth_clients.contains_key(&client.client_id) {
+            return Err(DatabaseError::Conflict);
+        }
+
+        oauth_clients.insert(client.client_id.clone(), client);
+        Ok(())
+    }
+
+    async fn get_oauth_client(&self, client_id: &str) -> Result<OAuthClient, DatabaseError> {
+        self.oauth_clients
+            .lock()
+            .unwrap()
+            .get(client_id)
+            .cloned()
+            .ok_or(DatabaseError::NotFound)
+    }
+
+    async fn add_authorization_code(&self, code: AuthorizationCode) -> Result<(), DatabaseError> {
+        self.authorization_codes.lock().unwrap().push(code);
+        Ok(())
+    }
+
+    async fn consume_authorization_code(
+        &self,
+        code_hash: &str,
+    ) -> Result<AuthorizationCode, DatabaseError> {
+        let now = token::unix_timestamp(SystemTime::now());
+        let mut authorization_codes = self.authorization_codes.lock().unwrap();
+        authorization_codes.retain(|c| c.expires_at > now);
+
+        let index = authorization_codes
+            .iter()
+            .position(|c| c.code_hash == code_hash)
+            .ok_or(DatabaseError::NotFound)?;
+        Ok(authorization_codes.swap_remove(index))
+    }
+
+    async fn get_login_failures(&self, key: &str) -> Result<Option<LoginFailures>, DatabaseError> {
+        let now = SystemTime::now();
+        let mut login_failures = self.login_failures.lock().unwrap();
+        login_failures.retain(|_, (_, expires_at)| *expires_at > now);
+
+        Ok(login_failures
+            .get(key)
+            .map(|(failures, _)| failures.clone()))
+    }
+
+    async fn record_login_failure(
+        &self,
+        key: &str,
+        failed_at: SystemTime,
+        retain_for: Duration,
+    ) -> Result<LoginFailures, DatabaseError> {
+        let now = SystemTime::now();
+        let mut login_failures = self.login_failures.lock().unwrap();
+        login_failures.retain(|_, (_, expires_at)| *expires_at > now);
+
+        let count = login_failures
+            .get(key)
+            .map_or(0, |(failures, _)| failures.count);
+        let failures = LoginFailures {
+            count: count.saturating_add(1),
+            last_failure: failed_at,
+        };
+        login_failures.insert(key.to_string(), (failures.clone(), now + retain_for));
+        Ok(failures)
+    }
+
+    async fn clear_login_failures(&self, key: &str) -> Result<(), DatabaseError> {
+        self.login_failures.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn get_rate_limit_bucket(
+        &self,
+        key: &str,
+    ) -> Result<Option<RateLimitBucket>, DatabaseError> {
+        let now = SystemTime::now();
+        let mut rate_limit_buckets = self.rate_limit_buckets.lock().unwrap();
+        rate_limit_buckets.retain(|_, (_, expires_at)| *expires_at > now);
+
+        Ok(rate_limit_buckets
+            .get(key)
+            .map(|(bucket, _)| bucket.clone()))
+    }
+
+    async fn replace_rate_limit_bucket(
+        &self,
+        key: &str,
+        expected: Option<&RateLimitBucket>,
+        bucket: RateLimitBucket,
+        retain_for: Duration,
+    ) -> Result<(), DatabaseError> {
+        let now = SystemTime::now();
+        let mut rate_limit_buckets = self.rate_limit_buckets.lock().unwrap();
+        rate_limit_buckets.retain(|_, (_, expires_at)| *expires_at > now);
+
+        if rate_limit_buckets.get(key).map(|(bucket, _)| bucket) != expected {
+            return Err(DatabaseError::Conflict);
+        }
+
+        rate_limit_buckets.insert(key.to_string(), (bucket, now + retain_for));
+        Ok(())
+    }
+
+    async fn revoke_token(
+        &self,
+        token_id: &str,
+        expires_at: SystemTime,
+    ) -> Result<(), DatabaseError> {
+        let now = SystemTime::now();
+        let mut revoked_tokens = self.revoked_tokens.lock().unwrap();
+        revoked_tokens.retain(|_, expires_at| *expires_at > now);
+        revoked_tokens.insert(token_id.to_string(), expires_at);
+        Ok(())
+    }
+
+    async fn revoke_user_tokens(
+        &self,
+        user_id: Uuid,
+        issued_before: SystemTime,
+        _tokens_expire_at: SystemTime,
+    ) -> Result<(), DatabaseError> {
+        self.user_token_revocations
+            .lock()
+            .unwrap()
+            .insert(user_id, issued_before);
+        self.refresh_tokens
+            .lock()
+            .unwrap()
+            .retain(|t| t.user_id != user_id);
+        Ok(())
+    }
+
+    async fn is_token_revoked(
+        &self,
+        token_id: &str,
+        user_id: Uuid,
+        issued_at: SystemTime,
+    ) -> Result<bool, DatabaseError> {
+        if self.revoked_tokens.lock().unwrap().contains_key(token_id) {
+            return Ok(true);
+        }
+
+        Ok(self
+            .user_token_revocations
+            .lock()
+            .unwrap()
+            .get(&user_id)
+            .is_some_and(|revoked_before| issued_at < *revoked_before))
+    }
+}
@@ -0,0 +1,634 @@
+//! Database access.
+
+mod memory;
+mod scylla_db;
+
+pub use memory::SimpleMemoryDatabase;
+pub use scylla_db::ScyllaDbSession;
+
+use crate::password::PasswordHashError;
+use axum::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    time::{Duration, SystemTime},
+};
+use uuid::Uuid;
+
+/// The model for a User in a database.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct User {
+    pub email: String,
+    pub password: String,
+}
+
+/// The model for the profile of a user in a database.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UserProfile {
+    /// Stable identifier of the user, which API tokens identify the user by.
+    pub id: Uuid,
+
+    pub email: String,
+
+    /// Name which the user chose to be displayed as, if any.
+    pub display_name: Option<String>,
+
+    /// Registration time. Unknown for users registered before it was
+    /// recorded.
+    pub created_at: Option<SystemTime>,
+
+    /// Time of the latest successful login, if any.
+    pub last_login_at: Option<SystemTime>,
+
+    /// Arbitrary data stored alongside the profile.
+    pub metadata: Map<String, Value>,
+
+    /// Names of the roles granted to the user, sorted and without duplicates.
+    pub roles: Vec<String>,
+
+    /// Names of the permissions granted to the user, sorted and without
+    /// duplicates.
+    pub permissions: Vec<String>,
+
+    /// Whether an administrator disabled the user, who cannot log in while
+    /// disabled.
+    pub disabled: bool,
+}
+
+/// A page of users listed from a database.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UserPage {
+    pub users: Vec<UserProfile>,
+
+    /// Cursor to continue listing the following users with, unless there are
+    /// none.
+    pub next_cursor: Option<String>,
+}
+
+/// The model for a refresh token in a database.
+///
+/// Only a hash of the token's secret is stored. Tokens issued by rotating an
+/// existing token share its `family_id`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RefreshToken {
+    pub family_id: String,
+    pub token_hash: String,
+    pub user_id: Uuid,
+
+    /// Expiry time in seconds since the unix epoch.
+    pub expires_at: u64,
+
+    /// Whether the token has already been exchanged for a new one.
+    pub consumed: bool,
+}
+
+/// The model for a pending password reset in a database.
+///
+/// Only a hash of the reset code sent to the user is stored.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PasswordReset {
+    pub code_hash: String,
+    pub user_id: Uuid,
+
+    /// Expiry time in seconds since the unix epoch.
+    pub expires_at: u64,
+}
+
+/// The model for a personal API key in a database.
+///
+/// Only a hash of the key's secret is stored. Keys start with their
+/// `key_id`, which users can tell their keys apart by.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub key_id: String,
+    pub key_hash: String,
+    pub user_id: Uuid,
+
+    /// Name which the user gave the key.
+    pub name: String,
+
+    /// Permissions which the key grants, out of those granted to the user,
+    /// sorted and without duplicates.
+    pub scopes: Vec<String>,
+
+    pub created_at: SystemTime,
+
+    /// Expiry time in seconds since the unix epoch, unless the key is valid
+    /// until it is revoked.
+    pub expires_at: Option<u64>,
+}
+
+/// The model for a client of the OAuth authorization server in a database.
+///
+/// Only a hash of the client's secret is stored.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OAuthClient {
+    pub client_id: String,
+
+    /// Hash of the client's secret, unless the client is a public client,
+    /// such as a single-page application, which cannot keep a secret.
+    pub secret_hash: Option<String>,
+
+    /// Scopes which the client may be granted, sorted and without
+    /// duplicates.
+    pub scopes: Vec<String>,
+
+    /// URIs which users may be redirected to with authorization codes for the
+    /// client.
+    pub redirect_uris: Vec<String>,
+
+    pub created_at: SystemTime,
+}
+
+/// The model for an OAuth authorization code in a database, which a client
+/// exchanges for a token of the user who granted it.
+///
+/// Only a hash of the code is stored.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct AuthorizationCode {
+    pub code_hash: String,
+    pub client_id: String,
+    pub user_id: Uuid,
+
+    /// Redirect URI given in the authorization request, if any, which the
+    /// client must present again when exchanging the code.
+    pub redirect_uri: Option<String>,
+
+    /// Scopes which the user granted, sorted and without duplicates.
+    pub scopes: Vec<String>,
+
+    /// PKCE code challenge, as derived from the client's code verifier with
+    /// the `S256` method.
+    pub code_challenge: String,
+
+    /// Expiry time in seconds since the unix epoch.
+    pub expires_at: u64,
+}
+
+/// The model for the failed logins counted for a user or client in a
+/// database.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LoginFailures {
+    /// Number of failed logins since the count was last cleared or expired.
+    pub count: u32,
+
+    /// Time of the latest failed login.
+    pub last_failure: SystemTime,
+}
+
+/// The model for a token bucket of a rate limit in a database.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct RateLimitBucket {
+    /// Number of requests which can currently be made, including fractions
+    /// of requests which have been refilled so far.
+    pub tokens: f64,
+
+    /// Time at which `tokens` was last updated.
+    pub updated_at: SystemTime,
+}
+
+/// Everything stored about a user in a database, as exported for the user.
+///
+/// Failed logins are counted by e-mail rather than by user, and are not
+/// included.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UserData {
+    pub profile: UserProfile,
+    pub email_verified: bool,
+
+    /// The user's unexpired refresh tokens.
+    pub refresh_tokens: Vec<RefreshToken>,
+
+    /// The user's pending password resets.
+    pub password_resets: Vec<PasswordReset>,
+
+    /// The user's unexpired API keys.
+    pub api_keys: Vec<ApiKey>,
+
+    /// Time before which all of the user's API tokens were revoked, if any.
+    pub tokens_revoked_before: Option<SystemTime>,
+}
+
+/// An error which occurred while accessing a database.
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub enum DatabaseError {
+    /// A record could not be added because it conflicts with an existing one.
+    Conflict,
+
+    /// The requested record does not exist.
+    NotFound,
+
+    /// The provided password does not match the stored one.
+    WrongPassword,
+
+    /// The database backend could not be reached or failed to run a query.
+    Unavailable(Box<dyn Error + Send + Sync>),
+
+    /// A stored record is malformed and could not be interpreted.
+    CorruptRecord(String),
+
+    /// A password could not be hashed or checked against its stored hash.
+    PasswordHashing(PasswordHashError),
+}
+
+impl Display for DatabaseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Conflict => write!(f, "record conflicts with an existing record"),
+            Self::NotFound => write!(f, "record not found"),
+            Self::WrongPassword => write!(f, "wrong password"),
+            Self::Unavailable(error) => write!(f, "database unavailable: {error}"),
+            Self::CorruptRecord(reason) => write!(f, "corrupt record: {reason}"),
+            Self::PasswordHashing(error) => write!(f, "password hashing failed: {error}"),
+        }
+    }
+}
+
+impl Error for DatabaseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Unavailable(error) => Some(error.as_ref()),
+            Self::PasswordHashing(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Trait for database access types.
+#[async_trait]
+pub trait Database: Clone + Sync + Send {
+    /// Adds a new user, with a new user id, the [`DEFAULT_ROLE`] and an
+    /// otherwise empty profile.
+    ///
+    /// [`DEFAULT_ROLE`]: crate::roles::DEFAULT_ROLE
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::Conflict`] if a user with the same e-mail
+    /// already exists.
+    async fn try_add_user(&self, user: User) -> Result<(), DatabaseError>;
+
+    /// Checks whether a user exists and the given password is correct.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::NotFound`] if no user with the given e-mail
+    /// exists, or [`DatabaseError::WrongPassword`] if the password does not
+    /// match.
+    async fn validate_user(&self, user: &User) -> Result<(), DatabaseError>;
+
+    /// Returns the profile of a user.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::NotFound`] if no user with the given id
+    /// exists.
+    async fn get_user(&self, user_id: Uuid) -> Result<UserProfile, DatabaseError>;
+
+    /// Returns the profile of the user with the given e-mail.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::NotFound`] if no user with the given e-mail
+    /// exists.
+    async fn get_user_by_email(&self, email: &str) -> Result<UserProfile, DatabaseError>;
+
+    /// Replaces the display name and metadata of a user's profile.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::NotFound`] if no user with the given id
+    /// exists.
+    async fn update_user_profile(
+        &self,
+        user_id: Uuid,
+        display_name: Option<&str>,
+        metadata: &Map<String, Value>,
+    ) -> Result<(), DatabaseError>;
+
+    /// Replaces the roles and permissions granted to a user.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::NotFound`] if no user with the given id
+    /// exists.
+    async fn set_user_roles(
+        &self,
+        user_id: Uuid,
+        roles: &[String],
+        permissions: &[String],
+    ) -> Result<(), DatabaseError>;
+
+    /// Disables or re-enables a user.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::NotFound`] if no user with the given id
+    /// exists.
+    async fn set_user_disabled(&self, user_id: Uuid, disabled: bool) -> Result<(), DatabaseError>;
+
+    /// Lists the users whose e-mail starts with `email_prefix`, one page at a
+    /// time and in no particular order.
+    ///
+    /// Returns at most `limit` users, along with a cursor to pass as `cursor`
+    /// to list the following ones. A page may hold fewer than `limit` users
+    /// even if more follow. A `limit` of zero is treated as one.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::NotFound`] if `cursor` is malformed.
+    async fn list_users(
+        &self,
+        email_prefix: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<UserPage, DatabaseError>;
+
+    /// Records a successful login of a user.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::NotFound`] if no user with the given id
+    /// exists.
+    async fn set_last_login(
+        &self,
+        user_id: Uuid,
+        logged_in_at: SystemTime,
+    ) -> Result<(), DatabaseError>;
+
+    /// Checks whether a user with the given e-mail exists.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the user cannot be looked up.
+    async fn user_exists(&self, email: &str) -> Result<bool, DatabaseError>;
+
+    /// Checks whether a user's e-mail address has been verified. New users
+    /// start out unverified.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::NotFound`] if no user with the given e-mail
+    /// exists.
+    async fn is_user_verified(&self, email: &str) -> Result<bool, DatabaseError>;
+
+    /// Marks a user's e-mail address as verified.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::NotFound`] if no user with the given e-mail
+    /// exists.
+    async fn set_user_verified(&self, email: &str) -> Result<(), DatabaseError>;
+
+    /// Changes a user's e-mail address from `old_email` to `new_email`, and
+    /// marks the new address as verified. The user's pending password resets
+    /// are removed, as their codes were sent to the previous address.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::NotFound`] if no user with the given id
+    /// exists, or [`DatabaseError::Conflict`] if `new_email` belongs to
+    /// another user or the user's address is no longer `old_email`.
+    async fn change_user_email(
+        &self,
+        user_id: Uuid,
+        old_email: &str,
+        new_email: &str,
+    ) -> Result<(), DatabaseError>;
+
+    /// Returns everything stored about a user.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::NotFound`] if no user with the given id
+    /// exists.
+    async fn export_user(&self, user_id: Uuid) -> Result<UserData, DatabaseError>;
+
+    /// Deletes a user along with their e-mail address, refresh tokens and
+    /// pending password resets.
+    ///
+    /// The user's API tokens are revoked rather than removed, which only needs
+    /// to be retained until `tokens_expire_at`, once every token issued to the
+    /// user has expired.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::NotFound`] if no user with the given id
+    /// exists.
+    async fn delete_user(
+        &self,
+        user_id: Uuid,
+        tokens_expire_at: SystemTime,
+    ) -> Result<(), DatabaseError>;
+
+    /// Replaces the password of an existing user.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::NotFound`] if no user with the given id
+    /// exists.
+    async fn update_password(&self, user_id: Uuid, password: String) -> Result<(), DatabaseError>;
+
+    /// Stores a new refresh token.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the token cannot be stored.
+    async fn add_refresh_token(&self, token: RefreshToken) -> Result<(), DatabaseError>;
+
+    /// Marks an unexpired refresh token as consumed and returns it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::NotFound`] if no such token exists or it has
+    /// expired, or [`DatabaseError::Conflict`] if it has already been
+    /// consumed.
+    async fn consume_refresh_token(
+        &self,
+        family_id: &str,
+        token_hash: &str,
+    ) -> Result<RefreshToken, DatabaseError>;
+
+    /// Removes every refresh token belonging to a token family.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tokens cannot be removed.
+    async fn revoke_refresh_token_family(&self, family_id: &str) -> Result<(), DatabaseError>;
+
+    /// Stores a new password reset.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the reset cannot be stored.
+    async fn add_password_reset(&self, reset: PasswordReset) -> Result<(), DatabaseError>;
+
+    /// Removes an unexpired password reset and returns it, so that its code
+    /// can only be used once.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::NotFound`] if no such reset exists, it has
+    /// expired or it has already been used.
+    async fn consume_password_reset(&self, code_hash: &str)
+        -> Result<PasswordReset, DatabaseError>;
+
+    /// Stores a new API key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key cannot be stored.
+    async fn add_api_key(&self, key: ApiKey) -> Result<(), DatabaseError>;
+
+    /// Retrieves an unexpired API key by its id.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::NotFound`] if no such key exists or it has
+    /// expired.
+    async fn get_api_key(&self, key_id: &str) -> Result<ApiKey, DatabaseError>;
+
+    /// Retrieves the unexpired API keys of a user.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the keys cannot be retrieved.
+    async fn get_user_api_keys(&self, user_id: Uuid) -> Result<Vec<ApiKey>, DatabaseError>;
+
+    /// Deletes an API key of a user.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::NotFound`] if the user has no such key.
+    async fn delete_api_key(&self, user_id: Uuid, key_id: &str) -> Result<(), DatabaseError>;
+
+    /// Registers a new OAuth client.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::Conflict`] if a client with the same id is
+    /// already registered.
+    async fn add_oauth_client(&self, client: OAuthClient) -> Result<(), DatabaseError>;
+
+    /// Retrieves a registered OAuth client by its id.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::NotFound`] if no such client is registered.
+    async fn get_oauth_client(&self, client_id: &str) -> Result<OAuthClient, DatabaseError>;
+
+    /// Stores a new OAuth authorization code.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the code cannot be stored.
+    async fn add_authorization_code(&self, code: AuthorizationCode) -> Result<(), DatabaseError>;
+
+    /// Removes an unexpired OAuth authorization code and returns it, so that
+    /// it can only be exchanged once.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::NotFound`] if no such code exists, it has
+    /// expired or it has already been exchanged.
+    async fn consume_authorization_code(
+        &self,
+        code_hash: &str,
+    ) -> Result<AuthorizationCode, DatabaseError>;
+
+    /// Returns the failed logins counted for a key, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the count cannot be retrieved.
+    async fn get_login_failures(&self, key: &str) -> Result<Option<LoginFailures>, DatabaseError>;
+
+    /// Counts a failed login for a key and returns the updated count. The
+    /// count expires once no further logins have failed for `retain_for`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the count cannot be updated.
+    async fn record_login_failure(
+        &self,
+        key: &str,
+        failed_at: SystemTime,
+        retain_for: Duration,
+    ) -> Result<LoginFailures, DatabaseError>;
+
+    /// Removes the failed logins counted for a key.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the count cannot be removed.
+    async fn clear_login_failures(&self, key: &str) -> Result<(), DatabaseError>;
+
+    /// Returns the token bucket of a rate limit, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bucket cannot be retrieved.
+    async fn get_rate_limit_bucket(
+        &self,
+        key: &str,
+    ) -> Result<Option<RateLimitBucket>, DatabaseError>;
+
+    /// Replaces the token bucket of a rate limit, provided that it is still
+    /// equal to `expected`, or still does not exist if `expected` is `None`.
+    /// The bucket expires after `retain_for`.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DatabaseError::Conflict`] if the bucket was changed
+    /// concurrently.
+    async fn replace_rate_limit_bucket(
+        &self,
+        key: &str,
+        expected: Option<&RateLimitBucket>,
+        bucket: RateLimitBucket,
+        retain_for: Duration,
+    ) -> Result<(), DatabaseError>;
+
+    /// Revokes a single API token. The revocation only needs to be retained
+    /// until `expires_at`, after which the token is rejected anyway.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the revocation cannot be stored.
+    async fn revoke_token(
+        &self,
+        token_id: &str,
+        expires_at: SystemTime,
+    ) -> Result<(), DatabaseError>;
+
+    /// Revokes every API token issued to a user before `issued_before`, and
+    /// removes all of the user's refresh tokens. The revocation only needs to
+    /// be retained until `tokens_expire_at`, once every such token has
+    /// expired.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the revocation cannot be stored.
+    async fn revoke_user_tokens(
+        &self,
+        user_id: Uuid,
+        issued_before: SystemTime,
+        tokens_expire_at: SystemTime,
+    ) -> Result<(), DatabaseError>;
+
+    /// Checks whether an API token has been revoked, either individually or
+    /// as part of a revocation of all of the user's tokens.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the revocation state cannot be retrieved.
+    async fn is_token_revoked(
+        &self,
+        token_id: &str,
+        user_id: Uuid,
+        issued_at: SystemTime,
+    ) -> Result<bool, DatabaseError>;
+}
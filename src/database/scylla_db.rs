@@ -0,0 +1,1884 @@
+//! ``ScyllaDB`` database access.
+
+use super::{
+    ApiKey, AuthorizationCode, Database, DatabaseError, LoginFailures, OAuthClient, PasswordReset,
+    RateLimitBucket, RefreshToken, User, UserData, UserPage, UserProfile,
+};
+use crate::{
+    password::{self, HashingPool, PasswordHasher},
+    roles, token,
+};
+use axum::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use scylla::{
+    frame::{response::cql_to_rust::FromRow, value::ValueList},
+    prepared_statement::PreparedStatement,
+    QueryResult, Session, SessionBuilder,
+};
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    error::Error,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::join;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+/// Number of times counting a failed login is attempted when the count is
+/// updated concurrently.
+const MAX_LOGIN_FAILURE_UPDATE_ATTEMPTS: usize = 5;
+
+/// Maximum number of users scanned for a single page of a user listing, so
+/// that searching for a rare e-mail prefix returns short pages rather than
+/// scanning the whole table at once.
+const MAX_LISTING_SCANNED_USERS: usize = 1000;
+
+/// Columns of a user's profile: id, email, display name, creation time, last
+/// login time, metadata, roles, permissions and whether the user is disabled.
+type UserRow = (
+    Option<Uuid>,
+    String,
+    Option<String>,
+    Option<i64>,
+    Option<i64>,
+    Option<String>,
+    Option<Vec<String>>,
+    Option<Vec<String>>,
+    Option<bool>,
+);
+
+/// Columns which the `users` table had besides the e-mail and password hash
+/// while users were keyed by e-mail, along with their types. Tables created
+/// before some of them were introduced lack those.
+const LEGACY_USER_COLUMNS: [(&str, &str); 6] = [
+    ("verified", "BOOLEAN"),
+    ("id", "UUID"),
+    ("display_name", "TEXT"),
+    ("created_at", "BIGINT"),
+    ("last_login_at", "BIGINT"),
+    ("metadata", "TEXT"),
+];
+
+/// Columns of a user from while users were keyed by e-mail: e-mail, password
+/// hash, whether the e-mail is verified, id, display name, creation time,
+/// last login time and metadata.
+type LegacyUserRow = (
+    String,
+    Option<String>,
+    Option<bool>,
+    Option<Uuid>,
+    Option<String>,
+    Option<i64>,
+    Option<i64>,
+    Option<String>,
+);
+
+/// Columns of an API key: key id, key hash, user id, name, scopes, creation
+/// time and expiry time.
+type ApiKeyRow = (
+    String,
+    String,
+    Uuid,
+    String,
+    Option<Vec<String>>,
+    i64,
+    Option<i64>,
+);
+
+/// A ``ScyllaDB`` session.
+#[derive(Clone)]
+pub struct ScyllaDbSession {
+    session: Arc<Session>,
+    password_hasher: Arc<dyn PasswordHasher>,
+    hashing_pool: HashingPool,
+
+    /// Hash which passwords are checked against when a user does not exist.
+    dummy_password_hash: Arc<str>,
+
+    claim_email_statement: Arc<PreparedStatement>,
+    release_email_statement: Arc<PreparedStatement>,
+    get_user_id_statement: Arc<PreparedStatement>,
+    add_user_statement: Arc<PreparedStatement>,
+    get_password_statement: Arc<PreparedStatement>,
+    get_user_statement: Arc<PreparedStatement>,
+    list_users_statement: Arc<PreparedStatement>,
+    change_email_statement: Arc<PreparedStatement>,
+    delete_user_statement: Arc<PreparedStatement>,
+    revoke_deleted_user_tokens_statement: Arc<PreparedStatement>,
+    get_user_refresh_tokens_statement: Arc<PreparedStatement>,
+    get_user_password_resets_statement: Arc<PreparedStatement>,
+    delete_password_reset_statement: Arc<PreparedStatement>,
+    update_user_profile_statement: Arc<PreparedStatement>,
+    set_last_login_statement: Arc<PreparedStatement>,
+    set_user_roles_statement: Arc<PreparedStatement>,
+    set_user_disabled_statement: Arc<PreparedStatement>,
+    update_password_hash_statement: Arc<PreparedStatement>,
+    update_password_statement: Arc<PreparedStatement>,
+    add_refresh_token_statement: Arc<PreparedStatement>,
+    get_refresh_token_statement: Arc<PreparedStatement>,
+    consume_refresh_token_statement: Arc<PreparedStatement>,
+    revoke_refresh_token_family_statement: Arc<PreparedStatement>,
+    get_user_refresh_token_families_statement: Arc<PreparedStatement>,
+    revoke_token_statement: Arc<PreparedStatement>,
+    revoke_user_tokens_statement: Arc<PreparedStatement>,
+    get_revoked_token_statement: Arc<PreparedStatement>,
+    get_user_token_revocation_statement: Arc<PreparedStatement>,
+    add_password_reset_statement: Arc<PreparedStatement>,
+    get_password_reset_statement: Arc<PreparedStatement>,
+    consume_password_reset_statement: Arc<PreparedStatement>,
+    add_api_key_statement: Arc<PreparedStatement>,
+    get_api_key_statement: Arc<PreparedStatement>,
+    get_user_api_keys_statement: Arc<PreparedStatement>,
+    delete_api_key_statement: Arc<PreparedStatement>,
+    add_oauth_client_statement: Arc<PreparedStatement>,
+    get_oauth_client_statement: Arc<PreparedStatement>,
+    add_authorization_code_statement: Arc<PreparedStatement>,
+    get_authorization_code_statement: Arc<PreparedStatement>,
+    consume_authorization_code_statement: Arc<PreparedStatement>,
+    get_user_verified_statement: Arc<PreparedStatement>,
+    set_user_verified_statement: Arc<PreparedStatement>,
+    get_login_failures_statement: Arc<PreparedStatement>,
+    add_login_failures_statement: Arc<PreparedStatement>,
+    update_login_failures_statement: Arc<PreparedStatement>,
+    clear_login_failures_statement: Arc<PreparedStatement>,
+    get_rate_limit_bucket_statement: Arc<PreparedStatement>,
+    add_rate_limit_bucket_statement: Arc<PreparedStatement>,
+    update_rate_limit_bucket_statement: Arc<PreparedStatement>,
+}
+
+impl ScyllaDbSession {
+    /// Creates a ``ScyllaDB`` session. New passwords are hashed with
+    /// `password_hasher`, and stored hashes are upgraded to it on login.
+    ///
+    /// # Errors
+    ///
+    /// If the session or a prepared statement cannot be created, returns an
+    /// appropriate error.
+    pub async fn new(
+        hostnames: &[impl AsRef<str>],
+        password_hasher: Arc<dyn PasswordHasher>,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        debug!("creating ScyllaDB session");
+
+        let session = SessionBuilder::new().known_nodes(hostnames).build().await?;
+
+        debug!("preparing ScyllaDB statements");
+
+        let (
+            claim_email_statement,
+            release_email_statement,
+            get_user_id_statement,
+            add_user_statement,
+            get_password_statement,
+            get_user_statement,
+            list_users_statement,
+            change_email_statement,
+            delete_user_statement,
+            revoke_deleted_user_tokens_statement,
+            get_user_refresh_tokens_statement,
+            get_user_password_resets_statement,
+            delete_password_reset_statement,
+            update_user_profile_statement,
+            set_last_login_statement,
+            set_user_roles_statement,
+            set_user_disabled_statement,
+            update_password_hash_statement,
+            update_password_statement,
+            add_refresh_token_statement,
+            get_refresh_token_statement,
+            consume_refresh_token_statement,
+            revoke_refresh_token_family_statement,
+            get_user_refresh_token_families_statement,
+            revoke_token_statement,
+            revoke_user_tokens_statement,
+            get_revoked_token_statement,
+            get_user_token_revocation_statement,
+            add_password_reset_statement,
+            get_password_reset_statement,
+            consume_password_reset_statement,
+            add_api_key_statement,
+            get_api_key_statement,
+            get_user_api_keys_statement,
+            delete_api_key_statement,
+            add_oauth_client_statement,
+            get_oauth_client_statement,
+            add_authorization_code_statement,
+            get_authorization_code_statement,
+            consume_authorization_code_statement,
+            get_user_verified_statement,
+            set_user_verified_statement,
+            get_login_failures_statement,
+            add_login_failures_statement,
+            update_login_failures_statement,
+            clear_login_failures_statement,
+            get_rate_limit_bucket_statement,
+            add_rate_limit_bucket_statement,
+            update_rate_limit_bucket_statement,
+        ) = join!(
+            session.prepare(
+                "INSERT INTO axum_api.user_emails (email, user_id) VALUES (?, ?) IF NOT EXISTS",
+            ),
+            session.prepare("DELETE FROM axum_api.user_emails WHERE email = ? IF user_id = ?"),
+            session.prepare("SELECT user_id FROM axum_api.user_emails WHERE email = ?"),
+            session.prepare(
+                "INSERT INTO axum_api.users \
+                (id, email, password_hash, verified, created_at, roles) \
+                VALUES (?, ?, ?, false, ?, ?)",
+            ),
+            session.prepare("SELECT password_hash FROM axum_api.users WHERE id = ?"),
+            session.prepare(
+                "SELECT id, email, display_name, created_at, last_login_at, metadata, roles, \
+                permissions, disabled FROM axum_api.users WHERE id = ?",
+            ),
+            session.prepare(
+                "SELECT id, email, display_name, created_at, last_login_at, metadata, roles, \
+                permissions, disabled FROM axum_api.users",
+            ),
+            session.prepare(
+                "UPDATE axum_api.users SET email = ?, verified = true WHERE id = ? IF email = ?",
+            ),
+            session.prepare("DELETE FROM axum_api.users WHERE id = ?"),
+            session.prepare(
+                "INSERT INTO axum_api.user_token_revocations (user_id, revoked_before) \
+                VALUES (?, ?) USING TTL ?",
+            ),
+            session.prepare(
+                "SELECT family_id, token_hash, expires_at, consumed FROM axum_api.refresh_tokens \
+                WHERE user_id = ?",
+            ),
+            session.prepare(
+                "SELECT code_hash, expires_at FROM axum_api.password_resets WHERE user_id = ?",
+            ),
+            session.prepare("DELETE FROM axum_api.password_resets WHERE code_hash = ?"),
+            session.prepare(
+                "UPDATE axum_api.users SET display_name = ?, metadata = ? WHERE id = ? IF EXISTS",
+            ),
+            session.prepare("UPDATE axum_api.users SET last_login_at = ? WHERE id = ? IF EXISTS"),
+            session.prepare(
+                "UPDATE axum_api.users SET roles = ?, permissions = ? WHERE id = ? IF EXISTS",
+            ),
+            session.prepare("UPDATE axum_api.users SET disabled = ? WHERE id = ? IF EXISTS"),
+            session.prepare(
+                "UPDATE axum_api.users SET password_hash = ? WHERE id = ? IF password_hash = ?",
+            ),
+            session.prepare("UPDATE axum_api.users SET password_hash = ? WHERE id = ? IF EXISTS"),
+            session.prepare(
+                "INSERT INTO axum_api.refresh_tokens \
+                (family_id, token_hash, user_id, expires_at, consumed) VALUES (?, ?, ?, ?, ?) \
+                USING TTL ?",
+            ),
+            session.prepare(
+                "SELECT user_id, expires_at, consumed FROM axum_api.refresh_tokens \
+                WHERE family_id = ? AND token_hash = ?",
+            ),
+            session.prepare(
+                "UPDATE axum_api.refresh_tokens USING TTL ? SET consumed = true \
+                WHERE family_id = ? AND token_hash = ? IF consumed = false",
+            ),
+            session.prepare("DELETE FROM axum_api.refresh_tokens WHERE family_id = ?"),
+            session.prepare("SELECT family_id FROM axum_api.refresh_tokens WHERE user_id = ?"),
+            session
+                .prepare("INSERT INTO axum_api.revoked_tokens (token_id) VALUES (?) USING TTL ?"),
+            session.prepare(
+                "INSERT INTO axum_api.user_token_revocations (user_id, revoked_before) \
+                VALUES (?, ?) USING TTL ?",
+            ),
+            session.prepare("SELECT token_id FROM axum_api.revoked_tokens WHERE token_id = ?"),
+            session.prepare(
+                "SELECT revoked_before FROM axum_api.user_token_revocations WHERE user_id = ?",
+            ),
+            session.prepare(
+                "INSERT INTO axum_api.password_resets (code_hash, user_id, expires_at) \
+                VALUES (?, ?, ?) USING TTL ?",
+            ),
+            session.prepare(
+                "SELECT user_id, expires_at FROM axum_api.password_resets WHERE code_hash = ?",
+            ),
+            session.prepare("DELETE FROM axum_api.password_resets WHERE code_hash = ? IF EXISTS"),
+            session.prepare(
+                "INSERT INTO axum_api.api_keys \
+                (key_id, key_hash, user_id, name, scopes, created_at, expires_at) \
+                VALUES (?, ?, ?, ?, ?, ?, ?) USING TTL ?",
+            ),
+            session.prepare(
+                "SELECT key_id, key_hash, user_id, name, scopes, created_at, expires_at \
+                FROM axum_api.api_keys WHERE key_id = ?",
+            ),
+            session.prepare(
+                "SELECT key_id, key_hash, user_id, name, scopes, created_at, expires_at \
+                FROM axum_api.api_keys WHERE user_id = ?",
+            ),
+            session.prepare("DELETE FROM axum_api.api_keys WHERE key_id = ? IF user_id = ?"),
+            session.prepare(
+                "INSERT INTO axum_api.oauth_clients \
+                (client_id, secret_hash, scopes, redirect_uris, created_at) \
+                VALUES (?, ?, ?, ?, ?) IF NOT EXISTS",
+            ),
+            session.prepare(
+                "SELECT secret_hash, scopes, redirect_uris, created_at \
+                FROM axum_api.oauth_clients WHERE client_id = ?",
+            ),
+            session.prepare(
+                "INSERT INTO axum_api.authorization_codes \
+                (code_hash, client_id, user_id, redirect_uri, scopes, code_challenge, expires_at) \
+                VALUES (?, ?, ?, ?, ?, ?, ?) USING TTL ?",
+            ),
+            session.prepare(
+                "SELECT client_id, user_id, redirect_uri, scopes, code_challenge, expires_at \
+                FROM axum_api.authorization_codes WHERE code_hash = ?",
+            ),
+            session
+                .prepare("DELETE FROM axum_api.authorization_codes WHERE code_hash = ? IF EXISTS",),
+            session.prepare("SELECT verified FROM axum_api.users WHERE id = ?"),
+            session.prepare("UPDATE axum_api.users SET verified = true WHERE id = ? IF EXISTS"),
+            session.prepare(
+                "SELECT failures, last_failure FROM axum_api.login_failures WHERE subject = ?",
+            ),
+            session.prepare(
+                "INSERT INTO axum_api.login_failures (subject, failures, last_failure) \
+                VALUES (?, 1, ?) IF NOT EXISTS USING TTL ?",
+            ),
+            session.prepare(
+                "UPDATE axum_api.login_failures USING TTL ? SET failures = ?, last_failure = ? \
+                WHERE subject = ? IF failures = ?",
+            ),
+            session.prepare("DELETE FROM axum_api.login_failures WHERE subject = ?"),
+            session.prepare(
+                "SELECT tokens, updated_at FROM axum_api.rate_limit_buckets WHERE bucket_key = ?",
+            ),
+            session.prepare(
+                "INSERT INTO axum_api.rate_limit_buckets (bucket_key, tokens, updated_at) \
+                VALUES (?, ?, ?) IF NOT EXISTS USING TTL ?",
+            ),
+            session.prepare(
+                "UPDATE axum_api.rate_limit_buckets USING TTL ? SET tokens = ?, updated_at = ? \
+                WHERE bucket_key = ? IF tokens = ? AND updated_at = ?",
+            ),
+        );
+
+        Ok(Self {
+            session: Arc::new(session),
+            dummy_password_hash: password::dummy_hash(password_hasher.as_ref())?.into(),
+            password_hasher,
+            hashing_pool: HashingPool::default(),
+            claim_email_statement: Arc::new(claim_email_statement?),
+            release_email_statement: Arc::new(release_email_statement?),
+            get_user_id_statement: Arc::new(get_user_id_statement?),
+            add_user_statement: Arc::new(add_user_statement?),
+            get_password_statement: Arc::new(get_password_statement?),
+            get_user_statement: Arc::new(get_user_statement?),
+            list_users_statement: Arc::new(list_users_statement?),
+            change_email_statement: Arc::new(change_email_statement?),
+            delete_user_statement: Arc::new(delete_user_statement?),
+            revoke_deleted_user_tokens_statement: Arc::new(revoke_deleted_user_tokens_statement?),
+            get_user_refresh_tokens_statement: Arc::new(get_user_refresh_tokens_statement?),
+            get_user_password_resets_statement: Arc::new(get_user_password_resets_statement?),
+            delete_password_reset_statement: Arc::new(delete_password_reset_statement?),
+            update_user_profile_statement: Arc::new(update_user_profile_statement?),
+            set_last_login_statement: Arc::new(set_last_login_statement?),
+            set_user_roles_statement: Arc::new(set_user_roles_statement?),
+            set_user_disabled_statement: Arc::new(set_user_disabled_statement?),
+            update_password_hash_statement: Arc::new(update_password_hash_statement?),
+            update_password_statement: Arc::new(update_password_statement?),
+            add_refresh_token_statement: Arc::new(add_refresh_token_statement?),
+            get_refresh_token_statement: Arc::new(get_refresh_token_statement?),
+            consume_refresh_token_statement: Arc::new(consume_refresh_token_statement?),
+            revoke_refresh_token_family_statement: Arc::new(revoke_refresh_token_family_statement?),
+            get_user_refresh_token_families_statement: Arc::new(
+                get_user_refresh_token_families_statement?,
+            ),
+            revoke_token_statement: Arc::new(revoke_token_statement?),
+            revoke_user_tokens_statement: Arc::new(revoke_user_tokens_statement?),
+            get_revoked_token_statement: Arc::new(get_revoked_token_statement?),
+            get_user_token_revocation_statement: Arc::new(get_user_token_revocation_statement?),
+            add_password_reset_statement: Arc::new(add_password_reset_statement?),
+            get_password_reset_statement: Arc::new(get_password_reset_statement?),
+            consume_password_reset_statement: Arc::new(consume_password_reset_statement?),
+            add_api_key_statement: Arc::new(add_api_key_statement?),
+            get_api_key_statement: Arc::new(get_api_key_statement?),
+            get_user_api_keys_statement: Arc::new(get_user_api_keys_statement?),
+            delete_api_key_statement: Arc::new(delete_api_key_statement?),
+            add_oauth_client_statement: Arc::new(add_oauth_client_statement?),
+            get_oauth_client_statement: Arc::new(get_oauth_client_statement?),
+            add_authorization_code_statement: Arc::new(add_authorization_code_statement?),
+            get_authorization_code_statement: Arc::new(get_authorization_code_statement?),
+            consume_authorization_code_statement: Arc::new(consume_authorization_code_statement?),
+            get_user_verified_statement: Arc::new(get_user_verified_statement?),
+            set_user_verified_statement: Arc::new(set_user_verified_statement?),
+            get_login_failures_statement: Arc::new(get_login_failures_statement?),
+            add_login_failures_statement: Arc::new(add_login_failures_statement?),
+            update_login_failures_statement: Arc::new(update_login_failures_statement?),
+            clear_login_failures_statement: Arc::new(clear_login_failures_statement?),
+            get_rate_limit_bucket_statement: Arc::new(get_rate_limit_bucket_statement?),
+            add_rate_limit_bucket_statement: Arc::new(add_rate_limit_bucket_statement?),
+            update_rate_limit_bucket_statement: Arc::new(update_rate_limit_bucket_statement?),
+        })
+    }
+
+    /// Sets the pool which passwords are hashed and verified on. Defaults to
+    /// [`HashingPool::default`].
+    #[must_use]
+    pub fn with_hashing_pool(mut self, hashing_pool: HashingPool) -> Self {
+        self.hashing_pool = hashing_pool;
+        self
+    }
+
+    /// Migrates users stored by an earlier version, in which the `users`
+    /// table is keyed by e-mail, to users keyed by id whose e-mail addresses
+    /// are claimed in the `user_emails` table. Users without an id are
+    /// assigned one, and every user is given the [`roles::DEFAULT_ROLE`].
+    ///
+    /// Users are first moved to a `legacy_users` table, which is dropped once
+    /// all of them have been migrated, so that an interrupted migration can
+    /// be run again. Returns the number of users migrated, which is zero if
+    /// the database needs no migration.
+    ///
+    /// The `password_resets` table is recreated if its resets are keyed by
+    /// e-mail, which drops pending resets. Their users can request new codes.
+    ///
+    /// # Errors
+    ///
+    /// If the session cannot be created or a query fails, returns an
+    /// appropriate error.
+    pub async fn migrate(
+        hostnames: &[impl AsRef<str>],
+    ) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        let session = SessionBuilder::new().known_nodes(hostnames).build().await?;
+
+        if table_columns(&session, "password_resets")
+            .await?
+            .contains_key("user_email")
+        {
+            info!("recreating password_resets keyed by user id");
+            session
+                .query("DROP TABLE axum_api.password_resets", &[])
+                .await?;
+            session
+                .query(
+                    "CREATE TABLE IF NOT EXISTS axum_api.password_resets (\
+                    code_hash TEXT PRIMARY KEY, user_id UUID, expires_at BIGINT)",
+                    &[],
+                )
+                .await?;
+            session
+                .query(
+                    "CREATE INDEX IF NOT EXISTS ON axum_api.password_resets (user_id)",
+                    &[],
+                )
+                .await?;
+        }
+
+        let user_columns = table_columns(&session, "users").await?;
+        if user_columns.get("email").map(String::as_str) == Some("partition_key") {
+            info!("moving users keyed by e-mail to legacy_users");
+            for (column, column_type) in LEGACY_USER_COLUMNS {
+                if !user_columns.contains_key(column) {
+                    session
+                        .query(
+                            format!("ALTER TABLE axum_api.users ADD {column} {column_type}"),
+                            &[],
+                        )
+                        .await?;
+                }
+            }
+            session
+                .query(
+                    "CREATE TABLE IF NOT EXISTS axum_api.legacy_users (\
+                    email TEXT PRIMARY KEY, password_hash TEXT, verified BOOLEAN, id UUID, \
+                    display_name TEXT, created_at BIGINT, last_login_at BIGINT, metadata TEXT)",
+                    &[],
+                )
+                .await?;
+            let add_legacy_user_statement = session
+                .prepare(
+                    "INSERT INTO axum_api.legacy_users (email, password_hash, verified, id, \
+                    display_name, created_at, last_login_at, metadata) \
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+                )
+                .await?;
+            let legacy_users = all_rows::<LegacyUserRow>(
+                &session,
+                "SELECT email, password_hash, verified, id, display_name, created_at, \
+                last_login_at, metadata FROM axum_api.users",
+            )
+            .await?;
+            for legacy_user in legacy_users {
+                session
+                    .execute(&add_legacy_user_statement, legacy_user)
+                    .await?;
+            }
+            session.query("DROP TABLE axum_api.users", &[]).await?;
+        }
+
+        if table_columns(&session, "legacy_users").await?.is_empty() {
+            return Ok(0);
+        }
+
+        session
+            .query(
+                "CREATE TABLE IF NOT EXISTS axum_api.users (\
+                id UUID PRIMARY KEY, email TEXT, password_hash TEXT, verified BOOLEAN, \
+                display_name TEXT, created_at BIGINT, last_login_at BIGINT, metadata TEXT, \
+                roles SET<TEXT>, permissions SET<TEXT>, disabled BOOLEAN)",
+                &[],
+            )
+            .await?;
+        session
+            .query(
+                "CREATE TABLE IF NOT EXISTS axum_api.user_emails (\
+                email TEXT PRIMARY KEY, user_id UUID)",
+                &[],
+            )
+            .await?;
+        let claim_email_statement = session
+            .prepare(
+                "INSERT INTO axum_api.user_emails (email, user_id) VALUES (?, ?) IF NOT EXISTS",
+            )
+            .await?;
+        let get_user_id_statement = session
+            .prepare("SELECT user_id FROM axum_api.user_emails WHERE email = ?")
+            .await?;
+        let add_user_statement = session
+            .prepare(
+                "INSERT INTO axum_api.users (id, email, password_hash, verified, \
+                display_name, created_at, last_login_at, metadata, roles) \
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .await?;
+
+        let legacy_users = all_rows::<LegacyUserRow>(
+            &session,
+            "SELECT email, password_hash, verified, id, display_name, created_at, \
+            last_login_at, metadata FROM axum_api.legacy_users",
+        )
+        .await?;
+        let mut migrated = 0;
+        for (
+            email,
+            password_hash,
+            verified,
+            id,
+            display_name,
+            created_at,
+            last_login_at,
+            metadata,
+        ) in legacy_users
+        {
+            // rows without a password hash are left over from updates of
+            // users which did not exist
+            let Some(password_hash) = password_hash else {
+                continue;
+            };
+
+            // an interrupted migration may have claimed the e-mail already,
+            // possibly with a newly assigned id
+            let id = id.unwrap_or_else(Uuid::new_v4);
+            let result = session
+                .execute(&claim_email_statement, (&email, id))
+                .await?;
+            let id = if lwt_applied(result)? {
+                id
+            } else {
+                session
+                    .execute(&get_user_id_statement, (&email,))
+                    .await?
+                    .first_row_typed::<(Uuid,)>()?
+                    .0
+            };
+
+            session
+                .execute(
+                    &add_user_statement,
+                    (
+                        id,
+                        &email,
+                        password_hash,
+                        verified,
+                        display_name,
+                        created_at,
+                        last_login_at,
+                        metadata,
+                        vec![roles::DEFAULT_ROLE],
+                    ),
+                )
+                .await?;
+            migrated += 1;
+        }
+        session
+            .query("DROP TABLE axum_api.legacy_users", &[])
+            .await?;
+        info!("migrated {migrated} users to be keyed by id");
+
+        Ok(migrated)
+    }
+}
+
+impl ScyllaDbSession {
+    /// Hashes a password on the hashing pool, so as to not stall the async
+    /// runtime.
+    async fn hash_password(&self, password: String) -> Result<String, DatabaseError> {
+        let password_hasher = Arc::clone(&self.password_hasher);
+        self.hashing_pool
+            .run(move || password_hasher.hash(&password))
+            .await
+            .and_then(|hash| hash)
+            .map_err(DatabaseError::PasswordHashing)
+    }
+
+    /// Replaces a user's outdated password hash with one created by the
+    /// current password hasher. Nothing is changed if the stored hash no
+    /// longer matches `old_hash`, e.g. due to a concurrent login.
+    async fn rehash_password(
+        &self,
+        user_id: Uuid,
+        password: String,
+        old_hash: String,
+    ) -> Result<(), DatabaseError> {
+        let new_hash = self.hash_password(password).await?;
+
+        let result = self
+            .session
+            .execute(
+                &self.update_password_hash_statement,
+                (new_hash, user_id, old_hash),
+            )
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+        if lwt_applied(result)? {
+            info!("upgraded outdated password hash");
+        }
+
+        Ok(())
+    }
+
+    /// Looks up the id of the user with the given e-mail, if any.
+    async fn get_user_id(&self, email: &str) -> Result<Option<Uuid>, DatabaseError> {
+        Ok(self
+            .session
+            .execute(&self.get_user_id_statement, (email,))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?
+            .maybe_first_row_typed::<(Uuid,)>()
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?
+            .map(|(user_id,)| user_id))
+    }
+
+    /// Removes a user's claim on an e-mail, unless another user has claimed
+    /// it since.
+    async fn release_email(&self, email: &str, user_id: Uuid) -> Result<(), DatabaseError> {
+        self.session
+            .execute(&self.release_email_statement, (email, user_id))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+        Ok(())
+    }
+
+    /// Returns the unexpired refresh tokens of a user.
+    async fn get_user_refresh_tokens(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<RefreshToken>, DatabaseError> {
+        let rows = self
+            .session
+            .execute(&self.get_user_refresh_tokens_statement, (user_id,))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?
+            .rows_typed::<(String, String, i64, bool)>()
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?;
+
+        let mut refresh_tokens = Vec::with_capacity(rows.len());
+        for (family_id, token_hash, expires_at, consumed) in rows {
+            let expires_at = u64::try_from(expires_at).map_err(|_| {
+                DatabaseError::CorruptRecord("negative refresh token expiry".into())
+            })?;
+            if seconds_until(expires_at) > 0 {
+                refresh_tokens.push(RefreshToken {
+                    family_id,
+                    token_hash,
+                    user_id,
+                    expires_at,
+                    consumed,
+                });
+            }
+        }
+        Ok(refresh_tokens)
+    }
+
+    /// Returns the unexpired password resets of a user.
+    async fn get_user_password_resets(
+        &self,
+        user_id: Uuid,
+    ) -> Result<Vec<PasswordReset>, DatabaseError> {
+        let rows = self
+            .session
+            .execute(&self.get_user_password_resets_statement, (user_id,))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?
+            .rows_typed::<(String, i64)>()
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?;
+
+        let mut password_resets = Vec::with_capacity(rows.len());
+        for (code_hash, expires_at) in rows {
+            let expires_at = u64::try_from(expires_at).map_err(|_| {
+                DatabaseError::CorruptRecord("negative password reset expiry".into())
+            })?;
+            if seconds_until(expires_at) > 0 {
+                password_resets.push(PasswordReset {
+                    code_hash,
+                    user_id,
+                    expires_at,
+                });
+            }
+        }
+        Ok(password_resets)
+    }
+
+    /// Removes the pending password resets of a user.
+    async fn delete_user_password_resets(&self, user_id: Uuid) -> Result<(), DatabaseError> {
+        for password_reset in self.get_user_password_resets(user_id).await? {
+            self.session
+                .execute(
+                    &self.delete_password_reset_statement,
+                    (password_reset.code_hash,),
+                )
+                .await
+                .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+        }
+        Ok(())
+    }
+
+    /// Selects the profile columns of a user, if the user exists.
+    async fn select_user(
+        &self,
+        statement: &PreparedStatement,
+        values: impl ValueList,
+    ) -> Result<Option<UserRow>, DatabaseError> {
+        self.session
+            .execute(statement, values)
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?
+            .maybe_first_row_typed::<UserRow>()
+            .map_err(|e| {
+                error!("malformed user row: {e}");
+                DatabaseError::CorruptRecord(e.to_string())
+            })
+    }
+}
+
+/// Converts the profile columns of a user to a profile.
+fn user_profile_from_row(
+    (id, email, display_name, created_at, last_login_at, metadata, roles, permissions, disabled): UserRow,
+) -> Result<UserProfile, DatabaseError> {
+    let metadata = match metadata {
+        Some(metadata) => serde_json::from_str::<Map<String, Value>>(&metadata)
+            .map_err(|e| DatabaseError::CorruptRecord(format!("malformed user metadata: {e}")))?,
+        None => Map::new(),
+    };
+
+    Ok(UserProfile {
+        id: id.ok_or_else(|| DatabaseError::CorruptRecord("user has no id".into()))?,
+        email,
+        display_name,
+        created_at: created_at.map(cql_to_unix_timestamp_millis).transpose()?,
+        last_login_at: last_login_at
+            .map(cql_to_unix_timestamp_millis)
+            .transpose()?,
+        metadata,
+        roles: roles.unwrap_or_default(),
+        permissions: permissions.unwrap_or_default(),
+        disabled: disabled.unwrap_or(false),
+    })
+}
+
+/// Converts the columns of an API key to a key, or to `None` if it has
+/// expired.
+fn api_key_from_row(
+    (key_id, key_hash, user_id, name, scopes, created_at, expires_at): ApiKeyRow,
+) -> Result<Option<ApiKey>, DatabaseError> {
+    let expires_at = expires_at
+        .map(u64::try_from)
+        .transpose()
+        .map_err(|_| DatabaseError::CorruptRecord("negative API key expiry".into()))?;
+    if expires_at.is_some_and(|expires_at| seconds_until(expires_at) <= 0) {
+        return Ok(None);
+    }
+
+    Ok(Some(ApiKey {
+        key_id,
+        key_hash,
+        user_id,
+        name,
+        scopes: scopes.unwrap_or_default(),
+        created_at: cql_to_unix_timestamp_millis(created_at)?,
+        expires_at,
+    }))
+}
+
+/// Returns whether a lightweight transaction was applied.
+fn lwt_applied(result: QueryResult) -> Result<bool, DatabaseError> {
+    result
+        .first_row()
+        .ok()
+        .and_then(|row| row.columns.into_iter().next().flatten())
+        .and_then(|value| value.as_boolean())
+        .ok_or_else(|| {
+            DatabaseError::CorruptRecord(
+                "lightweight transaction returned no [applied] column".into(),
+            )
+        })
+}
+
+#[async_trait]
+impl Database for ScyllaDbSession {
+    async fn try_add_user(&self, user: User) -> Result<(), DatabaseError> {
+        let password_hash = self.hash_password(user.password).await?;
+        let user_id = Uuid::new_v4();
+        let created_at = unix_timestamp_millis_to_cql(SystemTime::now())?;
+
+        // the e-mail is claimed first, so that only one user can register it
+        let result = self
+            .session
+            .execute(&self.claim_email_statement, (&user.email, user_id))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+        if !lwt_applied(result)? {
+            return Err(DatabaseError::Conflict);
+        }
+
+        if let Err(e) = self
+            .session
+            .execute(
+                &self.add_user_statement,
+                (
+                    user_id,
+                    &user.email,
+                    password_hash,
+                    created_at,
+                    vec![roles::DEFAULT_ROLE],
+                ),
+            )
+            .await
+        {
+            // free the e-mail again, so that registering it can be retried
+            if let Err(e) = self.release_email(&user.email, user_id).await {
+                warn!("failed to release e-mail of unregistered user: {e}");
+            }
+            return Err(DatabaseError::Unavailable(e.into()));
+        }
+
+        Ok(())
+    }
+
+    async fn validate_user(&self, user: &User) -> Result<(), DatabaseError> {
+        let stored_user = match self.get_user_id(&user.email).await? {
+            Some(user_id) => self
+                .session
+                .execute(&self.get_password_statement, (user_id,))
+                .await
+                .map_err(|e| DatabaseError::Unavailable(e.into()))?
+                .maybe_first_row_typed::<(String,)>()
+                .map_err(|e| {
+                    error!("malformed password row for user: {e}");
+                    DatabaseError::CorruptRecord(e.to_string())
+                })?
+                .map(|(password_hash,)| (user_id, password_hash)),
+            None => None,
+        };
+
+        // check unknown users against a dummy hash, so that they take as long
+        // to reject as existing users
+        let password_hasher = Arc::clone(&self.password_hasher);
+        let password = user.password.clone();
+        let stored_hash = stored_user.as_ref().map_or_else(
+            || self.dummy_password_hash.to_string(),
+            |(_, password_hash)| password_hash.clone(),
+        );
+        let matches = self
+            .hashing_pool
+            .run(move || password_hasher.verify(&password, &stored_hash))
+            .await
+            .map_err(DatabaseError::PasswordHashing)?
+            .map_err(|e| {
+                error!("malformed password_hash for user: {e}");
+                DatabaseError::CorruptRecord(e.to_string())
+            })?;
+        let Some((user_id, password_hash)) = stored_user else {
+            return Err(DatabaseError::NotFound);
+        };
+        if !matches {
+            return Err(DatabaseError::WrongPassword);
+        }
+
+        if self.password_hasher.needs_rehash(&password_hash) {
+            // failing to upgrade the hash does not affect the login itself
+            if let Err(e) = self
+                .rehash_password(user_id, user.password.clone(), password_hash)
+                .await
+            {
+                warn!("failed to upgrade password hash: {e}");
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn get_user(&self, user_id: Uuid) -> Result<UserProfile, DatabaseError> {
+        let row = self
+            .select_user(&self.get_user_statement, (user_id,))
+            .await?
+            .ok_or(DatabaseError::NotFound)?;
+        user_profile_from_row(row)
+    }
+
+    async fn get_user_by_email(&self, email: &str) -> Result<UserProfile, DatabaseError> {
+        let user_id = self
+            .get_user_id(email)
+            .await?
+            .ok_or(DatabaseError::NotFound)?;
+        self.get_user(user_id).await
+    }
+
+    async fn update_user_profile(
+        &self,
+        user_id: Uuid,
+        display_name: Option<&str>,
+        metadata: &Map<String, Value>,
+    ) -> Result<(), DatabaseError> {
+        let metadata = serde_json::to_string(metadata)
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?;
+
+        let result = self
+            .session
+            .execute(
+                &self.update_user_profile_statement,
+                (display_name, metadata, user_id),
+            )
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+
+        if lwt_applied(result)? {
+            Ok(())
+        } else {
+            Err(DatabaseError::NotFound)
+        }
+    }
+
+    async fn set_user_roles(
+        &self,
+        user_id: Uuid,
+        roles: &[String],
+        permissions: &[String],
+    ) -> Result<(), DatabaseError> {
+        let result = self
+            .session
+            .execute(
+                &self.set_user_roles_statement,
+                (roles, permissions, user_id),
+            )
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+
+        if lwt_applied(result)? {
+            Ok(())
+        } else {
+            Err(DatabaseError::NotFound)
+        }
+    }
+
+    async fn set_user_disabled(&self, user_id: Uuid, disabled: bool) -> Result<(), DatabaseError> {
+        let result = self
+            .session
+            .execute(&self.set_user_disabled_statement, (disabled, user_id))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+
+        if lwt_applied(result)? {
+            Ok(())
+        } else {
+            Err(DatabaseError::NotFound)
+        }
+    }
+
+    async fn list_users(
+        &self,
+        email_prefix: &str,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> Result<UserPage, DatabaseError> {
+        // users are listed in token order, and the cursor is the encoded paging
+        // state of the scan; e-mail prefixes are matched as rows are scanned
+        let limit = limit.max(1);
+        let mut paging_state = cursor
+            .map(|cursor| {
+                URL_SAFE_NO_PAD
+                    .decode(cursor)
+                    .map_err(|_| DatabaseError::NotFound)
+            })
+            .transpose()?
+            .map(Into::into);
+        let mut users = Vec::new();
+        let mut scanned = 0;
+
+        loop {
+            let mut statement = (*self.list_users_statement).clone();
+            let page_size = (limit - users.len()).min(MAX_LISTING_SCANNED_USERS - scanned);
+            statement.set_page_size(i32::try_from(page_size.max(1)).unwrap_or(i32::MAX));
+
+            let mut result = self
+                .session
+                .execute_paged(&statement, &[], paging_state)
+                .await
+                .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+            paging_state = result.paging_state.take();
+
+            let rows = result.rows_typed::<UserRow>().map_err(|e| {
+                error!("malformed user row: {e}");
+                DatabaseError::CorruptRecord(e.to_string())
+            })?;
+            for row in rows {
+                let row = row.map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?;
+                let profile = user_profile_from_row(row)?;
+                scanned += 1;
+                if profile.email.starts_with(email_prefix) {
+                    users.push(profile);
+                }
+            }
+
+            if paging_state.is_none()
+                || users.len() >= limit
+                || scanned >= MAX_LISTING_SCANNED_USERS
+            {
+                break;
+            }
+        }
+
+        Ok(UserPage {
+            users,
+            next_cursor: paging_state.map(|state| URL_SAFE_NO_PAD.encode(state)),
+        })
+    }
+
+    async fn set_last_login(
+        &self,
+        user_id: Uuid,
+        logged_in_at: SystemTime,
+    ) -> Result<(), DatabaseError> {
+        let result = self
+            .session
+            .execute(
+                &self.set_last_login_statement,
+                (unix_timestamp_millis_to_cql(logged_in_at)?, user_id),
+            )
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+
+        if lwt_applied(result)? {
+            Ok(())
+        } else {
+            Err(DatabaseError::NotFound)
+        }
+    }
+
+    async fn user_exists(&self, email: &str) -> Result<bool, DatabaseError> {
+        Ok(self.get_user_id(email).await?.is_some())
+    }
+
+    async fn is_user_verified(&self, email: &str) -> Result<bool, DatabaseError> {
+        let user_id = self
+            .get_user_id(email)
+            .await?
+            .ok_or(DatabaseError::NotFound)?;
+        let (verified,) = self
+            .session
+            .execute(&self.get_user_verified_statement, (user_id,))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?
+            .maybe_first_row_typed::<(Option<bool>,)>()
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?
+            .ok_or(DatabaseError::NotFound)?;
+
+        // users registered before e-mail verification was introduced have no
+        // verification state, and are treated as verified
+        Ok(verified.unwrap_or(true))
+    }
+
+    async fn set_user_verified(&self, email: &str) -> Result<(), DatabaseError> {
+        let user_id = self
+            .get_user_id(email)
+            .await?
+            .ok_or(DatabaseError::NotFound)?;
+        let result = self
+            .session
+            .execute(&self.set_user_verified_statement, (user_id,))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+
+        if lwt_applied(result)? {
+            Ok(())
+        } else {
+            Err(DatabaseError::NotFound)
+        }
+    }
+
+    async fn change_user_email(
+        &self,
+        user_id: Uuid,
+        old_email: &str,
+        new_email: &str,
+    ) -> Result<(), DatabaseError> {
+        self.get_user(user_id).await?;
+
+        // the new e-mail is claimed first, so that no other user can take it
+        // while the user's own e-mail is swapped
+        let result = self
+            .session
+            .execute(&self.claim_email_statement, (new_email, user_id))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+        if !lwt_applied(result)? {
+            return Err(DatabaseError::Conflict);
+        }
+
+        let applied = match self
+            .session
+            .execute(
+                &self.change_email_statement,
+                (new_email, user_id, old_email),
+            )
+            .await
+        {
+            Ok(result) => lwt_applied(result),
+            Err(e) => Err(DatabaseError::Unavailable(e.into())),
+        };
+        if !matches!(applied, Ok(true)) {
+            // the e-mail was changed concurrently, or the swap failed
+            if let Err(e) = self.release_email(new_email, user_id).await {
+                warn!("failed to release e-mail after failed change: {e}");
+            }
+            return applied.and(Err(DatabaseError::Conflict));
+        }
+
+        // the old e-mail stays claimed if this fails, so it cannot be
+        // registered again, but it no longer identifies the user
+        if let Err(e) = self.release_email(old_email, user_id).await {
+            warn!("failed to release previous e-mail of user: {e}");
+        }
+
+        self.delete_user_password_resets(user_id).await
+    }
+
+    async fn export_user(&self, user_id: Uuid) -> Result<UserData, DatabaseError> {
+        let profile = self.get_user(user_id).await?;
+
+        let (verified,) = self
+            .session
+            .execute(&self.get_user_verified_statement, (user_id,))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?
+            .maybe_first_row_typed::<(Option<bool>,)>()
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?
+            .ok_or(DatabaseError::NotFound)?;
+        let tokens_revoked_before = self
+            .session
+            .execute(&self.get_user_token_revocation_statement, (user_id,))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?
+            .maybe_first_row_typed::<(i64,)>()
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?
+            .map(|(revoked_before,)| cql_to_unix_timestamp_millis(revoked_before))
+            .transpose()?;
+
+        Ok(UserData {
+            email_verified: verified.unwrap_or(true),
+            refresh_tokens: self.get_user_refresh_tokens(user_id).await?,
+            password_resets: self.get_user_password_resets(user_id).await?,
+            api_keys: self.get_user_api_keys(user_id).await?,
+            tokens_revoked_before,
+            profile,
+        })
+    }
+
+    async fn delete_user(
+        &self,
+        user_id: Uuid,
+        tokens_expire_at: SystemTime,
+    ) -> Result<(), DatabaseError> {
+        let profile = self.get_user(user_id).await?;
+
+        // the tokens are revoked first, so that they are rejected even if
+        // deleting the user fails partway
+        let ttl = seconds_until(token::unix_timestamp(tokens_expire_at) + 1).max(1);
+        self.session
+            .execute(
+                &self.revoke_deleted_user_tokens_statement,
+                (
+                    user_id,
+                    unix_timestamp_millis_to_cql(SystemTime::now())?,
+                    ttl,
+                ),
+            )
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+
+        for refresh_token in self.get_user_refresh_tokens(user_id).await? {
+            self.revoke_refresh_token_family(&refresh_token.family_id)
+                .await?;
+        }
+        self.delete_user_password_resets(user_id).await?;
+        for api_key in self.get_user_api_keys(user_id).await? {
+            self.delete_api_key(user_id, &api_key.key_id).await?;
+        }
+
+        self.session
+            .execute(&self.delete_user_statement, (user_id,))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+        self.release_email(&profile.email, user_id).await
+    }
+
+    async fn update_password(&self, user_id: Uuid, password: String) -> Result<(), DatabaseError> {
+        let password_hash = self.hash_password(password).await?;
+
+        let result = self
+            .session
+            .execute(&self.update_password_statement, (password_hash, user_id))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+
+        if lwt_applied(result)? {
+            Ok(())
+        } else {
+            Err(DatabaseError::NotFound)
+        }
+    }
+
+    async fn add_refresh_token(&self, token: RefreshToken) -> Result<(), DatabaseError> {
+        let ttl = seconds_until(token.expires_at);
+        if ttl <= 0 {
+            return Ok(()); // already expired; nothing worth storing
+        }
+
+        self.session
+            .execute(
+                &self.add_refresh_token_statement,
+                (
+                    token.family_id,
+                    token.token_hash,
+                    token.user_id,
+                    unix_timestamp_to_cql(token.expires_at)?,
+                    token.consumed,
+                    ttl,
+                ),
+            )
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+
+        Ok(())
+    }
+
+    async fn consume_refresh_token(
+        &self,
+        family_id: &str,
+        token_hash: &str,
+    ) -> Result<RefreshToken, DatabaseError> {
+        let (user_id, expires_at, consumed) = self
+            .session
+            .execute(&self.get_refresh_token_statement, (family_id, token_hash))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?
+            .maybe_first_row_typed::<(Uuid, i64, bool)>()
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?
+            .ok_or(DatabaseError::NotFound)?;
+        let expires_at = u64::try_from(expires_at)
+            .map_err(|_| DatabaseError::CorruptRecord("negative refresh token expiry".into()))?;
+
+        let ttl = seconds_until(expires_at);
+        if ttl <= 0 {
+            return Err(DatabaseError::NotFound);
+        }
+        if consumed {
+            return Err(DatabaseError::Conflict);
+        }
+
+        let result = self
+            .session
+            .execute(
+                &self.consume_refresh_token_statement,
+                (ttl, family_id, token_hash),
+            )
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+        if !lwt_applied(result)? {
+            // consumed concurrently by another request
+            return Err(DatabaseError::Conflict);
+        }
+
+        Ok(RefreshToken {
+            family_id: family_id.to_string(),
+            token_hash: token_hash.to_string(),
+            user_id,
+            expires_at,
+            consumed: true,
+        })
+    }
+
+    async fn revoke_refresh_token_family(&self, family_id: &str) -> Result<(), DatabaseError> {
+        self.session
+            .execute(&self.revoke_refresh_token_family_statement, (family_id,))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+
+        Ok(())
+    }
+
+    async fn add_password_reset(&self, reset: PasswordReset) -> Result<(), DatabaseError> {
+        let ttl = seconds_until(reset.expires_at);
+        if ttl <= 0 {
+            return Ok(()); // already expired; nothing worth storing
+        }
+
+        self.session
+            .execute(
+                &self.add_password_reset_statement,
+                (
+                    reset.code_hash,
+                    reset.user_id,
+                    unix_timestamp_to_cql(reset.expires_at)?,
+                    ttl,
+                ),
+            )
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+
+        Ok(())
+    }
+
+    async fn consume_password_reset(
+        &self,
+        code_hash: &str,
+    ) -> Result<PasswordReset, DatabaseError> {
+        let (user_id, expires_at) = self
+            .session
+            .execute(&self.get_password_reset_statement, (code_hash,))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?
+            .maybe_first_row_typed::<(Uuid, i64)>()
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?
+            .ok_or(DatabaseError::NotFound)?;
+        let expires_at = u64::try_from(expires_at)
+            .map_err(|_| DatabaseError::CorruptRecord("negative password reset expiry".into()))?;
+        if seconds_until(expires_at) <= 0 {
+            return Err(DatabaseError::NotFound);
+        }
+
+        let result = self
+            .session
+            .execute(&self.consume_password_reset_statement, (code_hash,))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+        if !lwt_applied(result)? {
+            // used concurrently by another request
+            return Err(DatabaseError::NotFound);
+        }
+
+        Ok(PasswordReset {
+            code_hash: code_hash.to_string(),
+            user_id,
+            expires_at,
+        })
+    }
+
+    async fn add_api_key(&self, key: ApiKey) -> Result<(), DatabaseError> {
+        // a TTL of 0 stores the key until it is deleted
+        let ttl = match key.expires_at {
+            Some(expires_at) => {
+                let ttl = seconds_until(expires_at);
+                if ttl <= 0 {
+                    return Ok(()); // already expired; nothing worth storing
+                }
+                ttl
+            }
+            None => 0,
+        };
+
+        self.session
+            .execute(
+                &self.add_api_key_statement,
+                (
+                    key.key_id,
+                    key.key_hash,
+                    key.user_id,
+                    key.name,
+                    key.scopes,
+                    unix_timestamp_millis_to_cql(key.created_at)?,
+                    key.expires_at.map(unix_timestamp_to_cql).transpose()?,
+                    ttl,
+                ),
+            )
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+
+        Ok(())
+    }
+
+    async fn get_api_key(&self, key_id: &str) -> Result<ApiKey, DatabaseError> {
+        let row = self
+            .session
+            .execute(&self.get_api_key_statement, (key_id,))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?
+            .maybe_first_row_typed::<ApiKeyRow>()
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?
+            .ok_or(DatabaseError::NotFound)?;
+
+        api_key_from_row(row)?.ok_or(DatabaseError::NotFound)
+    }
+
+    async fn get_user_api_keys(&self, user_id: Uuid) -> Result<Vec<ApiKey>, DatabaseError> {
+        let rows = self
+            .session
+            .execute(&self.get_user_api_keys_statement, (user_id,))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?
+            .rows_typed::<ApiKeyRow>()
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?;
+
+        let mut api_keys = Vec::with_capacity(rows.len());
+        for row in rows {
+            api_keys.extend(api_key_from_row(row)?);
+        }
+        Ok(api_keys)
+    }
+
+    async fn delete_api_key(&self, user_id: Uuid, key_id: &str) -> Result<(), DatabaseError> {
+        let result = self
+            .session
+            .execute(&self.delete_api_key_statement, (key_id, user_id))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+
+        if lwt_applied(result)? {
+            Ok(())
+        } else {
+            Err(DatabaseError::NotFound)
+        }
+    }
+
+    async fn add_oauth_client(&self, client: OAuthClient) -> Result<(), DatabaseError> {
+        let result = self
+            .session
+            .execute(
+                &self.add_oauth_client_statement,
+                (
+                    client.client_id,
+                    client.secret_hash,
+                    client.scopes,
+                    client.redirect_uris,
+                    unix_timestamp_millis_to_cql(client.created_at)?,
+                ),
+            )
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+
+        if lwt_applied(result)? {
+            Ok(())
+        } else {
+            Err(DatabaseError::Conflict)
+        }
+    }
+
+    async fn get_oauth_client(&self, client_id: &str) -> Result<OAuthClient, DatabaseError> {
+        let (secret_hash, scopes, redirect_uris, created_at) = self
+            .session
+            .execute(&self.get_oauth_client_statement, (client_id,))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?
+            .maybe_first_row_typed::<(
+                Option<String>,
+                Option<Vec<String>>,
+                Option<Vec<String>>,
+                i64,
+            )>()
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?
+            .ok_or(DatabaseError::NotFound)?;
+
+        Ok(OAuthClient {
+            client_id: client_id.to_string(),
+            secret_hash,
+            scopes: scopes.unwrap_or_default(),
+            redirect_uris: redirect_uris.unwrap_or_default(),
+            created_at: cql_to_unix_timestamp_millis(created_at)?,
+        })
+    }
+
+    async fn add_authorization_code(&self, code: AuthorizationCode) -> Result<(), DatabaseError> {
+        let ttl = seconds_until(code.expires_at);
+        if ttl <= 0 {
+            return Ok(()); // already expired; nothing worth storing
+        }
+
+        self.session
+            .execute(
+                &self.add_authorization_code_statement,
+                (
+                    code.code_hash,
+                    code.client_id,
+                    code.user_id,
+                    code.redirect_uri,
+                    code.scopes,
+                    code.code_challenge,
+                    unix_timestamp_to_cql(code.expires_at)?,
+                    ttl,
+                ),
+            )
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+
+        Ok(())
+    }
+
+    async fn consume_authorization_code(
+        &self,
+        code_hash: &str,
+    ) -> Result<AuthorizationCode, DatabaseError> {
+        let (client_id, user_id, redirect_uri, scopes, code_challenge, expires_at) = self
+            .session
+            .execute(&self.get_authorization_code_statement, (code_hash,))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?
+            .maybe_first_row_typed::<(
+                String,
+                Uuid,
+                Option<String>,
+                Option<Vec<String>>,
+                String,
+                i64,
+            )>()
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?
+            .ok_or(DatabaseError::NotFound)?;
+        let expires_at = u64::try_from(expires_at).map_err(|_| {
+            DatabaseError::CorruptRecord("negative authorization code expiry".into())
+        })?;
+        if seconds_until(expires_at) <= 0 {
+            return Err(DatabaseError::NotFound);
+        }
+
+        let result = self
+            .session
+            .execute(&self.consume_authorization_code_statement, (code_hash,))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+        if !lwt_applied(result)? {
+            // exchanged concurrently by another request
+            return Err(DatabaseError::NotFound);
+        }
+
+        Ok(AuthorizationCode {
+            code_hash: code_hash.to_string(),
+            client_id,
+            user_id,
+            redirect_uri,
+            scopes: scopes.unwrap_or_default(),
+            code_challenge,
+            expires_at,
+        })
+    }
+
+    async fn get_login_failures(&self, key: &str) -> Result<Option<LoginFailures>, DatabaseError> {
+        let Some((count, last_failure)) = self
+            .session
+            .execute(&self.get_login_failures_statement, (key,))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?
+            .maybe_first_row_typed::<(i32, i64)>()
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(LoginFailures {
+            count: u32::try_from(count)
+                .map_err(|_| DatabaseError::CorruptRecord("negative login failure count".into()))?,
+            last_failure: cql_to_unix_timestamp_millis(last_failure)?,
+        }))
+    }
+
+    async fn record_login_failure(
+        &self,
+        key: &str,
+        failed_at: SystemTime,
+        retain_for: Duration,
+    ) -> Result<LoginFailures, DatabaseError> {
+        // counter columns cannot expire, so failures are counted in a regular
+        // table with a TTL, incremented by compare-and-set
+        let ttl = i32::try_from(retain_for.as_secs().max(1)).unwrap_or(i32::MAX);
+        let last_failure = unix_timestamp_millis_to_cql(failed_at)?;
+
+        for _ in 0..MAX_LOGIN_FAILURE_UPDATE_ATTEMPTS {
+            let count = self.get_login_failures(key).await?.map(|f| f.count);
+            let result = match count {
+                None => {
+                    self.session
+                        .execute(&self.add_login_failures_statement, (key, last_failure, ttl))
+                        .await
+                }
+                Some(count) => {
+                    let count = i32::try_from(count).unwrap_or(i32::MAX);
+                    self.session
+                        .execute(
+                            &self.update_login_failures_statement,
+                            (ttl, count.saturating_add(1), last_failure, key, count),
+                        )
+                        .await
+                }
+            }
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+
+            if lwt_applied(result)? {
+                return Ok(LoginFailures {
+                    count: count.unwrap_or(0).saturating_add(1),
+                    last_failure: failed_at,
+                });
+            }
+        }
+
+        warn!("could not count failed login due to concurrent updates");
+        Err(DatabaseError::Unavailable(
+            "too many concurrent updates of login failures".into(),
+        ))
+    }
+
+    async fn clear_login_failures(&self, key: &str) -> Result<(), DatabaseError> {
+        self.session
+            .execute(&self.clear_login_failures_statement, (key,))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+
+        Ok(())
+    }
+
+    async fn get_rate_limit_bucket(
+        &self,
+        key: &str,
+    ) -> Result<Option<RateLimitBucket>, DatabaseError> {
+        let Some((tokens, updated_at)) = self
+            .session
+            .execute(&self.get_rate_limit_bucket_statement, (key,))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?
+            .maybe_first_row_typed::<(f64, i64)>()
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?
+        else {
+            return Ok(None);
+        };
+
+        Ok(Some(RateLimitBucket {
+            tokens,
+            updated_at: cql_to_unix_timestamp_millis(updated_at)?,
+        }))
+    }
+
+    async fn replace_rate_limit_bucket(
+        &self,
+        key: &str,
+        expected: Option<&RateLimitBucket>,
+        bucket: RateLimitBucket,
+        retain_for: Duration,
+    ) -> Result<(), DatabaseError> {
+        let ttl = i32::try_from(retain_for.as_secs().max(1)).unwrap_or(i32::MAX);
+        let updated_at = unix_timestamp_millis_to_cql(bucket.updated_at)?;
+
+        let result = match expected {
+            None => {
+                self.session
+                    .execute(
+                        &self.add_rate_limit_bucket_statement,
+                        (key, bucket.tokens, updated_at, ttl),
+                    )
+                    .await
+            }
+            Some(expected) => {
+                self.session
+                    .execute(
+                        &self.update_rate_limit_bucket_statement,
+                        (
+                            ttl,
+                            bucket.tokens,
+                            updated_at,
+                            key,
+                            expected.tokens,
+                            unix_timestamp_millis_to_cql(expected.updated_at)?,
+                        ),
+                    )
+                    .await
+            }
+        }
+        .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+
+        if lwt_applied(result)? {
+            Ok(())
+        } else {
+            Err(DatabaseError::Conflict)
+        }
+    }
+
+    async fn revoke_token(
+        &self,
+        token_id: &str,
+        expires_at: SystemTime,
+    ) -> Result<(), DatabaseError> {
+        let ttl = seconds_until(token::unix_timestamp(expires_at) + 1);
+        if ttl <= 0 {
+            return Ok(()); // already expired; nothing worth storing
+        }
+
+        self.session
+            .execute(&self.revoke_token_statement, (token_id, ttl))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+
+        Ok(())
+    }
+
+    async fn revoke_user_tokens(
+        &self,
+        user_id: Uuid,
+        issued_before: SystemTime,
+        tokens_expire_at: SystemTime,
+    ) -> Result<(), DatabaseError> {
+        let ttl = seconds_until(token::unix_timestamp(tokens_expire_at) + 1).max(1);
+        self.session
+            .execute(
+                &self.revoke_user_tokens_statement,
+                (user_id, unix_timestamp_millis_to_cql(issued_before)?, ttl),
+            )
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?;
+
+        let family_ids = self
+            .session
+            .execute(&self.get_user_refresh_token_families_statement, (user_id,))
+            .await
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?
+            .rows_typed::<(String,)>()
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?;
+        for (family_id,) in family_ids {
+            self.revoke_refresh_token_family(&family_id).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn is_token_revoked(
+        &self,
+        token_id: &str,
+        user_id: Uuid,
+        issued_at: SystemTime,
+    ) -> Result<bool, DatabaseError> {
+        let (revoked_token, user_token_revocation) = join!(
+            self.session
+                .execute(&self.get_revoked_token_statement, (token_id,)),
+            self.session
+                .execute(&self.get_user_token_revocation_statement, (user_id,)),
+        );
+
+        let revoked_token = revoked_token
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?
+            .maybe_first_row()
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?;
+        if revoked_token.is_some() {
+            return Ok(true);
+        }
+
+        let revoked_before = user_token_revocation
+            .map_err(|e| DatabaseError::Unavailable(e.into()))?
+            .maybe_first_row_typed::<(i64,)>()
+            .map_err(|e| DatabaseError::CorruptRecord(e.to_string()))?;
+        Ok(match revoked_before {
+            Some((revoked_before,)) => unix_timestamp_millis_to_cql(issued_at)? < revoked_before,
+            None => false,
+        })
+    }
+}
+
+/// Returns the columns of a table in the `axum_api` keyspace along with their
+/// kinds, e.g. `partition_key`. Returns no columns if the table does not
+/// exist.
+async fn table_columns(
+    session: &Session,
+    table: &str,
+) -> Result<HashMap<String, String>, Box<dyn Error + Send + Sync>> {
+    Ok(session
+        .query(
+            "SELECT column_name, kind FROM system_schema.columns \
+            WHERE keyspace_name = 'axum_api' AND table_name = ?",
+            (table,),
+        )
+        .await?
+        .rows_typed::<(String, String)>()?
+        .collect::<Result<_, _>>()?)
+}
+
+/// Returns every row which a query returns, fetching them page by page.
+async fn all_rows<R: FromRow>(
+    session: &Session,
+    query: &str,
+) -> Result<Vec<R>, Box<dyn Error + Send + Sync>> {
+    let mut statement = session.prepare(query).await?;
+    statement.set_page_size(1000);
+
+    let mut rows = Vec::new();
+    let mut paging_state = None;
+    loop {
+        let mut result = session.execute_paged(&statement, &[], paging_state).await?;
+        paging_state = result.paging_state.take();
+        for row in result.rows_typed::<R>()? {
+            rows.push(row?);
+        }
+
+        if paging_state.is_none() {
+            return Ok(rows);
+        }
+    }
+}
+
+/// Converts a point in time to milliseconds since the unix epoch, as a CQL
+/// `BIGINT`.
+fn unix_timestamp_millis_to_cql(time: SystemTime) -> Result<i64, DatabaseError> {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .ok()
+        .and_then(|duration| i64::try_from(duration.as_millis()).ok())
+        .ok_or_else(|| DatabaseError::CorruptRecord("timestamp out of range".into()))
+}
+
+/// Converts milliseconds since the unix epoch, as a CQL `BIGINT`, to a point
+/// in time.
+fn cql_to_unix_timestamp_millis(millis: i64) -> Result<SystemTime, DatabaseError> {
+    u64::try_from(millis)
+        .map(|millis| SystemTime::UNIX_EPOCH + Duration::from_millis(millis))
+        .map_err(|_| DatabaseError::CorruptRecord("timestamp out of range".into()))
+}
+
+/// Returns the number of seconds from now until the given unix timestamp,
+/// which may be negative if the timestamp is in the past.
+fn seconds_until(unix_timestamp: u64) -> i32 {
+    let now = token::unix_timestamp(SystemTime::now());
+    let seconds = i128::from(unix_timestamp) - i128::from(now);
+    i32::try_from(seconds).unwrap_or(if seconds > 0 { i32::MAX } else { i32::MIN })
+}
+
+/// Converts a unix timestamp to a CQL `BIGINT`.
+fn unix_timestamp_to_cql(unix_timestamp: u64) -> Result<i64, DatabaseError> {
+    i64::try_from(unix_timestamp)
+        .map_err(|_| DatabaseError::CorruptRecord("timestamp out of range".into()))
+}
@@ -4,9 +4,18 @@
 //! given, which allows for easily running a simple server that reroutes all API
 //! endpoints under `/api`.
 mod api;
+pub mod auth;
 pub mod database;
+pub mod mail;
+pub mod oauth;
+pub mod password;
+pub mod rate_limit;
+pub mod roles;
 mod server_state;
+pub mod throttling;
 pub mod token;
+pub mod validation;
+pub mod verification;
 
-pub use api::create_api_router;
+pub use api::{create_admin_router, create_api_router, create_well_known_router};
 pub use server_state::ServerState;
@@ -0,0 +1,162 @@
+//! Sending e-mail.
+
+use axum::async_trait;
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Tokio1Executor,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+    path::PathBuf,
+};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt, sync::Mutex};
+
+/// An e-mail message.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Mail {
+    /// Address of the recipient.
+    pub to: String,
+    pub subject: String,
+
+    /// Plain text body.
+    pub body: String,
+}
+
+/// Trait for types which deliver e-mail.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    /// Sends a message.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message cannot be delivered.
+    async fn send(&self, mail: Mail) -> Result<(), MailError>;
+}
+
+/// An error which occurred while sending e-mail.
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub enum MailError {
+    /// The recipient address is not a valid e-mail address.
+    InvalidAddress(String),
+
+    /// The message could not be handed to the transport.
+    Transport(Box<dyn Error + Send + Sync>),
+}
+
+impl Display for MailError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidAddress(address) => write!(f, "invalid e-mail address: {address}"),
+            Self::Transport(error) => write!(f, "could not send e-mail: {error}"),
+        }
+    }
+}
+
+impl Error for MailError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Transport(error) => Some(error.as_ref()),
+            Self::InvalidAddress(_) => None,
+        }
+    }
+}
+
+/// A mailer which sends e-mail through an SMTP relay.
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    /// Creates a mailer which connects to an SMTP relay over TLS, and
+    /// authenticates with the given credentials, if any. Messages are sent
+    /// from the address `from`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `from` is not a valid address, or the relay
+    /// transport cannot be created.
+    pub fn new(
+        host: &str,
+        port: u16,
+        credentials: Option<(String, String)>,
+        from: &str,
+    ) -> Result<Self, MailError> {
+        let mut transport = AsyncSmtpTransport::<Tokio1Executor>::relay(host)
+            .map_err(|e| MailError::Transport(e.into()))?
+            .port(port);
+        if let Some((username, password)) = credentials {
+            transport = transport.credentials(Credentials::new(username, password));
+        }
+
+        Ok(Self {
+            transport: transport.build(),
+            from: from
+                .parse()
+                .map_err(|_| MailError::InvalidAddress(from.to_string()))?,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, mail: Mail) -> Result<(), MailError> {
+        let message = lettre::Message::builder()
+            .from(self.from.clone())
+            .to(mail
+                .to
+                .parse()
+                .map_err(|_| MailError::InvalidAddress(mail.to.clone()))?)
+            .subject(mail.subject)
+            .body(mail.body)
+            .map_err(|e| MailError::Transport(e.into()))?;
+
+        self.transport
+            .send(message)
+            .await
+            .map_err(|e| MailError::Transport(e.into()))?;
+        Ok(())
+    }
+}
+
+/// A mailer which appends messages to a file instead of delivering them, one
+/// JSON-encoded [`Mail`] per line.
+///
+/// Useful for development and testing.
+pub struct FileMailer {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileMailer {
+    /// Creates a mailer which appends messages to the file at `path`,
+    /// creating it if necessary.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: Mail) -> Result<(), MailError> {
+        let mut line = serde_json::to_string(&mail).map_err(|e| MailError::Transport(e.into()))?;
+        line.push('\n');
+
+        let _guard = self.lock.lock().await;
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .map_err(|e| MailError::Transport(e.into()))?
+            .write_all(line.as_bytes())
+            .await
+            .map_err(|e| MailError::Transport(e.into()))
+    }
+}
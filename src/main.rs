@@ -1,8 +1,25 @@
-use axum::Router;
-use axum_api::{create_api_router, database::ScyllaDbSession, token::TokenManager, ServerState};
+use axum::{body::Body, middleware, Router};
+use axum_api::{
+    create_admin_router, create_api_router, create_well_known_router,
+    database::{Database, ScyllaDbSession},
+    mail::{FileMailer, Mailer, SmtpMailer},
+    password::{BreachedPasswords, HashingPool, PasswordHashingConfig, PasswordPolicy},
+    rate_limit::{
+        rate_limit, DatabaseRateLimitStore, MemoryRateLimitStore, Quota, RateLimitKey, RateLimiting,
+    },
+    roles::ADMIN_ROLE,
+    throttling::{FailureLimits, LoginThrottling},
+    token::{self, KeyFamily, SigningKey, TokenManager, VerificationKey},
+    validation,
+    verification::{EmailVerification, UnverifiedLogin},
+    ServerState,
+};
 use clap::Parser;
 use serde::{Deserialize, Serialize};
-use std::{error::Error, fs, str::FromStr, time::Duration};
+use std::{
+    collections::HashMap, error::Error, fs, net::SocketAddr, num::NonZeroUsize, str::FromStr,
+    sync::Arc, time::Duration,
+};
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::EnvFilter;
 
@@ -16,6 +33,70 @@ struct Arguments {
     /// it already exists.
     #[arg(short, long)]
     generate_config: bool,
+
+    /// Rotate the signing key in the config file and exit. The current signing
+    /// key is retired, but still used to verify tokens it signed. Takes the
+    /// signing algorithm of the new key.
+    #[arg(long, value_name = "SIGNING_ALGORITHM")]
+    rotate_key: Option<String>,
+
+    /// Path to the secret of the new signing key, for an HMAC algorithm
+    #[arg(long, requires = "rotate_key")]
+    secret_path: Option<String>,
+
+    /// Path to the private key of the new signing key, for an asymmetric
+    /// algorithm
+    #[arg(long, requires = "rotate_key", requires = "public_key_path")]
+    private_key_path: Option<String>,
+
+    /// Path to the public key of the new signing key, for an asymmetric
+    /// algorithm
+    #[arg(long, requires = "rotate_key", requires = "private_key_path")]
+    public_key_path: Option<String>,
+
+    /// Key id of the new signing key. Defaults to an id derived from the key.
+    #[arg(long, requires = "rotate_key")]
+    key_id: Option<String>,
+
+    /// Migrate users stored by an earlier version, which are keyed by e-mail,
+    /// to users keyed by id and exit. Create the tables of
+    /// `resources/init_database.cql` first.
+    #[arg(long)]
+    migrate: bool,
+
+    /// Grant the admin role to the registered user with the given e-mail
+    /// address and exit. Their tokens include the role once refreshed.
+    #[arg(long, value_name = "EMAIL")]
+    grant_admin: Option<String>,
+
+    /// Register an OAuth client with the given client id, print its secret,
+    /// unless it is a public client, and exit
+    #[arg(long, value_name = "CLIENT_ID")]
+    register_client: Option<String>,
+
+    /// Comma-separated scopes which the new OAuth client may be granted
+    #[arg(
+        long,
+        value_name = "SCOPES",
+        value_delimiter = ',',
+        requires = "register_client"
+    )]
+    client_scopes: Vec<String>,
+
+    /// Comma-separated URIs which the new OAuth client may redirect users to
+    /// with authorization codes
+    #[arg(
+        long,
+        value_name = "URIS",
+        value_delimiter = ',',
+        requires = "register_client"
+    )]
+    redirect_uris: Vec<String>,
+
+    /// Register the new OAuth client as a public client without a secret,
+    /// such as a single-page application
+    #[arg(long, requires = "register_client")]
+    public_client: bool,
 }
 
 /// Server config
@@ -27,18 +108,479 @@ struct Config {
     /// Hosts which the ScyllaDB instance is listening on.
     database_hosts: Vec<String>,
 
+    /// Algorithm and parameters used to hash passwords. Passwords hashed
+    /// differently are rehashed the next time their user logs in.
+    #[serde(default)]
+    password_hashing: PasswordHashingConfig,
+
+    /// Pool of threads which passwords are hashed on. Requests needing a
+    /// password hashed are rejected with `503 Service Unavailable` while its
+    /// queue is full.
+    #[serde(default)]
+    hashing_pool: HashingPoolConfig,
+
+    /// Rules which passwords must follow when registering or changing them.
+    /// Only non-empty passwords are required if not configured.
+    #[serde(default)]
+    password_policy: PasswordPolicy,
+
+    /// List of breached passwords, which new passwords must not be in.
+    breached_passwords: Option<BreachedPasswordsConfig>,
+
+    /// Throttling of failed logins per account and per client IP address.
+    /// Failed logins are not throttled if not configured.
+    login_throttling: Option<LoginThrottlingConfig>,
+
+    /// Rate limiting of requests to API endpoints. Requests are not rate
+    /// limited if not configured.
+    rate_limiting: Option<RateLimitingConfig>,
+
     /// Lifetime of an API token in seconds
     lifetime: u64,
 
     /// Leeway for lifetime checks of API tokens in seconds
     lifetime_leeway: u64,
 
+    /// Lifetime of a refresh token in seconds
+    #[serde(default = "default_refresh_lifetime")]
+    refresh_lifetime: u64,
+
+    /// Lifetime of a password reset code in seconds
+    #[serde(default = "default_password_reset_lifetime")]
+    password_reset_lifetime: u64,
+
+    /// Mailer used to send e-mail to users. Password resets are unavailable
+    /// if none is configured.
+    mailer: Option<MailerConfig>,
+
+    /// E-mail verification of newly registered users, which requires a mailer.
+    /// Users do not need to verify their e-mail address if not configured.
+    email_verification: Option<EmailVerificationConfig>,
+
     /// Algorithm used to sign JSON web tokens. Must be supported by the
     /// `jsonwebtoken` crate.
     signing_algorithm: String,
 
-    /// Path to a file containing the secret used for JSON web token generation.
+    /// Path to a file containing the secret used for JSON web token generation
+    /// with an HMAC algorithm (`HS*`).
     secret_path: String,
+
+    /// Paths to the key pair used with an RSA algorithm (`RS*` or `PS*`).
+    rsa_key: Option<KeyPairPaths>,
+
+    /// Paths to the key pair used with an elliptic curve algorithm (`ES*`).
+    ec_key: Option<KeyPairPaths>,
+
+    /// Paths to the key pair used with the `EdDSA` algorithm.
+    ed25519_key: Option<KeyPairPaths>,
+
+    /// Key id of the signing key, sent in the header of each JSON web token.
+    /// Defaults to an id derived from the key.
+    key_id: Option<String>,
+
+    /// Keys which are no longer used to sign JSON web tokens, but still used
+    /// to verify tokens signed before they were retired.
+    #[serde(default)]
+    retired_keys: Vec<RetiredKey>,
+}
+
+/// Lifetime of a refresh token in seconds, for config files from before
+/// refresh tokens were introduced.
+fn default_refresh_lifetime() -> u64 {
+    TokenManager::DEFAULT_REFRESH_LIFETIME.as_secs()
+}
+
+/// Lifetime of a password reset code in seconds, for config files from
+/// before password resets were introduced.
+fn default_password_reset_lifetime() -> u64 {
+    TokenManager::DEFAULT_PASSWORD_RESET_LIFETIME.as_secs()
+}
+
+/// Configuration of a mailer.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum MailerConfig {
+    /// Send e-mail through an SMTP relay over TLS.
+    Smtp {
+        host: String,
+        port: u16,
+
+        /// Username to authenticate with, if the relay requires it.
+        username: Option<String>,
+
+        /// Path to a file containing the password to authenticate with.
+        password_path: Option<String>,
+
+        /// Address which e-mail is sent from.
+        from: String,
+    },
+
+    /// Append e-mail to a file as JSON lines instead of sending it.
+    File { path: String },
+}
+
+impl MailerConfig {
+    /// Creates a mailer according to the configuration.
+    fn create_mailer(&self) -> Result<Arc<dyn Mailer>, Box<dyn Error + Send + Sync>> {
+        Ok(match self {
+            Self::Smtp {
+                host,
+                port,
+                username,
+                password_path,
+                from,
+            } => {
+                let credentials = match (username, password_path) {
+                    (Some(username), Some(password_path)) => Some((
+                        username.clone(),
+                        fs::read_to_string(password_path)?.trim_end().to_string(),
+                    )),
+                    (None, None) => None,
+                    _ => {
+                        return Err("username and password_path must be configured together".into())
+                    }
+                };
+                Arc::new(SmtpMailer::new(host, *port, credentials, from)?)
+            }
+            Self::File { path } => Arc::new(FileMailer::new(path)),
+        })
+    }
+}
+
+/// Configuration of e-mail verification.
+#[derive(Serialize, Deserialize)]
+struct EmailVerificationConfig {
+    /// Whether users who have not verified their e-mail address can log in
+    /// (`allow`), can only log in with restricted tokens (`restrict`), or
+    /// cannot log in (`refuse`).
+    unverified_login: UnverifiedLogin,
+
+    /// URL of the `/verify` endpoint, which verification links point to.
+    link_url: String,
+
+    /// Lifetime of a verification link in seconds
+    lifetime: u64,
+}
+
+/// Configuration of the password hashing pool.
+#[derive(Serialize, Deserialize)]
+struct HashingPoolConfig {
+    /// Number of passwords hashed at a time. Defaults to the number of
+    /// available CPUs.
+    workers: Option<usize>,
+
+    /// Number of passwords which may wait to be hashed, per worker.
+    queued_per_worker: usize,
+}
+
+impl HashingPoolConfig {
+    fn create_hashing_pool(&self) -> Result<HashingPool, Box<dyn Error + Send + Sync>> {
+        let workers = match self.workers {
+            Some(0) => return Err("hashing_pool must have at least one worker".into()),
+            Some(workers) => workers,
+            None => std::thread::available_parallelism().map_or(1, NonZeroUsize::get),
+        };
+
+        Ok(HashingPool::new(
+            workers,
+            workers.saturating_mul(self.queued_per_worker),
+        ))
+    }
+}
+
+impl Default for HashingPoolConfig {
+    fn default() -> Self {
+        Self {
+            workers: None,
+            queued_per_worker: 16,
+        }
+    }
+}
+
+/// Configuration of a list of breached passwords.
+#[derive(Serialize, Deserialize)]
+struct BreachedPasswordsConfig {
+    /// Path to the list, in the format of Have I Been Pwned's downloadable
+    /// list of SHA-1 hashes ordered by hash.
+    path: String,
+
+    /// Minimum number of times a password must occur in breaches to be
+    /// rejected.
+    min_occurrences: u64,
+}
+
+/// Configuration of login throttling.
+#[derive(Serialize, Deserialize)]
+struct LoginThrottlingConfig {
+    /// Limits on failed logins to a single account.
+    per_account: FailureLimitsConfig,
+
+    /// Limits on failed logins from a single client IP address.
+    per_ip: FailureLimitsConfig,
+}
+
+impl LoginThrottlingConfig {
+    fn create_login_throttling(&self) -> LoginThrottling {
+        LoginThrottling {
+            per_account: self.per_account.create_failure_limits(),
+            per_ip: self.per_ip.create_failure_limits(),
+        }
+    }
+}
+
+/// Configuration of the limits on failed logins for a single account or
+/// client.
+#[derive(Serialize, Deserialize)]
+struct FailureLimitsConfig {
+    /// Number of failed logins before further attempts are delayed.
+    free_failures: u32,
+
+    /// Delay after the first delayed failure in seconds, which is doubled with
+    /// each further failure.
+    base_delay: u64,
+
+    /// Maximum delay between attempts in seconds.
+    max_delay: u64,
+
+    /// Number of failed logins which lock out further attempts. Logins are
+    /// never locked out if not configured.
+    lockout_failures: Option<u32>,
+
+    /// Duration of a lockout in seconds.
+    lockout_duration: u64,
+
+    /// Time in seconds after the latest failed login after which failures
+    /// are no longer counted.
+    retention: u64,
+}
+
+impl FailureLimitsConfig {
+    fn create_failure_limits(&self) -> FailureLimits {
+        FailureLimits {
+            free_failures: self.free_failures,
+            base_delay: Duration::from_secs(self.base_delay),
+            max_delay: Duration::from_secs(self.max_delay),
+            lockout_failures: self.lockout_failures,
+            lockout_duration: Duration::from_secs(self.lockout_duration),
+            retention: Duration::from_secs(self.retention),
+        }
+    }
+}
+
+/// Configuration of rate limiting.
+#[derive(Serialize, Deserialize)]
+struct RateLimitingConfig {
+    /// Where token buckets are stored.
+    store: RateLimitStoreConfig,
+
+    /// Quota for endpoints without a quota of their own. Such endpoints are
+    /// not rate limited if not configured.
+    default_quota: Option<QuotaConfig>,
+
+    /// Quotas by endpoint path, relative to `/api`, e.g. `/login`.
+    #[serde(default)]
+    route_quotas: HashMap<String, QuotaConfig>,
+}
+
+impl RateLimitingConfig {
+    fn create_rate_limiting(&self, database: &ScyllaDbSession) -> RateLimiting {
+        let mut rate_limiting = RateLimiting::new(match self.store {
+            RateLimitStoreConfig::Memory => Arc::new(MemoryRateLimitStore::new()),
+            RateLimitStoreConfig::Database => {
+                Arc::new(DatabaseRateLimitStore::new(database.clone()))
+            }
+        });
+        if let Some(quota) = &self.default_quota {
+            rate_limiting = rate_limiting.with_default_quota(quota.create_quota());
+        }
+        for (route, quota) in &self.route_quotas {
+            rate_limiting = rate_limiting.with_route_quota(route, quota.create_quota());
+        }
+
+        rate_limiting
+    }
+}
+
+/// Configuration of where token buckets of rate limits are stored.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum RateLimitStoreConfig {
+    /// In memory, so that limits only hold for a single server.
+    Memory,
+
+    /// In the database, so that limits hold across every server sharing it.
+    Database,
+}
+
+/// Configuration of a rate limit quota.
+#[derive(Serialize, Deserialize)]
+struct QuotaConfig {
+    /// Number of requests which can be made at once.
+    capacity: u32,
+
+    /// Period in seconds in which the full capacity is refilled.
+    period: u64,
+
+    /// What clients are told apart by: their IP address (`ip`), the user of
+    /// their API token (`user`) or their API key (`api_key`).
+    #[serde(default)]
+    key: RateLimitKey,
+}
+
+impl QuotaConfig {
+    fn create_quota(&self) -> Quota {
+        Quota {
+            capacity: self.capacity,
+            period: Duration::from_secs(self.period),
+            key: self.key,
+        }
+    }
+}
+
+/// Paths to the files of a PEM-encoded key pair.
+#[derive(Serialize, Deserialize)]
+struct KeyPairPaths {
+    /// Path to the private key, in PKCS#8 format.
+    private_key_path: String,
+
+    /// Path to the public key.
+    public_key_path: String,
+}
+
+/// A key which is only used to verify JSON web tokens.
+#[derive(Serialize, Deserialize)]
+struct RetiredKey {
+    /// Key id of the key.
+    key_id: String,
+
+    /// Algorithm which the key was used to sign tokens with.
+    signing_algorithm: String,
+
+    /// Path to the secret for an HMAC algorithm, or otherwise to the public
+    /// key.
+    key_path: String,
+}
+
+impl Config {
+    /// Creates a token manager with the configured keys.
+    fn create_token_manager(&self) -> Result<TokenManager, Box<dyn Error + Send + Sync>> {
+        let mut token_manager = TokenManager::new(
+            Duration::from_secs(self.lifetime),
+            Duration::from_secs(self.lifetime_leeway),
+            self.load_signing_key()?,
+        );
+        *token_manager.refresh_lifetime_mut() = Duration::from_secs(self.refresh_lifetime);
+        *token_manager.password_reset_lifetime_mut() =
+            Duration::from_secs(self.password_reset_lifetime);
+        if let Some(email_verification) = &self.email_verification {
+            *token_manager.email_verification_lifetime_mut() =
+                Duration::from_secs(email_verification.lifetime);
+        }
+
+        for retired_key in &self.retired_keys {
+            let algorithm = jsonwebtoken::Algorithm::from_str(&retired_key.signing_algorithm)?;
+            let key_data = fs::read(&retired_key.key_path)?;
+            let key = match KeyFamily::of(algorithm) {
+                KeyFamily::Hmac => VerificationKey::from_secret(algorithm, &key_data)?,
+                _ => VerificationKey::from_pem(algorithm, &key_data)?,
+            };
+            token_manager.add_retired_key(key.with_key_id(&retired_key.key_id))?;
+        }
+
+        Ok(token_manager)
+    }
+
+    /// Loads the signing key for the configured signing algorithm.
+    fn load_signing_key(&self) -> Result<SigningKey, Box<dyn Error + Send + Sync>> {
+        let algorithm = jsonwebtoken::Algorithm::from_str(self.signing_algorithm.as_str())?;
+
+        let signing_key = match KeyFamily::of(algorithm) {
+            KeyFamily::Hmac => SigningKey::from_secret(algorithm, &fs::read(&self.secret_path)?)?,
+            _ => {
+                let key_pair = self.key_pair_paths(algorithm)?;
+                SigningKey::from_pem(
+                    algorithm,
+                    &fs::read(&key_pair.private_key_path)?,
+                    &fs::read(&key_pair.public_key_path)?,
+                )?
+            }
+        };
+
+        Ok(match &self.key_id {
+            Some(key_id) => signing_key.with_key_id(key_id),
+            None => signing_key,
+        })
+    }
+
+    /// Returns the configured key pair paths for an asymmetric algorithm.
+    fn key_pair_paths(
+        &self,
+        algorithm: jsonwebtoken::Algorithm,
+    ) -> Result<&KeyPairPaths, Box<dyn Error + Send + Sync>> {
+        let (key_pair, key_name) = match KeyFamily::of(algorithm) {
+            KeyFamily::Hmac => return Err(format!("{algorithm:?} does not use a key pair").into()),
+            KeyFamily::Rsa => (&self.rsa_key, "rsa_key"),
+            KeyFamily::Ec => (&self.ec_key, "ec_key"),
+            KeyFamily::Ed25519 => (&self.ed25519_key, "ed25519_key"),
+        };
+
+        Ok(key_pair.as_ref().ok_or_else(|| {
+            format!("signing algorithm {algorithm:?} requires {key_name} to be configured")
+        })?)
+    }
+
+    /// Retires the current signing key and configures a new one, as given by
+    /// the command line arguments.
+    fn rotate_key(&mut self, arguments: &Arguments) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let current_algorithm = jsonwebtoken::Algorithm::from_str(&self.signing_algorithm)?;
+        let current_key_path = match KeyFamily::of(current_algorithm) {
+            KeyFamily::Hmac => self.secret_path.clone(),
+            _ => self
+                .key_pair_paths(current_algorithm)?
+                .public_key_path
+                .clone(),
+        };
+        self.retired_keys.push(RetiredKey {
+            key_id: self.load_signing_key()?.key_id().to_string(),
+            signing_algorithm: self.signing_algorithm.clone(),
+            key_path: current_key_path,
+        });
+
+        let algorithm = jsonwebtoken::Algorithm::from_str(
+            arguments
+                .rotate_key
+                .as_ref()
+                .expect("no key rotation requested"),
+        )?;
+        self.signing_algorithm = format!("{algorithm:?}");
+        self.key_id = arguments.key_id.clone();
+        if KeyFamily::of(algorithm) == KeyFamily::Hmac {
+            self.secret_path = arguments
+                .secret_path
+                .clone()
+                .ok_or("--secret-path is required for an HMAC algorithm")?;
+        } else {
+            let key_pair = Some(KeyPairPaths {
+                private_key_path: arguments
+                    .private_key_path
+                    .clone()
+                    .ok_or("--private-key-path is required for an asymmetric algorithm")?,
+                public_key_path: arguments
+                    .public_key_path
+                    .clone()
+                    .ok_or("--public-key-path is required for an asymmetric algorithm")?,
+            });
+            match KeyFamily::of(algorithm) {
+                KeyFamily::Rsa => self.rsa_key = key_pair,
+                KeyFamily::Ec => self.ec_key = key_pair,
+                _ => self.ed25519_key = key_pair,
+            }
+        }
+
+        // make sure the resulting config is usable
+        self.create_token_manager()?;
+        Ok(())
+    }
 }
 
 impl Default for Config {
@@ -46,14 +588,135 @@ impl Default for Config {
         Self {
             server_host: "127.0.0.1:3000".to_string(),
             database_hosts: vec!["127.0.0.1:9042".to_string()],
+            password_hashing: PasswordHashingConfig::default(),
+            hashing_pool: HashingPoolConfig::default(),
+            password_policy: PasswordPolicy {
+                min_length: 10,
+                max_bytes: Some(72),
+                require_lowercase: true,
+                require_uppercase: true,
+                require_digit: true,
+                require_symbol: false,
+                reject_email_local_part: true,
+                min_strength: Some(3),
+            },
+            breached_passwords: None,
+            login_throttling: Some(LoginThrottlingConfig {
+                per_account: FailureLimitsConfig {
+                    free_failures: 5,
+                    base_delay: 1,
+                    max_delay: 60,
+                    lockout_failures: Some(10),
+                    lockout_duration: 15 * 60,
+                    retention: 60 * 60,
+                },
+                per_ip: FailureLimitsConfig {
+                    free_failures: 20,
+                    base_delay: 1,
+                    max_delay: 60,
+                    lockout_failures: Some(100),
+                    lockout_duration: 15 * 60,
+                    retention: 60 * 60,
+                },
+            }),
+            rate_limiting: Some(RateLimitingConfig {
+                store: RateLimitStoreConfig::Memory,
+                default_quota: Some(QuotaConfig {
+                    capacity: 60,
+                    period: 60,
+                    key: RateLimitKey::User,
+                }),
+                route_quotas: HashMap::from(
+                    [
+                        ("/register", 5),
+                        ("/login", 10),
+                        ("/password/forgot", 5),
+                        ("/verify/resend", 5),
+                    ]
+                    .map(|(route, capacity)| {
+                        (
+                            route.to_string(),
+                            QuotaConfig {
+                                capacity,
+                                period: 60,
+                                key: RateLimitKey::Ip,
+                            },
+                        )
+                    }),
+                ),
+            }),
             lifetime: 600,
             lifetime_leeway: 30,
+            refresh_lifetime: default_refresh_lifetime(),
+            password_reset_lifetime: default_password_reset_lifetime(),
+            mailer: Some(MailerConfig::File {
+                path: "resources/outbox.jsonl".to_string(),
+            }),
+            email_verification: Some(EmailVerificationConfig {
+                unverified_login: UnverifiedLogin::Refuse,
+                link_url: "http://127.0.0.1:3000/api/verify".to_string(),
+                lifetime: 24 * 60 * 60,
+            }),
             signing_algorithm: "HS256".to_string(),
             secret_path: "resources/secret".to_string(),
+            rsa_key: None,
+            ec_key: None,
+            ed25519_key: None,
+            key_id: None,
+            retired_keys: Vec::new(),
         }
     }
 }
 
+/// Adds the admin role to the roles of the user with the given e-mail address.
+async fn grant_admin(
+    database: &ScyllaDbSession,
+    email: &str,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let email = validation::normalize_email(email)?;
+    let profile = database.get_user_by_email(&email).await?;
+
+    let mut roles = profile.roles;
+    roles.push(ADMIN_ROLE.to_string());
+    roles.sort_unstable();
+    roles.dedup();
+    database
+        .set_user_roles(profile.id, &roles, &profile.permissions)
+        .await?;
+
+    Ok(())
+}
+
+/// Registers an OAuth client as given by the arguments, returning its secret
+/// unless it is a public client.
+async fn register_client(
+    database: &ScyllaDbSession,
+    arguments: &Arguments,
+    client_id: &str,
+) -> Result<Option<String>, Box<dyn Error + Send + Sync>> {
+    validation::check_client_id(client_id)?;
+    let scopes = validation::normalize_grant_names(&arguments.client_scopes)?;
+    for redirect_uri in &arguments.redirect_uris {
+        validation::check_redirect_uri(redirect_uri)
+            .map_err(|e| format!("redirect URI {redirect_uri} {e}"))?;
+    }
+    if arguments.public_client && arguments.redirect_uris.is_empty() {
+        return Err("public clients need at least one redirect URI".into());
+    }
+
+    let (secret, client) = if arguments.public_client {
+        let client = token::new_public_oauth_client(client_id, &scopes, &arguments.redirect_uris);
+        (None, client)
+    } else {
+        let (secret, client) =
+            token::new_oauth_client(client_id, &scopes, &arguments.redirect_uris);
+        (Some(secret), client)
+    };
+    database.add_oauth_client(client).await?;
+
+    Ok(secret)
+}
+
 /// Runs a simple server which merely reroutes requests to /api to the API
 /// router.
 #[tokio::main]
@@ -73,23 +736,83 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         return Ok(());
     }
 
-    let config = serde_json::from_slice::<Config>(&fs::read(&arguments.config_file)?)?;
+    let mut config = serde_json::from_slice::<Config>(&fs::read(&arguments.config_file)?)?;
+
+    if arguments.rotate_key.is_some() {
+        config.rotate_key(&arguments)?;
+        fs::write(
+            &arguments.config_file,
+            serde_json::to_string_pretty(&config)?,
+        )?;
+
+        return Ok(());
+    }
+
+    if arguments.migrate {
+        let migrated = ScyllaDbSession::migrate(&config.database_hosts).await?;
+        println!("migrated {migrated} users");
+
+        return Ok(());
+    }
+
+    let database = ScyllaDbSession::new(&config.database_hosts, config.password_hashing.build()?)
+        .await?
+        .with_hashing_pool(config.hashing_pool.create_hashing_pool()?);
+
+    if let Some(email) = &arguments.grant_admin {
+        grant_admin(&database, email).await?;
+
+        return Ok(());
+    }
+
+    if let Some(client_id) = &arguments.register_client {
+        if let Some(secret) = register_client(&database, &arguments, client_id).await? {
+            println!("{secret}");
+        }
+
+        return Ok(());
+    }
 
-    let state = ServerState::new(
-        ScyllaDbSession::new(&config.database_hosts).await?,
-        TokenManager::new(
-            Duration::from_secs(config.lifetime),
-            Duration::from_secs(config.lifetime_leeway),
-            jsonwebtoken::Algorithm::from_str(config.signing_algorithm.as_str())?,
-            fs::read_to_string(&config.secret_path)?,
-        ),
-    );
+    let mut state = ServerState::new(database, config.create_token_manager()?)
+        .with_password_policy(config.password_policy.clone());
+    if let Some(breached_passwords) = &config.breached_passwords {
+        state = state.with_breached_passwords(BreachedPasswords::open(
+            &breached_passwords.path,
+            breached_passwords.min_occurrences,
+        )?);
+    }
+    if let Some(login_throttling) = &config.login_throttling {
+        state = state.with_login_throttling(login_throttling.create_login_throttling());
+    }
+    if let Some(rate_limiting) = &config.rate_limiting {
+        let rate_limiting = rate_limiting.create_rate_limiting(state.database());
+        state = state.with_rate_limiting(rate_limiting);
+    }
+    if let Some(mailer) = &config.mailer {
+        state = state.with_mailer(mailer.create_mailer()?);
+    }
+    if let Some(email_verification) = &config.email_verification {
+        if config.mailer.is_none() {
+            return Err("email_verification requires a mailer to be configured".into());
+        }
+        state = state.with_email_verification(EmailVerification {
+            unverified_login: email_verification.unverified_login,
+            link_url: email_verification.link_url.clone(),
+        });
+    }
+    let api_router = create_api_router()
+        .merge(create_admin_router())
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit::<ScyllaDbSession, Body>,
+        ));
     let root_router = Router::new()
-        .nest("/api", create_api_router())
+        .nest("/api", api_router)
+        .merge(create_well_known_router())
         .layer(TraceLayer::new_for_http())
         .with_state(state);
     axum::Server::bind(&config.server_host.parse()?)
-        .serve(root_router.into_make_service())
+        .serve(root_router.into_make_service_with_connect_info::<SocketAddr>())
         .await?;
 
     Ok(())
@@ -0,0 +1,518 @@
+//! OAuth 2.0 authorization server, as per RFC 6749.
+//!
+//! Clients are registered by storing the record returned by
+//! [`token::new_oauth_client`] or [`token::new_public_oauth_client`] with
+//! [`Database::add_oauth_client`]. Confidential clients obtain access tokens
+//! for themselves from `/oauth/token` with the client credentials grant,
+//! authenticating with HTTP Basic authentication or with `client_id` and
+//! `client_secret` form parameters. Routes can require such a token by taking
+//! an [`AuthenticatedClient`](crate::auth::AuthenticatedClient).
+//!
+//! Clients obtain tokens on behalf of users with the authorization code
+//! grant, sending users to `/oauth/authorize` to log in and grant the request,
+//! and exchanging the code which users are redirected back with at
+//! `/oauth/token`. PKCE with the `S256` method, as per RFC 7636, is required
+//! of every client.
+
+use crate::{
+    api::database_error_status,
+    database::{Database, DatabaseError, OAuthClient},
+    token,
+};
+use axum::{
+    headers::{authorization::Basic, Authorization},
+    http::{header, HeaderValue, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+    Json,
+};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use serde_json::json;
+use tracing::info;
+
+/// Length of a PKCE code challenge derived with the `S256` method, i.e. of an
+/// unpadded base64url-encoded SHA-256 hash.
+const CODE_CHALLENGE_LENGTH: usize = 43;
+
+/// Minimum length of a PKCE code verifier, as per RFC 7636, section 4.1.
+const MIN_CODE_VERIFIER_LENGTH: usize = 43;
+
+/// Maximum length of a PKCE code verifier, as per RFC 7636, section 4.1.
+const MAX_CODE_VERIFIER_LENGTH: usize = 128;
+
+/// Content security policy of the pages shown by the authorization endpoint,
+/// which must not be framed by other sites, so that users cannot be tricked
+/// into granting requests.
+const PAGE_CONTENT_SECURITY_POLICY: &str =
+    "default-src 'none'; style-src 'unsafe-inline'; frame-ancestors 'none'";
+
+/// An error response of the authorization or token endpoint, as per RFC 6749,
+/// sections 4.1.2.1 and 5.2.
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub enum OAuthError {
+    /// The request is missing a parameter, repeats one or is otherwise
+    /// malformed. Holds a description of the problem.
+    InvalidRequest(String),
+
+    /// The client is unknown, or did not authenticate correctly.
+    InvalidClient,
+
+    /// The authorization code is unknown, expired or already exchanged, or
+    /// was issued to another client, to another redirect URI or for another
+    /// code verifier.
+    InvalidGrant,
+
+    /// The client may not use the grant type, such as public clients the
+    /// client credentials grant.
+    UnauthorizedClient,
+
+    /// The grant type is not supported.
+    UnsupportedGrantType,
+
+    /// The response type of an authorization request is not supported.
+    UnsupportedResponseType,
+
+    /// The user denied the authorization request.
+    AccessDenied,
+
+    /// A requested scope is unknown, or may not be granted to the client.
+    InvalidScope,
+
+    /// The request could not be processed, e.g. due to the database being
+    /// unavailable. Holds the status code to respond with.
+    Unavailable(StatusCode),
+}
+
+impl OAuthError {
+    /// Returns the error code, as registered for RFC 6749.
+    fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidRequest(_) => "invalid_request",
+            Self::InvalidClient => "invalid_client",
+            Self::InvalidGrant => "invalid_grant",
+            Self::UnauthorizedClient => "unauthorized_client",
+            Self::UnsupportedGrantType => "unsupported_grant_type",
+            Self::UnsupportedResponseType => "unsupported_response_type",
+            Self::AccessDenied => "access_denied",
+            Self::InvalidScope => "invalid_scope",
+            Self::Unavailable(_) => "server_error",
+        }
+    }
+}
+
+impl From<DatabaseError> for OAuthError {
+    fn from(error: DatabaseError) -> Self {
+        Self::Unavailable(database_error_status(&error))
+    }
+}
+
+impl IntoResponse for OAuthError {
+    fn into_response(self) -> Response {
+        let mut body = json!({ "error": self.code() });
+        if let Self::InvalidRequest(description) = &self {
+            body["error_description"] = description.as_str().into();
+        }
+
+        match self {
+            // clients may have tried to authenticate with HTTP Basic
+            // authentication, which asks for a challenge
+            Self::InvalidClient => (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Basic realm=\"oauth\"")],
+                Json(body),
+            )
+                .into_response(),
+            Self::Unavailable(status_code) => (status_code, Json(body)).into_response(),
+            _ => (StatusCode::BAD_REQUEST, Json(body)).into_response(),
+        }
+    }
+}
+
+/// Returns the id and secret which a client authenticated with, either
+/// through HTTP Basic authentication or through form parameters, but not
+/// both. Public clients identify themselves with a `client_id` form parameter
+/// alone.
+///
+/// # Errors
+///
+/// Returns [`OAuthError::InvalidRequest`] if the client used both methods,
+/// and [`OAuthError::InvalidClient`] if it used neither.
+pub(crate) fn client_credentials(
+    authorization: Option<&Authorization<Basic>>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+) -> Result<(String, Option<String>), OAuthError> {
+    match (authorization, client_id, client_secret) {
+        (Some(authorization), None, None) => {
+            // as per RFC 6749, section 2.3.1, both are form-urlencoded
+            let decode = |value: &str| {
+                percent_decode_str(&value.replace('+', " "))
+                    .decode_utf8()
+                    .map(String::from)
+                    .map_err(|_| OAuthError::InvalidClient)
+            };
+            Ok((
+                decode(authorization.username())?,
+                Some(decode(authorization.password())?),
+            ))
+        }
+        (Some(_), _, _) => Err(OAuthError::InvalidRequest(
+            "client authenticated with more than one method".into(),
+        )),
+        (None, Some(client_id), client_secret) => Ok((client_id, client_secret)),
+        (None, None, _) => Err(OAuthError::InvalidClient),
+    }
+}
+
+/// Authenticates a client with its id and secret. Public clients, which have
+/// no secret, must not provide one.
+///
+/// # Errors
+///
+/// Returns [`OAuthError::InvalidClient`] if the client is unknown or the
+/// secret is wrong or missing.
+pub(crate) async fn authenticate_client<D: Database>(
+    database: &D,
+    client_id: &str,
+    client_secret: Option<&str>,
+) -> Result<OAuthClient, OAuthError> {
+    let client = match database.get_oauth_client(client_id).await {
+        Ok(client) => client,
+        Err(DatabaseError::NotFound) => {
+            info!("unknown OAuth client provided");
+            return Err(OAuthError::InvalidClient);
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let authenticated = match (&client.secret_hash, client_secret) {
+        (Some(_), Some(client_secret)) => token::verify_client_secret(&client, client_secret),
+        (None, None) => true,
+        _ => false,
+    };
+    if authenticated {
+        Ok(client)
+    } else {
+        info!("wrong or missing secret provided by OAuth client");
+        Err(OAuthError::InvalidClient)
+    }
+}
+
+/// Returns the scopes to grant for a space-delimited `scope` parameter,
+/// sorted and without duplicates. Clients which do not request any scopes
+/// are granted all of their scopes.
+///
+/// # Errors
+///
+/// Returns [`OAuthError::InvalidScope`] if a requested scope may not be
+/// granted to the client.
+pub(crate) fn granted_scopes(
+    allowed_scopes: &[String],
+    requested_scopes: Option<&str>,
+) -> Result<Vec<String>, OAuthError> {
+    let Some(requested_scopes) = requested_scopes else {
+        return Ok(allowed_scopes.to_vec());
+    };
+
+    let mut scopes = requested_scopes
+        .split(' ')
+        .filter(|scope| !scope.is_empty())
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    scopes.sort_unstable();
+    scopes.dedup();
+
+    if scopes.iter().all(|scope| allowed_scopes.contains(scope)) {
+        Ok(scopes)
+    } else {
+        info!("OAuth client requested scopes it may not be granted");
+        Err(OAuthError::InvalidScope)
+    }
+}
+
+/// Checks a PKCE code verifier, as per RFC 7636, section 4.1.
+///
+/// # Errors
+///
+/// Returns [`OAuthError::InvalidRequest`] if the verifier is malformed.
+pub(crate) fn check_code_verifier(code_verifier: &str) -> Result<(), OAuthError> {
+    if (MIN_CODE_VERIFIER_LENGTH..=MAX_CODE_VERIFIER_LENGTH).contains(&code_verifier.len())
+        && code_verifier
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_' | '~'))
+    {
+        Ok(())
+    } else {
+        Err(OAuthError::InvalidRequest("malformed code_verifier".into()))
+    }
+}
+
+/// Query parameters of an authorization request, as per RFC 6749, section
+/// 4.1.1, and RFC 7636, section 4.3.
+#[derive(Deserialize)]
+pub(crate) struct AuthorizationParams {
+    response_type: Option<String>,
+    client_id: Option<String>,
+    redirect_uri: Option<String>,
+    scope: Option<String>,
+    state: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
+}
+
+/// An authorization request which has been checked, and which users can be
+/// asked to grant.
+pub(crate) struct AuthorizationRequest {
+    pub client: OAuthClient,
+
+    /// URI to redirect the user back to the client with.
+    pub redirect_uri: String,
+
+    /// Whether the client gave the redirect URI, rather than relying on it
+    /// being the only one registered, in which case it must give it again
+    /// when exchanging the code.
+    pub redirect_uri_given: bool,
+
+    pub state: Option<String>,
+
+    /// Scopes to grant, sorted and without duplicates.
+    pub scopes: Vec<String>,
+
+    pub code_challenge: String,
+}
+
+impl AuthorizationRequest {
+    /// Returns a response redirecting the user back to the client with the
+    /// given query parameters, along with the request's `state`.
+    pub fn redirect(&self, params: &[(&str, &str)]) -> Response {
+        let mut uri = self.redirect_uri.clone();
+        let mut separator = if uri.contains('?') { '&' } else { '?' };
+        for (name, value) in params
+            .iter()
+            .copied()
+            .chain(self.state.as_deref().map(|state| ("state", state)))
+        {
+            uri.push(separator);
+            uri.push_str(name);
+            uri.push('=');
+            uri.extend(utf8_percent_encode(value, NON_ALPHANUMERIC));
+            separator = '&';
+        }
+
+        Redirect::to(&uri).into_response()
+    }
+
+    /// Returns a response redirecting the user back to the client with an
+    /// error, as per RFC 6749, section 4.1.2.1.
+    pub fn redirect_error(&self, error: &OAuthError) -> Response {
+        match error {
+            OAuthError::InvalidRequest(description) => {
+                self.redirect(&[("error", error.code()), ("error_description", description)])
+            }
+            _ => self.redirect(&[("error", error.code())]),
+        }
+    }
+}
+
+/// Checks the parameters of an authorization request.
+///
+/// # Errors
+///
+/// If the client or its redirect URI cannot be determined, returns an error
+/// page, as users must not be redirected to unverified URIs. Otherwise,
+/// returns a response redirecting the user back to the client with an error
+/// if the request is invalid.
+pub(crate) async fn check_authorization_request<D: Database>(
+    database: &D,
+    params: AuthorizationParams,
+) -> Result<AuthorizationRequest, Response> {
+    let Some(client_id) = params.client_id else {
+        return Err(error_page(
+            StatusCode::BAD_REQUEST,
+            "The request does not name a client.",
+        ));
+    };
+    let client = match database.get_oauth_client(&client_id).await {
+        Ok(client) => client,
+        Err(DatabaseError::NotFound) => {
+            info!("authorization requested for unknown OAuth client");
+            return Err(error_page(
+                StatusCode::BAD_REQUEST,
+                "The client is not registered.",
+            ));
+        }
+        Err(e) => {
+            return Err(error_page(
+                database_error_status(&e),
+                "The request could not be processed. Try again later.",
+            ))
+        }
+    };
+
+    let redirect_uri_given = params.redirect_uri.is_some();
+    let redirect_uri = match params.redirect_uri {
+        Some(redirect_uri) if client.redirect_uris.contains(&redirect_uri) => redirect_uri,
+        Some(_) => {
+            info!("authorization requested with unregistered redirect URI");
+            return Err(error_page(
+                StatusCode::BAD_REQUEST,
+                "The redirect URI is not registered for the client.",
+            ));
+        }
+        None if client.redirect_uris.len() == 1 => client.redirect_uris[0].clone(),
+        None => {
+            return Err(error_page(
+                StatusCode::BAD_REQUEST,
+                "The request does not specify a redirect URI.",
+            ))
+        }
+    };
+
+    let mut request = AuthorizationRequest {
+        client,
+        redirect_uri,
+        redirect_uri_given,
+        state: params.state,
+        scopes: Vec::new(),
+        code_challenge: String::new(),
+    };
+
+    let error = match (
+        params.response_type.as_deref(),
+        params.code_challenge,
+        params.code_challenge_method.as_deref(),
+    ) {
+        (None, _, _) => OAuthError::InvalidRequest("missing response_type".into()),
+        (Some(response_type), _, _) if response_type != "code" => {
+            OAuthError::UnsupportedResponseType
+        }
+        (_, None, _) => OAuthError::InvalidRequest("code_challenge is required".into()),
+        (_, _, method) if method != Some("S256") => {
+            OAuthError::InvalidRequest("code_challenge_method must be S256".into())
+        }
+        (_, Some(code_challenge), _)
+            if code_challenge.len() != CODE_CHALLENGE_LENGTH
+                || !code_challenge
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_')) =>
+        {
+            OAuthError::InvalidRequest("malformed code_challenge".into())
+        }
+        (_, Some(code_challenge), _) => {
+            match granted_scopes(&request.client.scopes, params.scope.as_deref()) {
+                Ok(scopes) => {
+                    request.scopes = scopes;
+                    request.code_challenge = code_challenge;
+                    return Ok(request);
+                }
+                Err(error) => error,
+            }
+        }
+    };
+
+    info!("rejected invalid OAuth authorization request");
+    Err(request.redirect_error(&error))
+}
+
+/// Returns a page asking the user to log in and grant an authorization
+/// request, showing an error if their previous attempt failed. The e-mail
+/// address field is filled in with `email`.
+pub(crate) fn authorization_page(
+    request: &AuthorizationRequest,
+    email: &str,
+    error: Option<&str>,
+    status_code: StatusCode,
+) -> Response {
+    let client_id = escape_html(&request.client.client_id);
+    let scopes = if request.scopes.is_empty() {
+        format!("<p><strong>{client_id}</strong> would like to know who you are.</p>")
+    } else {
+        let items = request
+            .scopes
+            .iter()
+            .map(|scope| format!("<li>{}</li>", escape_html(scope)))
+            .collect::<String>();
+        format!(
+            "<p><strong>{client_id}</strong> would like to access your account \
+            with these permissions:</p><ul>{items}</ul>"
+        )
+    };
+    let error = error.map_or_else(String::new, |error| {
+        format!("<p role=\"alert\">{}</p>", escape_html(error))
+    });
+    let email = escape_html(email);
+
+    // without an action, the form is posted to the page's own URI, including
+    // the query parameters of the request
+    page(
+        status_code,
+        &format!("Authorize {client_id}"),
+        &format!(
+            "{scopes}{error}\
+            <form method=\"post\">\
+            <label>E-mail address \
+            <input type=\"email\" name=\"email\" value=\"{email}\" autocomplete=\"username\" required></label>\
+            <label>Password \
+            <input type=\"password\" name=\"password\" autocomplete=\"current-password\" required></label>\
+            <button type=\"submit\" name=\"decision\" value=\"allow\">Allow</button> \
+            <button type=\"submit\" name=\"decision\" value=\"deny\" formnovalidate>Deny</button>\
+            </form>"
+        ),
+    )
+}
+
+/// Returns a page describing an authorization request which cannot be
+/// processed.
+pub(crate) fn error_page(status_code: StatusCode, message: &str) -> Response {
+    page(
+        status_code,
+        "Authorization failed",
+        &format!("<p>{}</p>", escape_html(message)),
+    )
+}
+
+/// Returns an HTML page with the given title and body, which must not be
+/// cached or framed.
+fn page(status_code: StatusCode, title: &str, body: &str) -> Response {
+    let html = format!(
+        "<!DOCTYPE html>\
+        <html lang=\"en\">\
+        <head>\
+        <meta charset=\"utf-8\">\
+        <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+        <title>{title}</title>\
+        <style>body {{ font-family: sans-serif; max-width: 24rem; margin: 2rem auto; }} \
+        label {{ display: block; margin: 1rem 0; }} input {{ display: block; width: 100%; }} \
+        [role=alert] {{ color: #b00020; }}</style>\
+        </head>\
+        <body><main><h1>{title}</h1>{body}</main></body>\
+        </html>"
+    );
+
+    let mut response = (status_code, Html(html)).into_response();
+    let headers = response.headers_mut();
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    headers.insert(header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+    headers.insert(
+        header::CONTENT_SECURITY_POLICY,
+        HeaderValue::from_static(PAGE_CONTENT_SECURITY_POLICY),
+    );
+    response
+}
+
+/// Escapes text for inclusion in HTML, including in quoted attribute values.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
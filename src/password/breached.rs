@@ -0,0 +1,110 @@
+//! Offline checks against lists of breached passwords.
+
+use memmap2::Mmap;
+use sha1::{Digest, Sha1};
+use std::{cmp::Ordering, fs::File, io, path::Path};
+
+/// Length of a hex-encoded SHA-1 hash.
+const HASH_LENGTH: usize = 40;
+
+/// A list of breached passwords in the format of Have I Been Pwned's
+/// downloadable password list.
+///
+/// Each line of the list holds the uppercase hex-encoded SHA-1 hash of a
+/// password and the number of times it occurs in breaches, separated by a
+/// colon, e.g. `5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824`. Lines must be
+/// sorted by hash, so that the list can be binary searched. The list is
+/// memory-mapped rather than read into memory, as it may be tens of
+/// gigabytes in size.
+pub struct BreachedPasswords {
+    list: Mmap,
+
+    /// Minimum number of occurrences for a password to count as breached.
+    min_occurrences: u64,
+}
+
+impl BreachedPasswords {
+    /// Memory-maps a breached password list. Passwords occurring at least
+    /// `min_occurrences` times (and at least once) count as breached.
+    ///
+    /// The file must not be modified while it is mapped, so lists should be
+    /// updated by replacing the file rather than writing to it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened or mapped.
+    pub fn open(path: impl AsRef<Path>, min_occurrences: u64) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the file is only read, and modifying it while mapped is
+        // documented to be unsupported
+        let list = unsafe { Mmap::map(&file)? };
+
+        Ok(Self {
+            list,
+            min_occurrences: min_occurrences.max(1),
+        })
+    }
+
+    /// Returns the number of times a password occurs in breaches, which is 0
+    /// if it is not in the list.
+    ///
+    /// This may read from disk, so it should not be called on an async
+    /// runtime's worker threads.
+    #[must_use]
+    pub fn occurrences(&self, password: &str) -> u64 {
+        let hash = Sha1::digest(password.as_bytes())
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect::<String>();
+        let hash = hash.as_bytes();
+
+        let list = &self.list[..];
+        // both bounds are always at the start of a line
+        let (mut low, mut high) = (0, list.len());
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let start = list[low..middle]
+                .iter()
+                .rposition(|&byte| byte == b'\n')
+                .map_or(low, |i| low + i + 1);
+            let end = list[middle..]
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(list.len(), |i| middle + i);
+
+            let line = &list[start..end];
+            let line_hash = &line[..line.len().min(HASH_LENGTH)];
+            match compare_hashes(line_hash, hash) {
+                Ordering::Less => low = end + 1,
+                Ordering::Greater => high = start,
+                Ordering::Equal => return parse_occurrences(&line[line_hash.len()..]),
+            }
+        }
+
+        0
+    }
+
+    /// Returns whether a password occurs in breaches often enough to count as
+    /// breached.
+    #[must_use]
+    pub fn contains(&self, password: &str) -> bool {
+        self.occurrences(password) >= self.min_occurrences
+    }
+}
+
+/// Compares hex-encoded hashes, ignoring case.
+fn compare_hashes(a: &[u8], b: &[u8]) -> Ordering {
+    a.iter()
+        .map(u8::to_ascii_uppercase)
+        .cmp(b.iter().map(u8::to_ascii_uppercase))
+}
+
+/// Parses the occurrence count following a hash, e.g. `:42\r`. Lines without
+/// a valid count are assumed to occur once.
+fn parse_occurrences(rest: &[u8]) -> u64 {
+    std::str::from_utf8(rest)
+        .ok()
+        .and_then(|rest| rest.strip_prefix(':'))
+        .and_then(|count| count.trim().parse().ok())
+        .unwrap_or(1)
+}
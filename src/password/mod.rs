@@ -0,0 +1,278 @@
+//! Password hashing and policies.
+
+mod breached;
+mod policy;
+mod pool;
+
+use argon2::{
+    password_hash::{self, PasswordHash, PasswordVerifier, SaltString},
+    Argon2, Params, Version,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display, Formatter},
+    sync::Arc,
+};
+
+pub use breached::BreachedPasswords;
+pub use policy::{PasswordPolicy, PasswordRule, PolicyViolation};
+pub use pool::HashingPool;
+
+/// Trait for password hashing algorithms.
+///
+/// Hashes are encoded as strings which contain the algorithm, its parameters
+/// and the salt alongside the hash itself: PHC strings for argon2, and the
+/// equivalent modular crypt format for bcrypt.
+#[allow(clippy::module_name_repetitions)]
+pub trait PasswordHasher: Send + Sync {
+    /// Hashes a password with a new random salt.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if hashing fails.
+    fn hash(&self, password: &str) -> Result<String, PasswordHashError>;
+
+    /// Checks a password against a hash.
+    ///
+    /// Hashes created by any of the algorithms in this module can be verified,
+    /// regardless of the algorithm the hasher itself uses.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the hash is malformed or uses an unknown algorithm.
+    fn verify(&self, password: &str, hash: &str) -> Result<bool, PasswordHashError> {
+        verify(password, hash)
+    }
+
+    /// Returns whether a hash was created with a different algorithm or
+    /// different parameters than this hasher uses, and should therefore be
+    /// replaced with a new hash when the password is next available.
+    fn needs_rehash(&self, hash: &str) -> bool;
+}
+
+/// Checks a password against a hash created by any of the algorithms in this
+/// module.
+///
+/// # Errors
+///
+/// Returns an error if the hash is malformed or uses an unknown algorithm.
+pub fn verify(password: &str, hash: &str) -> Result<bool, PasswordHashError> {
+    if hash.starts_with("$2") {
+        bcrypt::verify(password, hash).map_err(|e| PasswordHashError::MalformedHash(e.to_string()))
+    } else if hash.starts_with("$argon2") {
+        let hash =
+            PasswordHash::new(hash).map_err(|e| PasswordHashError::MalformedHash(e.to_string()))?;
+        if hash.hash.is_none() {
+            return Err(PasswordHashError::MalformedHash(
+                "argon2 hash has no output".into(),
+            ));
+        }
+
+        match Argon2::default().verify_password(password.as_bytes(), &hash) {
+            Ok(()) => Ok(true),
+            Err(password_hash::Error::Password) => Ok(false),
+            Err(e) => Err(PasswordHashError::MalformedHash(e.to_string())),
+        }
+    } else {
+        Err(PasswordHashError::MalformedHash(
+            "unknown hashing algorithm".into(),
+        ))
+    }
+}
+
+/// Hashes a random password with a hasher.
+///
+/// When a user does not exist, the provided password should still be checked
+/// against such a hash, so that the time taken to reject the credentials does
+/// not reveal whether the user exists.
+///
+/// # Errors
+///
+/// Returns an error if hashing fails.
+pub fn dummy_hash(hasher: &dyn PasswordHasher) -> Result<String, PasswordHashError> {
+    hasher.hash(&crate::token::random_token_string(16))
+}
+
+/// Compares two byte strings in time which depends only on their lengths.
+#[must_use]
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// An error which occurred while hashing or verifying a password.
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub enum PasswordHashError {
+    /// The hashing parameters are not supported by the algorithm.
+    InvalidParameters(String),
+
+    /// A stored hash could not be parsed.
+    MalformedHash(String),
+
+    /// Too many passwords are already waiting to be hashed.
+    Overloaded,
+}
+
+impl Display for PasswordHashError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidParameters(reason) => {
+                write!(f, "invalid password hashing parameters: {reason}")
+            }
+            Self::MalformedHash(reason) => write!(f, "malformed password hash: {reason}"),
+            Self::Overloaded => write!(f, "too many passwords waiting to be hashed"),
+        }
+    }
+}
+
+impl std::error::Error for PasswordHashError {}
+
+/// Password hashing with bcrypt.
+///
+/// Passwords longer than 72 bytes are truncated by the algorithm.
+pub struct BcryptHasher {
+    cost: u32,
+}
+
+impl BcryptHasher {
+    const MIN_COST: u32 = 4;
+    const MAX_COST: u32 = 31;
+
+    /// Creates a hasher with the given cost, i.e. the base-2 logarithm of the
+    /// number of iterations.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cost is outside of the range supported by
+    /// bcrypt.
+    pub fn new(cost: u32) -> Result<Self, PasswordHashError> {
+        if !(Self::MIN_COST..=Self::MAX_COST).contains(&cost) {
+            return Err(PasswordHashError::InvalidParameters(format!(
+                "bcrypt cost must be between {} and {}",
+                Self::MIN_COST,
+                Self::MAX_COST
+            )));
+        }
+
+        Ok(Self { cost })
+    }
+}
+
+impl PasswordHasher for BcryptHasher {
+    fn hash(&self, password: &str) -> Result<String, PasswordHashError> {
+        bcrypt::hash(password, self.cost)
+            .map_err(|e| PasswordHashError::InvalidParameters(e.to_string()))
+    }
+
+    fn needs_rehash(&self, hash: &str) -> bool {
+        // "$2b$13$..."
+        let mut parts = hash.split('$').skip(1);
+        let is_bcrypt = parts.next().is_some_and(|version| version.starts_with('2'));
+        let cost = parts.next().and_then(|cost| cost.parse::<u32>().ok());
+
+        !is_bcrypt || cost != Some(self.cost)
+    }
+}
+
+/// Password hashing with argon2id.
+pub struct Argon2Hasher {
+    argon2: Argon2<'static>,
+}
+
+impl Argon2Hasher {
+    /// Creates a hasher with the given memory cost in KiB, time cost (number
+    /// of passes) and degree of parallelism.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the parameters are not supported by argon2.
+    pub fn new(
+        memory_cost: u32,
+        time_cost: u32,
+        parallelism: u32,
+    ) -> Result<Self, PasswordHashError> {
+        let params = Params::new(memory_cost, time_cost, parallelism, None)
+            .map_err(|e| PasswordHashError::InvalidParameters(e.to_string()))?;
+
+        Ok(Self {
+            argon2: Argon2::new(argon2::Algorithm::Argon2id, Version::V0x13, params),
+        })
+    }
+}
+
+impl PasswordHasher for Argon2Hasher {
+    fn hash(&self, password: &str) -> Result<String, PasswordHashError> {
+        let salt = SaltString::generate(&mut rand::rngs::OsRng);
+
+        Ok(
+            argon2::PasswordHasher::hash_password(&self.argon2, password.as_bytes(), &salt)
+                .map_err(|e| PasswordHashError::InvalidParameters(e.to_string()))?
+                .to_string(),
+        )
+    }
+
+    fn needs_rehash(&self, hash: &str) -> bool {
+        let Ok(hash) = PasswordHash::new(hash) else {
+            return true;
+        };
+        let Ok(params) = Params::try_from(&hash) else {
+            return true;
+        };
+        let current_params = self.argon2.params();
+
+        hash.algorithm != argon2::Algorithm::Argon2id.ident()
+            || hash.version != Some(Version::V0x13.into())
+            || params.m_cost() != current_params.m_cost()
+            || params.t_cost() != current_params.t_cost()
+            || params.p_cost() != current_params.p_cost()
+    }
+}
+
+/// Configuration of the algorithm used to hash new passwords.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "algorithm", rename_all = "snake_case")]
+pub enum PasswordHashingConfig {
+    Bcrypt {
+        /// Base-2 logarithm of the number of iterations.
+        cost: u32,
+    },
+    Argon2id {
+        /// Memory cost in KiB.
+        memory_cost: u32,
+
+        /// Number of passes.
+        time_cost: u32,
+
+        /// Degree of parallelism.
+        parallelism: u32,
+    },
+}
+
+impl PasswordHashingConfig {
+    /// Creates a hasher according to the configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the parameters are not supported by the algorithm.
+    pub fn build(&self) -> Result<Arc<dyn PasswordHasher>, PasswordHashError> {
+        Ok(match *self {
+            Self::Bcrypt { cost } => Arc::new(BcryptHasher::new(cost)?),
+            Self::Argon2id {
+                memory_cost,
+                time_cost,
+                parallelism,
+            } => Arc::new(Argon2Hasher::new(memory_cost, time_cost, parallelism)?),
+        })
+    }
+}
+
+impl Default for PasswordHashingConfig {
+    /// Argon2id with the minimum parameters recommended by OWASP.
+    fn default() -> Self {
+        Self::Argon2id {
+            memory_cost: 19 * 1024,
+            time_cost: 2,
+            parallelism: 1,
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Password policies.
+
+use serde::{Deserialize, Serialize};
+
+/// Rules which new passwords must follow.
+///
+/// The default policy only requires passwords to be non-empty.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+#[allow(clippy::struct_excessive_bools, clippy::module_name_repetitions)]
+pub struct PasswordPolicy {
+    /// Minimum length in characters.
+    pub min_length: usize,
+
+    /// Maximum length in bytes. Should be at most 72 when hashing with bcrypt,
+    /// which ignores any further bytes.
+    pub max_bytes: Option<usize>,
+
+    /// Whether at least one lowercase letter is required.
+    pub require_lowercase: bool,
+
+    /// Whether at least one uppercase letter is required.
+    pub require_uppercase: bool,
+
+    /// Whether at least one digit is required.
+    pub require_digit: bool,
+
+    /// Whether at least one character which is neither alphanumeric nor
+    /// whitespace is required.
+    pub require_symbol: bool,
+
+    /// Whether passwords which contain the local part of the user's e-mail
+    /// address are rejected, ignoring case. Local parts shorter than 3
+    /// characters are not checked.
+    pub reject_email_local_part: bool,
+
+    /// Minimum strength score as estimated by zxcvbn, from 0 (too guessable)
+    /// to 4 (very unguessable).
+    pub min_strength: Option<u8>,
+}
+
+impl PasswordPolicy {
+    /// Local parts shorter than this are not checked by
+    /// `reject_email_local_part`, as they would reject too many passwords.
+    const MIN_CHECKED_LOCAL_PART_LENGTH: usize = 3;
+
+    /// Checks a new password of the user with the given e-mail address.
+    ///
+    /// # Errors
+    ///
+    /// Returns a violation for each rule which the password does not follow.
+    pub fn check(&self, password: &str, email: &str) -> Result<(), Vec<PolicyViolation>> {
+        let mut violations = Vec::new();
+        let mut violate =
+            |rule, message: String| violations.push(PolicyViolation { rule, message });
+
+        let length = password.chars().count();
+        if length < self.min_length {
+            violate(
+                PasswordRule::MinLength,
+                format!("must be at least {} characters long", self.min_length),
+            );
+        }
+        if let Some(max_bytes) = self.max_bytes {
+            if password.len() > max_bytes {
+                violate(
+                    PasswordRule::MaxBytes,
+                    format!("must be at most {max_bytes} bytes long"),
+                );
+            }
+        }
+
+        for (required, rule, predicate, description) in [
+            (
+                self.require_lowercase,
+                PasswordRule::RequireLowercase,
+                char::is_lowercase as fn(char) -> bool,
+                "a lowercase letter",
+            ),
+            (
+                self.require_uppercase,
+                PasswordRule::RequireUppercase,
+                char::is_uppercase,
+                "an uppercase letter",
+            ),
+            (
+                self.require_digit,
+                PasswordRule::RequireDigit,
+                char::is_numeric,
+                "a digit",
+            ),
+            (
+                self.require_symbol,
+                PasswordRule::RequireSymbol,
+                |c: char| !c.is_alphanumeric() && !c.is_whitespace(),
+                "a symbol",
+            ),
+        ] {
+            if required && !password.chars().any(predicate) {
+                violate(rule, format!("must contain {description}"));
+            }
+        }
+
+        let local_part = email
+            .rsplit_once('@')
+            .map_or(email, |(local_part, _)| local_part);
+        if self.reject_email_local_part
+            && local_part.chars().count() >= Self::MIN_CHECKED_LOCAL_PART_LENGTH
+            && password.to_lowercase().contains(&local_part.to_lowercase())
+        {
+            violate(
+                PasswordRule::RejectEmailLocalPart,
+                "must not contain the e-mail address".to_string(),
+            );
+        }
+
+        if let Some(min_strength) = self.min_strength {
+            // blank passwords cannot be evaluated, and are as weak as it gets
+            let entropy = zxcvbn::zxcvbn(password, &[email, local_part]).ok();
+            if entropy.as_ref().map_or(0, zxcvbn::Entropy::score) < min_strength {
+                let warning = entropy
+                    .as_ref()
+                    .and_then(|entropy| entropy.feedback().as_ref())
+                    .and_then(zxcvbn::feedback::Feedback::warning);
+                violate(
+                    PasswordRule::MinStrength,
+                    match warning {
+                        Some(warning) => format!("is too easy to guess: {warning}"),
+                        None => "is too easy to guess".to_string(),
+                    },
+                );
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 1,
+            max_bytes: None,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            reject_email_local_part: false,
+            min_strength: None,
+        }
+    }
+}
+
+/// A rule which new passwords must follow, named after the [`PasswordPolicy`]
+/// field which configures it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PasswordRule {
+    MinLength,
+    MaxBytes,
+    RequireLowercase,
+    RequireUppercase,
+    RequireDigit,
+    RequireSymbol,
+    RejectEmailLocalPart,
+    MinStrength,
+
+    /// The password must not be in the server's list of
+    /// [`BreachedPasswords`](super::BreachedPasswords).
+    BreachedPasswords,
+}
+
+/// A rule which a password does not follow.
+#[derive(Clone, Debug, Serialize)]
+#[allow(clippy::module_name_repetitions)]
+pub struct PolicyViolation {
+    pub rule: PasswordRule,
+
+    /// Human-readable description of the rule, phrased to follow the name of
+    /// the field, e.g. "password must contain a digit".
+    pub message: String,
+}
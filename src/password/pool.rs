@@ -0,0 +1,78 @@
+//! Bounded pool for password hashing.
+
+use super::PasswordHashError;
+use std::{num::NonZeroUsize, sync::Arc, thread};
+use tokio::sync::Semaphore;
+
+/// A bounded pool of blocking threads for hashing passwords.
+///
+/// Password hashing is deliberately slow, and would stall the async runtime if
+/// run on its worker threads. The pool runs hashing on blocking threads
+/// instead, at most `workers` at a time, and queues at most `max_queued`
+/// further tasks. Tasks beyond that are rejected right away with
+/// [`PasswordHashError::Overloaded`], so that bursts of logins are shed rather
+/// than piling up ever longer response times.
+#[derive(Clone)]
+#[allow(clippy::module_name_repetitions)]
+pub struct HashingPool {
+    /// Permits for running tasks.
+    workers: Arc<Semaphore>,
+
+    /// Permits for running or queued tasks.
+    slots: Arc<Semaphore>,
+}
+
+impl HashingPool {
+    /// Creates a pool running at most `workers` tasks at a time, with at most
+    /// `max_queued` further tasks waiting.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `workers` is zero.
+    #[must_use]
+    pub fn new(workers: usize, max_queued: usize) -> Self {
+        assert!(workers > 0, "hashing pool needs at least one worker");
+
+        Self {
+            workers: Arc::new(Semaphore::new(workers)),
+            slots: Arc::new(Semaphore::new(workers.saturating_add(max_queued))),
+        }
+    }
+
+    /// Runs a hashing task on the pool once a worker is available.
+    ///
+    /// The task keeps its place in the pool until it finishes, even if the
+    /// returned future is dropped before.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`PasswordHashError::Overloaded`] if the queue is full.
+    pub async fn run<T: Send + 'static>(
+        &self,
+        task: impl FnOnce() -> T + Send + 'static,
+    ) -> Result<T, PasswordHashError> {
+        let slot = Arc::clone(&self.slots)
+            .try_acquire_owned()
+            .map_err(|_| PasswordHashError::Overloaded)?;
+        let worker = Arc::clone(&self.workers)
+            .acquire_owned()
+            .await
+            .expect("hashing pool semaphore closed");
+
+        Ok(tokio::task::spawn_blocking(move || {
+            let _permits = (slot, worker);
+            task()
+        })
+        .await
+        .expect("password hashing panicked"))
+    }
+}
+
+impl Default for HashingPool {
+    /// A pool with a worker per available CPU, and a queue of 16 tasks per
+    /// worker.
+    fn default() -> Self {
+        let workers = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+        Self::new(workers, workers * 16)
+    }
+}
@@ -0,0 +1,425 @@
+//! Rate limiting of API requests.
+//!
+//! Requests are limited by token buckets: each client has a bucket per route
+//! holding up to a quota's `capacity` of requests, which refills continuously
+//! at `capacity` requests per `period`. Requests to routes without a quota of
+//! their own share a bucket for the default quota, if any. Clients are told
+//! about their limit by the `RateLimit-*` headers of the IETF draft on rate
+//! limit headers, and requests exceeding it are rejected with
+//! `429 Too Many Requests`.
+//!
+//! The limits are applied by the [`rate_limit`] middleware:
+//!
+//! ```ignore
+//! let api_router = create_api_router().route_layer(middleware::from_fn_with_state(
+//!     state.clone(),
+//!     rate_limit::<ScyllaDbSession, Body>,
+//! ));
+//! ```
+
+use crate::{
+    auth,
+    database::{Database, DatabaseError, RateLimitBucket},
+    throttling, token, ServerState,
+};
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, MatchedPath, OriginalUri, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+use tracing::{info, warn};
+
+/// Header holding an API key, by which requests can be rate limited.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// Number of times a shared token bucket is updated when it is updated
+/// concurrently.
+const MAX_BUCKET_UPDATE_ATTEMPTS: usize = 5;
+
+/// Number of requests after which expired buckets are removed from a
+/// [`MemoryRateLimitStore`].
+const MEMORY_STORE_PRUNE_INTERVAL: u32 = 1024;
+
+/// The number of requests a client may make in a period.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Quota {
+    /// Number of requests which can be made at once.
+    pub capacity: u32,
+
+    /// Period in which the full capacity is refilled.
+    pub period: Duration,
+
+    /// What clients are told apart by.
+    pub key: RateLimitKey,
+}
+
+impl Quota {
+    /// Returns the number of requests refilled per second.
+    fn refill_rate(&self) -> f64 {
+        f64::from(self.capacity) / self.period.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+
+    /// Returns the bucket refilled until `now`, and with a token taken from it
+    /// if the request is allowed, along with the resulting status.
+    fn take(
+        &self,
+        bucket: Option<&RateLimitBucket>,
+        now: SystemTime,
+    ) -> (RateLimitBucket, RateLimitStatus) {
+        let capacity = f64::from(self.capacity);
+        let tokens = bucket.map_or(capacity, |bucket| {
+            let elapsed = now
+                .duration_since(bucket.updated_at)
+                .unwrap_or_default()
+                .as_secs_f64();
+            (bucket.tokens + elapsed * self.refill_rate()).min(capacity)
+        });
+
+        let allowed = tokens >= 1.0;
+        let tokens = if allowed { tokens - 1.0 } else { tokens };
+        let seconds_until =
+            |target: f64| Duration::from_secs_f64((target - tokens).max(0.0) / self.refill_rate());
+
+        let status = RateLimitStatus {
+            allowed,
+            // truncation is intended, as only whole requests can be made
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            remaining: tokens.floor() as u32,
+            reset_after: seconds_until(capacity),
+            retry_after: (!allowed).then(|| seconds_until(1.0)),
+        };
+        (
+            RateLimitBucket {
+                tokens,
+                updated_at: now,
+            },
+            status,
+        )
+    }
+}
+
+/// What clients are told apart by for rate limiting.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[allow(clippy::module_name_repetitions)]
+pub enum RateLimitKey {
+    /// The client's IP address. IPv6 addresses are grouped by their /64
+    /// prefix.
+    #[default]
+    Ip,
+
+    /// The user of the API token given as a bearer token, or the IP address
+    /// if no valid token is given. Revoked tokens are not rejected, as that
+    /// would require a database query.
+    User,
+
+    /// The API key given in the [`API_KEY_HEADER`] or as an `ApiKey`
+    /// credential in the `Authorization` header, or the IP address if none is
+    /// given. The key is not checked, so this should only be used for routes
+    /// which reject requests without a valid API key.
+    ApiKey,
+}
+
+/// The state of a client's rate limit after a request.
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub struct RateLimitStatus {
+    /// Whether the request is allowed.
+    pub allowed: bool,
+
+    /// Number of requests which can still be made right away.
+    pub remaining: u32,
+
+    /// Time until the full capacity is available again.
+    pub reset_after: Duration,
+
+    /// Time until the next request can be made, if this one is rejected.
+    pub retry_after: Option<Duration>,
+}
+
+/// Trait for storage of token buckets.
+#[async_trait]
+#[allow(clippy::module_name_repetitions)]
+pub trait RateLimitStore: Send + Sync {
+    /// Takes a token from the bucket with the given key, if there is one,
+    /// and returns the resulting status.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bucket cannot be retrieved or updated.
+    async fn acquire(&self, key: &str, quota: &Quota) -> Result<RateLimitStatus, DatabaseError>;
+}
+
+/// A rate limit store which keeps token buckets in memory, so limits only
+/// hold for a single server.
+#[derive(Default)]
+#[allow(clippy::module_name_repetitions)]
+pub struct MemoryRateLimitStore {
+    buckets: Mutex<MemoryBuckets>,
+}
+
+#[derive(Default)]
+struct MemoryBuckets {
+    /// Token buckets along with the times at which they are full again, by
+    /// key.
+    buckets: HashMap<String, (RateLimitBucket, SystemTime)>,
+
+    /// Number of requests since full buckets were last removed.
+    requests_since_pruning: u32,
+}
+
+impl MemoryRateLimitStore {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryRateLimitStore {
+    async fn acquire(&self, key: &str, quota: &Quota) -> Result<RateLimitStatus, DatabaseError> {
+        let now = SystemTime::now();
+        let mut memory_buckets = self.buckets.lock().unwrap();
+
+        memory_buckets.requests_since_pruning += 1;
+        if memory_buckets.requests_since_pruning >= MEMORY_STORE_PRUNE_INTERVAL {
+            // full buckets are no different from missing ones
+            memory_buckets
+                .buckets
+                .retain(|_, (_, full_at)| *full_at > now);
+            memory_buckets.requests_since_pruning = 0;
+        }
+
+        let (bucket, status) = quota.take(
+            memory_buckets.buckets.get(key).map(|(bucket, _)| bucket),
+            now,
+        );
+        memory_buckets
+            .buckets
+            .insert(key.to_string(), (bucket, now + status.reset_after));
+        Ok(status)
+    }
+}
+
+/// A rate limit store which keeps token buckets in a database, so limits hold
+/// across every server sharing the database.
+#[allow(clippy::module_name_repetitions)]
+pub struct DatabaseRateLimitStore<D: Database> {
+    database: D,
+}
+
+impl<D: Database> DatabaseRateLimitStore<D> {
+    pub fn new(database: D) -> Self {
+        Self { database }
+    }
+}
+
+#[async_trait]
+impl<D: Database> RateLimitStore for DatabaseRateLimitStore<D> {
+    async fn acquire(&self, key: &str, quota: &Quota) -> Result<RateLimitStatus, DatabaseError> {
+        for _ in 0..MAX_BUCKET_UPDATE_ATTEMPTS {
+            let bucket = self.database.get_rate_limit_bucket(key).await?;
+            let (new_bucket, status) = quota.take(bucket.as_ref(), SystemTime::now());
+
+            // full buckets are no different from missing ones, so they need
+            // not be retained any longer
+            match self
+                .database
+                .replace_rate_limit_bucket(key, bucket.as_ref(), new_bucket, status.reset_after)
+                .await
+            {
+                Ok(()) => return Ok(status),
+                Err(DatabaseError::Conflict) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(DatabaseError::Unavailable(
+            "too many concurrent updates of rate limit bucket".into(),
+        ))
+    }
+}
+
+/// Configuration of rate limiting.
+///
+/// When set in the [`ServerState`], the [`rate_limit`] middleware limits
+/// requests to each route with a quota.
+#[derive(Clone)]
+#[allow(clippy::module_name_repetitions)]
+pub struct RateLimiting {
+    store: Arc<dyn RateLimitStore>,
+    default_quota: Option<Quota>,
+    route_quotas: HashMap<String, Quota>,
+}
+
+impl RateLimiting {
+    /// Creates a configuration with no quotas, keeping token buckets in the
+    /// given store.
+    pub fn new(store: Arc<dyn RateLimitStore>) -> Self {
+        Self {
+            store,
+            default_quota: None,
+            route_quotas: HashMap::new(),
+        }
+    }
+
+    /// Sets the quota for routes without one of their own.
+    #[must_use]
+    pub fn with_default_quota(mut self, quota: Quota) -> Self {
+        self.default_quota = Some(quota);
+        self
+    }
+
+    /// Sets the quota for a route, given as its path in the router, such as
+    /// `/login`.
+    #[must_use]
+    pub fn with_route_quota(mut self, route: impl Into<String>, quota: Quota) -> Self {
+        self.route_quotas.insert(route.into(), quota);
+        self
+    }
+}
+
+/// Middleware which limits the rate of requests according to the
+/// [`RateLimiting`] configuration of the server state, for use with
+/// [`axum::middleware::from_fn_with_state`] as a route layer.
+///
+/// Requests are let through if the limit cannot be checked, e.g. due to the
+/// store being unavailable.
+pub async fn rate_limit<D: Database, B: Send>(
+    State(state): State<ServerState<D>>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let Some(rate_limiting) = state.rate_limiting() else {
+        return next.run(request).await;
+    };
+
+    let route = route(&request);
+    let (bucket_route, quota) = match rate_limiting.route_quotas.get(&route) {
+        Some(quota) => (route.as_str(), quota),
+        None => match &rate_limiting.default_quota {
+            Some(quota) => ("*", quota),
+            None => return next.run(request).await,
+        },
+    };
+
+    let ip = connect_info.map(|ConnectInfo(address)| address.ip());
+    let client = client_key(&state, quota.key, request.headers(), ip);
+    let status = match rate_limiting
+        .store
+        .acquire(&format!("{bucket_route} {client}"), quota)
+        .await
+    {
+        Ok(status) => status,
+        Err(e) => {
+            warn!("could not check rate limit: {e}");
+            return next.run(request).await;
+        }
+    };
+
+    let mut response = if status.allowed {
+        next.run(request).await
+    } else {
+        info!("rejected request exceeding rate limit");
+        let mut response = StatusCode::TOO_MANY_REQUESTS.into_response();
+        if let Some(retry_after) = status.retry_after {
+            response.headers_mut().insert(
+                header::RETRY_AFTER,
+                HeaderValue::from(whole_seconds(retry_after)),
+            );
+        }
+        response
+    };
+
+    let headers = response.headers_mut();
+    for (name, value) in [
+        ("ratelimit-limit", quota.capacity.to_string()),
+        ("ratelimit-remaining", status.remaining.to_string()),
+        (
+            "ratelimit-reset",
+            whole_seconds(status.reset_after).to_string(),
+        ),
+        (
+            "ratelimit-policy",
+            format!("{};w={}", quota.capacity, whole_seconds(quota.period)),
+        ),
+    ] {
+        headers.insert(
+            HeaderName::from_static(name),
+            HeaderValue::try_from(value).expect("rate limit header values are numeric"),
+        );
+    }
+
+    response
+}
+
+/// Returns the path of the route a request was routed to, relative to the
+/// router the middleware is applied to, even if that router is nested.
+fn route<B>(request: &Request<B>) -> String {
+    let path = request.uri().path();
+    let Some(matched_path) = request.extensions().get::<MatchedPath>() else {
+        return path.to_string();
+    };
+
+    // the matched path includes the prefixes of nested routers, which are
+    // stripped from the request's URI
+    let original_path = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map_or(path, |OriginalUri(uri)| uri.path());
+    let prefix = original_path
+        .strip_suffix(path)
+        .unwrap_or_default()
+        .trim_end_matches('/');
+
+    matched_path
+        .as_str()
+        .strip_prefix(prefix)
+        .unwrap_or(matched_path.as_str())
+        .to_string()
+}
+
+/// Returns the key which identifies the client making a request.
+fn client_key<D: Database>(
+    state: &ServerState<D>,
+    key: RateLimitKey,
+    headers: &HeaderMap,
+    ip: Option<IpAddr>,
+) -> String {
+    match key {
+        RateLimitKey::Ip => {}
+        RateLimitKey::User => {
+            let user_id = headers
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .and_then(|token| state.token_manager().decode_token(token).ok())
+                .map(|payload| payload.sub);
+            if let Some(user_id) = user_id {
+                return format!("user:{user_id}");
+            }
+        }
+        RateLimitKey::ApiKey => {
+            if let Some(api_key) = auth::request_api_key(headers) {
+                return format!("api_key:{}", token::hash_token_string(api_key));
+            }
+        }
+    }
+
+    ip.map_or_else(|| "ip:unknown".to_string(), throttling::ip_key)
+}
+
+/// Returns the number of seconds in a duration, rounded up.
+fn whole_seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
@@ -0,0 +1,125 @@
+//! Role-based access control.
+//!
+//! Users have roles, such as [`ADMIN_ROLE`], and fine-grained permissions,
+//! which are embedded into their API tokens as the `roles` and `scope`
+//! claims. Routes can require a role by taking a [`RequireRole`] as a handler
+//! argument, or by protecting a whole router with it:
+//!
+//! ```ignore
+//! let admin_router = Router::new()
+//!     .route("/reports", get(handler))
+//!     .route_layer(middleware::from_extractor_with_state::<RequireRole<Admin>, _>(
+//!         state.clone(),
+//!     ));
+//! ```
+//!
+//! Permissions can be required in the same way with [`RequirePermission`], or
+//! checked with [`AuthenticatedUser::has_permission`].
+
+use crate::{
+    auth::{AuthenticatedUser, AuthenticationRejection},
+    database::Database,
+    ServerState,
+};
+use axum::{async_trait, extract::FromRequestParts, http::request::Parts};
+use std::marker::PhantomData;
+use tracing::info;
+
+/// Role which every newly registered user has.
+pub const DEFAULT_ROLE: &str = "user";
+
+/// Role of administrators, who can assign roles to users.
+pub const ADMIN_ROLE: &str = "admin";
+
+/// Role of support staff.
+pub const SUPPORT_ROLE: &str = "support";
+
+/// Trait for types naming a role which [`RequireRole`] requires.
+pub trait Role: Send + Sync {
+    /// The name of the role.
+    const NAME: &'static str;
+}
+
+/// The [`ADMIN_ROLE`].
+pub struct Admin;
+
+impl Role for Admin {
+    const NAME: &'static str = ADMIN_ROLE;
+}
+
+/// The [`SUPPORT_ROLE`].
+pub struct Support;
+
+impl Role for Support {
+    const NAME: &'static str = SUPPORT_ROLE;
+}
+
+/// Trait for types naming a permission which [`RequirePermission`] requires.
+pub trait Permission: Send + Sync {
+    /// The name of the permission.
+    const NAME: &'static str;
+}
+
+/// A user who authenticated a request with a valid API token granting the
+/// role `R`.
+///
+/// Extracting this rejects the request like [`AuthenticatedUser`] does, or
+/// with `403 Forbidden` if the token does not grant the role.
+pub struct RequireRole<R: Role> {
+    pub user: AuthenticatedUser,
+    role: PhantomData<R>,
+}
+
+#[async_trait]
+impl<D: Database, R: Role> FromRequestParts<ServerState<D>> for RequireRole<R> {
+    type Rejection = AuthenticationRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ServerState<D>,
+    ) -> Result<Self, Self::Rejection> {
+        let user = AuthenticatedUser::from_request_parts(parts, state).await?;
+
+        if !user.has_role(R::NAME) {
+            info!("rejected token lacking the {} role", R::NAME);
+            return Err(AuthenticationRejection::Forbidden);
+        }
+
+        Ok(Self {
+            user,
+            role: PhantomData,
+        })
+    }
+}
+
+/// A user who authenticated a request with a valid API token granting the
+/// permission `P`.
+///
+/// Extracting this rejects the request like [`AuthenticatedUser`] does, or
+/// with `403 Forbidden` if the token does not grant the permission.
+pub struct RequirePermission<P: Permission> {
+    pub user: AuthenticatedUser,
+    permission: PhantomData<P>,
+}
+
+#[async_trait]
+impl<D: Database, P: Permission> FromRequestParts<ServerState<D>> for RequirePermission<P> {
+    type Rejection = AuthenticationRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ServerState<D>,
+    ) -> Result<Self, Self::Rejection> {
+        let user = AuthenticatedUser::from_request_parts(parts, state).await?;
+
+        if !user.has_permission(P::NAME) {
+            info!("rejected token lacking the {} permission", P::NAME);
+            return Err(AuthenticationRejection::Forbidden);
+        }
+
+        Ok(Self {
+            user,
+            permission: PhantomData,
+        })
+    }
+}
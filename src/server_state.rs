@@ -1,4 +1,12 @@
-use crate::{database::Database, token::TokenManager};
+use crate::{
+    database::Database,
+    mail::Mailer,
+    password::{BreachedPasswords, PasswordPolicy},
+    rate_limit::RateLimiting,
+    throttling::LoginThrottling,
+    token::TokenManager,
+    verification::{EmailVerification, UnverifiedLogin},
+};
 use std::sync::Arc;
 
 /// The internal state of the server.
@@ -12,6 +20,26 @@ pub struct ServerState<D: Database> {
 
     /// Token manager for API JWTs.
     token_manager: Arc<TokenManager>,
+
+    /// Mailer for messages to users, such as password reset codes.
+    mailer: Option<Arc<dyn Mailer>>,
+
+    /// Configuration of e-mail verification, if users are to verify their
+    /// e-mail address.
+    email_verification: Option<EmailVerification>,
+
+    /// Policy which new passwords must follow.
+    password_policy: Arc<PasswordPolicy>,
+
+    /// List of breached passwords, which new passwords must not be in.
+    breached_passwords: Option<Arc<BreachedPasswords>>,
+
+    /// Configuration of login throttling, if failed logins are to be
+    /// throttled.
+    login_throttling: Option<LoginThrottling>,
+
+    /// Configuration of rate limiting, if requests are to be rate limited.
+    rate_limiting: Option<RateLimiting>,
 }
 
 impl<D: Database> ServerState<D> {
@@ -20,9 +48,63 @@ impl<D: Database> ServerState<D> {
         Self {
             database,
             token_manager: Arc::new(token_manager),
+            mailer: None,
+            email_verification: None,
+            password_policy: Arc::default(),
+            breached_passwords: None,
+            login_throttling: None,
+            rate_limiting: None,
         }
     }
 
+    /// Sets the mailer used to send messages to users. Without one, endpoints
+    /// which send e-mail are unavailable.
+    #[must_use]
+    pub fn with_mailer(mut self, mailer: Arc<dyn Mailer>) -> Self {
+        self.mailer = Some(mailer);
+        self
+    }
+
+    /// Enables e-mail verification. Verification links are sent through the
+    /// mailer, so one must be set as well.
+    #[must_use]
+    pub fn with_email_verification(mut self, email_verification: EmailVerification) -> Self {
+        self.email_verification = Some(email_verification);
+        self
+    }
+
+    /// Sets the policy which passwords must follow when registering or
+    /// changing them. By default, passwords only need to be non-empty.
+    #[must_use]
+    pub fn with_password_policy(mut self, password_policy: PasswordPolicy) -> Self {
+        self.password_policy = Arc::new(password_policy);
+        self
+    }
+
+    /// Sets a list of breached passwords, which passwords must not be in when
+    /// registering or changing them.
+    #[must_use]
+    pub fn with_breached_passwords(mut self, breached_passwords: BreachedPasswords) -> Self {
+        self.breached_passwords = Some(Arc::new(breached_passwords));
+        self
+    }
+
+    /// Enables throttling of failed logins, which delays and eventually locks
+    /// out further logins to the same account or from the same IP address.
+    #[must_use]
+    pub fn with_login_throttling(mut self, login_throttling: LoginThrottling) -> Self {
+        self.login_throttling = Some(login_throttling);
+        self
+    }
+
+    /// Enables rate limiting of requests by the
+    /// [`rate_limit`](crate::rate_limit::rate_limit) middleware.
+    #[must_use]
+    pub fn with_rate_limiting(mut self, rate_limiting: RateLimiting) -> Self {
+        self.rate_limiting = Some(rate_limiting);
+        self
+    }
+
     pub fn database(&self) -> &D {
         &self.database
     }
@@ -30,4 +112,36 @@ impl<D: Database> ServerState<D> {
     pub fn token_manager(&self) -> Arc<TokenManager> {
         Arc::clone(&self.token_manager)
     }
+
+    pub fn mailer(&self) -> Option<Arc<dyn Mailer>> {
+        self.mailer.clone()
+    }
+
+    pub fn email_verification(&self) -> Option<&EmailVerification> {
+        self.email_verification.as_ref()
+    }
+
+    pub fn password_policy(&self) -> &PasswordPolicy {
+        &self.password_policy
+    }
+
+    pub fn breached_passwords(&self) -> Option<Arc<BreachedPasswords>> {
+        self.breached_passwords.clone()
+    }
+
+    pub fn login_throttling(&self) -> Option<&LoginThrottling> {
+        self.login_throttling.as_ref()
+    }
+
+    pub fn rate_limiting(&self) -> Option<&RateLimiting> {
+        self.rate_limiting.as_ref()
+    }
+
+    /// Returns whether API tokens of users who have not verified their e-mail
+    /// address are restricted.
+    pub(crate) fn restricts_unverified_users(&self) -> bool {
+        self.email_verification
+            .as_ref()
+            .is_some_and(|v| v.unverified_login != UnverifiedLogin::Allow)
+    }
 }
@@ -0,0 +1,197 @@
+//! Throttling of failed logins.
+//!
+//! Failed logins are counted per account and per client IP address. Once
+//! more than a few logins have failed, further attempts are delayed
+//! exponentially, and after too many failures, logins are locked out
+//! entirely for a while. Blocked attempts are rejected before the password is
+//! checked, so that guessing passwords does not cost any hashing.
+
+use crate::database::{Database, DatabaseError};
+use std::{
+    net::IpAddr,
+    time::{Duration, SystemTime},
+};
+use tracing::warn;
+
+/// Configuration of login throttling.
+///
+/// When set in the [`ServerState`](crate::ServerState), failed logins delay
+/// and eventually lock out further logins.
+#[derive(Clone)]
+pub struct LoginThrottling {
+    /// Limits on failed logins to a single account.
+    pub per_account: FailureLimits,
+
+    /// Limits on failed logins from a single client IP address. IPv6
+    /// addresses are grouped by their /64 prefix, as clients are commonly
+    /// assigned a whole prefix.
+    pub per_ip: FailureLimits,
+}
+
+/// Limits on failed logins for a single account or client.
+#[derive(Clone)]
+pub struct FailureLimits {
+    /// Number of failed logins before further attempts are delayed.
+    pub free_failures: u32,
+
+    /// Delay after the first delayed failure, which is doubled with each
+    /// further failure.
+    pub base_delay: Duration,
+
+    /// Maximum delay between attempts before a lockout.
+    pub max_delay: Duration,
+
+    /// Number of failed logins which lock out further attempts, if any.
+    pub lockout_failures: Option<u32>,
+
+    /// Duration of a lockout.
+    pub lockout_duration: Duration,
+
+    /// Duration after the latest failed login after which failures are no
+    /// longer counted. Extended to the lockout duration if shorter.
+    pub retention: Duration,
+}
+
+impl FailureLimits {
+    /// Returns how long attempts are blocked after the latest failed login,
+    /// given the number of failed logins.
+    #[must_use]
+    pub fn block_duration(&self, failures: u32) -> Option<Duration> {
+        if self.is_lockout(failures) {
+            return Some(self.lockout_duration);
+        }
+
+        let delayed_failures = failures.checked_sub(self.free_failures)?.checked_sub(1)?;
+        let factor = 2u32.checked_pow(delayed_failures).unwrap_or(u32::MAX);
+        Some(
+            self.base_delay
+                .checked_mul(factor)
+                .unwrap_or(Duration::MAX)
+                .min(self.max_delay),
+        )
+    }
+
+    /// Returns whether the number of failed logins locks out further
+    /// attempts.
+    fn is_lockout(&self, failures: u32) -> bool {
+        self.lockout_failures
+            .is_some_and(|lockout_failures| failures >= lockout_failures)
+    }
+
+    fn retention(&self) -> Duration {
+        self.retention.max(self.lockout_duration)
+    }
+}
+
+/// A subject whose failed logins are counted.
+#[derive(Clone, Copy)]
+pub(crate) enum LoginSubject<'a> {
+    Account(&'a str),
+    Ip(IpAddr),
+}
+
+impl LoginSubject<'_> {
+    /// Returns the key under which failures are counted in the database.
+    pub(crate) fn key(&self) -> String {
+        match self {
+            Self::Account(email) => format!("account:{email}"),
+            Self::Ip(ip) => ip_key(*ip),
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Self::Account(_) => "account",
+            Self::Ip(_) => "IP address",
+        }
+    }
+}
+
+/// Returns a key identifying the client with an IP address. IPv6 addresses
+/// are grouped by their /64 prefix, as clients are commonly assigned a whole
+/// prefix.
+pub(crate) fn ip_key(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => format!("ip:{ip}"),
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            format!(
+                "ip:{:x}:{:x}:{:x}:{:x}::/64",
+                segments[0], segments[1], segments[2], segments[3]
+            )
+        }
+    }
+}
+
+impl LoginThrottling {
+    fn limits(&self, subject: LoginSubject) -> &FailureLimits {
+        match subject {
+            LoginSubject::Account(_) => &self.per_account,
+            LoginSubject::Ip(_) => &self.per_ip,
+        }
+    }
+
+    /// Returns how much longer logins by any of the subjects are blocked, if
+    /// at all.
+    pub(crate) async fn blocked_for(
+        &self,
+        database: &impl Database,
+        subjects: &[LoginSubject<'_>],
+    ) -> Result<Option<Duration>, DatabaseError> {
+        let now = SystemTime::now();
+        let mut blocked_for = None;
+
+        for &subject in subjects {
+            let Some(failures) = database.get_login_failures(&subject.key()).await? else {
+                continue;
+            };
+            let remaining = self
+                .limits(subject)
+                .block_duration(failures.count)
+                .and_then(|duration| (failures.last_failure + duration).duration_since(now).ok())
+                .filter(|remaining| !remaining.is_zero());
+            blocked_for = blocked_for.max(remaining);
+        }
+
+        Ok(blocked_for)
+    }
+
+    /// Counts a failed login for each of the subjects, and returns how long
+    /// further logins are blocked as a result, if at all.
+    pub(crate) async fn record_failure(
+        &self,
+        database: &impl Database,
+        subjects: &[LoginSubject<'_>],
+    ) -> Result<Option<Duration>, DatabaseError> {
+        let now = SystemTime::now();
+        let mut blocked_for = None;
+
+        for &subject in subjects {
+            let limits = self.limits(subject);
+            let failures = database
+                .record_login_failure(&subject.key(), now, limits.retention())
+                .await?;
+            if limits.lockout_failures == Some(failures.count) {
+                warn!(
+                    "locked out logins to {} for {} seconds after {} failed logins",
+                    subject.description(),
+                    limits.lockout_duration.as_secs(),
+                    failures.count
+                );
+            }
+            blocked_for = blocked_for.max(limits.block_duration(failures.count));
+        }
+
+        Ok(blocked_for)
+    }
+
+    /// Resets the failed logins counted for a subject after a successful
+    /// login.
+    pub(crate) async fn record_success(
+        &self,
+        database: &impl Database,
+        subject: LoginSubject<'_>,
+    ) -> Result<(), DatabaseError> {
+        database.clear_login_failures(&subject.key()).await
+    }
+}
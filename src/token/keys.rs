@@ -0,0 +1,485 @@
+//! Keys for signing and verifying JSON web tokens.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    decode, encode, errors::Error, Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use simple_asn1::{oid, ASN1Block};
+use std::fmt::{self, Display, Formatter};
+
+/// A family of signing algorithms which share a kind of key.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KeyFamily {
+    /// HMAC algorithms (`HS*`), using a shared secret.
+    Hmac,
+
+    /// RSA algorithms (`RS*` and `PS*`), using an RSA key pair.
+    Rsa,
+
+    /// Elliptic curve algorithms (`ES*`), using an EC key pair.
+    Ec,
+
+    /// The `EdDSA` algorithm, using an Ed25519 key pair.
+    Ed25519,
+}
+
+impl KeyFamily {
+    /// Returns the family of keys used by a signing algorithm.
+    #[must_use]
+    pub fn of(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => Self::Hmac,
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512 => Self::Rsa,
+            Algorithm::ES256 | Algorithm::ES384 => Self::Ec,
+            Algorithm::EdDSA => Self::Ed25519,
+        }
+    }
+}
+
+impl Display for KeyFamily {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Hmac => write!(f, "HMAC"),
+            Self::Rsa => write!(f, "RSA"),
+            Self::Ec => write!(f, "EC"),
+            Self::Ed25519 => write!(f, "Ed25519"),
+        }
+    }
+}
+
+/// An error which occurred while loading a key or managing a keyring.
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub enum KeyError {
+    /// The kind of key provided is not used by the signing algorithm.
+    AlgorithmMismatch(Algorithm),
+
+    /// A key could not be parsed.
+    InvalidKey(Error),
+
+    /// A public key could not be parsed into a JSON web key.
+    InvalidPublicKey(String),
+
+    /// The private and public key do not belong together, or do not suit the
+    /// signing algorithm.
+    KeyPairMismatch(Error),
+
+    /// A keyring already contains a key with the same key id.
+    DuplicateKeyId(String),
+}
+
+impl Display for KeyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AlgorithmMismatch(algorithm) => write!(
+                f,
+                "signing algorithm {algorithm:?} requires a {} key",
+                KeyFamily::of(*algorithm)
+            ),
+            Self::InvalidKey(error) => write!(f, "invalid key: {error}"),
+            Self::InvalidPublicKey(reason) => write!(f, "invalid public key: {reason}"),
+            Self::KeyPairMismatch(error) => write!(
+                f,
+                "key pair cannot sign and verify tokens with the signing algorithm: {error}"
+            ),
+            Self::DuplicateKeyId(key_id) => write!(f, "duplicate key id {key_id:?}"),
+        }
+    }
+}
+
+impl std::error::Error for KeyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::InvalidKey(error) | Self::KeyPairMismatch(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// A key for verifying tokens signed with a particular algorithm.
+#[allow(clippy::module_name_repetitions)]
+pub struct VerificationKey {
+    key_id: String,
+    algorithm: Algorithm,
+    decoding_key: DecodingKey,
+
+    /// The public key as a JSON web key, unless the key is a secret.
+    jwk: Option<Value>,
+}
+
+impl VerificationKey {
+    /// Creates a key for an HMAC algorithm from a shared secret.
+    ///
+    /// The key id defaults to a prefix of the secret's SHA-256 hash.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the algorithm is not an HMAC algorithm.
+    pub fn from_secret(algorithm: Algorithm, secret: &[u8]) -> Result<Self, KeyError> {
+        if KeyFamily::of(algorithm) != KeyFamily::Hmac {
+            return Err(KeyError::AlgorithmMismatch(algorithm));
+        }
+
+        let mut key_id = URL_SAFE_NO_PAD.encode(Sha256::digest(secret));
+        key_id.truncate(16);
+
+        Ok(Self {
+            key_id,
+            algorithm,
+            decoding_key: DecodingKey::from_secret(secret),
+            jwk: None,
+        })
+    }
+
+    /// Creates a key for an asymmetric algorithm from a PEM-encoded public
+    /// key.
+    ///
+    /// The key id defaults to the key's JWK thumbprint, as per RFC 7638.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the algorithm is an HMAC algorithm, or if the key
+    /// cannot be parsed as a key of the algorithm's family.
+    pub fn from_pem(algorithm: Algorithm, public_key_pem: &[u8]) -> Result<Self, KeyError> {
+        let decoding_key = match KeyFamily::of(algorithm) {
+            KeyFamily::Hmac => return Err(KeyError::AlgorithmMismatch(algorithm)),
+            KeyFamily::Rsa => DecodingKey::from_rsa_pem(public_key_pem),
+            KeyFamily::Ec => DecodingKey::from_ec_pem(public_key_pem),
+            KeyFamily::Ed25519 => DecodingKey::from_ed_pem(public_key_pem),
+        }
+        .map_err(KeyError::InvalidKey)?;
+
+        let key_parameters = public_key_parameters(algorithm, public_key_pem)?;
+        let thumbprint = URL_SAFE_NO_PAD.encode(Sha256::digest(key_parameters.to_string()));
+
+        let mut key = Self {
+            key_id: String::new(),
+            algorithm,
+            decoding_key,
+            jwk: Some(key_parameters),
+        };
+        key.set_key_id(thumbprint);
+        Ok(key)
+    }
+
+    /// Replaces the default key id.
+    #[must_use]
+    pub fn with_key_id(mut self, key_id: impl Into<String>) -> Self {
+        self.set_key_id(key_id.into());
+        self
+    }
+
+    #[must_use]
+    pub fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    #[must_use]
+    pub fn algorithm(&self) -> Algorithm {
+        self.algorithm
+    }
+
+    /// Returns the public key as a JSON web key, or `None` if the key is a
+    /// secret which must not be published.
+    #[must_use]
+    pub fn jwk(&self) -> Option<&Value> {
+        self.jwk.as_ref()
+    }
+
+    pub(crate) fn decoding_key(&self) -> &DecodingKey {
+        &self.decoding_key
+    }
+
+    fn set_key_id(&mut self, key_id: String) {
+        if let Some(jwk) = &mut self.jwk {
+            jwk["kid"] = key_id.clone().into();
+            jwk["alg"] = json!(self.algorithm);
+            jwk["use"] = "sig".into();
+        }
+        self.key_id = key_id;
+    }
+}
+
+/// A key for signing tokens with a particular algorithm, along with the key
+/// for verifying them.
+#[allow(clippy::module_name_repetitions)]
+pub struct SigningKey {
+    encoding_key: EncodingKey,
+    verification_key: VerificationKey,
+}
+
+impl SigningKey {
+    /// Creates a key for an HMAC algorithm from a shared secret.
+    ///
+    /// The key id defaults to a prefix of the secret's SHA-256 hash.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the algorithm is not an HMAC algorithm.
+    pub fn from_secret(algorithm: Algorithm, secret: &[u8]) -> Result<Self, KeyError> {
+        Self::new(
+            EncodingKey::from_secret(secret),
+            VerificationKey::from_secret(algorithm, secret)?,
+        )
+    }
+
+    /// Creates a key for an asymmetric algorithm from a PEM-encoded private
+    /// key and the matching PEM-encoded public key.
+    ///
+    /// The key id defaults to the public key's JWK thumbprint, as per
+    /// RFC 7638.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the algorithm is an HMAC algorithm, if either key
+    /// cannot be parsed as a key of the algorithm's family, or if the keys do
+    /// not form a working key pair for the algorithm.
+    pub fn from_pem(
+        algorithm: Algorithm,
+        private_key_pem: &[u8],
+        public_key_pem: &[u8],
+    ) -> Result<Self, KeyError> {
+        let encoding_key = match KeyFamily::of(algorithm) {
+            KeyFamily::Hmac => return Err(KeyError::AlgorithmMismatch(algorithm)),
+            KeyFamily::Rsa => EncodingKey::from_rsa_pem(private_key_pem),
+            KeyFamily::Ec => EncodingKey::from_ec_pem(private_key_pem),
+            KeyFamily::Ed25519 => EncodingKey::from_ed_pem(private_key_pem),
+        }
+        .map_err(KeyError::InvalidKey)?;
+
+        Self::new(
+            encoding_key,
+            VerificationKey::from_pem(algorithm, public_key_pem)?,
+        )
+    }
+
+    /// Replaces the default key id.
+    #[must_use]
+    pub fn with_key_id(mut self, key_id: impl Into<String>) -> Self {
+        self.verification_key = self.verification_key.with_key_id(key_id);
+        self
+    }
+
+    #[must_use]
+    pub fn key_id(&self) -> &str {
+        self.verification_key.key_id()
+    }
+
+    /// Returns the algorithm this key signs tokens with.
+    #[must_use]
+    pub fn algorithm(&self) -> Algorithm {
+        self.verification_key.algorithm()
+    }
+
+    /// Returns the key for verifying tokens signed with this key.
+    #[must_use]
+    pub fn verification_key(&self) -> &VerificationKey {
+        &self.verification_key
+    }
+
+    pub(crate) fn encoding_key(&self) -> &EncodingKey {
+        &self.encoding_key
+    }
+
+    /// Creates a key, making sure that it can verify tokens it signed.
+    fn new(encoding_key: EncodingKey, verification_key: VerificationKey) -> Result<Self, KeyError> {
+        #[derive(Serialize, Deserialize)]
+        struct TestClaims {
+            test: bool,
+        }
+
+        let algorithm = verification_key.algorithm();
+        let mut validation = Validation::new(algorithm);
+        validation.required_spec_claims.clear();
+        validation.validate_exp = false;
+
+        let token = encode(
+            &Header::new(algorithm),
+            &TestClaims { test: true },
+            &encoding_key,
+        )
+        .map_err(KeyError::KeyPairMismatch)?;
+        decode::<TestClaims>(&token, verification_key.decoding_key(), &validation)
+            .map_err(KeyError::KeyPairMismatch)?;
+
+        Ok(Self {
+            encoding_key,
+            verification_key,
+        })
+    }
+}
+
+/// A set of keys consisting of one active key for signing new tokens and any
+/// number of retired keys which are only used to verify tokens signed before
+/// they were retired.
+pub struct Keyring {
+    signing_key: SigningKey,
+    retired_keys: Vec<VerificationKey>,
+}
+
+impl Keyring {
+    /// Creates a keyring with no retired keys.
+    #[must_use]
+    pub fn new(signing_key: SigningKey) -> Self {
+        Self {
+            signing_key,
+            retired_keys: Vec::new(),
+        }
+    }
+
+    /// Returns the key used to sign new tokens.
+    #[must_use]
+    pub fn signing_key(&self) -> &SigningKey {
+        &self.signing_key
+    }
+
+    /// Returns the retired keys, which are still used to verify tokens.
+    #[must_use]
+    pub fn retired_keys(&self) -> &[VerificationKey] {
+        &self.retired_keys
+    }
+
+    /// Adds a retired key which is only used to verify tokens.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the keyring already contains a key with the same
+    /// key id.
+    pub fn add_retired_key(&mut self, key: VerificationKey) -> Result<(), KeyError> {
+        if self.find(key.key_id()).is_some() {
+            return Err(KeyError::DuplicateKeyId(key.key_id().to_string()));
+        }
+
+        self.retired_keys.push(key);
+        Ok(())
+    }
+
+    /// Makes a new key the signing key. The previous signing key is retired
+    /// and remains available for verifying tokens it signed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the keyring already contains a key with the same
+    /// key id.
+    pub fn rotate(&mut self, signing_key: SigningKey) -> Result<(), KeyError> {
+        if self.find(signing_key.key_id()).is_some() {
+            return Err(KeyError::DuplicateKeyId(signing_key.key_id().to_string()));
+        }
+
+        let previous_key = std::mem::replace(&mut self.signing_key, signing_key);
+        self.retired_keys.push(previous_key.verification_key);
+        Ok(())
+    }
+
+    /// Removes a retired key, after which tokens signed with it are no longer
+    /// accepted. Returns whether such a key existed.
+    pub fn remove_retired_key(&mut self, key_id: &str) -> bool {
+        let key_count = self.retired_keys.len();
+        self.retired_keys.retain(|key| key.key_id() != key_id);
+        self.retired_keys.len() != key_count
+    }
+
+    /// Returns every key which verifies tokens, starting with the signing
+    /// key.
+    pub fn verification_keys(&self) -> impl Iterator<Item = &VerificationKey> {
+        std::iter::once(self.signing_key.verification_key()).chain(&self.retired_keys)
+    }
+
+    /// Finds the key with the given key id which verifies tokens.
+    #[must_use]
+    pub fn find(&self, key_id: &str) -> Option<&VerificationKey> {
+        self.verification_keys().find(|key| key.key_id() == key_id)
+    }
+
+    /// Returns the public keys of the keyring as a JSON web key set. Secret
+    /// keys are omitted.
+    #[must_use]
+    pub fn jwks(&self) -> Value {
+        let keys = self
+            .verification_keys()
+            .filter_map(VerificationKey::jwk)
+            .collect::<Vec<_>>();
+
+        json!({ "keys": keys })
+    }
+}
+
+/// Extracts the required public key parameters of a JSON web key, as per
+/// RFC 7638, from a PEM-encoded `SubjectPublicKeyInfo` structure.
+fn public_key_parameters(algorithm: Algorithm, public_key_pem: &[u8]) -> Result<Value, KeyError> {
+    let invalid = |reason: &str| KeyError::InvalidPublicKey(reason.to_string());
+
+    let pem = pem::parse(public_key_pem).map_err(|e| invalid(&e.to_string()))?;
+    let blocks = simple_asn1::from_der(&pem.contents).map_err(|e| invalid(&e.to_string()))?;
+    let Some(ASN1Block::Sequence(_, subject_public_key_info)) = blocks.first() else {
+        return Err(invalid("not a SubjectPublicKeyInfo structure"));
+    };
+    let [ASN1Block::Sequence(_, algorithm_identifier), ASN1Block::BitString(_, _, public_key)] =
+        subject_public_key_info.as_slice()
+    else {
+        return Err(invalid("not a SubjectPublicKeyInfo structure"));
+    };
+
+    match (KeyFamily::of(algorithm), algorithm_identifier.as_slice()) {
+        (KeyFamily::Rsa, [ASN1Block::ObjectIdentifier(_, key_type), ..])
+            if *key_type == oid!(1, 2, 840, 113_549, 1, 1, 1) =>
+        {
+            let blocks = simple_asn1::from_der(public_key).map_err(|e| invalid(&e.to_string()))?;
+            let Some(ASN1Block::Sequence(_, integers)) = blocks.first() else {
+                return Err(invalid("malformed RSA public key"));
+            };
+            let [ASN1Block::Integer(_, modulus), ASN1Block::Integer(_, exponent)] =
+                integers.as_slice()
+            else {
+                return Err(invalid("malformed RSA public key"));
+            };
+
+            Ok(json!({
+                "kty": "RSA",
+                "n": URL_SAFE_NO_PAD.encode(modulus.to_bytes_be().1),
+                "e": URL_SAFE_NO_PAD.encode(exponent.to_bytes_be().1),
+            }))
+        }
+        (
+            KeyFamily::Ec,
+            [ASN1Block::ObjectIdentifier(_, key_type), ASN1Block::ObjectIdentifier(_, curve)],
+        ) if *key_type == oid!(1, 2, 840, 10_045, 2, 1) => {
+            let (curve_name, coordinate_length) = if *curve == oid!(1, 2, 840, 10_045, 3, 1, 7) {
+                ("P-256", 32)
+            } else if *curve == oid!(1, 3, 132, 0, 34) {
+                ("P-384", 48)
+            } else {
+                return Err(invalid("unsupported elliptic curve"));
+            };
+            if public_key.len() != 1 + 2 * coordinate_length || public_key[0] != 0x04 {
+                return Err(invalid("EC public key is not an uncompressed point"));
+            }
+            let (x, y) = public_key[1..].split_at(coordinate_length);
+
+            Ok(json!({
+                "kty": "EC",
+                "crv": curve_name,
+                "x": URL_SAFE_NO_PAD.encode(x),
+                "y": URL_SAFE_NO_PAD.encode(y),
+            }))
+        }
+        (KeyFamily::Ed25519, [ASN1Block::ObjectIdentifier(_, key_type)])
+            if *key_type == oid!(1, 3, 101, 112) =>
+        {
+            Ok(json!({
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "x": URL_SAFE_NO_PAD.encode(public_key),
+            }))
+        }
+        _ => Err(KeyError::AlgorithmMismatch(algorithm)),
+    }
+}
@@ -0,0 +1,971 @@
+//! Generation of JSON web tokens for API access.
+
+mod keys;
+
+pub use keys::{KeyError, KeyFamily, Keyring, SigningKey, VerificationKey};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{
+    decode, decode_header, encode,
+    errors::{Error, ErrorKind},
+    Header, Validation,
+};
+use rand::RngCore;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::{
+    fmt::{self, Display, Formatter},
+    sync::RwLock,
+    time::{Duration, SystemTime},
+};
+use uuid::Uuid;
+
+use crate::database::{
+    ApiKey, AuthorizationCode, Database, DatabaseError, OAuthClient, PasswordReset, RefreshToken,
+    UserProfile,
+};
+
+/// Prefix of every API key, which tells them apart from other credentials
+/// at a glance.
+pub const API_KEY_PREFIX: &str = "ak_";
+
+/// Configurable manager for JSON web tokens for API access.
+#[allow(clippy::module_name_repetitions)]
+pub struct TokenManager {
+    lifetime: Duration,
+    lifetime_leeway: Duration,
+    refresh_lifetime: Duration,
+    password_reset_lifetime: Duration,
+    email_verification_lifetime: Duration,
+    authorization_code_lifetime: Duration,
+    keyring: RwLock<Keyring>,
+}
+
+impl TokenManager {
+    /// Default lifetime of a refresh token.
+    pub const DEFAULT_REFRESH_LIFETIME: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+    /// Default lifetime of a password reset code.
+    pub const DEFAULT_PASSWORD_RESET_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+    const DEFAULT_EMAIL_VERIFICATION_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+    const DEFAULT_AUTHORIZATION_CODE_LIFETIME: Duration = Duration::from_secs(60);
+
+    /// Creates a new token manager.
+    #[must_use]
+    pub fn new(lifetime: Duration, lifetime_leeway: Duration, signing_key: SigningKey) -> Self {
+        Self {
+            lifetime,
+            lifetime_leeway,
+            refresh_lifetime: Self::DEFAULT_REFRESH_LIFETIME,
+            password_reset_lifetime: Self::DEFAULT_PASSWORD_RESET_LIFETIME,
+            email_verification_lifetime: Self::DEFAULT_EMAIL_VERIFICATION_LIFETIME,
+            authorization_code_lifetime: Self::DEFAULT_AUTHORIZATION_CODE_LIFETIME,
+            keyring: RwLock::new(Keyring::new(signing_key)),
+        }
+    }
+
+    #[must_use]
+    pub fn lifetime(&self) -> Duration {
+        self.lifetime
+    }
+
+    #[must_use]
+    pub fn lifetime_mut(&mut self) -> &mut Duration {
+        &mut self.lifetime
+    }
+
+    #[must_use]
+    pub fn lifetime_leeway(&self) -> Duration {
+        self.lifetime_leeway
+    }
+
+    #[must_use]
+    pub fn lifetime_leeway_mut(&mut self) -> &mut Duration {
+        &mut self.lifetime_leeway
+    }
+
+    /// Lifetime of a refresh token. Defaults to 30 days.
+    #[must_use]
+    pub fn refresh_lifetime(&self) -> Duration {
+        self.refresh_lifetime
+    }
+
+    #[must_use]
+    pub fn refresh_lifetime_mut(&mut self) -> &mut Duration {
+        &mut self.refresh_lifetime
+    }
+
+    /// Lifetime of a password reset code. Defaults to 1 hour.
+    #[must_use]
+    pub fn password_reset_lifetime(&self) -> Duration {
+        self.password_reset_lifetime
+    }
+
+    #[must_use]
+    pub fn password_reset_lifetime_mut(&mut self) -> &mut Duration {
+        &mut self.password_reset_lifetime
+    }
+
+    /// Lifetime of an e-mail verification token. Defaults to 24 hours.
+    #[must_use]
+    pub fn email_verification_lifetime(&self) -> Duration {
+        self.email_verification_lifetime
+    }
+
+    #[must_use]
+    pub fn email_verification_lifetime_mut(&mut self) -> &mut Duration {
+        &mut self.email_verification_lifetime
+    }
+
+    /// Lifetime of an OAuth authorization code. Defaults to 1 minute.
+    #[must_use]
+    pub fn authorization_code_lifetime(&self) -> Duration {
+        self.authorization_code_lifetime
+    }
+
+    #[must_use]
+    pub fn authorization_code_lifetime_mut(&mut self) -> &mut Duration {
+        &mut self.authorization_code_lifetime
+    }
+
+    /// Returns when every token issued until now will have expired, including
+    /// the leeway, after which their revocations need not be retained.
+    #[must_use]
+    pub fn tokens_expire_at(&self) -> SystemTime {
+        SystemTime::now() + self.lifetime.max(self.refresh_lifetime) + self.lifetime_leeway
+    }
+
+    /// Makes a new key the signing key for new tokens. The previous signing
+    /// key is retired, so tokens it signed remain valid.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a key with the same key id is already known.
+    pub fn rotate_signing_key(&self, signing_key: SigningKey) -> Result<(), KeyError> {
+        self.keyring.write().unwrap().rotate(signing_key)
+    }
+
+    /// Adds a retired key, so that tokens signed with it are accepted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a key with the same key id is already known.
+    pub fn add_retired_key(&self, key: VerificationKey) -> Result<(), KeyError> {
+        self.keyring.write().unwrap().add_retired_key(key)
+    }
+
+    /// Removes a retired key, so that tokens signed with it are no longer
+    /// accepted. Returns whether such a key existed.
+    pub fn remove_retired_key(&self, key_id: &str) -> bool {
+        self.keyring.write().unwrap().remove_retired_key(key_id)
+    }
+
+    /// Returns the public keys used to verify tokens as a JSON web key set.
+    #[must_use]
+    pub fn jwks(&self) -> Value {
+        self.keyring.read().unwrap().jwks()
+    }
+
+    /// Creates a new token according to the `TokenManager` configuration,
+    /// granting the given roles and permissions.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if token encoding fails.
+    pub fn new_token(
+        &self,
+        user_id: Uuid,
+        roles: &[String],
+        permissions: &[String],
+    ) -> Result<String, Error> {
+        let payload = TokenPayload::new(user_id, self.lifetime, true, roles, permissions);
+        payload.encode(self.keyring.read().unwrap().signing_key())
+    }
+
+    /// Creates a new token for a user whose e-mail address has not been
+    /// verified. Such tokens are marked with a false `email_verified` claim.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if token encoding fails.
+    pub fn new_unverified_token(
+        &self,
+        user_id: Uuid,
+        roles: &[String],
+        permissions: &[String],
+    ) -> Result<String, Error> {
+        let payload = TokenPayload::new(user_id, self.lifetime, false, roles, permissions);
+        payload.encode(self.keyring.read().unwrap().signing_key())
+    }
+
+    /// Creates a new token which a user authorized an OAuth client to act
+    /// with on their behalf, granting only the given scopes and no roles.
+    /// Such tokens carry the client's id in a `client_id` claim.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if token encoding fails.
+    pub fn new_delegated_token(
+        &self,
+        user_id: Uuid,
+        client_id: &str,
+        scopes: &[String],
+        email_verified: bool,
+    ) -> Result<String, Error> {
+        let payload = TokenPayload {
+            client_id: Some(client_id.to_string()),
+            ..TokenPayload::new(user_id, self.lifetime, email_verified, &[], scopes)
+        };
+        payload.encode(self.keyring.read().unwrap().signing_key())
+    }
+
+    /// Creates a signed token which proves ownership of an e-mail address
+    /// when presented back, e.g. through a link sent to that address.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if token encoding fails.
+    pub fn new_email_verification_token(&self, user_email: &str) -> Result<String, Error> {
+        let payload = EmailVerificationPayload {
+            exp: unix_timestamp(SystemTime::now() + self.email_verification_lifetime),
+            verify_email: user_email.to_string(),
+        };
+        encode_claims(&payload, self.keyring.read().unwrap().signing_key())
+    }
+
+    /// Decodes an e-mail verification token, returning the e-mail address it
+    /// verifies.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if decoding fails or the token is invalid.
+    pub fn decode_email_verification_token(&self, token: &str) -> Result<String, TokenError> {
+        decode_claims::<EmailVerificationPayload>(
+            token,
+            &self.keyring.read().unwrap(),
+            self.lifetime_leeway,
+        )
+        .map(|payload| payload.verify_email)
+        .map_err(TokenError::Invalid)
+    }
+
+    /// Creates a signed token which confirms a change of a user's e-mail
+    /// address when presented back through a link sent to the new address.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if token encoding fails.
+    pub fn new_email_change_token(&self, change: &EmailChange) -> Result<String, Error> {
+        let payload = EmailChangePayload {
+            exp: unix_timestamp(SystemTime::now() + self.email_verification_lifetime),
+            change_email_of: change.user_id,
+            old_email: change.old_email.clone(),
+            new_email: change.new_email.clone(),
+        };
+        encode_claims(&payload, self.keyring.read().unwrap().signing_key())
+    }
+
+    /// Decodes an e-mail change token, returning the change it confirms.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if decoding fails or the token is invalid.
+    pub fn decode_email_change_token(&self, token: &str) -> Result<EmailChange, TokenError> {
+        decode_claims::<EmailChangePayload>(
+            token,
+            &self.keyring.read().unwrap(),
+            self.lifetime_leeway,
+        )
+        .map(|payload| EmailChange {
+            user_id: payload.change_email_of,
+            old_email: payload.old_email,
+            new_email: payload.new_email,
+        })
+        .map_err(TokenError::Invalid)
+    }
+
+    /// Creates a new opaque refresh token for a user.
+    ///
+    /// Returns the token to hand out to the client along with the record to
+    /// store in the database. If `family_id` is given, the new token
+    /// continues an existing token family; otherwise a new family is started.
+    #[must_use]
+    pub fn new_refresh_token(
+        &self,
+        user_id: Uuid,
+        family_id: Option<&str>,
+    ) -> (String, RefreshToken) {
+        let family_id = family_id.map_or_else(|| random_token_string(16), str::to_string);
+        let secret = random_token_string(32);
+
+        let record = RefreshToken {
+            family_id: family_id.clone(),
+            token_hash: hash_token_string(&secret),
+            user_id,
+            expires_at: unix_timestamp(SystemTime::now() + self.refresh_lifetime),
+            consumed: false,
+        };
+
+        (format!("{family_id}.{secret}"), record)
+    }
+
+    /// Creates a new single-use password reset code for a user.
+    ///
+    /// Returns the code to send to the user along with the record to store in
+    /// the database.
+    #[must_use]
+    pub fn new_password_reset(&self, user_id: Uuid) -> (String, PasswordReset) {
+        let code = random_token_string(32);
+
+        let record = PasswordReset {
+            code_hash: hash_token_string(&code),
+            user_id,
+            expires_at: unix_timestamp(SystemTime::now() + self.password_reset_lifetime),
+        };
+
+        (code, record)
+    }
+
+    /// Creates a new single-use OAuth authorization code, with which a client
+    /// obtains a token of the user who granted it the given scopes.
+    ///
+    /// Returns the code to redirect the user back to the client with along
+    /// with the record to store in the database.
+    #[must_use]
+    pub fn new_authorization_code(
+        &self,
+        client_id: &str,
+        user_id: Uuid,
+        redirect_uri: Option<&str>,
+        scopes: &[String],
+        code_challenge: &str,
+    ) -> (String, AuthorizationCode) {
+        let code = random_token_string(32);
+
+        let record = AuthorizationCode {
+            code_hash: hash_token_string(&code),
+            client_id: client_id.to_string(),
+            user_id,
+            redirect_uri: redirect_uri.map(str::to_string),
+            scopes: scopes.to_vec(),
+            code_challenge: code_challenge.to_string(),
+            expires_at: unix_timestamp(SystemTime::now() + self.authorization_code_lifetime),
+        };
+
+        (code, record)
+    }
+
+    /// Creates a new personal API key for a user, granting the given
+    /// permissions and expiring at the given unix timestamp, if any.
+    ///
+    /// Returns the key to hand out to the user along with the record to store
+    /// in the database.
+    #[must_use]
+    pub fn new_api_key(
+        &self,
+        user_id: Uuid,
+        name: &str,
+        scopes: &[String],
+        expires_at: Option<u64>,
+    ) -> (String, ApiKey) {
+        let key_id = format!("{API_KEY_PREFIX}{}", random_token_string(6));
+        let secret = random_token_string(32);
+
+        let record = ApiKey {
+            key_id: key_id.clone(),
+            key_hash: hash_token_string(&secret),
+            user_id,
+            name: name.to_string(),
+            scopes: scopes.to_vec(),
+            created_at: SystemTime::now(),
+            expires_at,
+        };
+
+        (format!("{key_id}.{secret}"), record)
+    }
+
+    /// Checks a personal API key and returns a payload describing it, as if
+    /// it were a token.
+    ///
+    /// The payload grants those of the key's scopes which the user is still
+    /// granted, whether as roles or as permissions. Keys remain valid until
+    /// they expire or are deleted, but are rejected while their user is
+    /// disabled.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key is malformed, unknown or expired, if its
+    /// user is disabled, or if it cannot be checked against the database.
+    pub async fn validate_api_key<D: Database>(
+        &self,
+        api_key: &str,
+        database: &D,
+    ) -> Result<TokenPayload, TokenError> {
+        let invalid = || TokenError::Invalid(ErrorKind::InvalidToken.into());
+        let (key_id, key_hash) = parse_api_key(api_key).ok_or_else(invalid)?;
+
+        let key = match database.get_api_key(&key_id).await {
+            Ok(key) => key,
+            Err(DatabaseError::NotFound) => return Err(invalid()),
+            Err(e) => return Err(TokenError::Database(e)),
+        };
+        if !crate::password::constant_time_eq(key.key_hash.as_bytes(), key_hash.as_bytes()) {
+            return Err(invalid());
+        }
+
+        let profile = match database.get_user(key.user_id).await {
+            Ok(profile) => profile,
+            Err(DatabaseError::NotFound) => return Err(invalid()),
+            Err(e) => return Err(TokenError::Database(e)),
+        };
+        if profile.disabled {
+            return Err(TokenError::Revoked);
+        }
+        let email_verified = match database.is_user_verified(&profile.email).await {
+            Ok(email_verified) => email_verified,
+            Err(DatabaseError::NotFound) => return Err(invalid()),
+            Err(e) => return Err(TokenError::Database(e)),
+        };
+
+        Ok(TokenPayload::for_api_key(&key, &profile, email_verified))
+    }
+
+    /// Creates a new access token for an OAuth client itself, as issued by the
+    /// client credentials grant, granting the given scopes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if token encoding fails.
+    pub fn new_client_token(&self, client_id: &str, scopes: &[String]) -> Result<String, Error> {
+        let now = SystemTime::now();
+        let payload = ClientTokenPayload {
+            exp: unix_timestamp(now + self.lifetime),
+            iat: unix_timestamp(now),
+            jti: random_token_string(16),
+            sub: client_id.to_string(),
+            client_id: client_id.to_string(),
+            scope: scopes.join(" "),
+            token_use: TokenUse::Client,
+        };
+        encode_claims(&payload, self.keyring.read().unwrap().signing_key())
+    }
+
+    /// Decodes an access token issued to an OAuth client itself.
+    ///
+    /// Such tokens cannot be revoked, so they are only as short-lived as the
+    /// configured token lifetime.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if decoding fails or the token is invalid, including
+    /// if it was issued to a user.
+    pub fn decode_client_token(&self, token: &str) -> Result<ClientTokenPayload, TokenError> {
+        let payload = decode_claims::<ClientTokenPayload>(
+            token,
+            &self.keyring.read().unwrap(),
+            self.lifetime_leeway,
+        )
+        .map_err(TokenError::Invalid)?;
+
+        if payload.token_use != TokenUse::Client {
+            return Err(TokenError::Invalid(ErrorKind::InvalidToken.into()));
+        }
+
+        Ok(payload)
+    }
+
+    /// Decodes a token into a payload according to the `TokenManager`
+    /// configuration, without checking whether it has been revoked.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if decoding fails or the token is invalid, including
+    /// if it was issued to an OAuth client itself.
+    pub fn decode_token(&self, token: &str) -> Result<TokenPayload, TokenError> {
+        let payload =
+            TokenPayload::decode(token, &self.keyring.read().unwrap(), self.lifetime_leeway)
+                .map_err(TokenError::Invalid)?;
+
+        if payload.token_use != TokenUse::User {
+            return Err(TokenError::Invalid(ErrorKind::InvalidToken.into()));
+        }
+
+        Ok(payload)
+    }
+
+    /// Decodes a token into a payload according to the `TokenManager`
+    /// configuration, and checks that it has not been revoked.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if decoding fails, if the token is invalid or revoked,
+    /// or if the revocation state cannot be retrieved from the database.
+    pub async fn decode_and_validate_token<D: Database>(
+        &self,
+        token: String,
+        database: &D,
+    ) -> Result<TokenPayload, TokenError> {
+        let payload = self.decode_token(&token)?;
+
+        if database
+            .is_token_revoked(&payload.jti, payload.sub, payload.issued_at())
+            .await
+            .map_err(TokenError::Database)?
+        {
+            return Err(TokenError::Revoked);
+        }
+
+        Ok(payload)
+    }
+
+    /// Revokes a single token until it would have expired anyway.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the revocation cannot be stored.
+    pub async fn revoke_token<D: Database>(
+        &self,
+        payload: &TokenPayload,
+        database: &D,
+    ) -> Result<(), DatabaseError> {
+        // tokens remain acceptable for the leeway duration past their expiry
+        let expires_at =
+            SystemTime::UNIX_EPOCH + Duration::from_secs(payload.exp) + self.lifetime_leeway;
+        database.revoke_token(&payload.jti, expires_at).await
+    }
+}
+
+/// An error which occurred while validating a token.
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub enum TokenError {
+    /// The token could not be decoded or failed validation.
+    Invalid(Error),
+
+    /// The token has been revoked.
+    Revoked,
+
+    /// The revocation state of the token could not be retrieved.
+    Database(DatabaseError),
+}
+
+impl Display for TokenError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(error) => write!(f, "invalid token: {error}"),
+            Self::Revoked => write!(f, "token has been revoked"),
+            Self::Database(error) => write!(f, "could not check token revocation: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Invalid(error) => Some(error),
+            Self::Database(error) => Some(error),
+            Self::Revoked => None,
+        }
+    }
+}
+
+/// Splits a refresh token into its family id and the hash of its secret, as
+/// stored in a [`RefreshToken`] record.
+///
+/// Returns `None` if the token is malformed.
+#[must_use]
+pub fn parse_refresh_token(token: &str) -> Option<(String, String)> {
+    let (family_id, secret) = token.split_once('.')?;
+    if family_id.is_empty() || secret.is_empty() {
+        return None;
+    }
+
+    Some((family_id.to_string(), hash_token_string(secret)))
+}
+
+/// Splits an API key into its key id and the hash of its secret, as stored
+/// in an [`ApiKey`] record.
+///
+/// Returns `None` if the key is malformed.
+#[must_use]
+pub fn parse_api_key(api_key: &str) -> Option<(String, String)> {
+    let (key_id, secret) = api_key.split_once('.')?;
+    if key_id.len() <= API_KEY_PREFIX.len()
+        || !key_id.starts_with(API_KEY_PREFIX)
+        || secret.is_empty()
+    {
+        return None;
+    }
+
+    Some((key_id.to_string(), hash_token_string(secret)))
+}
+
+/// Creates a new confidential OAuth client with a random secret, which may be
+/// granted the given scopes and redirect users to the given URIs.
+///
+/// Returns the secret to hand out to the client along with the record to
+/// store in the database.
+#[must_use]
+pub fn new_oauth_client(
+    client_id: &str,
+    scopes: &[String],
+    redirect_uris: &[String],
+) -> (String, OAuthClient) {
+    let secret = random_token_string(32);
+    let mut record = new_public_oauth_client(client_id, scopes, redirect_uris);
+    record.secret_hash = Some(hash_token_string(&secret));
+
+    (secret, record)
+}
+
+/// Creates a new public OAuth client without a secret, such as a single-page
+/// application, which may be granted the given scopes and redirect users to
+/// the given URIs.
+#[must_use]
+pub fn new_public_oauth_client(
+    client_id: &str,
+    scopes: &[String],
+    redirect_uris: &[String],
+) -> OAuthClient {
+    OAuthClient {
+        client_id: client_id.to_string(),
+        secret_hash: None,
+        scopes: scopes.to_vec(),
+        redirect_uris: redirect_uris.to_vec(),
+        created_at: SystemTime::now(),
+    }
+}
+
+/// Checks a client secret against the hash stored for an OAuth client.
+/// Public clients have no secret to check against.
+#[must_use]
+pub fn verify_client_secret(client: &OAuthClient, secret: &str) -> bool {
+    client.secret_hash.as_ref().is_some_and(|secret_hash| {
+        crate::password::constant_time_eq(
+            secret_hash.as_bytes(),
+            hash_token_string(secret).as_bytes(),
+        )
+    })
+}
+
+/// Derives a PKCE code challenge from a code verifier with the `S256`
+/// method, as per RFC 7636, section 4.2.
+#[must_use]
+pub fn code_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+/// Generates a random URL-safe string from the given number of random bytes.
+pub(crate) fn random_token_string(byte_count: usize) -> String {
+    let mut bytes = vec![0; byte_count];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Hashes a secret token string for storage.
+pub(crate) fn hash_token_string(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Converts a point in time to seconds since the unix epoch.
+pub(crate) fn unix_timestamp(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .expect("time predates unix epoch, somehow")
+        .as_secs()
+}
+
+/// The payload of a JSON web token for API access
+#[derive(Clone, Serialize, Deserialize)]
+#[allow(clippy::module_name_repetitions)]
+pub struct TokenPayload {
+    pub exp: u64,
+
+    /// Issue time in seconds since the unix epoch. Fractional, so that tokens
+    /// issued right after a revocation of all of a user's tokens can be told
+    /// apart from the revoked ones.
+    pub iat: f64,
+
+    /// Unique identifier of the token, used for revocation.
+    pub jti: String,
+
+    /// Id of the user the token was issued to, which stays the same even if
+    /// the user's e-mail address changes.
+    pub sub: Uuid,
+
+    /// Whether the user's e-mail address had been verified when the token was
+    /// issued. Tokens from before e-mail verification was introduced lack the
+    /// claim, and are treated as verified.
+    #[serde(default = "default_email_verified")]
+    pub email_verified: bool,
+
+    /// Roles granted to the user when the token was issued.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+
+    /// Permissions granted to the user when the token was issued, delimited
+    /// by spaces as per RFC 8693.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub scope: String,
+
+    /// Id of the OAuth client which the user authorized to act on their
+    /// behalf with the token, if it was issued to one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+
+    /// Whom the token was issued to. Tokens from before OAuth clients were
+    /// introduced lack the claim, and were issued to users.
+    #[serde(default)]
+    pub token_use: TokenUse,
+}
+
+fn default_email_verified() -> bool {
+    true
+}
+
+/// Whom an API token was issued to, as given by its `token_use` claim.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenUse {
+    /// A user, or an OAuth client acting on a user's behalf.
+    #[default]
+    User,
+
+    /// An OAuth client acting on its own behalf.
+    Client,
+}
+
+impl TokenPayload {
+    /// Creates a payload object.
+    #[must_use]
+    fn new(
+        user_id: Uuid,
+        lifetime: Duration,
+        email_verified: bool,
+        roles: &[String],
+        permissions: &[String],
+    ) -> Self {
+        let now = SystemTime::now();
+        Self {
+            exp: unix_timestamp(now + lifetime),
+            iat: now
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .expect("time predates unix epoch, somehow")
+                .as_secs_f64(),
+            jti: random_token_string(16),
+            sub: user_id,
+            email_verified,
+            roles: roles.to_vec(),
+            scope: permissions.join(" "),
+            client_id: None,
+            token_use: TokenUse::User,
+        }
+    }
+
+    /// Creates a payload describing a personal API key of a user, which
+    /// expires along with the key.
+    ///
+    /// The key id serves as the token id, and its creation time as the issue
+    /// time. Only roles which the key's scopes name explicitly are granted, so
+    /// that e.g. an administrator's key cannot administer unless it has the
+    /// `admin` scope. Keys of users whose e-mail address is unverified are
+    /// restricted like their tokens.
+    #[must_use]
+    fn for_api_key(key: &ApiKey, user: &UserProfile, email_verified: bool) -> Self {
+        Self {
+            exp: key.expires_at.unwrap_or(u64::MAX),
+            iat: key
+                .created_at
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .expect("time predates unix epoch, somehow")
+                .as_secs_f64(),
+            jti: key.key_id.clone(),
+            sub: user.id,
+            email_verified,
+            roles: user
+                .roles
+                .iter()
+                .filter(|role| key.scopes.contains(role))
+                .cloned()
+                .collect(),
+            scope: key
+                .scopes
+                .iter()
+                .filter(|scope| user.permissions.contains(scope))
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(" "),
+            client_id: None,
+            token_use: TokenUse::User,
+        }
+    }
+
+    /// Returns the permissions granted by the token.
+    pub fn permissions(&self) -> impl Iterator<Item = &str> {
+        self.scope.split_whitespace()
+    }
+
+    /// Returns the issue time of the token.
+    ///
+    /// Unrepresentable issue times are treated as the unix epoch, so that such
+    /// tokens are covered by any revocation.
+    #[must_use]
+    pub fn issued_at(&self) -> SystemTime {
+        Duration::try_from_secs_f64(self.iat)
+            .ok()
+            .and_then(|iat| SystemTime::UNIX_EPOCH.checked_add(iat))
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+    }
+
+    /// Encodes the payload into a JSON web token.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if encoding fails.
+    fn encode(&self, signing_key: &SigningKey) -> Result<String, Error> {
+        encode_claims(self, signing_key)
+    }
+
+    /// Decodes a payload from a JSON web token.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if decoding fails.
+    fn decode(
+        token: impl AsRef<str>,
+        keyring: &Keyring,
+        lifetime_leeway: Duration,
+    ) -> Result<Self, Error> {
+        decode_claims(token.as_ref(), keyring, lifetime_leeway)
+    }
+}
+
+/// The payload of a JSON web token which an OAuth client obtained for
+/// itself, as per RFC 9068.
+///
+/// Its `sub` is the client's id rather than a user id. Its `token_use` claim
+/// tells it apart from a [`TokenPayload`], regardless of what the client id
+/// looks like.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ClientTokenPayload {
+    pub exp: u64,
+    pub iat: u64,
+    pub jti: String,
+    pub sub: String,
+    pub client_id: String,
+
+    /// Scopes granted to the client, delimited by spaces.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub scope: String,
+
+    /// Always [`TokenUse::Client`].
+    pub token_use: TokenUse,
+}
+
+impl ClientTokenPayload {
+    /// Returns the scopes granted by the token.
+    pub fn scopes(&self) -> impl Iterator<Item = &str> {
+        self.scope.split_whitespace()
+    }
+}
+
+/// The payload of a JSON web token for e-mail verification.
+///
+/// It shares no claims besides `exp` with [`TokenPayload`], so that neither
+/// kind of token can be decoded as the other.
+#[derive(Serialize, Deserialize)]
+struct EmailVerificationPayload {
+    exp: u64,
+
+    /// The e-mail address being verified.
+    verify_email: String,
+}
+
+/// A change of a user's e-mail address, which takes effect once the new
+/// address is confirmed.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct EmailChange {
+    pub user_id: Uuid,
+
+    /// The user's address when the change was requested. The change is not
+    /// applied if the address has changed since.
+    pub old_email: String,
+
+    pub new_email: String,
+}
+
+/// The payload of a JSON web token for an e-mail change.
+///
+/// Like [`EmailVerificationPayload`], it shares no claims besides `exp` with
+/// the other kinds of tokens.
+#[derive(Serialize, Deserialize)]
+struct EmailChangePayload {
+    exp: u64,
+
+    /// The id of the user whose e-mail address is changed.
+    change_email_of: Uuid,
+    old_email: String,
+    new_email: String,
+}
+
+/// Encodes claims into a JSON web token signed with the given key.
+///
+/// # Errors
+///
+/// Returns an error if encoding fails.
+fn encode_claims(claims: &impl Serialize, signing_key: &SigningKey) -> Result<String, Error> {
+    let mut header = Header::new(signing_key.algorithm());
+    header.kid = Some(signing_key.key_id().to_string());
+
+    encode(&header, claims, signing_key.encoding_key())
+}
+
+/// Decodes claims from a JSON web token signed with a key in the keyring.
+///
+/// # Errors
+///
+/// Returns an error if decoding fails.
+fn decode_claims<T: DeserializeOwned>(
+    token: &str,
+    keyring: &Keyring,
+    lifetime_leeway: Duration,
+) -> Result<T, Error> {
+    let header = decode_header(token)?;
+    if let Some(key_id) = header.kid {
+        let key = keyring
+            .find(&key_id)
+            .ok_or(Error::from(ErrorKind::InvalidSignature))?;
+        return decode_claims_with_key(token, key, lifetime_leeway);
+    }
+
+    // tokens issued before key ids were introduced may have been signed with
+    // any key of their algorithm which has been retired since
+    let mut result = Err(Error::from(ErrorKind::InvalidSignature));
+    for key in keyring
+        .verification_keys()
+        .filter(|key| key.algorithm() == header.alg)
+    {
+        result = decode_claims_with_key(token, key, lifetime_leeway);
+        if !matches!(&result, Err(e) if matches!(e.kind(), ErrorKind::InvalidSignature)) {
+            break;
+        }
+    }
+    result
+}
+
+/// Decodes claims from a JSON web token signed with the given key.
+///
+/// # Errors
+///
+/// Returns an error if decoding fails.
+fn decode_claims_with_key<T: DeserializeOwned>(
+    token: &str,
+    key: &VerificationKey,
+    lifetime_leeway: Duration,
+) -> Result<T, Error> {
+    let mut validation = Validation::new(key.algorithm());
+    validation.leeway = lifetime_leeway.as_secs();
+
+    Ok(decode::<T>(token, key.decoding_key(), &validation)?.claims)
+}
@@ -0,0 +1,483 @@
+//! Validation and normalization of request input.
+//!
+//! Request bodies are validated by extracting them as [`Valid<T>`] instead of
+//! [`Json<T>`](axum::Json). Invalid input is rejected with
+//! `422 Unprocessable Entity` and a body listing an error for each invalid
+//! field:
+//!
+//! ```json
+//! {"errors": [{"field": "email", "message": "must contain an @"}]}
+//! ```
+//!
+//! Errors due to the [`PasswordPolicy`](crate::password::PasswordPolicy) also
+//! name the rule which was violated:
+//!
+//! ```json
+//! {"errors": [{"field": "password", "rule": "require_digit", "message": "must contain a digit"}]}
+//! ```
+
+use crate::{
+    database::User,
+    password::{PasswordRule, PolicyViolation},
+};
+use axum::{
+    async_trait,
+    body::HttpBody,
+    extract::{rejection::JsonRejection, FromRequest},
+    http::{Request, StatusCode},
+    response::{IntoResponse, Response},
+    BoxError, Json,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Map, Value};
+use unicode_normalization::UnicodeNormalization;
+use uuid::Uuid;
+
+/// Maximum length of an e-mail address in bytes, as per RFC 5321.
+pub const MAX_EMAIL_LENGTH: usize = 254;
+
+/// Maximum length of the local part of an e-mail address in bytes, as per
+/// RFC 5321.
+const MAX_LOCAL_PART_LENGTH: usize = 64;
+
+/// Maximum length of a domain label in bytes, as per RFC 1035.
+const MAX_DOMAIN_LABEL_LENGTH: usize = 63;
+
+/// Maximum length of a password in bytes, which bounds the cost of hashing it.
+pub const MAX_PASSWORD_LENGTH: usize = 1024;
+
+/// Maximum length of a display name in characters.
+pub const MAX_DISPLAY_NAME_LENGTH: usize = 100;
+
+/// Maximum length of a user's metadata in bytes, when encoded as JSON.
+pub const MAX_METADATA_LENGTH: usize = 16 * 1024;
+
+/// Maximum length of the name of a role or permission in bytes.
+pub const MAX_GRANT_NAME_LENGTH: usize = 64;
+
+/// Maximum number of roles or of permissions a user can have.
+pub const MAX_GRANTS: usize = 64;
+
+/// Maximum length of the id of an OAuth client in bytes.
+pub const MAX_CLIENT_ID_LENGTH: usize = 64;
+
+/// Maximum length of a redirect URI of an OAuth client in bytes.
+pub const MAX_REDIRECT_URI_LENGTH: usize = 2000;
+
+/// Trait for request input which can be validated and normalized.
+pub trait Validate: Sized {
+    /// Checks the input, returning it in normalized form.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for each invalid field.
+    fn validate(self) -> Result<Self, ValidationErrors>;
+}
+
+impl Validate for User {
+    fn validate(self) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::new();
+        let email = errors.check("email", normalize_email(&self.email));
+        errors.check("password", check_password(&self.password));
+
+        errors.into_result(Self {
+            email: email.unwrap_or_default(),
+            password: self.password,
+        })
+    }
+}
+
+/// Normalizes an e-mail address and checks its syntax.
+///
+/// The address is normalized to Unicode normalization form KC and its domain
+/// is lowercased. The local part is left as is, since it may be case-sensitive.
+/// Internationalized addresses are accepted, but neither domain literals nor
+/// comments are.
+///
+/// # Errors
+///
+/// Returns a description of the problem if the address is invalid.
+pub fn normalize_email(email: &str) -> Result<String, String> {
+    let email = email.trim().nfkc().collect::<String>();
+    if email.len() > MAX_EMAIL_LENGTH {
+        return Err(format!("must be at most {MAX_EMAIL_LENGTH} bytes long"));
+    }
+
+    let (local_part, domain) = email.rsplit_once('@').ok_or("must contain an @")?;
+    check_local_part(local_part)?;
+    let domain = domain.to_lowercase();
+    check_domain(&domain)?;
+
+    Ok(format!("{local_part}@{domain}"))
+}
+
+/// Checks the syntax of the local part of an e-mail address, as per RFC 5322
+/// and RFC 6532.
+fn check_local_part(local_part: &str) -> Result<(), String> {
+    if local_part.is_empty() {
+        return Err("must have a non-empty local part".into());
+    }
+    if local_part.len() > MAX_LOCAL_PART_LENGTH {
+        return Err(format!(
+            "must have a local part at most {MAX_LOCAL_PART_LENGTH} bytes long"
+        ));
+    }
+
+    let valid = match local_part
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+    {
+        Some(quoted) => is_valid_quoted_string(quoted),
+        None => local_part
+            .split('.')
+            .all(|atom| !atom.is_empty() && atom.chars().all(is_atext)),
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err("must have a valid local part".into())
+    }
+}
+
+/// Checks the contents of a quoted string, without the surrounding quotes.
+fn is_valid_quoted_string(quoted: &str) -> bool {
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        let valid = match c {
+            '\\' => chars.next().is_some_and(|c| c == ' ' || is_printable(c)),
+            '"' => false,
+            c => c == ' ' || is_printable(c),
+        };
+        if !valid {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Checks the syntax of a lowercased domain name.
+fn check_domain(domain: &str) -> Result<(), String> {
+    let labels = domain.split('.').collect::<Vec<_>>();
+    if labels.len() < 2 {
+        return Err("must have a domain with at least two labels".into());
+    }
+
+    for label in labels {
+        if label.is_empty() || label.len() > MAX_DOMAIN_LABEL_LENGTH {
+            return Err(format!(
+                "must have domain labels between 1 and {MAX_DOMAIN_LABEL_LENGTH} bytes long"
+            ));
+        }
+        if label.starts_with('-')
+            || label.ends_with('-')
+            || !label.chars().all(|c| c.is_alphanumeric() || c == '-')
+        {
+            return Err("must have a valid domain".into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns whether a character may appear in an unquoted local part.
+fn is_atext(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c) || is_non_ascii_printable(c)
+}
+
+/// Returns whether a character is printable and not whitespace.
+fn is_printable(c: char) -> bool {
+    c.is_ascii_graphic() || is_non_ascii_printable(c)
+}
+
+fn is_non_ascii_printable(c: char) -> bool {
+    !c.is_ascii() && !c.is_control() && !c.is_whitespace()
+}
+
+/// Checks the length of a password.
+///
+/// # Errors
+///
+/// Returns a description of the problem if the password is invalid.
+pub fn check_password(password: &str) -> Result<(), String> {
+    if password.is_empty() {
+        Err("must not be empty".into())
+    } else if password.len() > MAX_PASSWORD_LENGTH {
+        Err(format!("must be at most {MAX_PASSWORD_LENGTH} bytes long"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Normalizes a display name and checks its length.
+///
+/// The name is trimmed and normalized to Unicode normalization form C.
+///
+/// # Errors
+///
+/// Returns a description of the problem if the name is invalid.
+pub fn normalize_display_name(display_name: &str) -> Result<String, String> {
+    let display_name = display_name.trim().nfc().collect::<String>();
+    if display_name.is_empty() {
+        Err("must not be empty".into())
+    } else if display_name.chars().count() > MAX_DISPLAY_NAME_LENGTH {
+        Err(format!(
+            "must be at most {MAX_DISPLAY_NAME_LENGTH} characters long"
+        ))
+    } else if display_name.chars().any(char::is_control) {
+        Err("must not contain control characters".into())
+    } else {
+        Ok(display_name)
+    }
+}
+
+/// Normalizes the names of roles or permissions and checks their syntax.
+///
+/// The names are sorted and deduplicated. They may only contain lowercase
+/// ASCII letters, digits, `_`, `-`, `.` and `:`, so that they can be joined
+/// into a space-delimited `scope` claim.
+///
+/// # Errors
+///
+/// Returns a description of the problem if a name is invalid or there are too
+/// many names.
+pub fn normalize_grant_names(names: &[String]) -> Result<Vec<String>, String> {
+    let mut names = names.to_vec();
+    names.sort_unstable();
+    names.dedup();
+
+    if names.len() > MAX_GRANTS {
+        return Err(format!("must contain at most {MAX_GRANTS} names"));
+    }
+    for name in &names {
+        if name.is_empty() {
+            return Err("must not contain empty names".into());
+        } else if name.len() > MAX_GRANT_NAME_LENGTH {
+            return Err(format!(
+                "must only contain names at most {MAX_GRANT_NAME_LENGTH} bytes long"
+            ));
+        } else if !name.chars().all(|c| {
+            c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-' | '.' | ':')
+        }) {
+            return Err(format!(
+                "name {name:?} must only contain lowercase letters, digits, _, -, . and :"
+            ));
+        }
+    }
+
+    Ok(names)
+}
+
+/// Checks the syntax of the id of an OAuth client.
+///
+/// Client ids may only contain ASCII letters, digits, `_`, `-` and `.`, and
+/// must not be UUIDs, so that tokens issued to clients cannot be mistaken for
+/// tokens issued to users.
+///
+/// # Errors
+///
+/// Returns a description of the problem if the id is invalid.
+pub fn check_client_id(client_id: &str) -> Result<(), String> {
+    if client_id.is_empty() {
+        Err("must not be empty".into())
+    } else if client_id.len() > MAX_CLIENT_ID_LENGTH {
+        Err(format!("must be at most {MAX_CLIENT_ID_LENGTH} bytes long"))
+    } else if !client_id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+    {
+        Err("must only contain letters, digits, _, - and .".into())
+    } else if Uuid::parse_str(client_id).is_ok() {
+        Err("must not be a UUID".into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks a redirect URI to register for an OAuth client.
+///
+/// URIs must be absolute `https` URIs, or `http` URIs of the loopback
+/// interface for native applications, as per RFC 8252, section 7.3, and must
+/// not contain a fragment, as per RFC 6749, section 3.1.2.
+///
+/// # Errors
+///
+/// Returns a description of the problem if the URI is invalid.
+pub fn check_redirect_uri(redirect_uri: &str) -> Result<(), String> {
+    let is_loopback = |rest: &str| {
+        ["localhost", "127.0.0.1", "[::1]"].iter().any(|host| {
+            rest.strip_prefix(host)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with([':', '/', '?']))
+        })
+    };
+
+    if redirect_uri.len() > MAX_REDIRECT_URI_LENGTH {
+        Err(format!(
+            "must be at most {MAX_REDIRECT_URI_LENGTH} bytes long"
+        ))
+    } else if !redirect_uri.chars().all(|c| c.is_ascii_graphic()) {
+        Err("must only contain printable ASCII characters".into())
+    } else if redirect_uri.contains('#') {
+        Err("must not contain a fragment".into())
+    } else if let Some(rest) = redirect_uri.strip_prefix("https://") {
+        if rest.is_empty() || rest.starts_with([':', '/', '?']) {
+            Err("must contain a host".into())
+        } else {
+            Ok(())
+        }
+    } else if redirect_uri
+        .strip_prefix("http://")
+        .is_some_and(is_loopback)
+    {
+        Ok(())
+    } else {
+        Err("must be an https URI, or an http URI of the loopback interface".into())
+    }
+}
+
+/// Checks the size of a user's metadata.
+///
+/// # Errors
+///
+/// Returns a description of the problem if the metadata is too large.
+pub fn check_metadata(metadata: &Map<String, Value>) -> Result<(), String> {
+    let length = serde_json::to_string(metadata).map_or(usize::MAX, |json| json.len());
+    if length > MAX_METADATA_LENGTH {
+        Err(format!(
+            "must be at most {MAX_METADATA_LENGTH} bytes long when encoded as JSON"
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// An error for a single invalid field.
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+
+    /// The password policy rule which was violated, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<PasswordRule>,
+
+    pub message: String,
+}
+
+/// The errors found while validating input.
+#[derive(Debug, Default)]
+#[allow(clippy::module_name_repetitions)]
+pub struct ValidationErrors {
+    pub errors: Vec<FieldError>,
+}
+
+impl ValidationErrors {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records an error for a field.
+    pub fn add(&mut self, field: &'static str, message: impl Into<String>) {
+        self.errors.push(FieldError {
+            field,
+            rule: None,
+            message: message.into(),
+        });
+    }
+
+    /// Records an error for each password policy rule a field violates.
+    pub fn add_policy_violations(&mut self, field: &'static str, violations: Vec<PolicyViolation>) {
+        self.errors
+            .extend(violations.into_iter().map(|violation| FieldError {
+                field,
+                rule: Some(violation.rule),
+                message: violation.message,
+            }));
+    }
+
+    /// Records the error of a field's check, if any, and returns the checked
+    /// value otherwise.
+    pub fn check<T>(&mut self, field: &'static str, result: Result<T, String>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(message) => {
+                self.add(field, message);
+                None
+            }
+        }
+    }
+
+    /// Returns the validated value if no errors were recorded.
+    ///
+    /// # Errors
+    ///
+    /// Returns `self` if any errors were recorded.
+    pub fn into_result<T>(self, value: T) -> Result<T, Self> {
+        if self.errors.is_empty() {
+            Ok(value)
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl IntoResponse for ValidationErrors {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(json!({ "errors": self.errors })),
+        )
+            .into_response()
+    }
+}
+
+/// Extractor for a JSON request body which is validated and normalized.
+#[derive(Debug)]
+pub struct Valid<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for Valid<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = ValidationRejection;
+
+    async fn from_request(request: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let Json(value) = Json::<T>::from_request(request, state)
+            .await
+            .map_err(ValidationRejection::Json)?;
+
+        Ok(Self(
+            value.validate().map_err(ValidationRejection::Invalid)?,
+        ))
+    }
+}
+
+/// The reason why a request body could not be extracted as [`Valid<T>`].
+#[derive(Debug)]
+#[allow(clippy::module_name_repetitions)]
+pub enum ValidationRejection {
+    /// The body is not JSON of the expected shape.
+    Json(JsonRejection),
+
+    /// The body is well-formed, but some fields are invalid.
+    Invalid(ValidationErrors),
+}
+
+impl IntoResponse for ValidationRejection {
+    fn into_response(self) -> Response {
+        match self {
+            Self::Json(rejection) => (
+                rejection.status(),
+                Json(json!({ "errors": [{ "field": null, "message": rejection.body_text() }] })),
+            )
+                .into_response(),
+            Self::Invalid(errors) => errors.into_response(),
+        }
+    }
+}
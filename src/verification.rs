@@ -0,0 +1,46 @@
+//! Verification of users' e-mail addresses.
+
+use serde::{Deserialize, Serialize};
+
+/// Configuration of e-mail verification.
+///
+/// When set in the [`ServerState`](crate::ServerState), newly registered users
+/// are sent a link to verify their e-mail address.
+#[derive(Clone)]
+pub struct EmailVerification {
+    /// How users who have not verified their e-mail address may log in.
+    pub unverified_login: UnverifiedLogin,
+
+    /// URL which verification links point to, i.e. where the `/verify`
+    /// endpoint is served. The verification code is appended as the `code`
+    /// query parameter.
+    pub link_url: String,
+}
+
+impl EmailVerification {
+    /// Returns the verification link containing a verification code.
+    #[must_use]
+    pub fn link(&self, code: &str) -> String {
+        let separator = if self.link_url.contains('?') {
+            '&'
+        } else {
+            '?'
+        };
+        format!("{}{separator}code={code}", self.link_url)
+    }
+}
+
+/// Policy for logins of users who have not verified their e-mail address.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnverifiedLogin {
+    /// Unverified users are issued regular API tokens.
+    Allow,
+
+    /// Unverified users are issued restricted API tokens, which are only
+    /// accepted by endpoints that do not require a verified e-mail address.
+    Restrict,
+
+    /// Unverified users cannot log in.
+    Refuse,
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use axum_api::{
+    database::{Database, DatabaseError, SimpleMemoryDatabase},
+    mail::FileMailer,
+};
+use common::{
+    delete_authorized, get_authorized, new_limits, new_outbox_path, post, register_and_login,
+    state_with_throttling, with_server_state,
+};
+use reqwest::{header, StatusCode};
+use serde_json::json;
+use serial_test::serial;
+use std::{error::Error, sync::Arc};
+
+#[tokio::test]
+#[serial]
+async fn delete_account() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+
+    with_server_state(
+        state_with_throttling(database.clone(), new_limits(10, None), new_limits(10, None)),
+        async {
+            let login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+            let token = login["token"].as_str().unwrap();
+            post(
+                "login",
+                json!({"email": "email@addre.ss", "password": "wrong"}),
+            )
+            .await;
+            let user_id = get_authorized("me", token).await.body.unwrap()["id"]
+                .as_str()
+                .unwrap()
+                .parse()?;
+
+            let response = delete_authorized("me", token, json!({"password": "wrong"})).await;
+            assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+            let response = get_authorized("me", token).await;
+            assert_eq!(response.status_code, StatusCode::OK);
+
+            let response = delete_authorized("me", token, json!({"password": "P_ass1Wo$rD"})).await;
+            assert_eq!(response.status_code, StatusCode::OK);
+            assert!(matches!(
+                database.export_user(user_id).await,
+                Err(DatabaseError::NotFound)
+            ));
+            assert!(database
+                .get_login_failures("account:email@addre.ss")
+                .await?
+                .is_none());
+
+            // neither the token nor the refresh token are accepted anymore
+            let response = get_authorized("me", token).await;
+            assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+            let response = post(
+                "token/refresh",
+                json!({ "refresh_token": login["refresh_token"] }),
+            )
+            .await;
+            assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+            let response = post(
+                "login",
+                json!({"email": "email@addre.ss", "password": "P_ass1Wo$rD"}),
+            )
+            .await;
+            assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+
+            // the e-mail address can be registered again
+            let response = post(
+                "register",
+                json!({"email": "email@addre.ss", "password": "P_ass1Wo$rD"}),
+            )
+            .await;
+            assert_eq!(response.status_code, StatusCode::OK);
+
+            Ok(())
+        },
+    )
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn export_account() -> Result<(), Box<dyn Error>> {
+    let outbox_path = new_outbox_path("export_account");
+    let state = state_with_throttling(
+        SimpleMemoryDatabase::new(),
+        new_limits(10, None),
+        new_limits(10, None),
+    )
+    .with_mailer(Arc::new(FileMailer::new(&outbox_path)));
+
+    with_server_state(state, async {
+        register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let token = login["token"].as_str().unwrap();
+        post(
+            "login",
+            json!({"email": "email@addre.ss", "password": "wrong"}),
+        )
+        .await;
+        post("password/forgot", json!({"email": "email@addre.ss"})).await;
+
+        let response = get_authorized("me/export", token).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert_eq!(
+            response.headers[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"account.json\""
+        );
+        let archive = response.body.unwrap();
+        assert_eq!(archive["profile"]["email"], "email@addre.ss");
+        assert!(archive["profile"]["last_login_at"].is_u64());
+        assert_eq!(archive["refresh_tokens"].as_array().unwrap().len(), 2);
+        assert!(archive["refresh_tokens"][0].get("token_hash").is_none());
+        assert_eq!(archive["password_resets"].as_array().unwrap().len(), 1);
+        assert_eq!(archive["tokens_revoked_before"], json!(null));
+        assert_eq!(archive["login_failures"]["count"], 1);
+        assert!(archive["exported_at"].is_u64());
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,314 @@
+mod common;
+
+use axum::Router;
+use axum_api::{
+    create_admin_router, create_api_router,
+    database::{Database, SimpleMemoryDatabase},
+    mail::FileMailer,
+    ServerState,
+};
+use common::{
+    delete_authorized, get_authorized, login, new_outbox_path, new_token_manager, post,
+    post_authorized, put_authorized, register_and_login, wait_for_outbox, with_router, with_server,
+};
+use reqwest::StatusCode;
+use serde_json::{json, Map, Value};
+use serial_test::serial;
+use std::{error::Error, path::Path, sync::Arc};
+use uuid::Uuid;
+
+fn admin_router(database: SimpleMemoryDatabase, outbox_path: &Path) -> Router {
+    let state = ServerState::new(database, new_token_manager())
+        .with_mailer(Arc::new(FileMailer::new(outbox_path)));
+
+    Router::new()
+        .merge(create_api_router())
+        .merge(create_admin_router())
+        .with_state(state)
+}
+
+/// Registers an administrator, returning their token.
+async fn register_admin(database: &SimpleMemoryDatabase) -> String {
+    register_and_login("admin@addre.ss", "P_ass1Wo$rD").await;
+    let admin = database.get_user_by_email("admin@addre.ss").await.unwrap();
+    database
+        .set_user_roles(admin.id, &["admin".to_string()], &[])
+        .await
+        .unwrap();
+
+    let login = register_and_login("admin@addre.ss", "P_ass1Wo$rD").await;
+    login["token"].as_str().unwrap().to_string()
+}
+
+fn emails(page: &Map<String, Value>) -> Vec<&str> {
+    page["users"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|user| user["email"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+#[serial]
+async fn list_users() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+    let outbox_path = new_outbox_path("list_users");
+
+    with_router(admin_router(database.clone(), &outbox_path), async {
+        let admin_token = register_admin(&database).await;
+        for email in ["alice@addre.ss", "alfred@addre.ss", "bob@addre.ss"] {
+            register_and_login(email, "P_ass1Wo$rD").await;
+        }
+
+        let login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let response = get_authorized("admin/users", login["token"].as_str().unwrap()).await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+
+        // pages are followed until there is no cursor
+        let mut listed = Vec::new();
+        let mut endpoint = "admin/users?limit=2".to_string();
+        loop {
+            let response = get_authorized(&endpoint, &admin_token).await;
+            assert_eq!(response.status_code, StatusCode::OK);
+            let page = response.body.unwrap();
+            assert!(emails(&page).len() <= 2);
+            listed.extend(emails(&page).into_iter().map(str::to_string));
+            let Some(cursor) = page["next_cursor"].as_str() else {
+                break;
+            };
+            endpoint = format!("admin/users?limit=2&cursor={cursor}");
+        }
+        listed.sort_unstable();
+        assert_eq!(
+            listed,
+            [
+                "admin@addre.ss",
+                "alfred@addre.ss",
+                "alice@addre.ss",
+                "bob@addre.ss",
+                "email@addre.ss"
+            ]
+        );
+
+        let response = get_authorized("admin/users?email_prefix=al", &admin_token).await;
+        let page = response.body.unwrap();
+        let mut listed = emails(&page);
+        listed.sort_unstable();
+        assert_eq!(listed, ["alfred@addre.ss", "alice@addre.ss"]);
+        assert_eq!(page["next_cursor"], Value::Null);
+
+        for (endpoint, status_code) in [
+            ("admin/users?limit=0", StatusCode::UNPROCESSABLE_ENTITY),
+            ("admin/users?limit=101", StatusCode::UNPROCESSABLE_ENTITY),
+            ("admin/users?cursor=malformed", StatusCode::BAD_REQUEST),
+        ] {
+            let response = get_authorized(endpoint, &admin_token).await;
+            assert_eq!(response.status_code, status_code);
+        }
+
+        // databases list at least one user per page
+        let page = database.list_users("", None, 0).await?;
+        assert_eq!(page.users.len(), 1);
+        assert!(page.next_cursor.is_some());
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn disable_users() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+    let outbox_path = new_outbox_path("disable_users");
+
+    with_router(admin_router(database.clone(), &outbox_path), async {
+        let admin_token = register_admin(&database).await;
+        let login_body = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let token = login_body["token"].as_str().unwrap();
+        let user_id = database.get_user_by_email("email@addre.ss").await?.id;
+
+        let response =
+            post_authorized(format!("admin/users/{user_id}/disable"), token, json!({})).await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+
+        let response = post_authorized(
+            format!("admin/users/{user_id}/disable"),
+            &admin_token,
+            json!({}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert_eq!(response.body.unwrap()["disabled"], true);
+
+        // disabled users' tokens are revoked, and they cannot log in again
+        let response = get_authorized("me", token).await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        let response = post(
+            "token/refresh",
+            json!({ "refresh_token": login_body["refresh_token"] }),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        let response = login("email@addre.ss", "P_ass1Wo$rD").await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+
+        let user = get_authorized(format!("admin/users/{user_id}"), &admin_token)
+            .await
+            .body
+            .unwrap();
+        assert_eq!(user["email"], "email@addre.ss");
+        assert_eq!(user["disabled"], true);
+        assert_eq!(user["email_verified"], false);
+
+        let response = post_authorized(
+            format!("admin/users/{user_id}/enable"),
+            &admin_token,
+            json!({}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert_eq!(response.body.unwrap()["disabled"], false);
+        let response = login("email@addre.ss", "P_ass1Wo$rD").await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        for endpoint in ["disable", "enable"] {
+            let response = post_authorized(
+                format!("admin/users/{}/{endpoint}", Uuid::new_v4()),
+                &admin_token,
+                json!({}),
+            )
+            .await;
+            assert_eq!(response.status_code, StatusCode::NOT_FOUND);
+        }
+        let response =
+            get_authorized(format!("admin/users/{}", Uuid::new_v4()), &admin_token).await;
+        assert_eq!(response.status_code, StatusCode::NOT_FOUND);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn reset_and_delete_users() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+    let outbox_path = new_outbox_path("reset_and_delete_users");
+
+    with_router(admin_router(database.clone(), &outbox_path), async {
+        let admin_token = register_admin(&database).await;
+        let login_body = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let token = login_body["token"].as_str().unwrap();
+        let user_id = database.get_user_by_email("email@addre.ss").await?.id;
+
+        let response = post_authorized(
+            format!("admin/users/{user_id}/password-reset"),
+            &admin_token,
+            json!({}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::ACCEPTED);
+
+        // the previous password and tokens stop working at once
+        let response = get_authorized("me", token).await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        let response = login("email@addre.ss", "P_ass1Wo$rD").await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+
+        let messages = wait_for_outbox(&outbox_path, 1).await;
+        assert_eq!(messages[0].to, "email@addre.ss");
+        let code = messages[0]
+            .body
+            .split_once("one: ")
+            .unwrap()
+            .1
+            .split_whitespace()
+            .next()
+            .unwrap();
+        let response = post(
+            "password/reset",
+            json!({"code": code, "new_password": "N3w_P_ass1Wo$rD"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let response = login("email@addre.ss", "N3w_P_ass1Wo$rD").await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let token = response.body.unwrap()["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let response =
+            delete_authorized(format!("admin/users/{user_id}"), &admin_token, json!({})).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let response = get_authorized("me", &token).await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        let response = login("email@addre.ss", "N3w_P_ass1Wo$rD").await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        let response = get_authorized(format!("admin/users/{user_id}"), &admin_token).await;
+        assert_eq!(response.status_code, StatusCode::NOT_FOUND);
+        let response =
+            delete_authorized(format!("admin/users/{user_id}"), &admin_token, json!({})).await;
+        assert_eq!(response.status_code, StatusCode::NOT_FOUND);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn reject_invalid_role_assignments() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+    let outbox_path = new_outbox_path("reject_invalid_role_assignments");
+
+    with_router(admin_router(database.clone(), &outbox_path), async {
+        let admin_token = register_admin(&database).await;
+        let admin_id = database.get_user_by_email("admin@addre.ss").await?.id;
+
+        for roles in [json!(["Admin"]), json!(["with space"]), json!([""])] {
+            let response = put_authorized(
+                format!("admin/users/{admin_id}/roles"),
+                &admin_token,
+                json!({ "roles": roles }),
+            )
+            .await;
+            assert_eq!(response.status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        }
+
+        let response = put_authorized(
+            format!("admin/users/{}/roles", Uuid::new_v4()),
+            &admin_token,
+            json!({"roles": ["user"]}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::NOT_FOUND);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn admin_endpoints_are_not_in_api_router() -> Result<(), Box<dyn Error>> {
+    with_server(async {
+        let login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let token = login["token"].as_str().unwrap();
+
+        let response = get_authorized("admin/users", token).await;
+        assert_eq!(response.status_code, StatusCode::NOT_FOUND);
+        let response = put_authorized(
+            format!("admin/users/{}/roles", Uuid::new_v4()),
+            token,
+            json!({"roles": ["admin"]}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::NOT_FOUND);
+
+        Ok(())
+    })
+    .await
+}
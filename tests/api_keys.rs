@@ -0,0 +1,442 @@
+mod common;
+
+use axum::{response::IntoResponse, routing::get, Json, Router};
+use axum_api::{
+    create_admin_router, create_api_router,
+    database::{Database, SimpleMemoryDatabase},
+    rate_limit::API_KEY_HEADER,
+    roles::{Permission, RequirePermission},
+    verification::UnverifiedLogin,
+    ServerState,
+};
+use common::{
+    delete_authorized, get_authorized, new_outbox_path, new_token_manager, post_authorized,
+    put_authorized, register_and_login, state_with_verification, with_router, Response, ADDRESS,
+};
+use reqwest::{header, Method, StatusCode};
+use serde_json::{json, Map, Value};
+use serial_test::serial;
+use std::{error::Error, time::Duration};
+
+struct ReadTickets;
+
+impl Permission for ReadTickets {
+    const NAME: &'static str = "tickets:read";
+}
+
+async fn tickets(_user: RequirePermission<ReadTickets>) -> impl IntoResponse {
+    Json(json!({ "tickets": [] }))
+}
+
+fn downstream_router(database: SimpleMemoryDatabase) -> Router {
+    Router::new()
+        .merge(create_api_router())
+        .merge(create_admin_router())
+        .route("/tickets", get(tickets))
+        .with_state(ServerState::new(database, new_token_manager()))
+}
+
+/// Sends a request authenticated with an API key given in a header.
+async fn send_with_api_key(
+    method: Method,
+    endpoint: &str,
+    header_name: &str,
+    header_value: &str,
+) -> Response {
+    let response = reqwest::Client::new()
+        .request(method, format!("http://{ADDRESS}/{endpoint}"))
+        .header(header_name, header_value)
+        .send()
+        .await
+        .unwrap();
+
+    Response {
+        status_code: response.status(),
+        headers: response.headers().clone(),
+        body: response.json::<Map<String, Value>>().await.ok(),
+    }
+}
+
+async fn get_with_api_key(endpoint: &str, api_key: &str) -> Response {
+    send_with_api_key(
+        Method::GET,
+        endpoint,
+        header::AUTHORIZATION.as_str(),
+        &format!("ApiKey {api_key}"),
+    )
+    .await
+}
+
+async fn create_api_key(token: &str, request: Value) -> Map<String, Value> {
+    let response = post_authorized("me/api-keys", token, request).await;
+    assert_eq!(response.status_code, StatusCode::OK);
+    response.body.unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn create_and_revoke_api_keys() -> Result<(), Box<dyn Error>> {
+    with_router(downstream_router(SimpleMemoryDatabase::new()), async {
+        let login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let token = login["token"].as_str().unwrap();
+
+        let created = create_api_key(token, json!({"name": " CI bot ", "expires_in": 3600})).await;
+        let key = created["key"].as_str().unwrap();
+        let key_id = created["id"].as_str().unwrap();
+        assert!(key.starts_with(key_id));
+        assert!(key_id.starts_with("ak_"));
+        assert_eq!(created["name"], "CI bot");
+        assert!(created["expires_at"].as_u64().is_some());
+
+        // keys are accepted in either header, as the same user as tokens
+        let response = get_with_api_key("me", key).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert_eq!(response.body.unwrap()["email"], "email@addre.ss");
+        let response = send_with_api_key(Method::GET, "me", API_KEY_HEADER, key).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        for invalid_key in [
+            format!("{key_id}.wrong"),
+            "ak_.secret".to_string(),
+            key_id.into(),
+        ] {
+            let response = get_with_api_key("me", &invalid_key).await;
+            assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        }
+
+        // listed keys leave out the key itself
+        let response = get_authorized("me/api-keys", token).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let body = response.body.unwrap();
+        let api_keys = body["api_keys"].as_array().unwrap();
+        assert_eq!(api_keys.len(), 1);
+        assert_eq!(api_keys[0]["id"], key_id);
+        assert!(api_keys[0].get("key").is_none());
+
+        let response = delete_authorized(format!("me/api-keys/{key_id}"), token, json!({})).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let response = get_with_api_key("me", key).await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        let response = delete_authorized(format!("me/api-keys/{key_id}"), token, json!({})).await;
+        assert_eq!(response.status_code, StatusCode::NOT_FOUND);
+
+        // logging out with a key deletes it
+        let created = create_api_key(token, json!({"name": "deploy"})).await;
+        let key = created["key"].as_str().unwrap();
+        assert_eq!(created["expires_at"], Value::Null);
+        let response = send_with_api_key(
+            Method::POST,
+            "logout",
+            header::AUTHORIZATION.as_str(),
+            &format!("ApiKey {key}"),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let response = get_with_api_key("me", key).await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        let body = get_authorized("me/api-keys", token).await.body.unwrap();
+        assert_eq!(body["api_keys"], json!([]));
+
+        // keys expire
+        let created = create_api_key(token, json!({"name": "short", "expires_in": 1})).await;
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let response = get_with_api_key("me", created["key"].as_str().unwrap()).await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn scoped_api_keys() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+
+    with_router(downstream_router(database.clone()), async {
+        let login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let token = login["token"].as_str().unwrap();
+        let user_id = database.get_user_by_email("email@addre.ss").await?.id;
+        database
+            .set_user_roles(
+                user_id,
+                &["user".to_string()],
+                &["tickets:read".to_string()],
+            )
+            .await?;
+
+        for request in [
+            json!({"name": "bot", "scopes": ["tickets:write"]}),
+            json!({"name": "bot", "scopes": ["Tickets"]}),
+            json!({"name": "", "scopes": []}),
+            json!({"name": "bot", "expires_in": 0}),
+        ] {
+            let response = post_authorized("me/api-keys", token, request).await;
+            assert_eq!(response.status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        }
+
+        let scoped_key = create_api_key(token, json!({"name": "bot", "scopes": ["tickets:read"]}))
+            .await["key"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let unscoped_key = create_api_key(token, json!({"name": "bot"})).await["key"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let response = get_with_api_key("tickets", &scoped_key).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let response = get_with_api_key("tickets", &unscoped_key).await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+
+        // keys cannot create keys with wider scopes
+        let response = reqwest::Client::new()
+            .post(format!("http://{ADDRESS}/me/api-keys"))
+            .header(API_KEY_HEADER, &unscoped_key)
+            .json(&json!({"name": "bot", "scopes": ["tickets:read"]}))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // keys only grant scopes which the user still holds
+        database
+            .set_user_roles(user_id, &["user".to_string()], &[])
+            .await?;
+        let response = get_with_api_key("tickets", &scoped_key).await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+
+        // keys of disabled users are rejected
+        database.set_user_disabled(user_id, true).await?;
+        let response = get_with_api_key("me", &unscoped_key).await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn api_keys_cannot_manage_accounts() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+
+    with_router(downstream_router(database.clone()), async {
+        let login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let token = login["token"].as_str().unwrap();
+        let user_id = database.get_user_by_email("email@addre.ss").await?.id;
+        database
+            .set_user_roles(
+                user_id,
+                &["user".to_string()],
+                &["tickets:read".to_string()],
+            )
+            .await?;
+
+        let key = create_api_key(token, json!({"name": "ci", "scopes": ["tickets:read"]})).await
+            ["key"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let other_key = create_api_key(token, json!({"name": "deploy"})).await;
+        let other_key_id = other_key["id"].as_str().unwrap();
+
+        for (method, endpoint, body) in [
+            (
+                Method::PATCH,
+                "me".to_string(),
+                json!({"display_name": "Mallory"}),
+            ),
+            (Method::GET, "me/export".to_string(), json!({})),
+            (Method::GET, "me/api-keys".to_string(), json!({})),
+            (
+                Method::DELETE,
+                format!("me/api-keys/{other_key_id}"),
+                json!({}),
+            ),
+            (Method::POST, "logout/all".to_string(), json!({})),
+            (
+                Method::PUT,
+                "password".to_string(),
+                json!({"current_password": "P_ass1Wo$rD", "new_password": "n3w_P4ssw0rd"}),
+            ),
+            (
+                Method::POST,
+                "me/email".to_string(),
+                json!({"password": "P_ass1Wo$rD", "new_email": "new@addre.ss"}),
+            ),
+            (
+                Method::DELETE,
+                "me".to_string(),
+                json!({"password": "P_ass1Wo$rD"}),
+            ),
+        ] {
+            let response = reqwest::Client::new()
+                .request(method, format!("http://{ADDRESS}/{endpoint}"))
+                .header(API_KEY_HEADER, &key)
+                .json(&body)
+                .send()
+                .await?;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+
+        // the key still works within its scopes, and the other key is intact
+        let response = get_with_api_key("tickets", &key).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let response = get_with_api_key("me", other_key["key"].as_str().unwrap()).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert_eq!(response.body.unwrap()["display_name"], Value::Null);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn api_keys_of_administrators() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+
+    with_router(downstream_router(database.clone()), async {
+        register_and_login("admin@addre.ss", "P_ass1Wo$rD").await;
+        let admin_id = database.get_user_by_email("admin@addre.ss").await?.id;
+        database
+            .set_user_roles(
+                admin_id,
+                &["user".to_string(), "admin".to_string()],
+                &["tickets:read".to_string()],
+            )
+            .await?;
+        let login = register_and_login("admin@addre.ss", "P_ass1Wo$rD").await;
+        let token = login["token"].as_str().unwrap();
+        let response = get_authorized("admin/users", token).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        // keys only have the roles which their scopes name
+        for scopes in [json!([]), json!(["tickets:read"])] {
+            let key = create_api_key(token, json!({"name": "bot", "scopes": scopes})).await["key"]
+                .as_str()
+                .unwrap()
+                .to_string();
+            let response = get_with_api_key("admin/users", &key).await;
+            assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+        }
+
+        let admin_key = create_api_key(token, json!({"name": "admin", "scopes": ["admin"]})).await
+            ["key"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let response = get_with_api_key("admin/users", &admin_key).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let response = get_with_api_key("tickets", &admin_key).await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+
+        // roles which the user no longer has are not granted
+        database
+            .set_user_roles(admin_id, &["user".to_string()], &[])
+            .await?;
+        let response = get_with_api_key("admin/users", &admin_key).await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn api_keys_are_deleted_on_recovery() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+
+    with_router(downstream_router(database.clone()), async {
+        let login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let token = login["token"].as_str().unwrap();
+        let user_id = database.get_user_by_email("email@addre.ss").await?.id;
+
+        let key = create_api_key(token, json!({"name": "bot"})).await["key"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let response = put_authorized(
+            "password",
+            token,
+            json!({"current_password": "P_ass1Wo$rD", "new_password": "n3w_P4ssw0rd"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let token = response.body.unwrap()["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let response = get_with_api_key("me", &key).await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+
+        // keys do not come back when a disabled user is enabled again
+        let key = create_api_key(&token, json!({"name": "bot"})).await["key"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        register_and_login("admin@addre.ss", "P_ass1Wo$rD").await;
+        let admin_id = database.get_user_by_email("admin@addre.ss").await?.id;
+        database
+            .set_user_roles(admin_id, &["admin".to_string()], &[])
+            .await?;
+        let admin_login = register_and_login("admin@addre.ss", "P_ass1Wo$rD").await;
+        let admin_token = admin_login["token"].as_str().unwrap();
+        for endpoint in ["disable", "enable"] {
+            let response = post_authorized(
+                format!("admin/users/{user_id}/{endpoint}"),
+                admin_token,
+                json!({}),
+            )
+            .await;
+            assert_eq!(response.status_code, StatusCode::OK);
+        }
+        let response = get_with_api_key("me", &key).await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn api_keys_of_unverified_users() -> Result<(), Box<dyn Error>> {
+    let outbox_path = new_outbox_path("api_keys_of_unverified_users");
+    let state = state_with_verification(&outbox_path, UnverifiedLogin::Restrict);
+    let database = state.database().clone();
+    let router = Router::new()
+        .merge(create_api_router())
+        .route("/tickets", get(tickets))
+        .with_state(state);
+
+    with_router(router, async {
+        register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let user_id = database.get_user_by_email("email@addre.ss").await?.id;
+        database
+            .set_user_roles(
+                user_id,
+                &["user".to_string()],
+                &["tickets:read".to_string()],
+            )
+            .await?;
+        let (key, record) =
+            new_token_manager().new_api_key(user_id, "bot", &["tickets:read".to_string()], None);
+        database.add_api_key(record).await?;
+
+        // keys are restricted while the user's e-mail address is unverified
+        let response = get_with_api_key("tickets", &key).await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+        let response = get_with_api_key("me", &key).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        database.set_user_verified("email@addre.ss").await?;
+        let response = get_with_api_key("tickets", &key).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use axum::{body::Body, middleware, response::IntoResponse, routing::get, Json, Router};
+use axum_api::{
+    auth::{require_authentication, AuthenticatedUser},
+    create_api_router,
+    database::SimpleMemoryDatabase,
+};
+use common::{get_authorized, new_state, post_authorized, register_and_login, with_router};
+use reqwest::{header, StatusCode};
+use serde_json::json;
+use serial_test::serial;
+use std::error::Error;
+
+async fn whoami(user: AuthenticatedUser) -> impl IntoResponse {
+    Json(json!({ "user_id": user.user_id() }))
+}
+
+fn downstream_router() -> Router {
+    let state = new_state();
+    let protected_router = Router::new()
+        .route("/protected/whoami", get(whoami))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            require_authentication::<SimpleMemoryDatabase, Body>,
+        ));
+
+    Router::new()
+        .merge(create_api_router())
+        .route("/whoami", get(whoami))
+        .merge(protected_router)
+        .with_state(state)
+}
+
+#[tokio::test]
+#[serial]
+async fn accept_valid_token() -> Result<(), Box<dyn Error>> {
+    with_router(downstream_router(), async {
+        let login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let token = login["token"].as_str().unwrap();
+        let profile = get_authorized("me", token).await.body.unwrap();
+
+        for endpoint in ["whoami", "protected/whoami"] {
+            let response = get_authorized(endpoint, token).await;
+            assert_eq!(response.status_code, StatusCode::OK);
+            assert_eq!(response.body.unwrap()["user_id"], profile["id"]);
+        }
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn reject_missing_token() -> Result<(), Box<dyn Error>> {
+    with_router(downstream_router(), async {
+        for endpoint in ["whoami", "protected/whoami"] {
+            let response = reqwest::get(format!("http://{}/{endpoint}", common::ADDRESS))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+        }
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn reject_invalid_and_revoked_tokens() -> Result<(), Box<dyn Error>> {
+    with_router(downstream_router(), async {
+        let login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let token = login["token"].as_str().unwrap();
+        post_authorized("logout", token, json!({})).await;
+
+        for token in ["not.a.token", token] {
+            for endpoint in ["whoami", "protected/whoami"] {
+                let response = get_authorized(endpoint, token).await;
+                assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+                assert_eq!(
+                    response.headers[header::WWW_AUTHENTICATE],
+                    "Bearer error=\"invalid_token\""
+                );
+            }
+        }
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,106 @@
+mod common;
+
+use axum_api::password::BreachedPasswords;
+use common::{new_state, post, put_authorized, register_and_login, with_server_state};
+use reqwest::StatusCode;
+use serde_json::json;
+use serial_test::serial;
+use sha1::{Digest, Sha1};
+use std::{env, error::Error, fs, path::PathBuf};
+
+/// Writes a breached password list with the given passwords and occurrences,
+/// along with filler entries, and returns its path.
+fn write_list(name: &str, passwords: &[(&str, u64)], line_ending: &str) -> PathBuf {
+    let mut lines = passwords
+        .iter()
+        .map(|(password, occurrences)| {
+            let hash = Sha1::digest(password.as_bytes())
+                .iter()
+                .map(|byte| format!("{byte:02X}"))
+                .collect::<String>();
+            format!("{hash}:{occurrences}")
+        })
+        .chain((0..1000).map(|i| format!("{:040X}:{}", i * 0x1234_5678_9ABC_u64, i + 1)))
+        .collect::<Vec<_>>();
+    lines.sort();
+
+    let path = env::temp_dir().join(format!("axum_api_{name}_breached.txt"));
+    fs::write(&path, lines.join(line_ending) + line_ending).unwrap();
+    path
+}
+
+#[test]
+fn occurrences() {
+    let passwords = [("password", 9_545_824), ("hunter2", 17_043), ("pw", 2)];
+    for line_ending in ["\n", "\r\n"] {
+        let path = write_list("occurrences", &passwords, line_ending);
+        let breached_passwords = BreachedPasswords::open(&path, 1).unwrap();
+
+        for (password, occurrences) in passwords {
+            assert_eq!(breached_passwords.occurrences(password), occurrences);
+            assert!(breached_passwords.contains(password));
+        }
+        assert_eq!(breached_passwords.occurrences("P_ass1Wo$rD"), 0);
+        assert!(!breached_passwords.contains("P_ass1Wo$rD"));
+    }
+
+    let path = env::temp_dir().join("axum_api_occurrences_lowercase_breached.txt");
+    fs::write(
+        &path,
+        "5baa61e4c9b93f3f0682250b6cf8331b7ee68fd8:3\nffffffffffffffffffffffffffffffffffffffff:1\n",
+    )
+    .unwrap();
+    let breached_passwords = BreachedPasswords::open(&path, 1).unwrap();
+    assert_eq!(breached_passwords.occurrences("password"), 3);
+
+    fs::write(&path, "").unwrap();
+    let breached_passwords = BreachedPasswords::open(&path, 1).unwrap();
+    assert_eq!(breached_passwords.occurrences("password"), 0);
+}
+
+#[test]
+fn min_occurrences() {
+    let path = write_list("min_occurrences", &[("common", 100), ("rare", 2)], "\n");
+    let breached_passwords = BreachedPasswords::open(path, 10).unwrap();
+
+    assert!(breached_passwords.contains("common"));
+    assert!(!breached_passwords.contains("rare"));
+}
+
+#[tokio::test]
+#[serial]
+async fn reject_breached_passwords() -> Result<(), Box<dyn Error>> {
+    let path = write_list(
+        "reject_breached_passwords",
+        &[("password", 9_545_824)],
+        "\n",
+    );
+    let state = new_state().with_breached_passwords(BreachedPasswords::open(path, 1)?);
+
+    with_server_state(state, async {
+        let response = post(
+            "register",
+            json!({"email": "email@addre.ss", "password": "password"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        let body = response.body.unwrap();
+        assert_eq!(body["errors"][0]["field"], "password");
+        assert_eq!(body["errors"][0]["rule"], "breached_passwords");
+
+        let login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let response = put_authorized(
+            "password",
+            login["token"].as_str().unwrap(),
+            json!({"current_password": "P_ass1Wo$rD", "new_password": "password"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        let body = response.body.unwrap();
+        assert_eq!(body["errors"][0]["field"], "new_password");
+        assert_eq!(body["errors"][0]["rule"], "breached_passwords");
+
+        Ok(())
+    })
+    .await
+}
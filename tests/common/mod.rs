@@ -1,51 +1,246 @@
+#![allow(dead_code)]
+
+use axum::Router;
 use axum_api::{
-    create_api_router, database::SimpleMemoryDatabase, token::TokenManager, ServerState,
+    create_api_router, create_well_known_router,
+    database::SimpleMemoryDatabase,
+    mail::{FileMailer, Mail},
+    throttling::{FailureLimits, LoginThrottling},
+    token::{SigningKey, TokenManager},
+    verification::{EmailVerification, UnverifiedLogin},
+    ServerState,
+};
+use reqwest::{header::HeaderMap, StatusCode};
+use serde_json::{json, Map, Value};
+use std::{
+    env,
+    error::Error,
+    fs,
+    future::Future,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
 };
-use reqwest::StatusCode;
-use serde_json::{Map, Value};
-use std::{error::Error, future::Future, time::Duration};
 use tokio::task;
 
-const ADDRESS: &str = "127.0.0.1:29200";
+pub const ADDRESS: &str = "127.0.0.1:29200";
 
 pub struct Response {
     pub status_code: StatusCode,
+    pub headers: HeaderMap,
     pub body: Option<Map<String, Value>>,
 }
 
+pub fn new_token_manager() -> TokenManager {
+    TokenManager::new(
+        Duration::from_secs(10u64.pow(10)),
+        Duration::from_secs(60),
+        SigningKey::from_secret(jsonwebtoken::Algorithm::HS256, b"secret").unwrap(),
+    )
+}
+
+pub fn new_state() -> ServerState<SimpleMemoryDatabase> {
+    ServerState::new(SimpleMemoryDatabase::new(), new_token_manager())
+}
+
+/// Returns a state which sends verification links to a file outbox.
+pub fn state_with_verification(
+    outbox_path: &Path,
+    unverified_login: UnverifiedLogin,
+) -> ServerState<SimpleMemoryDatabase> {
+    new_state()
+        .with_mailer(Arc::new(FileMailer::new(outbox_path)))
+        .with_email_verification(EmailVerification {
+            unverified_login,
+            link_url: "http://localhost/verify".to_string(),
+        })
+}
+
+/// Returns limits which delay failed logins by a second after
+/// `free_failures`, doubling up to a minute.
+pub fn new_limits(free_failures: u32, lockout_failures: Option<u32>) -> FailureLimits {
+    FailureLimits {
+        free_failures,
+        base_delay: Duration::from_secs(1),
+        max_delay: Duration::from_secs(60),
+        lockout_failures,
+        lockout_duration: Duration::from_secs(15 * 60),
+        retention: Duration::from_secs(60 * 60),
+    }
+}
+
+pub fn state_with_throttling(
+    database: SimpleMemoryDatabase,
+    per_account: FailureLimits,
+    per_ip: FailureLimits,
+) -> ServerState<SimpleMemoryDatabase> {
+    ServerState::new(database, new_token_manager()).with_login_throttling(LoginThrottling {
+        per_account,
+        per_ip,
+    })
+}
+
+/// Returns a path for a file outbox, with no file at it yet.
+pub fn new_outbox_path(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("axum_api_{name}_outbox.jsonl"));
+    let _ = fs::remove_file(&path);
+    path
+}
+
+/// Reads the messages written to a file outbox.
+pub fn read_outbox(path: &Path) -> Vec<Mail> {
+    fs::read_to_string(path)
+        .unwrap_or_default()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+/// Waits until a file outbox contains at least `count` messages, which may be
+/// sent in the background, and returns them.
+pub async fn wait_for_outbox(path: &Path, count: usize) -> Vec<Mail> {
+    for _ in 0..100 {
+        let messages = read_outbox(path);
+        if messages.len() >= count {
+            return messages;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    panic!("outbox did not receive {count} messages");
+}
+
 pub async fn with_server(
     future: impl Future<Output = Result<(), Box<dyn Error>>>,
 ) -> Result<(), Box<dyn Error>> {
-    let server_task = task::spawn(async {
-        let state = ServerState::new(
-            SimpleMemoryDatabase::new(),
-            TokenManager::new(
-                Duration::from_secs(10u64.pow(10)),
-                Duration::from_secs(10u64.pow(10)),
-                jsonwebtoken::Algorithm::HS256,
-                "secret".into(),
-            ),
-        );
-        axum::Server::bind(&ADDRESS.parse().unwrap())
-            .serve(create_api_router().with_state(state).into_make_service()).await
-    });
+    with_server_state(new_state(), future).await
+}
+
+pub async fn with_server_state(
+    state: ServerState<SimpleMemoryDatabase>,
+    future: impl Future<Output = Result<(), Box<dyn Error>>>,
+) -> Result<(), Box<dyn Error>> {
+    let router = create_api_router()
+        .merge(create_well_known_router())
+        .with_state(state);
+    with_router(router, future).await
+}
+
+pub async fn with_router(
+    router: Router,
+    future: impl Future<Output = Result<(), Box<dyn Error>>>,
+) -> Result<(), Box<dyn Error>> {
+    let server = axum::Server::bind(&ADDRESS.parse().unwrap())
+        .serve(router.into_make_service_with_connect_info::<SocketAddr>());
+    let server_task = task::spawn(server);
 
     let return_value = future.await;
     server_task.abort();
     return_value
 }
 
+pub async fn get(endpoint: impl AsRef<str>) -> Response {
+    send(reqwest::Client::new().get(format!("http://{ADDRESS}/{}", endpoint.as_ref()))).await
+}
+
 pub async fn post(endpoint: impl AsRef<str>, json: Value) -> Response {
-    let response = reqwest::Client::new()
-        .post(format!("http://{ADDRESS}/{}", endpoint.as_ref()))
-        .json(&json)
-        .send()
+    send(
+        reqwest::Client::new()
+            .post(format!("http://{ADDRESS}/{}", endpoint.as_ref()))
+            .json(&json),
+    )
+    .await
+}
+
+pub async fn post_authorized(endpoint: impl AsRef<str>, token: &str, json: Value) -> Response {
+    send(
+        reqwest::Client::new()
+            .post(format!("http://{ADDRESS}/{}", endpoint.as_ref()))
+            .bearer_auth(token)
+            .json(&json),
+    )
+    .await
+}
+
+pub async fn put_authorized(endpoint: impl AsRef<str>, token: &str, json: Value) -> Response {
+    send(
+        reqwest::Client::new()
+            .put(format!("http://{ADDRESS}/{}", endpoint.as_ref()))
+            .bearer_auth(token)
+            .json(&json),
+    )
+    .await
+}
+
+pub async fn patch_authorized(endpoint: impl AsRef<str>, token: &str, json: Value) -> Response {
+    send(
+        reqwest::Client::new()
+            .patch(format!("http://{ADDRESS}/{}", endpoint.as_ref()))
+            .bearer_auth(token)
+            .json(&json),
+    )
+    .await
+}
+
+pub async fn delete_authorized(endpoint: impl AsRef<str>, token: &str, json: Value) -> Response {
+    send(
+        reqwest::Client::new()
+            .delete(format!("http://{ADDRESS}/{}", endpoint.as_ref()))
+            .bearer_auth(token)
+            .json(&json),
+    )
+    .await
+}
+
+pub async fn get_authorized(endpoint: impl AsRef<str>, token: &str) -> Response {
+    send(
+        reqwest::Client::new()
+            .get(format!("http://{ADDRESS}/{}", endpoint.as_ref()))
+            .bearer_auth(token),
+    )
+    .await
+}
+
+pub async fn login(email: &str, password: &str) -> Response {
+    post("login", json!({"email": email, "password": password})).await
+}
+
+/// Registers a user and logs in, returning the login response body.
+pub async fn register_and_login(email: &str, password: &str) -> Map<String, Value> {
+    post("register", json!({"email": email, "password": password})).await;
+    post("login", json!({"email": email, "password": password}))
         .await
-        .unwrap();
-    drop(json);
+        .body
+        .expect("response body could not be parsed as JSON object")
+}
+
+/// Extracts the code from a message containing a verification link.
+pub fn verification_code(body: &str) -> &str {
+    body.split_once("http://localhost/verify?code=")
+        .unwrap()
+        .1
+        .split_whitespace()
+        .next()
+        .unwrap()
+}
+
+/// Extracts the reset code from a password reset message.
+pub fn reset_code(body: &str) -> &str {
+    body.split_once("password: ")
+        .unwrap()
+        .1
+        .split_whitespace()
+        .next()
+        .unwrap()
+}
+
+async fn send(request: reqwest::RequestBuilder) -> Response {
+    let response = request.send().await.unwrap();
 
     Response {
         status_code: response.status(),
+        headers: response.headers().clone(),
         body: response.json::<Map<String, Value>>().await.ok(),
     }
 }
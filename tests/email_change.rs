@@ -0,0 +1,223 @@
+mod common;
+
+use axum_api::{mail::Mail, verification::UnverifiedLogin};
+use common::{
+    get, get_authorized, login, new_outbox_path, post, post_authorized, register_and_login,
+    reset_code, state_with_verification, verification_code, wait_for_outbox, with_server_state,
+};
+use reqwest::StatusCode;
+use serde_json::json;
+use serial_test::serial;
+use std::error::Error;
+
+fn message_to<'a>(messages: &'a [Mail], to: &str) -> &'a Mail {
+    messages.iter().find(|m| m.to == to).unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn change_email() -> Result<(), Box<dyn Error>> {
+    let outbox_path = new_outbox_path("change_email");
+
+    with_server_state(
+        state_with_verification(&outbox_path, UnverifiedLogin::Allow),
+        async {
+            let login_response = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+            let token = login_response["token"].as_str().unwrap();
+
+            let response = post_authorized(
+                "me/email",
+                token,
+                json!({"password": "P_ass1Wo$rD", "new_email": "new@ADDRE.SS"}),
+            )
+            .await;
+            assert_eq!(response.status_code, StatusCode::ACCEPTED);
+
+            // the change only takes effect once confirmed
+            let messages = wait_for_outbox(&outbox_path, 2).await;
+            let code = verification_code(&message_to(&messages, "new@addre.ss").body).to_string();
+            let profile = get_authorized("me", token).await.body.unwrap();
+            assert_eq!(profile["email"], "email@addre.ss");
+
+            let response = get(format!("verify?code={code}")).await;
+            assert_eq!(response.status_code, StatusCode::OK);
+
+            // the previous address is notified
+            let messages = wait_for_outbox(&outbox_path, 3).await;
+            assert!(messages[2..]
+                .iter()
+                .any(|m| m.to == "email@addre.ss" && m.body.contains("new@addre.ss")));
+
+            // tokens issued before the change continue to work
+            let profile = get_authorized("me", token).await.body.unwrap();
+            assert_eq!(profile["email"], "new@addre.ss");
+            let response = post(
+                "token/refresh",
+                json!({ "refresh_token": login_response["refresh_token"] }),
+            )
+            .await;
+            assert_eq!(response.status_code, StatusCode::OK);
+
+            let response = login("email@addre.ss", "P_ass1Wo$rD").await;
+            assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+            let response = login("new@addre.ss", "P_ass1Wo$rD").await;
+            assert_eq!(response.status_code, StatusCode::OK);
+            let token = response.body.unwrap()["token"]
+                .as_str()
+                .unwrap()
+                .to_string();
+            let body = get_authorized("token", &token).await.body.unwrap();
+            assert_eq!(body["email_verified"], true);
+
+            // the link cannot be used again, and the old address is free again
+            let response = get(format!("verify?code={code}")).await;
+            assert_eq!(response.status_code, StatusCode::CONFLICT);
+            let response = post(
+                "register",
+                json!({"email": "email@addre.ss", "password": "P_ass1Wo$rD"}),
+            )
+            .await;
+            assert_eq!(response.status_code, StatusCode::OK);
+
+            Ok(())
+        },
+    )
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn reject_invalid_changes() -> Result<(), Box<dyn Error>> {
+    let outbox_path = new_outbox_path("reject_invalid_email_changes");
+
+    with_server_state(
+        state_with_verification(&outbox_path, UnverifiedLogin::Allow),
+        async {
+            register_and_login("other@addre.ss", "P_ass1Wo$rD").await;
+            let login_response = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+            let token = login_response["token"].as_str().unwrap();
+
+            for (body, status_code) in [
+                (
+                    json!({"password": "wrong", "new_email": "new@addre.ss"}),
+                    StatusCode::FORBIDDEN,
+                ),
+                (
+                    json!({"password": "P_ass1Wo$rD", "new_email": "other@addre.ss"}),
+                    StatusCode::CONFLICT,
+                ),
+                (
+                    json!({"password": "P_ass1Wo$rD", "new_email": "email@addre.ss"}),
+                    StatusCode::CONFLICT,
+                ),
+                (
+                    json!({"password": "P_ass1Wo$rD", "new_email": "not an e-mail"}),
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                ),
+            ] {
+                let response = post_authorized("me/email", token, body).await;
+                assert_eq!(response.status_code, status_code);
+            }
+
+            // a link is refused once another change has been confirmed
+            for new_email in ["first@addre.ss", "second@addre.ss"] {
+                let response = post_authorized(
+                    "me/email",
+                    token,
+                    json!({"password": "P_ass1Wo$rD", "new_email": new_email}),
+                )
+                .await;
+                assert_eq!(response.status_code, StatusCode::ACCEPTED);
+            }
+            let messages = wait_for_outbox(&outbox_path, 4).await;
+            let response = get(format!(
+                "verify?code={}",
+                verification_code(&message_to(&messages, "second@addre.ss").body)
+            ))
+            .await;
+            assert_eq!(response.status_code, StatusCode::OK);
+            let response = get(format!(
+                "verify?code={}",
+                verification_code(&message_to(&messages, "first@addre.ss").body)
+            ))
+            .await;
+            assert_eq!(response.status_code, StatusCode::CONFLICT);
+
+            // a link is refused once another user has registered the address
+            let response = post_authorized(
+                "me/email",
+                token,
+                json!({"password": "P_ass1Wo$rD", "new_email": "taken@addre.ss"}),
+            )
+            .await;
+            assert_eq!(response.status_code, StatusCode::ACCEPTED);
+            register_and_login("taken@addre.ss", "P_ass1Wo$rD").await;
+            let messages = wait_for_outbox(&outbox_path, 7).await;
+            let message = messages
+                .iter()
+                .find(|m| m.to == "taken@addre.ss" && m.body.contains("change the e-mail"))
+                .unwrap();
+            let response = get(format!("verify?code={}", verification_code(&message.body))).await;
+            assert_eq!(response.status_code, StatusCode::CONFLICT);
+
+            let profile = get_authorized("me", token).await.body.unwrap();
+            assert_eq!(profile["email"], "second@addre.ss");
+
+            Ok(())
+        },
+    )
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn pending_password_resets_are_discarded() -> Result<(), Box<dyn Error>> {
+    let outbox_path = new_outbox_path("discard_password_resets_on_email_change");
+
+    with_server_state(
+        state_with_verification(&outbox_path, UnverifiedLogin::Allow),
+        async {
+            let login_response = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+            let token = login_response["token"].as_str().unwrap();
+            let response = post("password/forgot", json!({"email": "email@addre.ss"})).await;
+            assert_eq!(response.status_code, StatusCode::ACCEPTED);
+            let response = post_authorized(
+                "me/email",
+                token,
+                json!({"password": "P_ass1Wo$rD", "new_email": "new@addre.ss"}),
+            )
+            .await;
+            assert_eq!(response.status_code, StatusCode::ACCEPTED);
+
+            let messages = wait_for_outbox(&outbox_path, 3).await;
+            let message = messages
+                .iter()
+                .find(|m| m.subject == "Password reset")
+                .unwrap();
+            let reset_code = reset_code(&message.body).to_string();
+            let response = get(format!(
+                "verify?code={}",
+                verification_code(&message_to(&messages, "new@addre.ss").body)
+            ))
+            .await;
+            assert_eq!(response.status_code, StatusCode::OK);
+
+            // the code sent to the previous address neither resets the password
+            // of the user nor that of whoever registers the address next
+            register_and_login("email@addre.ss", "0th3r_P4ssw0rd").await;
+            let response = post(
+                "password/reset",
+                json!({"code": reset_code, "new_password": "n3w_P4ssw0rd"}),
+            )
+            .await;
+            assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+            let response = login("email@addre.ss", "0th3r_P4ssw0rd").await;
+            assert_eq!(response.status_code, StatusCode::OK);
+            let response = login("new@addre.ss", "P_ass1Wo$rD").await;
+            assert_eq!(response.status_code, StatusCode::OK);
+
+            Ok(())
+        },
+    )
+    .await
+}
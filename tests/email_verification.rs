@@ -0,0 +1,234 @@
+mod common;
+
+use axum_api::verification::UnverifiedLogin;
+use common::{
+    get, get_authorized, login, new_outbox_path, post, post_authorized, put_authorized,
+    read_outbox, register_and_login, state_with_verification, verification_code, wait_for_outbox,
+    with_server, with_server_state,
+};
+use reqwest::{header, StatusCode};
+use serde_json::json;
+use serial_test::serial;
+use std::{error::Error, time::Duration};
+
+#[tokio::test]
+#[serial]
+async fn refuse_unverified_login() -> Result<(), Box<dyn Error>> {
+    let outbox_path = new_outbox_path("refuse_unverified_login");
+
+    with_server_state(
+        state_with_verification(&outbox_path, UnverifiedLogin::Refuse),
+        async {
+            let response = post(
+                "register",
+                json!({"email": "email@addre.ss", "password": "P_ass1Wo$rD"}),
+            )
+            .await;
+            assert_eq!(response.status_code, StatusCode::OK);
+
+            let response = login("email@addre.ss", "P_ass1Wo$rD").await;
+            assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+
+            let messages = wait_for_outbox(&outbox_path, 1).await;
+            assert_eq!(messages[0].to, "email@addre.ss");
+            let code = verification_code(&messages[0].body);
+            let response = get(format!("verify?code={code}")).await;
+            assert_eq!(response.status_code, StatusCode::OK);
+
+            let response = login("email@addre.ss", "P_ass1Wo$rD").await;
+            assert_eq!(response.status_code, StatusCode::OK);
+            let token = response.body.unwrap()["token"]
+                .as_str()
+                .unwrap()
+                .to_string();
+            let body = get_authorized("token", &token).await.body.unwrap();
+            assert_eq!(body["valid"], true);
+            assert_eq!(body["email_verified"], true);
+
+            Ok(())
+        },
+    )
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn restrict_unverified_login() -> Result<(), Box<dyn Error>> {
+    let outbox_path = new_outbox_path("restrict_unverified_login");
+
+    with_server_state(
+        state_with_verification(&outbox_path, UnverifiedLogin::Restrict),
+        async {
+            let login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+            let token = login["token"].as_str().unwrap();
+            let body = get_authorized("token", token).await.body.unwrap();
+            assert_eq!(body["valid"], true);
+            assert_eq!(body["email_verified"], false);
+
+            let response = put_authorized(
+                "password",
+                token,
+                json!({"current_password": "P_ass1Wo$rD", "new_password": "n3w_P4ssw0rd"}),
+            )
+            .await;
+            assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+            assert_eq!(
+                response.headers[header::WWW_AUTHENTICATE],
+                "Bearer error=\"insufficient_scope\""
+            );
+
+            // verifying and refreshing lifts the restriction
+            let messages = wait_for_outbox(&outbox_path, 1).await;
+            let code = verification_code(&messages[0].body);
+            get(format!("verify?code={code}")).await;
+            let response = post(
+                "token/refresh",
+                json!({ "refresh_token": login["refresh_token"] }),
+            )
+            .await;
+            let token = response.body.unwrap()["token"]
+                .as_str()
+                .unwrap()
+                .to_string();
+            let response = put_authorized(
+                "password",
+                &token,
+                json!({"current_password": "P_ass1Wo$rD", "new_password": "n3w_P4ssw0rd"}),
+            )
+            .await;
+            assert_eq!(response.status_code, StatusCode::OK);
+
+            Ok(())
+        },
+    )
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn restricted_tokens_can_log_out() -> Result<(), Box<dyn Error>> {
+    let outbox_path = new_outbox_path("restricted_tokens_can_log_out");
+
+    with_server_state(
+        state_with_verification(&outbox_path, UnverifiedLogin::Restrict),
+        async {
+            let login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+            let token = login["token"].as_str().unwrap();
+
+            let response = post_authorized("logout", token, json!({})).await;
+            assert_eq!(response.status_code, StatusCode::OK);
+            let body = get_authorized("token", token).await.body.unwrap();
+            assert_eq!(body["valid"], false);
+
+            Ok(())
+        },
+    )
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn allow_unverified_login() -> Result<(), Box<dyn Error>> {
+    let outbox_path = new_outbox_path("allow_unverified_login");
+
+    with_server_state(
+        state_with_verification(&outbox_path, UnverifiedLogin::Allow),
+        async {
+            let login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+            let token = login["token"].as_str().unwrap();
+            let body = get_authorized("token", token).await.body.unwrap();
+            assert_eq!(body["email_verified"], false);
+
+            let response = put_authorized(
+                "password",
+                token,
+                json!({"current_password": "P_ass1Wo$rD", "new_password": "n3w_P4ssw0rd"}),
+            )
+            .await;
+            assert_eq!(response.status_code, StatusCode::OK);
+
+            Ok(())
+        },
+    )
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn resend_verification() -> Result<(), Box<dyn Error>> {
+    let outbox_path = new_outbox_path("resend_verification");
+
+    with_server_state(
+        state_with_verification(&outbox_path, UnverifiedLogin::Refuse),
+        async {
+            post(
+                "register",
+                json!({"email": "email@addre.ss", "password": "P_ass1Wo$rD"}),
+            )
+            .await;
+            wait_for_outbox(&outbox_path, 1).await;
+
+            let response = post("verify/resend", json!({"email": "email@addre.ss"})).await;
+            assert_eq!(response.status_code, StatusCode::ACCEPTED);
+            let messages = wait_for_outbox(&outbox_path, 2).await;
+            assert_eq!(messages[1].to, "email@addre.ss");
+
+            let code = verification_code(&messages[1].body);
+            let response = get(format!("verify?code={code}")).await;
+            assert_eq!(response.status_code, StatusCode::OK);
+
+            // neither verified nor unknown users are sent links
+            for email in ["email@addre.ss", "unknown@addre.ss"] {
+                let response = post("verify/resend", json!({ "email": email })).await;
+                assert_eq!(response.status_code, StatusCode::ACCEPTED);
+            }
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            assert_eq!(read_outbox(&outbox_path).len(), 2);
+
+            Ok(())
+        },
+    )
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn verify_with_invalid_code() -> Result<(), Box<dyn Error>> {
+    let outbox_path = new_outbox_path("verify_with_invalid_code");
+
+    with_server_state(
+        state_with_verification(&outbox_path, UnverifiedLogin::Restrict),
+        async {
+            let login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+
+            let response = get("verify?code=invalid").await;
+            assert_eq!(response.status_code, StatusCode::BAD_REQUEST);
+
+            // API tokens and verification codes are not interchangeable
+            let token = login["token"].as_str().unwrap();
+            let response = get(format!("verify?code={token}")).await;
+            assert_eq!(response.status_code, StatusCode::BAD_REQUEST);
+            let messages = wait_for_outbox(&outbox_path, 1).await;
+            let code = verification_code(&messages[0].body);
+            let body = get_authorized("token", code).await.body.unwrap();
+            assert_eq!(body["valid"], false);
+
+            Ok(())
+        },
+    )
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn resend_verification_without_configuration() -> Result<(), Box<dyn Error>> {
+    with_server(async {
+        register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+
+        let response = post("verify/resend", json!({"email": "email@addre.ss"})).await;
+        assert_eq!(response.status_code, StatusCode::NOT_IMPLEMENTED);
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use axum_api::{
+    database::SimpleMemoryDatabase,
+    password::{self, HashingPool, PasswordHashError, PasswordHasher},
+    ServerState,
+};
+use common::{new_token_manager, post, with_server_state};
+use reqwest::{header, StatusCode};
+use serde_json::json;
+use serial_test::serial;
+use std::{error::Error, sync::Arc, thread, time::Duration};
+use tokio::sync::oneshot;
+
+/// A hasher which takes a while to hash passwords, and stores them as they
+/// are.
+struct SlowHasher;
+
+impl PasswordHasher for SlowHasher {
+    fn hash(&self, password: &str) -> Result<String, PasswordHashError> {
+        thread::sleep(Duration::from_millis(300));
+        Ok(password.to_string())
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> Result<bool, PasswordHashError> {
+        thread::sleep(Duration::from_millis(300));
+        Ok(password::constant_time_eq(
+            password.as_bytes(),
+            hash.as_bytes(),
+        ))
+    }
+
+    fn needs_rehash(&self, _hash: &str) -> bool {
+        false
+    }
+}
+
+#[tokio::test]
+async fn queue_limit() -> Result<(), Box<dyn Error>> {
+    let pool = HashingPool::new(1, 1);
+    let (sender, receiver) = oneshot::channel::<()>();
+
+    let running = tokio::spawn({
+        let pool = pool.clone();
+        async move { pool.run(move || receiver.blocking_recv().is_ok()).await }
+    });
+    let queued = tokio::spawn({
+        let pool = pool.clone();
+        async move { pool.run(|| 2).await }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!queued.is_finished());
+
+    assert!(matches!(
+        pool.run(|| 3).await,
+        Err(PasswordHashError::Overloaded)
+    ));
+
+    sender.send(()).unwrap();
+    assert!(running.await??);
+    assert_eq!(queued.await??, 2);
+
+    // the freed up places in the pool can be taken again
+    assert_eq!(pool.run(|| 4).await?, 4);
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn overloaded() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new()
+        .with_password_hasher(Arc::new(SlowHasher))?
+        .with_hashing_pool(HashingPool::new(1, 0));
+    let state = ServerState::new(database, new_token_manager());
+
+    with_server_state(state, async {
+        let (first, second) = tokio::join!(
+            post(
+                "register",
+                json!({"email": "email@addre.ss", "password": "pw"})
+            ),
+            post(
+                "register",
+                json!({"email": "other@addre.ss", "password": "pw"})
+            ),
+        );
+        let mut status_codes = [first.status_code, second.status_code];
+        status_codes.sort();
+        assert_eq!(
+            status_codes,
+            [StatusCode::OK, StatusCode::SERVICE_UNAVAILABLE]
+        );
+        let rejected = if first.status_code == StatusCode::OK {
+            second
+        } else {
+            first
+        };
+        assert_eq!(rejected.headers.get(header::RETRY_AFTER).unwrap(), "1");
+
+        // the rejected request can be retried once the pool is free again
+        let response = post(
+            "register",
+            json!({"email": "other@addre.ss", "password": "pw"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        let response = post(
+            "login",
+            json!({"email": "other@addre.ss", "password": "pw"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        Ok(())
+    })
+    .await
+}
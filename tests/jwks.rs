@@ -0,0 +1,177 @@
+mod common;
+
+use axum_api::{
+    database::SimpleMemoryDatabase,
+    token::{KeyError, SigningKey, TokenManager, VerificationKey},
+    ServerState,
+};
+use common::{get, with_server, with_server_state};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use reqwest::StatusCode;
+use serde_json::Value;
+use serial_test::serial;
+use std::{error::Error, fs, time::Duration};
+use uuid::Uuid;
+
+fn load_key(algorithm: Algorithm, name: &str) -> SigningKey {
+    SigningKey::from_pem(
+        algorithm,
+        &fs::read(format!("tests/keys/{name}_private.pem")).unwrap(),
+        &fs::read(format!("tests/keys/{name}_public.pem")).unwrap(),
+    )
+    .unwrap()
+}
+
+fn new_token_manager(signing_key: SigningKey) -> TokenManager {
+    TokenManager::new(
+        Duration::from_secs(600),
+        Duration::from_secs(60),
+        signing_key,
+    )
+}
+
+#[test]
+fn default_key_id_is_jwk_thumbprint() {
+    let key = load_key(Algorithm::RS256, "rsa");
+    assert_eq!(key.key_id(), "a5r4biZpZlahSldC58dqKuI7GldNHcVAuESOfgt7gM8");
+
+    let key = key.with_key_id("custom");
+    assert_eq!(key.key_id(), "custom");
+    assert_eq!(key.verification_key().jwk().unwrap()["kid"], "custom");
+}
+
+#[tokio::test]
+async fn rotation_keeps_issued_tokens_valid() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+    let token_manager = new_token_manager(load_key(Algorithm::RS256, "rsa"));
+    let old_token = token_manager.new_token(Uuid::new_v4(), &[], &[])?;
+
+    let new_key = load_key(Algorithm::ES256, "ec");
+    let new_key_id = new_key.key_id().to_string();
+    token_manager.rotate_signing_key(new_key)?;
+    let new_token = token_manager.new_token(Uuid::new_v4(), &[], &[])?;
+    assert_eq!(
+        jsonwebtoken::decode_header(&new_token)?.kid.as_deref(),
+        Some(new_key_id.as_str())
+    );
+
+    for token in [&old_token, &new_token] {
+        token_manager
+            .decode_and_validate_token(token.clone(), &database)
+            .await?;
+    }
+
+    let old_key_id = jsonwebtoken::decode_header(&old_token)?.kid.unwrap();
+    assert!(token_manager.remove_retired_key(&old_key_id));
+    assert!(token_manager
+        .decode_and_validate_token(old_token, &database)
+        .await
+        .is_err());
+    token_manager
+        .decode_and_validate_token(new_token, &database)
+        .await?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn rotation_keeps_tokens_without_key_id_valid() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+    let token_manager = new_token_manager(load_key(Algorithm::RS256, "rsa"));
+
+    // re-sign a token without a key id, as issued before key ids existed
+    let token = token_manager.new_token(Uuid::new_v4(), &[], &[])?;
+    let claims = jsonwebtoken::decode::<Value>(
+        &token,
+        &DecodingKey::from_rsa_pem(&fs::read("tests/keys/rsa_public.pem")?)?,
+        &Validation::new(Algorithm::RS256),
+    )?
+    .claims;
+    let old_token = jsonwebtoken::encode(
+        &Header::new(Algorithm::RS256),
+        &claims,
+        &EncodingKey::from_rsa_pem(&fs::read("tests/keys/rsa_private.pem")?)?,
+    )?;
+
+    token_manager.rotate_signing_key(load_key(Algorithm::ES256, "ec"))?;
+    token_manager
+        .decode_and_validate_token(old_token.clone(), &database)
+        .await?;
+
+    let old_key_id = jsonwebtoken::decode_header(&token)?.kid.unwrap();
+    assert!(token_manager.remove_retired_key(&old_key_id));
+    assert!(token_manager
+        .decode_and_validate_token(old_token, &database)
+        .await
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn reject_duplicate_key_id() {
+    let token_manager = new_token_manager(load_key(Algorithm::RS256, "rsa"));
+
+    let result = token_manager.rotate_signing_key(load_key(Algorithm::RS256, "rsa"));
+    assert!(matches!(result, Err(KeyError::DuplicateKeyId(_))));
+
+    let key = VerificationKey::from_pem(
+        Algorithm::RS256,
+        &fs::read("tests/keys/rsa_public.pem").unwrap(),
+    )
+    .unwrap();
+    let result = token_manager.add_retired_key(key);
+    assert!(matches!(result, Err(KeyError::DuplicateKeyId(_))));
+}
+
+#[tokio::test]
+#[serial]
+async fn jwks_contains_public_keys() -> Result<(), Box<dyn Error>> {
+    let token_manager = new_token_manager(load_key(Algorithm::RS256, "rsa"));
+    token_manager.add_retired_key(VerificationKey::from_pem(
+        Algorithm::EdDSA,
+        &fs::read("tests/keys/ed25519_public.pem")?,
+    )?)?;
+    token_manager.add_retired_key(
+        VerificationKey::from_secret(Algorithm::HS256, b"secret")?.with_key_id("hmac"),
+    )?;
+    let state = ServerState::new(SimpleMemoryDatabase::new(), token_manager);
+
+    with_server_state(state, async {
+        let response = get(".well-known/jwks.json").await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        let keys = response.body.unwrap()["keys"].as_array().unwrap().clone();
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0]["kty"], "RSA");
+        assert_eq!(keys[0]["alg"], "RS256");
+        assert_eq!(keys[0]["use"], "sig");
+        assert_eq!(
+            keys[0]["kid"],
+            "a5r4biZpZlahSldC58dqKuI7GldNHcVAuESOfgt7gM8"
+        );
+        assert_eq!(keys[1]["kty"], "OKP");
+        assert_eq!(keys[1]["crv"], "Ed25519");
+        assert_eq!(keys[1]["alg"], "EdDSA");
+        assert!(keys.iter().all(|key| key.get("k").is_none()));
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn jwks_omits_secret_keys() -> Result<(), Box<dyn Error>> {
+    with_server(async {
+        let response = get(".well-known/jwks.json").await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert!(response.body.unwrap()["keys"]
+            .as_array()
+            .unwrap()
+            .is_empty());
+
+        Ok(())
+    })
+    .await
+}
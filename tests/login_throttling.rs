@@ -0,0 +1,237 @@
+mod common;
+
+use axum_api::database::{Database, SimpleMemoryDatabase};
+use common::{
+    delete_authorized, login, new_limits, put_authorized, register_and_login,
+    state_with_throttling, with_server_state, Response,
+};
+use reqwest::{header, StatusCode};
+use serde_json::json;
+use serial_test::serial;
+use std::{
+    error::Error,
+    time::{Duration, SystemTime},
+};
+
+fn retry_after(response: &Response) -> Option<u64> {
+    response
+        .headers
+        .get(header::RETRY_AFTER)
+        .map(|value| value.to_str().unwrap().parse().unwrap())
+}
+
+/// Moves the latest failed login counted for a key into the past, as if the
+/// client had waited.
+async fn wait_out(database: &SimpleMemoryDatabase, key: &str, duration: Duration) {
+    let failures = database.get_login_failures(key).await.unwrap().unwrap();
+    database.clear_login_failures(key).await.unwrap();
+    for _ in 0..failures.count {
+        database
+            .record_login_failure(
+                key,
+                failures.last_failure - duration,
+                Duration::from_secs(60 * 60),
+            )
+            .await
+            .unwrap();
+    }
+}
+
+#[test]
+fn block_duration() {
+    let limits = new_limits(2, Some(6));
+
+    assert_eq!(limits.block_duration(0), None);
+    assert_eq!(limits.block_duration(2), None);
+    assert_eq!(limits.block_duration(3), Some(Duration::from_secs(1)));
+    assert_eq!(limits.block_duration(4), Some(Duration::from_secs(2)));
+    assert_eq!(limits.block_duration(5), Some(Duration::from_secs(4)));
+    assert_eq!(limits.block_duration(6), Some(Duration::from_secs(15 * 60)));
+    assert_eq!(
+        limits.block_duration(100),
+        Some(Duration::from_secs(15 * 60))
+    );
+
+    let limits = new_limits(0, None);
+    assert_eq!(limits.block_duration(7), Some(Duration::from_secs(60)));
+    assert_eq!(
+        limits.block_duration(u32::MAX),
+        Some(Duration::from_secs(60))
+    );
+}
+
+#[tokio::test]
+#[serial]
+async fn exponential_backoff() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+    let state = state_with_throttling(database.clone(), new_limits(2, None), new_limits(100, None));
+
+    with_server_state(state, async {
+        register_and_login("email@addre.ss", "pw").await;
+
+        for _ in 0..2 {
+            let response = login("email@addre.ss", "wrong").await;
+            assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+            assert_eq!(retry_after(&response), None);
+        }
+
+        let response = login("email@addre.ss", "wrong").await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        assert_eq!(retry_after(&response), Some(1));
+
+        // even the correct password is rejected while delayed
+        let response = login("email@addre.ss", "pw").await;
+        assert_eq!(response.status_code, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(retry_after(&response), Some(1));
+
+        wait_out(&database, "account:email@addre.ss", Duration::from_secs(1)).await;
+        let response = login("email@addre.ss", "wrong").await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        assert_eq!(retry_after(&response), Some(2));
+
+        wait_out(&database, "account:email@addre.ss", Duration::from_secs(2)).await;
+        let response = login("email@addre.ss", "pw").await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        // a successful login resets the count
+        let response = login("email@addre.ss", "wrong").await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        assert_eq!(retry_after(&response), None);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn account_lockout() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+    let state = state_with_throttling(
+        database.clone(),
+        new_limits(100, Some(3)),
+        new_limits(100, None),
+    );
+
+    with_server_state(state, async {
+        register_and_login("email@addre.ss", "pw").await;
+        register_and_login("other@addre.ss", "pw").await;
+
+        for _ in 0..2 {
+            let response = login("email@addre.ss", "wrong").await;
+            assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        }
+        let response = login("email@addre.ss", "wrong").await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        assert_eq!(retry_after(&response), Some(15 * 60));
+
+        let response = login("email@addre.ss", "pw").await;
+        assert_eq!(response.status_code, StatusCode::TOO_MANY_REQUESTS);
+        let retry_after = retry_after(&response).unwrap();
+        assert!(retry_after > 14 * 60 && retry_after <= 15 * 60);
+
+        // other accounts are unaffected
+        let response = login("other@addre.ss", "pw").await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        wait_out(
+            &database,
+            "account:email@addre.ss",
+            Duration::from_secs(15 * 60),
+        )
+        .await;
+        let response = login("email@addre.ss", "pw").await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn ip_lockout() -> Result<(), Box<dyn Error>> {
+    let state = state_with_throttling(
+        SimpleMemoryDatabase::new(),
+        new_limits(100, None),
+        new_limits(100, Some(3)),
+    );
+
+    with_server_state(state, async {
+        register_and_login("email@addre.ss", "pw").await;
+
+        // failures are counted across accounts, including unknown ones
+        for email in ["email@addre.ss", "other@addre.ss", "unknown@addre.ss"] {
+            let response = login(email, "wrong").await;
+            assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        }
+
+        let response = login("email@addre.ss", "pw").await;
+        assert_eq!(response.status_code, StatusCode::TOO_MANY_REQUESTS);
+        assert!(retry_after(&response).is_some());
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn password_confirmations_are_throttled() -> Result<(), Box<dyn Error>> {
+    let state = state_with_throttling(
+        SimpleMemoryDatabase::new(),
+        new_limits(1, None),
+        new_limits(100, None),
+    );
+
+    with_server_state(state, async {
+        let login_response = register_and_login("email@addre.ss", "pw").await;
+        let token = login_response["token"].as_str().unwrap();
+
+        // wrong current passwords count as failed logins of the account
+        let change = |current_password| {
+            put_authorized(
+                "password",
+                token,
+                json!({"current_password": current_password, "new_password": "new"}),
+            )
+        };
+        let response = change("wrong").await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+        assert_eq!(retry_after(&response), None);
+        let response = delete_authorized("me", token, json!({"password": "wrong"})).await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+        assert_eq!(retry_after(&response), Some(1));
+
+        // even the correct password is rejected while delayed
+        for response in [
+            change("pw").await,
+            delete_authorized("me", token, json!({"password": "pw"})).await,
+            login("email@addre.ss", "pw").await,
+        ] {
+            assert_eq!(response.status_code, StatusCode::TOO_MANY_REQUESTS);
+            assert_eq!(retry_after(&response), Some(1));
+        }
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+async fn login_failures_expire() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+    let now = SystemTime::now();
+
+    let failures = database
+        .record_login_failure("key", now, Duration::from_secs(60))
+        .await?;
+    assert_eq!(failures.count, 1);
+    let failures = database
+        .record_login_failure("key", now, Duration::ZERO)
+        .await?;
+    assert_eq!(failures.count, 2);
+    assert!(database.get_login_failures("key").await?.is_none());
+
+    Ok(())
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use common::{get_authorized, post, post_authorized, register_and_login, with_server};
+use reqwest::StatusCode;
+use serde_json::json;
+use serial_test::serial;
+use std::error::Error;
+
+async fn is_token_valid(token: &str) -> bool {
+    get_authorized("token", token).await.body.unwrap()["valid"]
+        .as_bool()
+        .unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn logout_revokes_only_current_token() -> Result<(), Box<dyn Error>> {
+    with_server(async {
+        let first_login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let first_token = first_login["token"].as_str().unwrap();
+        let second_login = post(
+            "login",
+            json!({"email": "email@addre.ss", "password": "P_ass1Wo$rD"}),
+        )
+        .await
+        .body
+        .unwrap();
+        let second_token = second_login["token"].as_str().unwrap();
+
+        let response = post_authorized("logout", first_token, json!({})).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        assert!(!is_token_valid(first_token).await);
+        assert!(is_token_valid(second_token).await);
+
+        let response = post_authorized("logout", first_token, json!({})).await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn logout_all_revokes_every_token() -> Result<(), Box<dyn Error>> {
+    with_server(async {
+        let first_login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let second_login = post(
+            "login",
+            json!({"email": "email@addre.ss", "password": "P_ass1Wo$rD"}),
+        )
+        .await
+        .body
+        .unwrap();
+        let other_login = register_and_login("other@addre.ss", "P_ass1Wo$rD").await;
+
+        let response = post_authorized(
+            "logout/all",
+            first_login["token"].as_str().unwrap(),
+            json!({}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        for login in [&first_login, &second_login] {
+            assert!(!is_token_valid(login["token"].as_str().unwrap()).await);
+            let response = post(
+                "token/refresh",
+                json!({ "refresh_token": login["refresh_token"] }),
+            )
+            .await;
+            assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        }
+        assert!(is_token_valid(other_login["token"].as_str().unwrap()).await);
+
+        let new_login = post(
+            "login",
+            json!({"email": "email@addre.ss", "password": "P_ass1Wo$rD"}),
+        )
+        .await
+        .body
+        .unwrap();
+        assert!(is_token_valid(new_login["token"].as_str().unwrap()).await);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn logout_with_invalid_token() -> Result<(), Box<dyn Error>> {
+    with_server(async {
+        for endpoint in ["logout", "logout/all"] {
+            let response = post_authorized(endpoint, "not.a.token", json!({})).await;
+            assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        }
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,507 @@
+mod common;
+
+use axum::Router;
+use axum_api::{
+    create_api_router,
+    database::{Database, SimpleMemoryDatabase},
+    token, validation, ServerState,
+};
+use common::{
+    delete_authorized, get_authorized, new_token_manager, patch_authorized, post_authorized,
+    put_authorized, register_and_login, with_router, ADDRESS,
+};
+use reqwest::{header, redirect::Policy, StatusCode, Url};
+use serde_json::{json, Value};
+use serial_test::serial;
+use std::{error::Error, future::Future};
+
+const SPA_REDIRECT_URI: &str = "http://localhost:8080/callback";
+const PARTNER_REDIRECT_URI: &str = "https://partner.example/oauth/callback";
+
+/// Code verifier and challenge from RFC 7636, appendix B.
+const CODE_VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+const CODE_CHALLENGE: &str = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+/// Creates a router with a public client `spa` and a confidential client
+/// `partner`, returning the confidential client's secret.
+async fn authorization_router(database: &SimpleMemoryDatabase) -> (Router, String) {
+    let scopes = ["tickets:read".to_string(), "tickets:write".to_string()];
+    database
+        .add_oauth_client(token::new_public_oauth_client(
+            "spa",
+            &scopes,
+            &[SPA_REDIRECT_URI.to_string()],
+        ))
+        .await
+        .unwrap();
+    let (secret, client) = token::new_oauth_client(
+        "partner",
+        &scopes,
+        &[
+            PARTNER_REDIRECT_URI.to_string(),
+            "https://partner.example/other".to_string(),
+        ],
+    );
+    database.add_oauth_client(client).await.unwrap();
+
+    let router = Router::new()
+        .merge(create_api_router())
+        .with_state(ServerState::new(database.clone(), new_token_manager()));
+    (router, secret)
+}
+
+/// Runs a future against a server with the router of
+/// [`authorization_router`], once a user granted `tickets:read` has
+/// registered.
+async fn with_authorization_server(
+    database: &SimpleMemoryDatabase,
+    future: impl Future<Output = Result<(), Box<dyn Error>>>,
+) -> Result<(), Box<dyn Error>> {
+    let (router, _) = authorization_router(database).await;
+    with_router(router, async {
+        register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let user_id = database.get_user_by_email("email@addre.ss").await?.id;
+        database
+            .set_user_roles(
+                user_id,
+                &["user".to_string()],
+                &["tickets:read".to_string()],
+            )
+            .await?;
+        future.await
+    })
+    .await
+}
+
+/// Returns a client which does not follow redirects, so that they can be
+/// inspected.
+fn browser() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(Policy::none())
+        .build()
+        .unwrap()
+}
+
+fn authorization_query<'a>(client_id: &'a str, redirect_uri: &'a str) -> Vec<(&'a str, &'a str)> {
+    vec![
+        ("response_type", "code"),
+        ("client_id", client_id),
+        ("redirect_uri", redirect_uri),
+        ("scope", "tickets:read tickets:write"),
+        ("state", "af0ifjsldkj"),
+        ("code_challenge", CODE_CHALLENGE),
+        ("code_challenge_method", "S256"),
+    ]
+}
+
+/// Submits the login and consent page of an authorization request.
+async fn decide(query: &[(&str, &str)], form: &[(&str, &str)]) -> reqwest::Response {
+    browser()
+        .post(format!("http://{ADDRESS}/oauth/authorize"))
+        .query(query)
+        .form(form)
+        .send()
+        .await
+        .unwrap()
+}
+
+/// Returns the query parameters of the URI which a response redirects to.
+fn redirect_params(response: &reqwest::Response) -> Vec<(String, String)> {
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let location = response.headers()[header::LOCATION].to_str().unwrap();
+    Url::parse(location)
+        .unwrap()
+        .query_pairs()
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect()
+}
+
+fn param<'a>(params: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    params
+        .iter()
+        .find(|(param_name, _)| param_name == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// Logs in and allows an authorization request, returning the code.
+async fn obtain_code(query: &[(&str, &str)]) -> String {
+    let response = decide(
+        query,
+        &[
+            ("email", "email@addre.ss"),
+            ("password", "P_ass1Wo$rD"),
+            ("decision", "allow"),
+        ],
+    )
+    .await;
+    param(&redirect_params(&response), "code")
+        .unwrap()
+        .to_string()
+}
+
+/// Requests a token with a form-encoded body, returning the status code and
+/// body.
+async fn request_token(form: &[(&str, &str)], basic: Option<(&str, &str)>) -> (StatusCode, Value) {
+    let mut request = reqwest::Client::new()
+        .post(format!("http://{ADDRESS}/oauth/token"))
+        .form(form);
+    if let Some((client_id, client_secret)) = basic {
+        request = request.basic_auth(client_id, Some(client_secret));
+    }
+    let response = request.send().await.unwrap();
+    (response.status(), response.json().await.unwrap())
+}
+
+#[test]
+fn code_challenges() {
+    assert_eq!(token::code_challenge(CODE_VERIFIER), CODE_CHALLENGE);
+}
+
+#[test]
+fn redirect_uris() {
+    for redirect_uri in [
+        PARTNER_REDIRECT_URI,
+        "https://partner.example",
+        "https://partner.example:8443/callback?source=oauth",
+        "http://localhost/callback",
+        "http://127.0.0.1:8080",
+        "http://[::1]:8080/callback",
+    ] {
+        assert!(
+            validation::check_redirect_uri(redirect_uri).is_ok(),
+            "{redirect_uri}"
+        );
+    }
+    for redirect_uri in [
+        "",
+        "https://",
+        "https:///callback",
+        "http://partner.example/callback",
+        "http://localhost.partner.example/callback",
+        "https://partner.example/callback#fragment",
+        "https://partner.example/call back",
+        "partner.example/callback",
+        "javascript:alert(1)",
+    ] {
+        assert!(
+            validation::check_redirect_uri(redirect_uri).is_err(),
+            "{redirect_uri}"
+        );
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn authorization_code_flow() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+
+    with_authorization_server(&database, async {
+        let query = authorization_query("spa", SPA_REDIRECT_URI);
+
+        let response = browser()
+            .get(format!("http://{ADDRESS}/oauth/authorize"))
+            .query(&query)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::X_FRAME_OPTIONS], "DENY");
+        assert!(response.headers()[header::CONTENT_TYPE]
+            .to_str()?
+            .starts_with("text/html"));
+        let page = response.text().await?;
+        assert!(page.contains("<strong>spa</strong>"));
+        assert!(page.contains("<li>tickets:write</li>"));
+
+        // failed logins show the page again
+        let response = decide(
+            &query,
+            &[
+                ("email", "email@addre.ss"),
+                ("password", "wrong"),
+                ("decision", "allow"),
+            ],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let page = response.text().await?;
+        assert!(page.contains("role=\"alert\""));
+        assert!(page.contains("value=\"email@addre.ss\""));
+
+        let response = decide(&query, &[("decision", "deny")]).await;
+        let params = redirect_params(&response);
+        assert_eq!(param(&params, "error"), Some("access_denied"));
+        assert_eq!(param(&params, "state"), Some("af0ifjsldkj"));
+
+        let response = decide(
+            &query,
+            &[
+                ("email", "email@addre.ss"),
+                ("password", "P_ass1Wo$rD"),
+                ("decision", "allow"),
+            ],
+        )
+        .await;
+        let location = response.headers()[header::LOCATION].to_str()?;
+        assert!(location.starts_with(&format!("{SPA_REDIRECT_URI}?code=")));
+        let params = redirect_params(&response);
+        assert_eq!(param(&params, "state"), Some("af0ifjsldkj"));
+        let code = param(&params, "code").unwrap();
+
+        let form = [
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", SPA_REDIRECT_URI),
+            ("code_verifier", CODE_VERIFIER),
+            ("client_id", "spa"),
+        ];
+        let (status_code, body) = request_token(&form, None).await;
+        assert_eq!(status_code, StatusCode::OK);
+        assert_eq!(body["token_type"], "Bearer");
+        // scopes which the user lacks are left out
+        assert_eq!(body["scope"], "tickets:read");
+        assert!(body.get("refresh_token").is_none());
+        let token = body["access_token"].as_str().unwrap();
+
+        let response = get_authorized("me", token).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert_eq!(response.body.unwrap()["email"], "email@addre.ss");
+        let body = get_authorized("token", token).await.body.unwrap();
+        assert_eq!(body["client_id"], "spa");
+        assert_eq!(body["scope"], "tickets:read");
+        assert_eq!(body["roles"], json!([]));
+
+        // tokens of clients cannot create API keys with the user's permissions
+        let response = post_authorized("me/api-keys", token, json!({"name": "key"})).await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+
+        // codes can only be exchanged once
+        let (status_code, body) = request_token(&form, None).await;
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "invalid_grant");
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn delegated_tokens_cannot_manage_accounts() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+
+    with_authorization_server(&database, async {
+        let code = obtain_code(&authorization_query("spa", SPA_REDIRECT_URI)).await;
+        let (_, body) = request_token(
+            &[
+                ("grant_type", "authorization_code"),
+                ("code", &code),
+                ("redirect_uri", SPA_REDIRECT_URI),
+                ("code_verifier", CODE_VERIFIER),
+                ("client_id", "spa"),
+            ],
+            None,
+        )
+        .await;
+        let token = body["access_token"].as_str().unwrap();
+
+        for response in [
+            patch_authorized("me", token, json!({"display_name": "Mallory"})).await,
+            get_authorized("me/export", token).await,
+            get_authorized("me/api-keys", token).await,
+            delete_authorized("me/api-keys/ak_key", token, json!({})).await,
+            post_authorized("logout/all", token, json!({})).await,
+            put_authorized(
+                "password",
+                token,
+                json!({"current_password": "P_ass1Wo$rD", "new_password": "n3w_P4ssw0rd"}),
+            )
+            .await,
+            post_authorized(
+                "me/email",
+                token,
+                json!({"password": "P_ass1Wo$rD", "new_email": "new@addre.ss"}),
+            )
+            .await,
+            delete_authorized("me", token, json!({"password": "P_ass1Wo$rD"})).await,
+        ] {
+            assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+        }
+
+        // the token still identifies the user
+        let response = get_authorized("me", token).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert_eq!(response.body.unwrap()["email"], "email@addre.ss");
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn authorization_request_errors() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+
+    with_authorization_server(&database, async {
+        // requests which cannot be traced back to a registered redirect URI
+        // are not redirected
+        for query in [
+            authorization_query("unknown", SPA_REDIRECT_URI),
+            authorization_query("spa", "http://localhost:8080/elsewhere"),
+            authorization_query("partner", SPA_REDIRECT_URI),
+            vec![("response_type", "code"), ("client_id", "partner")],
+            vec![("client_id", "spa"), ("client_id", "partner")],
+        ] {
+            let response = browser()
+                .get(format!("http://{ADDRESS}/oauth/authorize"))
+                .query(&query)
+                .send()
+                .await?;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{query:?}");
+            assert!(!response.headers().contains_key(header::LOCATION));
+        }
+
+        // the only registered redirect URI is used if none is given
+        let response = browser()
+            .get(format!("http://{ADDRESS}/oauth/authorize"))
+            .query(&[("response_type", "token"), ("client_id", "spa")])
+            .send()
+            .await?;
+        let location = response.headers()[header::LOCATION].to_str()?;
+        assert!(location.starts_with(SPA_REDIRECT_URI));
+
+        for (name, value, error) in [
+            ("response_type", "token", "unsupported_response_type"),
+            ("code_challenge_method", "plain", "invalid_request"),
+            ("code_challenge", "too-short", "invalid_request"),
+            ("scope", "tickets:read admin", "invalid_scope"),
+        ] {
+            let mut query = authorization_query("spa", SPA_REDIRECT_URI);
+            query.retain(|(param_name, _)| *param_name != name);
+            query.push((name, value));
+            let response = browser()
+                .get(format!("http://{ADDRESS}/oauth/authorize"))
+                .query(&query)
+                .send()
+                .await?;
+            let params = redirect_params(&response);
+            assert_eq!(param(&params, "error"), Some(error), "{name}={value}");
+            assert_eq!(param(&params, "state"), Some("af0ifjsldkj"));
+        }
+
+        // PKCE is mandatory
+        let mut query = authorization_query("spa", SPA_REDIRECT_URI);
+        query.retain(|(name, _)| !name.starts_with("code_challenge"));
+        let response = decide(&query, &[("decision", "allow")]).await;
+        let params = redirect_params(&response);
+        assert_eq!(param(&params, "error"), Some("invalid_request"));
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn authorization_code_exchange_errors() -> Result<(), Box<dyn Error>> {
+    let (router, secret) = authorization_router(&SimpleMemoryDatabase::new()).await;
+
+    with_router(router, async {
+        register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let partner_query = authorization_query("partner", PARTNER_REDIRECT_URI);
+
+        for (redirect_uri, code_verifier, basic, status_code, error) in [
+            (
+                PARTNER_REDIRECT_URI,
+                "wrong-verifier-wrong-verifier-wrong-verifier",
+                Some(("partner", secret.as_str())),
+                StatusCode::BAD_REQUEST,
+                "invalid_grant",
+            ),
+            (
+                "https://partner.example/other",
+                CODE_VERIFIER,
+                Some(("partner", secret.as_str())),
+                StatusCode::BAD_REQUEST,
+                "invalid_grant",
+            ),
+            (
+                PARTNER_REDIRECT_URI,
+                "short",
+                Some(("partner", secret.as_str())),
+                StatusCode::BAD_REQUEST,
+                "invalid_request",
+            ),
+            (
+                PARTNER_REDIRECT_URI,
+                CODE_VERIFIER,
+                None,
+                StatusCode::UNAUTHORIZED,
+                "invalid_client",
+            ),
+            (
+                PARTNER_REDIRECT_URI,
+                CODE_VERIFIER,
+                Some(("partner", "wrong")),
+                StatusCode::UNAUTHORIZED,
+                "invalid_client",
+            ),
+        ] {
+            let code = obtain_code(&partner_query).await;
+            let mut form = vec![
+                ("grant_type", "authorization_code"),
+                ("code", code.as_str()),
+                ("redirect_uri", redirect_uri),
+                ("code_verifier", code_verifier),
+            ];
+            if basic.is_none() {
+                form.push(("client_id", "partner"));
+            }
+            let (actual_status_code, body) = request_token(&form, basic).await;
+            assert_eq!(actual_status_code, status_code, "{form:?}");
+            assert_eq!(body["error"], error, "{form:?}");
+        }
+
+        // codes are bound to the client they were issued to
+        let code = obtain_code(&authorization_query("spa", SPA_REDIRECT_URI)).await;
+        let (status_code, body) = request_token(
+            &[
+                ("grant_type", "authorization_code"),
+                ("code", &code),
+                ("redirect_uri", SPA_REDIRECT_URI),
+                ("code_verifier", CODE_VERIFIER),
+            ],
+            Some(("partner", &secret)),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "invalid_grant");
+
+        // public clients cannot use the client credentials grant
+        let (status_code, body) = request_token(
+            &[("grant_type", "client_credentials"), ("client_id", "spa")],
+            None,
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "unauthorized_client");
+
+        let code = obtain_code(&partner_query).await;
+        let (status_code, body) = request_token(
+            &[
+                ("grant_type", "authorization_code"),
+                ("code", &code),
+                ("redirect_uri", PARTNER_REDIRECT_URI),
+                ("code_verifier", CODE_VERIFIER),
+            ],
+            Some(("partner", &secret)),
+        )
+        .await;
+        assert_eq!(status_code, StatusCode::OK);
+        // the user is not granted any of the requested scopes
+        assert_eq!(body["scope"], "");
+
+        Ok(())
+    })
+    .await
+}
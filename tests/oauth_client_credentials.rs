@@ -0,0 +1,266 @@
+mod common;
+
+use axum::{response::IntoResponse, routing::get, Json, Router};
+use axum_api::{
+    auth::AuthenticatedClient,
+    create_api_router,
+    database::{Database, SimpleMemoryDatabase},
+    token, validation, ServerState,
+};
+use common::{
+    get_authorized, new_token_manager, register_and_login, with_router, Response, ADDRESS,
+};
+use reqwest::{header, StatusCode};
+use serde_json::{json, Map, Value};
+use serial_test::serial;
+use std::error::Error;
+
+async fn reports(client: AuthenticatedClient) -> impl IntoResponse {
+    if client.has_scope("reports:read") {
+        Json(json!({ "client_id": client.client_id() })).into_response()
+    } else {
+        StatusCode::FORBIDDEN.into_response()
+    }
+}
+
+/// Creates a router with a registered client, returning the client's secret.
+async fn client_router() -> (Router, String) {
+    let database = SimpleMemoryDatabase::new();
+    let (secret, client) = token::new_oauth_client(
+        "reporting-service",
+        &["reports:read".to_string(), "tickets:read".to_string()],
+        &[],
+    );
+    database.add_oauth_client(client).await.unwrap();
+
+    let router = Router::new()
+        .merge(create_api_router())
+        .route("/reports", get(reports))
+        .with_state(ServerState::new(database, new_token_manager()));
+    (router, secret)
+}
+
+/// Requests a token with a form-encoded body, optionally authenticating with
+/// HTTP Basic authentication.
+async fn request_token(form: &[(&str, &str)], basic: Option<(&str, &str)>) -> Response {
+    let mut request = reqwest::Client::new()
+        .post(format!("http://{ADDRESS}/oauth/token"))
+        .form(form);
+    if let Some((client_id, client_secret)) = basic {
+        request = request.basic_auth(client_id, Some(client_secret));
+    }
+    let response = request.send().await.unwrap();
+
+    Response {
+        status_code: response.status(),
+        headers: response.headers().clone(),
+        body: response.json::<Map<String, Value>>().await.ok(),
+    }
+}
+
+#[test]
+fn client_ids() {
+    for client_id in ["reporting-service", "svc.billing_2", "A"] {
+        assert!(
+            validation::check_client_id(client_id).is_ok(),
+            "{client_id}"
+        );
+    }
+    for client_id in [
+        "",
+        "with space",
+        "with:colon",
+        "0f6e6a8e-3c4b-4f4e-9a0e-6d2c1b7a9f10",
+        &"a".repeat(65),
+    ] {
+        assert!(
+            validation::check_client_id(client_id).is_err(),
+            "{client_id}"
+        );
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn client_credentials_grant() -> Result<(), Box<dyn Error>> {
+    let (router, secret) = client_router().await;
+
+    with_router(router, async {
+        let response = request_token(
+            &[("grant_type", "client_credentials")],
+            Some(("reporting-service", &secret)),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert_eq!(response.headers[header::CACHE_CONTROL], "no-store");
+        let body = response.body.unwrap();
+        assert_eq!(body["token_type"], "Bearer");
+        assert!(body["expires_in"].as_u64().unwrap() > 0);
+        assert_eq!(body["scope"], "reports:read tickets:read");
+        assert!(body.get("refresh_token").is_none());
+        let token = body["access_token"].as_str().unwrap();
+
+        let response = get_authorized("reports", token).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert_eq!(response.body.unwrap()["client_id"], "reporting-service");
+
+        // client tokens and user tokens are not interchangeable
+        let response = get_authorized("me", token).await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        let login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let response = get_authorized("reports", login["token"].as_str().unwrap()).await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+
+        // clients can authenticate in the body, and request fewer scopes
+        let response = request_token(
+            &[
+                ("grant_type", "client_credentials"),
+                ("client_id", "reporting-service"),
+                ("client_secret", &secret),
+                ("scope", "tickets:read"),
+            ],
+            None,
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let body = response.body.unwrap();
+        assert_eq!(body["scope"], "tickets:read");
+        let response = get_authorized("reports", body["access_token"].as_str().unwrap()).await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn token_request_errors() -> Result<(), Box<dyn Error>> {
+    let (router, secret) = client_router().await;
+
+    with_router(router, async {
+        let valid_basic = Some(("reporting-service", secret.as_str()));
+        for (form, basic, status_code, error) in [
+            (
+                vec![("grant_type", "client_credentials")],
+                Some(("reporting-service", "wrong")),
+                StatusCode::UNAUTHORIZED,
+                "invalid_client",
+            ),
+            (
+                vec![("grant_type", "client_credentials")],
+                Some(("unknown", secret.as_str())),
+                StatusCode::UNAUTHORIZED,
+                "invalid_client",
+            ),
+            (
+                vec![("grant_type", "client_credentials")],
+                None,
+                StatusCode::UNAUTHORIZED,
+                "invalid_client",
+            ),
+            (
+                vec![
+                    ("grant_type", "client_credentials"),
+                    ("client_id", "reporting-service"),
+                    ("client_secret", secret.as_str()),
+                ],
+                valid_basic,
+                StatusCode::BAD_REQUEST,
+                "invalid_request",
+            ),
+            (
+                vec![
+                    ("grant_type", "client_credentials"),
+                    ("grant_type", "client_credentials"),
+                ],
+                valid_basic,
+                StatusCode::BAD_REQUEST,
+                "invalid_request",
+            ),
+            (
+                vec![],
+                valid_basic,
+                StatusCode::BAD_REQUEST,
+                "invalid_request",
+            ),
+            (
+                vec![("grant_type", "password")],
+                valid_basic,
+                StatusCode::BAD_REQUEST,
+                "unsupported_grant_type",
+            ),
+            (
+                vec![
+                    ("grant_type", "client_credentials"),
+                    ("scope", "reports:read admin"),
+                ],
+                valid_basic,
+                StatusCode::BAD_REQUEST,
+                "invalid_scope",
+            ),
+        ] {
+            let response = request_token(&form, basic).await;
+            assert_eq!(response.status_code, status_code, "{form:?}");
+            assert_eq!(response.body.unwrap()["error"], error, "{form:?}");
+            if status_code == StatusCode::UNAUTHORIZED {
+                assert!(response.headers.contains_key(header::WWW_AUTHENTICATE));
+            }
+        }
+
+        // bodies must be form-encoded
+        let response = reqwest::Client::new()
+            .post(format!("http://{ADDRESS}/oauth/token"))
+            .basic_auth("reporting-service", Some(&secret))
+            .json(&json!({"grant_type": "client_credentials"}))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = response.json::<Value>().await?;
+        assert_eq!(body["error"], "invalid_request");
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn client_tokens_never_pass_as_user_tokens() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+    let router = Router::new()
+        .merge(create_api_router())
+        .route("/reports", get(reports))
+        .with_state(ServerState::new(database.clone(), new_token_manager()));
+
+    with_router(router, async {
+        register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let user_id = database.get_user_by_email("email@addre.ss").await?.id;
+
+        // client ids are only validated by the command line, so a client id
+        // may look like a user id when registered otherwise
+        let client_id = user_id.to_string();
+        let (secret, client) =
+            token::new_oauth_client(&client_id, &["reports:read".to_string()], &[]);
+        database.add_oauth_client(client).await?;
+
+        let response = request_token(
+            &[("grant_type", "client_credentials")],
+            Some((&client_id, &secret)),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let token = response.body.unwrap()["access_token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let response = get_authorized("reports", &token).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let response = get_authorized("me", &token).await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    })
+    .await
+}
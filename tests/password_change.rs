@@ -0,0 +1,138 @@
+mod common;
+
+use common::{get_authorized, post, put_authorized, register_and_login, with_server};
+use reqwest::StatusCode;
+use serde_json::json;
+use serial_test::serial;
+use std::error::Error;
+
+async fn is_token_valid(token: &str) -> bool {
+    get_authorized("token", token).await.body.unwrap()["valid"]
+        .as_bool()
+        .unwrap()
+}
+
+#[tokio::test]
+#[serial]
+async fn change_password() -> Result<(), Box<dyn Error>> {
+    with_server(async {
+        let login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+
+        let response = put_authorized(
+            "password",
+            login["token"].as_str().unwrap(),
+            json!({"current_password": "P_ass1Wo$rD", "new_password": "n3w_P4ssw0rd"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let body = response.body.unwrap();
+        assert!(is_token_valid(body["token"].as_str().unwrap()).await);
+
+        let response = post(
+            "login",
+            json!({"email": "email@addre.ss", "password": "P_ass1Wo$rD"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        let response = post(
+            "login",
+            json!({"email": "email@addre.ss", "password": "n3w_P4ssw0rd"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn change_password_revokes_other_tokens() -> Result<(), Box<dyn Error>> {
+    with_server(async {
+        let first_login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let second_login = post(
+            "login",
+            json!({"email": "email@addre.ss", "password": "P_ass1Wo$rD"}),
+        )
+        .await
+        .body
+        .unwrap();
+        let other_login = register_and_login("other@addre.ss", "P_ass1Wo$rD").await;
+
+        let response = put_authorized(
+            "password",
+            first_login["token"].as_str().unwrap(),
+            json!({"current_password": "P_ass1Wo$rD", "new_password": "n3w_P4ssw0rd"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        for login in [&first_login, &second_login] {
+            assert!(!is_token_valid(login["token"].as_str().unwrap()).await);
+            let response = post(
+                "token/refresh",
+                json!({ "refresh_token": login["refresh_token"] }),
+            )
+            .await;
+            assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        }
+        assert!(is_token_valid(other_login["token"].as_str().unwrap()).await);
+
+        let body = response.body.unwrap();
+        let response = post(
+            "token/refresh",
+            json!({ "refresh_token": body["refresh_token"] }),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn change_password_with_wrong_current_password() -> Result<(), Box<dyn Error>> {
+    with_server(async {
+        let login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let token = login["token"].as_str().unwrap();
+
+        let response = put_authorized(
+            "password",
+            token,
+            json!({"current_password": "wrong", "new_password": "n3w_P4ssw0rd"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+        assert!(is_token_valid(token).await);
+
+        let response = post(
+            "login",
+            json!({"email": "email@addre.ss", "password": "P_ass1Wo$rD"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn change_password_without_token() -> Result<(), Box<dyn Error>> {
+    with_server(async {
+        let response = put_authorized(
+            "password",
+            "invalid",
+            json!({"current_password": "P_ass1Wo$rD", "new_password": "n3w_P4ssw0rd"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,104 @@
+use axum_api::password::{
+    Argon2Hasher, BcryptHasher, PasswordHashError, PasswordHasher, PasswordHashingConfig,
+};
+use std::error::Error;
+
+#[test]
+fn hash_and_verify() -> Result<(), Box<dyn Error>> {
+    let hashers: [Box<dyn PasswordHasher>; 2] = [
+        Box::new(BcryptHasher::new(4)?),
+        Box::new(Argon2Hasher::new(256, 1, 1)?),
+    ];
+
+    for hasher in hashers {
+        let hash = hasher.hash("password")?;
+        assert!(hasher.verify("password", &hash)?);
+        assert!(!hasher.verify("wrong password", &hash)?);
+        assert_ne!(hash, hasher.hash("password")?);
+    }
+
+    Ok(())
+}
+
+#[test]
+fn argon2_hashes_are_phc_strings() -> Result<(), Box<dyn Error>> {
+    let hash = Argon2Hasher::new(256, 1, 1)?.hash("password")?;
+    assert!(hash.starts_with("$argon2id$v=19$m=256,t=1,p=1$"));
+
+    Ok(())
+}
+
+#[test]
+fn verify_hashes_of_other_algorithms() -> Result<(), Box<dyn Error>> {
+    let bcrypt_hasher = BcryptHasher::new(4)?;
+    let argon2_hasher = Argon2Hasher::new(256, 1, 1)?;
+
+    assert!(bcrypt_hasher.verify("password", &argon2_hasher.hash("password")?)?);
+    assert!(argon2_hasher.verify("password", &bcrypt_hasher.hash("password")?)?);
+
+    // hashes stored before passwords were hashed with a `PasswordHasher`
+    let legacy_hash =
+        bcrypt::hash_with_result("password", 4)?.format_for_version(bcrypt::Version::TwoA);
+    assert!(argon2_hasher.verify("password", &legacy_hash)?);
+    assert!(!argon2_hasher.verify("wrong password", &legacy_hash)?);
+
+    Ok(())
+}
+
+#[test]
+fn needs_rehash() -> Result<(), Box<dyn Error>> {
+    let bcrypt_hasher = BcryptHasher::new(4)?;
+    let argon2_hasher = Argon2Hasher::new(256, 1, 1)?;
+    let bcrypt_hash = bcrypt_hasher.hash("password")?;
+    let argon2_hash = argon2_hasher.hash("password")?;
+
+    assert!(!bcrypt_hasher.needs_rehash(&bcrypt_hash));
+    assert!(!argon2_hasher.needs_rehash(&argon2_hash));
+    assert!(bcrypt_hasher.needs_rehash(&argon2_hash));
+    assert!(argon2_hasher.needs_rehash(&bcrypt_hash));
+
+    assert!(BcryptHasher::new(5)?.needs_rehash(&bcrypt_hash));
+    assert!(Argon2Hasher::new(512, 1, 1)?.needs_rehash(&argon2_hash));
+    assert!(Argon2Hasher::new(256, 2, 1)?.needs_rehash(&argon2_hash));
+    assert!(Argon2Hasher::new(256, 1, 2)?.needs_rehash(&argon2_hash));
+
+    Ok(())
+}
+
+#[test]
+fn malformed_hashes() -> Result<(), Box<dyn Error>> {
+    let hasher = Argon2Hasher::new(256, 1, 1)?;
+
+    for hash in ["", "password", "$argon2id$v=19$m=abc", "$2a$04$tooshort"] {
+        assert!(matches!(
+            hasher.verify("password", hash),
+            Err(PasswordHashError::MalformedHash(_))
+        ));
+        assert!(hasher.needs_rehash(hash));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn config() -> Result<(), Box<dyn Error>> {
+    let config = serde_json::from_str::<PasswordHashingConfig>(
+        r#"{"algorithm": "argon2id", "memory_cost": 256, "time_cost": 1, "parallelism": 1}"#,
+    )?;
+    let hasher = config.build()?;
+    assert!(!hasher.needs_rehash(&Argon2Hasher::new(256, 1, 1)?.hash("password")?));
+
+    let config =
+        serde_json::from_str::<PasswordHashingConfig>(r#"{"algorithm": "bcrypt", "cost": 4}"#)?;
+    let hasher = config.build()?;
+    assert!(!hasher.needs_rehash(&BcryptHasher::new(4)?.hash("password")?));
+
+    assert!(PasswordHashingConfig::default().build().is_ok());
+    assert!(matches!(
+        serde_json::from_str::<PasswordHashingConfig>(r#"{"algorithm": "bcrypt", "cost": 99}"#)?
+            .build(),
+        Err(PasswordHashError::InvalidParameters(_))
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,211 @@
+mod common;
+
+use axum_api::{
+    database::SimpleMemoryDatabase,
+    mail::FileMailer,
+    password::{PasswordPolicy, PasswordRule},
+    ServerState,
+};
+use common::{
+    new_outbox_path, new_state, post, put_authorized, register_and_login, reset_code,
+    wait_for_outbox, with_server_state, Response,
+};
+use reqwest::StatusCode;
+use serde_json::json;
+use serial_test::serial;
+use std::{error::Error, sync::Arc};
+
+const STRONG_PASSWORD: &str = "Correct-h0rse-battery-staple";
+
+fn new_policy() -> PasswordPolicy {
+    PasswordPolicy {
+        min_length: 10,
+        max_bytes: Some(72),
+        require_lowercase: true,
+        require_uppercase: true,
+        require_digit: true,
+        require_symbol: false,
+        reject_email_local_part: true,
+        min_strength: Some(3),
+    }
+}
+
+fn state_with_policy() -> ServerState<SimpleMemoryDatabase> {
+    new_state().with_password_policy(new_policy())
+}
+
+/// Returns the violated rules listed in a response, asserting that the
+/// response is a validation error for `field`.
+fn violated_rules(response: Response, field: &str) -> Vec<String> {
+    assert_eq!(response.status_code, StatusCode::UNPROCESSABLE_ENTITY);
+    response.body.unwrap()["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| {
+            assert_eq!(error["field"], field);
+            assert!(error["message"].is_string());
+            error["rule"].as_str().unwrap().to_string()
+        })
+        .collect()
+}
+
+#[test]
+fn check_lists_every_violation() {
+    let violations = new_policy()
+        .check("jonathan", "jonathan@addre.ss")
+        .unwrap_err();
+    let rules = violations
+        .iter()
+        .map(|violation| violation.rule)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        rules,
+        [
+            PasswordRule::MinLength,
+            PasswordRule::RequireUppercase,
+            PasswordRule::RequireDigit,
+            PasswordRule::RejectEmailLocalPart,
+            PasswordRule::MinStrength,
+        ]
+    );
+
+    assert!(new_policy()
+        .check(STRONG_PASSWORD, "email@addre.ss")
+        .is_ok());
+    assert!(PasswordPolicy::default()
+        .check("pw", "email@addre.ss")
+        .is_ok());
+}
+
+#[test]
+fn check_blank_password_strength() {
+    let policy = PasswordPolicy {
+        min_length: 0,
+        min_strength: Some(1),
+        ..PasswordPolicy::default()
+    };
+    let violations = policy.check("", "email@addre.ss").unwrap_err();
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].rule, PasswordRule::MinStrength);
+}
+
+#[tokio::test]
+#[serial]
+async fn register_with_weak_password() -> Result<(), Box<dyn Error>> {
+    with_server_state(state_with_policy(), async {
+        let response = post(
+            "register",
+            json!({"email": "email@addre.ss", "password": "password"}),
+        )
+        .await;
+        assert_eq!(
+            violated_rules(response, "password"),
+            [
+                "min_length",
+                "require_uppercase",
+                "require_digit",
+                "min_strength"
+            ]
+        );
+
+        let response = post(
+            "register",
+            json!({"email": "jonathan@addre.ss", "password": "x9-JONATHAN-shelf-q"}),
+        )
+        .await;
+        assert_eq!(
+            violated_rules(response, "password"),
+            ["reject_email_local_part"]
+        );
+
+        let password = format!("{STRONG_PASSWORD}{}", "x".repeat(50));
+        let response = post(
+            "register",
+            json!({"email": "email@addre.ss", "password": password}),
+        )
+        .await;
+        assert_eq!(violated_rules(response, "password"), ["max_bytes"]);
+
+        let response = post(
+            "register",
+            json!({"email": "email@addre.ss", "password": STRONG_PASSWORD}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn change_to_weak_password() -> Result<(), Box<dyn Error>> {
+    with_server_state(state_with_policy(), async {
+        let login = register_and_login("email@addre.ss", STRONG_PASSWORD).await;
+
+        let response = put_authorized(
+            "password",
+            login["token"].as_str().unwrap(),
+            json!({"current_password": STRONG_PASSWORD, "new_password": "Password1"}),
+        )
+        .await;
+        let rules = violated_rules(response, "new_password");
+        assert!(rules.contains(&"min_length".to_string()));
+        assert!(rules.contains(&"min_strength".to_string()));
+
+        let response = post(
+            "login",
+            json!({"email": "email@addre.ss", "password": STRONG_PASSWORD}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn reset_to_weak_password() -> Result<(), Box<dyn Error>> {
+    let outbox_path = new_outbox_path("reset_to_weak_password");
+    let state = state_with_policy().with_mailer(Arc::new(FileMailer::new(&outbox_path)));
+
+    with_server_state(state, async {
+        register_and_login("email@addre.ss", STRONG_PASSWORD).await;
+        let response = post("password/forgot", json!({"email": "email@addre.ss"})).await;
+        assert_eq!(response.status_code, StatusCode::ACCEPTED);
+
+        let messages = wait_for_outbox(&outbox_path, 1).await;
+        let code = reset_code(&messages[0].body);
+
+        let response = post(
+            "password/reset",
+            json!({"code": code, "new_password": "email-address"}),
+        )
+        .await;
+        let rules = violated_rules(response, "new_password");
+        assert!(rules.contains(&"reject_email_local_part".to_string()));
+
+        // the code remains usable after a rejected password
+        let new_password = "Another-str0ng-passphrase";
+        let response = post(
+            "password/reset",
+            json!({"code": code, "new_password": new_password}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        let response = post(
+            "login",
+            json!({"email": "email@addre.ss", "password": new_password}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,143 @@
+mod common;
+
+use axum_api::{database::SimpleMemoryDatabase, mail::FileMailer, ServerState};
+use common::{
+    get_authorized, new_outbox_path, new_state, new_token_manager, post, read_outbox,
+    register_and_login, reset_code, wait_for_outbox, with_server, with_server_state,
+};
+use reqwest::StatusCode;
+use serde_json::json;
+use serial_test::serial;
+use std::{error::Error, path::Path, sync::Arc, time::Duration};
+
+fn state_with_outbox(outbox_path: &Path) -> ServerState<SimpleMemoryDatabase> {
+    new_state().with_mailer(Arc::new(FileMailer::new(outbox_path)))
+}
+
+#[tokio::test]
+#[serial]
+async fn reset_password() -> Result<(), Box<dyn Error>> {
+    let outbox_path = new_outbox_path("reset_password");
+
+    with_server_state(state_with_outbox(&outbox_path), async {
+        let login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+
+        let response = post("password/forgot", json!({"email": "email@addre.ss"})).await;
+        assert_eq!(response.status_code, StatusCode::ACCEPTED);
+
+        let messages = wait_for_outbox(&outbox_path, 1).await;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].to, "email@addre.ss");
+        let code = reset_code(&messages[0].body);
+
+        let response = post(
+            "password/reset",
+            json!({"code": code, "new_password": "n3w_P4ssw0rd"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        let response = get_authorized("token", login["token"].as_str().unwrap()).await;
+        assert_eq!(response.body.unwrap()["valid"], false);
+        let response = post(
+            "login",
+            json!({"email": "email@addre.ss", "password": "P_ass1Wo$rD"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        let response = post(
+            "login",
+            json!({"email": "email@addre.ss", "password": "n3w_P4ssw0rd"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        // codes are single-use
+        let response = post(
+            "password/reset",
+            json!({"code": code, "new_password": "0th3r_P4ssw0rd"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn forgot_password_for_unknown_user() -> Result<(), Box<dyn Error>> {
+    let outbox_path = new_outbox_path("forgot_password_for_unknown_user");
+
+    with_server_state(state_with_outbox(&outbox_path), async {
+        let response = post("password/forgot", json!({"email": "email@addre.ss"})).await;
+        assert_eq!(response.status_code, StatusCode::ACCEPTED);
+
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(read_outbox(&outbox_path).is_empty());
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn reset_password_with_invalid_code() -> Result<(), Box<dyn Error>> {
+    let outbox_path = new_outbox_path("reset_password_with_invalid_code");
+
+    with_server_state(state_with_outbox(&outbox_path), async {
+        register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+
+        let response = post(
+            "password/reset",
+            json!({"code": "invalid", "new_password": "n3w_P4ssw0rd"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn reset_password_with_expired_code() -> Result<(), Box<dyn Error>> {
+    let outbox_path = new_outbox_path("reset_password_with_expired_code");
+    let mut token_manager = new_token_manager();
+    *token_manager.password_reset_lifetime_mut() = Duration::ZERO;
+    let state = ServerState::new(SimpleMemoryDatabase::new(), token_manager)
+        .with_mailer(Arc::new(FileMailer::new(&outbox_path)));
+
+    with_server_state(state, async {
+        register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        post("password/forgot", json!({"email": "email@addre.ss"})).await;
+        let messages = wait_for_outbox(&outbox_path, 1).await;
+
+        let response = post(
+            "password/reset",
+            json!({"code": reset_code(&messages[0].body), "new_password": "n3w_P4ssw0rd"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn forgot_password_without_mailer() -> Result<(), Box<dyn Error>> {
+    with_server(async {
+        register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+
+        let response = post("password/forgot", json!({"email": "email@addre.ss"})).await;
+        assert_eq!(response.status_code, StatusCode::NOT_IMPLEMENTED);
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,120 @@
+mod common;
+
+use common::{get, get_authorized, patch_authorized, register_and_login, with_server};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use serial_test::serial;
+use std::{
+    error::Error,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use uuid::Uuid;
+
+async fn login_token(email: &str) -> String {
+    register_and_login(email, "pw").await["token"]
+        .as_str()
+        .unwrap()
+        .to_string()
+}
+
+#[tokio::test]
+#[serial]
+async fn get_profile() -> Result<(), Box<dyn Error>> {
+    with_server(async {
+        let token = login_token("email@addre.ss").await;
+
+        let response = get_authorized("me", &token).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let profile = response.body.unwrap();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        assert!(Uuid::parse_str(profile["id"].as_str().unwrap()).is_ok());
+        assert_eq!(profile["email"], "email@addre.ss");
+        assert_eq!(profile["display_name"], Value::Null);
+        assert!(profile["created_at"].as_u64().unwrap() <= now);
+        assert!(profile["last_login_at"].as_u64().unwrap() <= now);
+        assert_eq!(profile["metadata"], json!({}));
+
+        // tokens identify the user by id
+        let response = get_authorized("token", &token).await;
+        assert_eq!(response.body.unwrap()["user_id"], profile["id"]);
+
+        let other_token = login_token("other@addre.ss").await;
+        let other_profile = get_authorized("me", &other_token).await.body.unwrap();
+        assert_eq!(other_profile["email"], "other@addre.ss");
+        assert_ne!(other_profile["id"], profile["id"]);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn update_profile() -> Result<(), Box<dyn Error>> {
+    with_server(async {
+        let token = login_token("email@addre.ss").await;
+
+        let response = patch_authorized(
+            "me",
+            &token,
+            json!({"display_name": "  Name ", "metadata": {"a": 1, "b": {"c": 2}}}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let profile = response.body.unwrap();
+        assert_eq!(profile["display_name"], "Name");
+        assert_eq!(profile["metadata"], json!({"a": 1, "b": {"c": 2}}));
+
+        // metadata entries are merged, and removed if null
+        let response = patch_authorized(
+            "me",
+            &token,
+            json!({"metadata": {"a": null, "b": {"d": 3}, "e": true}}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let profile = response.body.unwrap();
+        assert_eq!(profile["display_name"], "Name");
+        assert_eq!(profile["metadata"], json!({"b": {"d": 3}, "e": true}));
+
+        let response = patch_authorized("me", &token, json!({"display_name": null})).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        let profile = get_authorized("me", &token).await.body.unwrap();
+        assert_eq!(profile["display_name"], Value::Null);
+        assert_eq!(profile["metadata"], json!({"b": {"d": 3}, "e": true}));
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn reject_invalid_updates() -> Result<(), Box<dyn Error>> {
+    with_server(async {
+        let token = login_token("email@addre.ss").await;
+
+        for update in [
+            json!({"display_name": "   "}),
+            json!({"display_name": "x".repeat(101)}),
+            json!({"display_name": "line\nbreak"}),
+            json!({"metadata": {"large": "x".repeat(16 * 1024)}}),
+        ] {
+            let response = patch_authorized("me", &token, update).await;
+            assert_eq!(response.status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        }
+
+        let profile = get_authorized("me", &token).await.body.unwrap();
+        assert_eq!(profile["display_name"], Value::Null);
+        assert_eq!(profile["metadata"], json!({}));
+
+        let response = get("me").await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        let response = patch_authorized("me", "invalid", json!({})).await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    })
+    .await
+}
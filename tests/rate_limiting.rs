@@ -0,0 +1,210 @@
+mod common;
+
+use axum::{body::Body, middleware, Router};
+use axum_api::{
+    create_api_router,
+    database::SimpleMemoryDatabase,
+    rate_limit::{
+        rate_limit, DatabaseRateLimitStore, MemoryRateLimitStore, Quota, RateLimitKey,
+        RateLimitStore, RateLimiting, API_KEY_HEADER,
+    },
+    ServerState,
+};
+use common::{get_authorized, new_state, post, register_and_login, with_router, Response, ADDRESS};
+use reqwest::{header, StatusCode};
+use serde_json::json;
+use serial_test::serial;
+use std::{error::Error, sync::Arc, time::Duration};
+
+fn quota(capacity: u32, key: RateLimitKey) -> Quota {
+    Quota {
+        capacity,
+        period: Duration::from_secs(60),
+        key,
+    }
+}
+
+/// Returns an API router with the rate limiting middleware, nested under
+/// `prefix`.
+fn rate_limited_router(state: ServerState<SimpleMemoryDatabase>, prefix: &str) -> Router {
+    let api_router = create_api_router().route_layer(middleware::from_fn_with_state(
+        state.clone(),
+        rate_limit::<SimpleMemoryDatabase, Body>,
+    ));
+    let router = if prefix.is_empty() {
+        api_router
+    } else {
+        Router::new().nest(prefix, api_router)
+    };
+
+    router.with_state(state)
+}
+
+fn header(response: &Response, name: &str) -> Option<u64> {
+    response
+        .headers
+        .get(name)
+        .map(|value| value.to_str().unwrap().parse().unwrap())
+}
+
+async fn get_token_with_api_key(api_key: &str) -> Response {
+    let response = reqwest::Client::new()
+        .get(format!("http://{ADDRESS}/token"))
+        .header(API_KEY_HEADER, api_key)
+        .send()
+        .await
+        .unwrap();
+
+    Response {
+        status_code: response.status(),
+        headers: response.headers().clone(),
+        body: None,
+    }
+}
+
+#[tokio::test]
+async fn token_bucket() -> Result<(), Box<dyn Error>> {
+    for store in [
+        Arc::new(MemoryRateLimitStore::new()) as Arc<dyn RateLimitStore>,
+        Arc::new(DatabaseRateLimitStore::new(SimpleMemoryDatabase::new())),
+    ] {
+        let quota = Quota {
+            capacity: 2,
+            period: Duration::from_millis(200),
+            key: RateLimitKey::Ip,
+        };
+
+        let status = store.acquire("key", &quota).await?;
+        assert!(status.allowed);
+        assert_eq!(status.remaining, 1);
+        let status = store.acquire("key", &quota).await?;
+        assert!(status.allowed);
+        assert_eq!(status.remaining, 0);
+        assert!(status.reset_after <= Duration::from_millis(200));
+
+        let status = store.acquire("key", &quota).await?;
+        assert!(!status.allowed);
+        assert!(status.retry_after.unwrap() <= Duration::from_millis(100));
+
+        // other keys have buckets of their own
+        assert!(store.acquire("other", &quota).await?.allowed);
+
+        // a token is refilled every 100 milliseconds
+        tokio::time::sleep(Duration::from_millis(110)).await;
+        assert!(store.acquire("key", &quota).await?.allowed);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+#[serial]
+async fn route_quotas() -> Result<(), Box<dyn Error>> {
+    let rate_limiting = RateLimiting::new(Arc::new(MemoryRateLimitStore::new()))
+        .with_default_quota(quota(5, RateLimitKey::Ip))
+        .with_route_quota("/login", quota(2, RateLimitKey::Ip));
+    let state = new_state().with_rate_limiting(rate_limiting);
+
+    with_router(rate_limited_router(state, "/api"), async {
+        let login = json!({"email": "email@addre.ss", "password": "pw"});
+        post("api/register", login.clone()).await;
+
+        let response = post("api/login", login.clone()).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert_eq!(header(&response, "ratelimit-limit"), Some(2));
+        assert_eq!(header(&response, "ratelimit-remaining"), Some(1));
+        assert!(header(&response, "ratelimit-reset").unwrap() <= 30);
+        assert_eq!(response.headers.get("ratelimit-policy").unwrap(), "2;w=60");
+
+        post("api/login", login.clone()).await;
+        let response = post("api/login", login.clone()).await;
+        assert_eq!(response.status_code, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(header(&response, "ratelimit-remaining"), Some(0));
+        let retry_after = header(&response, header::RETRY_AFTER.as_str()).unwrap();
+        assert!(retry_after > 0 && retry_after <= 30);
+
+        // routes without a quota of their own share the default quota, which
+        // the registration used up one request of
+        let response = post("api/token/refresh", json!({"refresh_token": "x"})).await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        assert_eq!(header(&response, "ratelimit-limit"), Some(5));
+        assert_eq!(header(&response, "ratelimit-remaining"), Some(3));
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn keyed_by_user() -> Result<(), Box<dyn Error>> {
+    let rate_limiting = RateLimiting::new(Arc::new(MemoryRateLimitStore::new()))
+        .with_route_quota("/token", quota(1, RateLimitKey::User));
+    let state = new_state().with_rate_limiting(rate_limiting);
+
+    with_router(rate_limited_router(state, ""), async {
+        let first_token = register_and_login("email@addre.ss", "pw").await["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let second_token = register_and_login("other@addre.ss", "pw").await["token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let response = get_authorized("token", &first_token).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let response = get_authorized("token", &first_token).await;
+        assert_eq!(response.status_code, StatusCode::TOO_MANY_REQUESTS);
+
+        // the other user, and unauthenticated requests by IP, have buckets of
+        // their own
+        let response = get_authorized("token", &second_token).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let response = get_authorized("token", "invalid").await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn keyed_by_api_key() -> Result<(), Box<dyn Error>> {
+    let rate_limiting = RateLimiting::new(Arc::new(MemoryRateLimitStore::new()))
+        .with_default_quota(quota(1, RateLimitKey::ApiKey));
+    let state = new_state().with_rate_limiting(rate_limiting);
+
+    with_router(rate_limited_router(state, ""), async {
+        let response = get_token_with_api_key("first").await;
+        assert_ne!(response.status_code, StatusCode::TOO_MANY_REQUESTS);
+        let response = get_token_with_api_key("first").await;
+        assert_eq!(response.status_code, StatusCode::TOO_MANY_REQUESTS);
+
+        let response = get_token_with_api_key("second").await;
+        assert_ne!(response.status_code, StatusCode::TOO_MANY_REQUESTS);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn without_rate_limiting() -> Result<(), Box<dyn Error>> {
+    with_router(rate_limited_router(new_state(), ""), async {
+        for _ in 0..10 {
+            let response = post(
+                "login",
+                json!({"email": "email@addre.ss", "password": "pw"}),
+            )
+            .await;
+            assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+            assert!(response.headers.get("ratelimit-limit").is_none());
+        }
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,103 @@
+mod common;
+
+use common::{post, with_server};
+use reqwest::StatusCode;
+use serde_json::json;
+use serial_test::serial;
+use std::error::Error;
+
+async fn login_refresh_token() -> String {
+    post(
+        "register",
+        json!({"email": "email@addre.ss", "password": "P_ass1Wo$rD"}),
+    )
+    .await;
+
+    let response = post(
+        "login",
+        json!({"email": "email@addre.ss", "password": "P_ass1Wo$rD"}),
+    )
+    .await;
+    response
+        .body
+        .expect("response body could not be parsed as JSON object")["refresh_token"]
+        .as_str()
+        .expect("login response contains no refresh token")
+        .to_string()
+}
+
+#[tokio::test]
+#[serial]
+async fn refresh_with_valid_token() -> Result<(), Box<dyn Error>> {
+    with_server(async {
+        let mut refresh_token = login_refresh_token().await;
+
+        for _ in 0..3 {
+            let response = post("token/refresh", json!({ "refresh_token": refresh_token })).await;
+            assert_eq!(response.status_code, StatusCode::OK);
+
+            let body = response
+                .body
+                .expect("response body could not be parsed as JSON object");
+            assert!(body.contains_key("token"));
+            let new_refresh_token = body["refresh_token"].as_str().unwrap().to_string();
+            assert_ne!(new_refresh_token, refresh_token);
+            refresh_token = new_refresh_token;
+        }
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn refresh_with_invalid_token() -> Result<(), Box<dyn Error>> {
+    with_server(async {
+        login_refresh_token().await;
+
+        for refresh_token in ["", ".", "abc", "abc.def", "abc.def.ghi"] {
+            let response = post("token/refresh", json!({ "refresh_token": refresh_token })).await;
+            assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+        }
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn refresh_token_reuse_revokes_family() -> Result<(), Box<dyn Error>> {
+    with_server(async {
+        let first_refresh_token = login_refresh_token().await;
+
+        let response = post(
+            "token/refresh",
+            json!({ "refresh_token": first_refresh_token }),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let second_refresh_token = response.body.unwrap()["refresh_token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let response = post(
+            "token/refresh",
+            json!({ "refresh_token": first_refresh_token }),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+
+        let response = post(
+            "token/refresh",
+            json!({ "refresh_token": second_refresh_token }),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use axum::{middleware, response::IntoResponse, routing::get, Json, Router};
+use axum_api::{
+    create_admin_router, create_api_router,
+    database::{Database, SimpleMemoryDatabase},
+    roles::{Admin, Permission, RequirePermission, RequireRole, Support},
+    ServerState,
+};
+use common::{
+    get_authorized, new_token_manager, post, put_authorized, register_and_login, with_router,
+};
+use reqwest::{header, StatusCode};
+use serde_json::{json, Map, Value};
+use serial_test::serial;
+use std::error::Error;
+
+struct ReadTickets;
+
+impl Permission for ReadTickets {
+    const NAME: &'static str = "tickets:read";
+}
+
+async fn support(RequireRole { user, .. }: RequireRole<Support>) -> impl IntoResponse {
+    Json(json!({ "user_id": user.user_id() }))
+}
+
+async fn tickets(_user: RequirePermission<ReadTickets>) -> impl IntoResponse {
+    Json(json!({ "tickets": [] }))
+}
+
+async fn reports() -> impl IntoResponse {
+    Json(json!({ "reports": [] }))
+}
+
+fn downstream_router(database: SimpleMemoryDatabase) -> Router {
+    let state = ServerState::new(database, new_token_manager());
+    let admin_router = Router::new()
+        .route("/admin/reports", get(reports))
+        .route_layer(middleware::from_extractor_with_state::<RequireRole<Admin>, _>(state.clone()));
+
+    Router::new()
+        .merge(create_api_router())
+        .merge(create_admin_router())
+        .route("/support", get(support))
+        .route("/tickets", get(tickets))
+        .merge(admin_router)
+        .with_state(state)
+}
+
+/// Registers an administrator, returning their token.
+async fn register_admin(database: &SimpleMemoryDatabase) -> String {
+    register_and_login("admin@addre.ss", "P_ass1Wo$rD").await;
+    let admin = database.get_user_by_email("admin@addre.ss").await.unwrap();
+    database
+        .set_user_roles(admin.id, &["admin".to_string()], &[])
+        .await
+        .unwrap();
+
+    let login = register_and_login("admin@addre.ss", "P_ass1Wo$rD").await;
+    login["token"].as_str().unwrap().to_string()
+}
+
+async fn refresh(login: &Map<String, Value>) -> String {
+    let response = post(
+        "token/refresh",
+        json!({ "refresh_token": login["refresh_token"] }),
+    )
+    .await;
+    response.body.unwrap()["token"]
+        .as_str()
+        .unwrap()
+        .to_string()
+}
+
+#[tokio::test]
+#[serial]
+async fn default_role() -> Result<(), Box<dyn Error>> {
+    with_router(downstream_router(SimpleMemoryDatabase::new()), async {
+        let login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let token = login["token"].as_str().unwrap();
+
+        let profile = get_authorized("me", token).await.body.unwrap();
+        assert_eq!(profile["roles"], json!(["user"]));
+        assert_eq!(profile["permissions"], json!([]));
+        let body = get_authorized("token", token).await.body.unwrap();
+        assert_eq!(body["roles"], json!(["user"]));
+        assert_eq!(body["scope"], "");
+
+        for endpoint in ["support", "tickets", "admin/reports"] {
+            let response = get_authorized(endpoint, token).await;
+            assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+            assert_eq!(
+                response.headers[header::WWW_AUTHENTICATE],
+                "Bearer error=\"insufficient_scope\""
+            );
+        }
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn assign_roles() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+
+    with_router(downstream_router(database.clone()), async {
+        let admin_token = register_admin(&database).await;
+        let response = get_authorized("admin/reports", &admin_token).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        let login = register_and_login("email@addre.ss", "P_ass1Wo$rD").await;
+        let token = login["token"].as_str().unwrap();
+        let user_id = get_authorized("me", token).await.body.unwrap()["id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        let request = json!({"roles": ["user", "support"], "permissions": ["tickets:read"]});
+        let response = put_authorized(
+            format!("admin/users/{user_id}/roles"),
+            token,
+            request.clone(),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+
+        let response = put_authorized(
+            format!("admin/users/{user_id}/roles"),
+            &admin_token,
+            request,
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let profile = response.body.unwrap();
+        assert_eq!(profile["roles"], json!(["support", "user"]));
+        assert_eq!(profile["permissions"], json!(["tickets:read"]));
+
+        // granted roles are embedded into the user's next token
+        let response = get_authorized("support", token).await;
+        assert_eq!(response.status_code, StatusCode::FORBIDDEN);
+        let token = refresh(&login).await;
+        let body = get_authorized("token", &token).await.body.unwrap();
+        assert_eq!(body["roles"], json!(["support", "user"]));
+        assert_eq!(body["scope"], "tickets:read");
+        let response = get_authorized("support", &token).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        assert_eq!(response.body.unwrap()["user_id"], user_id.as_str());
+        let response = get_authorized("tickets", &token).await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        // taking roles away revokes the user's tokens
+        let response = put_authorized(
+            format!("admin/users/{user_id}/roles"),
+            &admin_token,
+            json!({"roles": ["user"]}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+        let response = get_authorized("support", &token).await;
+        assert_eq!(response.status_code, StatusCode::UNAUTHORIZED);
+
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,94 @@
+use axum_api::{
+    database::SimpleMemoryDatabase,
+    token::{KeyError, SigningKey, TokenManager},
+};
+use jsonwebtoken::Algorithm;
+use std::{error::Error, fs, time::Duration};
+use uuid::Uuid;
+
+fn load_key(algorithm: Algorithm, name: &str) -> Result<SigningKey, KeyError> {
+    SigningKey::from_pem(
+        algorithm,
+        &fs::read(format!("tests/keys/{name}_private.pem")).unwrap(),
+        &fs::read(format!("tests/keys/{name}_public.pem")).unwrap(),
+    )
+}
+
+fn new_token_manager(signing_key: SigningKey) -> TokenManager {
+    TokenManager::new(
+        Duration::from_secs(600),
+        Duration::from_secs(60),
+        signing_key,
+    )
+}
+
+#[tokio::test]
+async fn sign_and_verify_with_asymmetric_keys() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+
+    for (algorithm, name) in [
+        (Algorithm::RS256, "rsa"),
+        (Algorithm::RS512, "rsa"),
+        (Algorithm::PS256, "rsa"),
+        (Algorithm::ES256, "ec"),
+        (Algorithm::EdDSA, "ed25519"),
+    ] {
+        let token_manager = new_token_manager(load_key(algorithm, name)?);
+
+        let user_id = Uuid::new_v4();
+        let token = token_manager.new_token(user_id, &[], &[])?;
+        let payload = token_manager
+            .decode_and_validate_token(token, &database)
+            .await?;
+        assert_eq!(payload.sub, user_id);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn reject_token_signed_with_other_key() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+    let token_manager = new_token_manager(load_key(Algorithm::RS256, "rsa")?);
+    let other_token_manager = new_token_manager(load_key(Algorithm::RS256, "rsa_other")?);
+
+    let token = other_token_manager.new_token(Uuid::new_v4(), &[], &[])?;
+    assert!(token_manager
+        .decode_and_validate_token(token, &database)
+        .await
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn reject_key_not_matching_algorithm() {
+    for (algorithm, name) in [
+        (Algorithm::RS256, "ec"),
+        (Algorithm::RS256, "ed25519"),
+        (Algorithm::ES256, "rsa"),
+        (Algorithm::ES384, "ec"),
+        (Algorithm::EdDSA, "rsa"),
+        (Algorithm::HS256, "rsa"),
+    ] {
+        assert!(
+            load_key(algorithm, name).is_err(),
+            "{name} key accepted for {algorithm:?}"
+        );
+    }
+
+    assert!(matches!(
+        SigningKey::from_secret(Algorithm::RS256, b"secret"),
+        Err(KeyError::AlgorithmMismatch(Algorithm::RS256))
+    ));
+}
+
+#[test]
+fn reject_mismatched_key_pair() {
+    let key = SigningKey::from_pem(
+        Algorithm::RS256,
+        &fs::read("tests/keys/rsa_private.pem").unwrap(),
+        &fs::read("tests/keys/rsa_other_public.pem").unwrap(),
+    );
+    assert!(matches!(key, Err(KeyError::KeyPairMismatch(_))));
+}
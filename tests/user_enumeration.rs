@@ -0,0 +1,142 @@
+use axum_api::{
+    database::{Database, DatabaseError, SimpleMemoryDatabase, User},
+    password::{Argon2Hasher, BcryptHasher, PasswordHasher},
+};
+use std::{
+    error::Error,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+const SAMPLE_COUNT: usize = 60;
+
+/// Maximum relative difference between corresponding quantiles of the two
+/// latency distributions.
+const TOLERANCE: f64 = 0.3;
+
+fn user(email: &str, password: &str) -> User {
+    User {
+        email: email.to_string(),
+        password: password.to_string(),
+    }
+}
+
+/// Measures the latency of rejecting an existing user with a wrong password,
+/// and that of rejecting an unknown user, alternating between the two.
+async fn measure_latencies(
+    database: &SimpleMemoryDatabase,
+) -> Result<(Vec<Duration>, Vec<Duration>), Box<dyn Error>> {
+    database
+        .try_add_user(user("user@example.com", "password"))
+        .await?;
+
+    let mut existing_user_latencies = Vec::new();
+    let mut unknown_user_latencies = Vec::new();
+    for _ in 0..SAMPLE_COUNT {
+        let start = Instant::now();
+        let result = database
+            .validate_user(&user("user@example.com", "wrong password"))
+            .await;
+        existing_user_latencies.push(start.elapsed());
+        assert!(matches!(result, Err(DatabaseError::WrongPassword)));
+
+        let start = Instant::now();
+        let result = database
+            .validate_user(&user("unknown@example.com", "wrong password"))
+            .await;
+        unknown_user_latencies.push(start.elapsed());
+        assert!(matches!(result, Err(DatabaseError::NotFound)));
+    }
+
+    Ok((existing_user_latencies, unknown_user_latencies))
+}
+
+fn assert_distributions_match(mut a: Vec<Duration>, mut b: Vec<Duration>) {
+    a.sort();
+    b.sort();
+
+    for quantile in [0.25, 0.5, 0.75] {
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss,
+            clippy::cast_precision_loss
+        )]
+        let index = (quantile * (SAMPLE_COUNT - 1) as f64) as usize;
+        let (a, b) = (a[index].as_secs_f64(), b[index].as_secs_f64());
+        let difference = (a - b).abs() / a.max(b);
+        assert!(
+            difference <= TOLERANCE,
+            "{quantile} quantiles differ by {:.0}%: {a}s and {b}s",
+            difference * 100.0
+        );
+    }
+}
+
+#[tokio::test]
+async fn unknown_users_take_as_long_to_reject() -> Result<(), Box<dyn Error>> {
+    let hashers: [Arc<dyn PasswordHasher>; 2] = [
+        Arc::new(BcryptHasher::new(4)?),
+        Arc::new(Argon2Hasher::new(512, 1, 1)?),
+    ];
+
+    for hasher in hashers {
+        let database = SimpleMemoryDatabase::new().with_password_hasher(hasher)?;
+        let (existing_user_latencies, unknown_user_latencies) =
+            measure_latencies(&database).await?;
+
+        // make sure the password is actually hashed for existing users
+        assert!(existing_user_latencies.iter().min().unwrap() > &Duration::from_micros(100));
+        assert_distributions_match(existing_user_latencies, unknown_user_latencies);
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn plaintext_passwords_are_validated() -> Result<(), Box<dyn Error>> {
+    let database = SimpleMemoryDatabase::new();
+    database
+        .try_add_user(user("user@example.com", "password"))
+        .await?;
+
+    assert!(database
+        .validate_user(&user("user@example.com", "password"))
+        .await
+        .is_ok());
+    assert!(matches!(
+        database
+            .validate_user(&user("user@example.com", "passwore"))
+            .await,
+        Err(DatabaseError::WrongPassword)
+    ));
+    assert!(matches!(
+        database
+            .validate_user(&user("unknown@example.com", ""))
+            .await,
+        Err(DatabaseError::NotFound)
+    ));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn hashed_passwords_are_validated() -> Result<(), Box<dyn Error>> {
+    let database =
+        SimpleMemoryDatabase::new().with_password_hasher(Arc::new(BcryptHasher::new(4)?))?;
+    database
+        .try_add_user(user("user@example.com", "password"))
+        .await?;
+
+    assert!(database
+        .validate_user(&user("user@example.com", "password"))
+        .await
+        .is_ok());
+    assert!(matches!(
+        database
+            .validate_user(&user("user@example.com", "passwore"))
+            .await,
+        Err(DatabaseError::WrongPassword)
+    ));
+
+    Ok(())
+}
@@ -0,0 +1,162 @@
+mod common;
+
+use axum_api::validation::{normalize_email, MAX_PASSWORD_LENGTH};
+use common::{post, with_server};
+use reqwest::StatusCode;
+use serde_json::{json, Value};
+use serial_test::serial;
+use std::error::Error;
+
+/// Returns the fields which a validation error response lists errors for.
+fn error_fields(response: &common::Response) -> Vec<Value> {
+    response.body.as_ref().unwrap()["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| error["field"].clone())
+        .collect()
+}
+
+#[test]
+fn valid_emails() {
+    for (email, normalized) in [
+        ("email@addre.ss", "email@addre.ss"),
+        ("Foo@X.COM", "Foo@x.com"),
+        ("  padded@addre.ss ", "padded@addre.ss"),
+        (
+            "first.last+tag@sub.domain.com",
+            "first.last+tag@sub.domain.com",
+        ),
+        (
+            "!#$%&'*+-/=?^_`{|}~@addre.ss",
+            "!#$%&'*+-/=?^_`{|}~@addre.ss",
+        ),
+        (
+            "\"quoted @ string\"@addre.ss",
+            "\"quoted @ string\"@addre.ss",
+        ),
+        ("ｆｕｌｌｗｉｄｔｈ@ＡＤＤＲＥ.ＳＳ", "fullwidth@addre.ss"),
+        ("üser@bücher.DE", "üser@bücher.de"),
+    ] {
+        assert_eq!(normalize_email(email).as_deref(), Ok(normalized), "{email}");
+    }
+}
+
+#[test]
+fn invalid_emails() {
+    for email in [
+        "",
+        "no-at-sign",
+        "@addre.ss",
+        "email@",
+        "email@localhost",
+        "email@addre..ss",
+        "email@-addre.ss",
+        "email@addre.ss-",
+        "email@addr_e.ss",
+        "email@[127.0.0.1]",
+        ".email@addre.ss",
+        "em..ail@addre.ss",
+        "em ail@addre.ss",
+        "em\"ail@addre.ss",
+        "\"unterminated@addre.ss",
+        "\"bad\"quote\"@addre.ss",
+        &format!("{}@addre.ss", "a".repeat(65)),
+        &format!("email@{}.ss", "a".repeat(64)),
+        &format!("email@{}.ss", ["a".repeat(60).as_str(); 5].join(".")),
+    ] {
+        assert!(normalize_email(email).is_err(), "{email}");
+    }
+}
+
+#[tokio::test]
+#[serial]
+async fn register_with_invalid_input() -> Result<(), Box<dyn Error>> {
+    with_server(async {
+        for (body, fields) in [
+            (
+                json!({"email": "no-at-sign", "password": "P_ass1Wo$rD"}),
+                vec![json!("email")],
+            ),
+            (
+                json!({"email": "email@addre.ss", "password": ""}),
+                vec![json!("password")],
+            ),
+            (
+                json!({"email": "email@addre.ss", "password": "a".repeat(MAX_PASSWORD_LENGTH + 1)}),
+                vec![json!("password")],
+            ),
+            (
+                json!({"email": "a".repeat(1_000_000), "password": ""}),
+                vec![json!("email"), json!("password")],
+            ),
+        ] {
+            let response = post("register", body).await;
+            assert_eq!(response.status_code, StatusCode::UNPROCESSABLE_ENTITY);
+            assert_eq!(error_fields(&response), fields);
+        }
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn login_with_invalid_input() -> Result<(), Box<dyn Error>> {
+    with_server(async {
+        let response = post(
+            "login",
+            json!({"email": "email@addre..ss", "password": "P_ass1Wo$rD"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error_fields(&response), vec![json!("email")]);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn malformed_request_body() -> Result<(), Box<dyn Error>> {
+    with_server(async {
+        let response = post("register", json!({"email": "email@addre.ss"})).await;
+        assert_eq!(response.status_code, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error_fields(&response), vec![Value::Null]);
+
+        Ok(())
+    })
+    .await
+}
+
+#[tokio::test]
+#[serial]
+async fn emails_are_normalized() -> Result<(), Box<dyn Error>> {
+    with_server(async {
+        let response = post(
+            "register",
+            json!({"email": "Email@ADDRE.SS", "password": "P_ass1Wo$rD"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        let response = post(
+            "register",
+            json!({"email": " Ｅｍａｉｌ@addre.ss", "password": "P_ass1Wo$rD"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::CONFLICT);
+
+        let response = post(
+            "login",
+            json!({"email": "Email@Addre.Ss", "password": "P_ass1Wo$rD"}),
+        )
+        .await;
+        assert_eq!(response.status_code, StatusCode::OK);
+
+        Ok(())
+    })
+    .await
+}